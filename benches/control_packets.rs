@@ -0,0 +1,65 @@
+//! Measures the throughput of creating, serializing and decoding control
+//! packets, like the log lines an extcap sends for each captured packet.
+//!
+//! Run with `cargo bench --bench control_packets`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use r_extcap::controls::{ControlBatch, ControlPacketDecoder, LoggerControl, SelectorControl};
+
+fn control_packets(c: &mut Criterion) {
+    let logger = LoggerControl::builder()
+        .control_number(0)
+        .display("Log")
+        .build();
+    let selector = SelectorControl::builder()
+        .control_number(1)
+        .display("Delay")
+        .build();
+    let line = String::from("Received packet from 192.168.0.1");
+    let packet = logger.add_log(line.as_str().into());
+    let bytes = packet.to_bytes();
+
+    let mut group = c.benchmark_group("control_packets");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("add_log (borrowed)", |b| {
+        b.iter(|| logger.add_log(black_box(line.as_str()).into()))
+    });
+    group.bench_function("add_log (owned)", |b| {
+        b.iter(|| logger.add_log(black_box(line.clone()).into()))
+    });
+    group.bench_function("add_value", |b| {
+        b.iter(|| selector.add_value(black_box("10"), Some(black_box("10 sec"))))
+    });
+    group.bench_function("to_bytes", |b| b.iter(|| black_box(&packet).to_bytes()));
+    group.bench_function("into_owned", |b| {
+        b.iter(|| black_box(packet.clone()).into_owned())
+    });
+    group.bench_function("batch", |b| {
+        b.iter_batched_ref(
+            ControlBatch::default,
+            |batch| {
+                batch.send(black_box(packet.clone()));
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("batch add_log", |b| {
+        b.iter_batched_ref(
+            ControlBatch::default,
+            |batch| {
+                batch.add_log(&logger, black_box(line.as_str()));
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("decode", |b| {
+        let mut decoder = ControlPacketDecoder::new();
+        b.iter(|| decoder.push_bytes(black_box(&bytes)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, control_packets);
+criterion_main!(benches);
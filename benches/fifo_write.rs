@@ -0,0 +1,71 @@
+//! Compares the throughput of writing small packets to a pipe directly and
+//! through a [`BufferedFifo`] with different flush policies.
+//!
+//! Run with `cargo bench --bench fifo_write`.
+
+#[cfg(unix)]
+fn main() {
+    use std::{
+        fs::File,
+        io::{Read, Write},
+        os::unix::io::FromRawFd,
+        time::{Duration, Instant},
+    };
+
+    use r_extcap::{
+        interface::DataLink,
+        sink::{PacketSink, PcapSink},
+        BufferedFifo, FlushPolicy,
+    };
+
+    const PACKETS: u32 = 200_000;
+
+    /// Creates a pipe, with a thread that drains the read end like Wireshark
+    /// does.
+    fn pipe() -> File {
+        let mut fds = [0; 2];
+        // Safety: `fds` has room for the two file descriptors.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // Safety: `pipe` returned two new file descriptors owned by no one else.
+        let (mut read_end, write_end) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        std::thread::spawn(move || {
+            let mut buffer = [0; 64 * 1024];
+            while read_end.read(&mut buffer).is_ok_and(|len| len > 0) {}
+        });
+        write_end
+    }
+
+    fn bench(name: &str, writer: impl Write) {
+        let mut sink = PcapSink::new(writer, DataLink::ETHERNET).unwrap();
+        let packet = [0_u8; 64];
+        let start = Instant::now();
+        for i in 0..PACKETS {
+            sink.write_packet(Duration::from_micros(i.into()), &packet)
+                .unwrap();
+        }
+        sink.into_inner().flush().unwrap();
+        let elapsed = start.elapsed();
+        println!(
+            "{name:<24} {elapsed:>10.2?} {:>12.0} packets/s",
+            f64::from(PACKETS) / elapsed.as_secs_f64()
+        );
+    }
+
+    bench("unbuffered", pipe());
+    bench(
+        "EveryWrite",
+        BufferedFifo::new(pipe(), FlushPolicy::EveryWrite),
+    );
+    bench(
+        "Size(64 KiB)",
+        BufferedFifo::new(pipe(), FlushPolicy::Size(64 * 1024)),
+    );
+    bench(
+        "Interval(100 ms)",
+        BufferedFifo::new(pipe(), FlushPolicy::default()),
+    );
+}
+
+#[cfg(not(unix))]
+fn main() {}
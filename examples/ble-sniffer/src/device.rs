@@ -0,0 +1,265 @@
+//! A simulated BLE sniffer device, reachable over TCP.
+//!
+//! Real sniffers are usually attached over USB or a serial port, and report
+//! each received packet with some radio metadata. This simulates that with a
+//! small TCP protocol, where each advertisement is sent as one record:
+//!
+//! | Offset | Length | Field                                  |
+//! |--------|--------|----------------------------------------|
+//! | 0      | 1      | Length of the rest of the record       |
+//! | 1      | 1      | Advertising channel index, 37 to 39    |
+//! | 2      | 1      | RSSI in dBm, as a signed byte          |
+//! | 3      | 6      | Advertiser address, big-endian         |
+//! | 9      | ...    | Advertising data                       |
+
+use std::{collections::BTreeSet, fmt::Display, io, time::Duration};
+
+use log::debug;
+use r_extcap::bluetooth::{rf_channel, LePseudoHeader};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// The device name that starts an in-process simulator instead of connecting
+/// to a device over TCP.
+pub const SIMULATED: &str = "simulated";
+
+/// The access address used by all advertising packets.
+const ADVERTISING_ACCESS_ADDRESS: u32 = 0x8E89_BED6;
+
+/// The interval between two advertisements sent by the simulator.
+const ADVERTISING_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A Bluetooth device address, most significant byte first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address(pub [u8; 6]);
+
+impl Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02X}:{b:02X}:{c:02X}:{d:02X}:{e:02X}:{g:02X}")
+    }
+}
+
+impl std::str::FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut address = [0; 6];
+        let mut parts = s.split(':');
+        for byte in &mut address {
+            let part = parts
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Address {s:?} is too short"))?;
+            *byte = u8::from_str_radix(part, 16)?;
+        }
+        anyhow::ensure!(parts.next().is_none(), "Address {s:?} is too long");
+        Ok(Self(address))
+    }
+}
+
+/// An advertisement received by the device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Advertisement {
+    /// The advertising channel index, 37 to 39.
+    pub channel: u8,
+    /// The received signal strength, in dBm.
+    pub rssi: i8,
+    /// The address of the advertiser.
+    pub address: Address,
+    /// The advertising data, as a sequence of AD structures.
+    pub data: Vec<u8>,
+}
+
+impl Advertisement {
+    /// Reads the next advertisement from `reader`, or returns `None` if the
+    /// device closed the connection.
+    pub async fn read(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<Self>> {
+        let len = match reader.read_u8().await {
+            Ok(len) => usize::from(len),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if len < 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Record of length {len} is too short"),
+            ));
+        }
+        let mut record = vec![0; len];
+        reader.read_exact(&mut record).await?;
+        Ok(Some(Self {
+            channel: record[0],
+            rssi: record[1] as i8,
+            address: Address(record[2..8].try_into().unwrap()),
+            data: record[8..].to_vec(),
+        }))
+    }
+
+    /// Encodes this advertisement as a device record.
+    fn to_record(&self) -> Vec<u8> {
+        let mut record = vec![(8 + self.data.len()) as u8, self.channel, self.rssi as u8];
+        record.extend_from_slice(&self.address.0);
+        record.extend_from_slice(&self.data);
+        record
+    }
+
+    /// Encodes this advertisement as an `ADV_IND` packet for the
+    /// `BLUETOOTH_LE_LL_WITH_PHDR` data link type.
+    ///
+    /// The simulated radio checks the CRC itself, so the CRC field is left
+    /// zeroed and the pseudo-header marks it as valid.
+    pub fn to_packet(&self) -> Vec<u8> {
+        let header = LePseudoHeader::builder()
+            .rf_channel(rf_channel(self.channel).unwrap_or_default())
+            .signal_power(self.rssi)
+            .reference_access_address(ADVERTISING_ACCESS_ADDRESS)
+            .dewhitened(true)
+            .crc_valid(true)
+            .build();
+        let mut packet = ADVERTISING_ACCESS_ADDRESS.to_le_bytes().to_vec();
+        // ADV_IND from a random device address.
+        packet.extend_from_slice(&[0x40, (6 + self.data.len()) as u8]);
+        packet.extend(self.address.0.iter().rev());
+        packet.extend_from_slice(&self.data);
+        packet.extend_from_slice(&[0; 3]);
+        header.encode(&packet)
+    }
+
+    /// Returns the advertiser address of a packet created by
+    /// [`to_packet`][Self::to_packet].
+    pub fn packet_address(packet: &[u8]) -> Option<Address> {
+        let offset = LePseudoHeader::LEN + 6;
+        let mut address: [u8; 6] = packet.get(offset..offset + 6)?.try_into().ok()?;
+        address.reverse();
+        Some(Address(address))
+    }
+}
+
+/// A peripheral advertised by the simulator.
+struct Peripheral {
+    address: Address,
+    name: &'static str,
+    rssi: i8,
+}
+
+const PERIPHERALS: [Peripheral; 3] = [
+    Peripheral {
+        address: Address([0xC0, 0xFF, 0xEE, 0x00, 0x00, 0x01]),
+        name: "Thermometer",
+        rssi: -45,
+    },
+    Peripheral {
+        address: Address([0xC0, 0xFF, 0xEE, 0x00, 0x00, 0x02]),
+        name: "Heart Rate",
+        rssi: -62,
+    },
+    Peripheral {
+        address: Address([0xC0, 0xFF, 0xEE, 0x00, 0x00, 0x03]),
+        name: "Door Lock",
+        rssi: -80,
+    },
+];
+
+/// Serves the simulated device on `listener`, sending the advertisements of
+/// [`PERIPHERALS`] in turn to every client, cycling through the advertising
+/// channels.
+async fn simulate(listener: TcpListener) -> io::Result<()> {
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        debug!("Simulator: client {peer} connected");
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ADVERTISING_INTERVAL);
+            for i in 0_usize.. {
+                interval.tick().await;
+                let peripheral = &PERIPHERALS[i % PERIPHERALS.len()];
+                // Flags: LE General Discoverable, BR/EDR not supported.
+                let mut data = vec![0x02, 0x01, 0x06];
+                data.extend_from_slice(&[peripheral.name.len() as u8 + 1, 0x09]);
+                data.extend_from_slice(peripheral.name.as_bytes());
+                let advertisement = Advertisement {
+                    channel: 37 + (i % 3) as u8,
+                    rssi: peripheral.rssi + (i % 7) as i8 - 3,
+                    address: peripheral.address,
+                    data,
+                };
+                if stream.write_all(&advertisement.to_record()).await.is_err() {
+                    debug!("Simulator: client {peer} disconnected");
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Connects to the sniffer device at `device`, which is either a `host:port`
+/// address or [`SIMULATED`].
+pub async fn connect(device: &str) -> io::Result<TcpStream> {
+    if device == SIMULATED {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let address = listener.local_addr()?;
+        tokio::spawn(simulate(listener));
+        TcpStream::connect(address).await
+    } else {
+        TcpStream::connect(device).await
+    }
+}
+
+/// Listens to `device` for `duration`, and returns the addresses of the
+/// advertisers seen in that time.
+pub async fn scan(device: &str, duration: Duration) -> io::Result<BTreeSet<Address>> {
+    let mut stream = connect(device).await?;
+    let mut addresses = BTreeSet::new();
+    let read = async {
+        while let Some(advertisement) = Advertisement::read(&mut stream).await? {
+            addresses.insert(advertisement.address);
+        }
+        Ok::<_, io::Error>(())
+    };
+    // Reading stops with a timeout error once `duration` has passed.
+    if let Ok(result) = tokio::time::timeout(duration, read).await {
+        result?;
+    }
+    Ok(addresses)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Address, Advertisement};
+
+    #[test]
+    fn record_round_trip() {
+        let advertisement = Advertisement {
+            channel: 38,
+            rssi: -70,
+            address: "C0:FF:EE:00:00:01".parse().unwrap(),
+            data: vec![0x02, 0x01, 0x06],
+        };
+        let record = advertisement.to_record();
+        let read = tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(Advertisement::read(&mut &record[..]))
+            .unwrap();
+        assert_eq!(read, Some(advertisement));
+    }
+
+    #[test]
+    fn packet_address() {
+        let advertisement = Advertisement {
+            channel: 37,
+            rssi: -40,
+            address: Address([0xC0, 0xFF, 0xEE, 0x00, 0x00, 0x02]),
+            data: vec![],
+        };
+        let packet = advertisement.to_packet();
+        // RF channel 0, RSSI, then the access address.
+        assert_eq!(&packet[..2], &[0, -40_i8 as u8]);
+        assert_eq!(&packet[10..14], &[0xD6, 0xBE, 0x89, 0x8E]);
+        assert_eq!(
+            Advertisement::packet_address(&packet),
+            Some(advertisement.address)
+        );
+        assert_eq!(advertisement.address.to_string(), "C0:FF:EE:00:00:02");
+    }
+}
@@ -0,0 +1,427 @@
+//! An async extcap that captures Bluetooth LE advertisements from a sniffer
+//! device connected over TCP. Run with `--device simulated` (the default) to
+//! use a simulated device with a few peripherals.
+//!
+//! This example shows how the pieces of an async extcap fit together:
+//!
+//! * The toolbar controls are handled by a
+//!   [`ControlHub`][r_extcap::controls::asynchronous::ControlHub] task, which
+//!   updates the [`Settings`] used by the capture loop.
+//! * Packets are written through a [`PcapSink`], with middleware dropping the
+//!   packets filtered out by the toolbar and by the `--peer` config.
+//! * The capture ends cleanly when Wireshark stops it, using
+//!   [`CaptureStep::on_capture_end`].
+//! * A second capture on the same device fails with a clear error, using an
+//!   [`InstanceLock`].
+//! * The `--peer` config is reloaded by scanning the device given in the
+//!   other config, `--device`.
+//! * `--install`, `--uninstall` and `--doctor` are handled by
+//!   [`InstallArgs`].
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI8, AtomicU8, Ordering},
+        Arc, LazyLock, OnceLock,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use log::{debug, info, warn};
+use r_extcap::{
+    bluetooth::rf_channel,
+    config::*,
+    controls::{
+        asynchronous::{ControlHubSender, ExtcapControlSenderTrait as _},
+        *,
+    },
+    help::ExtcapHelp,
+    install::InstallArgs,
+    instance::InstanceLock,
+    interface::{DataLink, Dlt, Interface, Metadata},
+    sink::{PacketSink, PcapSink, SinkPacket, Verdict},
+    CaptureStep, ExtcapArgs, ExtcapError, ExtcapStep,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+mod device;
+
+use device::{Address, Advertisement};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about)]
+struct AppArgs {
+    #[command(flatten)]
+    extcap: ExtcapArgs,
+
+    #[command(flatten)]
+    install: InstallArgs,
+
+    /// The sniffer device to connect to, as `host:port`, or `simulated`.
+    #[arg(long, default_value = device::SIMULATED)]
+    device: String,
+
+    /// Only capture the advertisements from this address, or `any`.
+    #[arg(long, default_value = "any")]
+    peer: String,
+}
+
+/// How long to listen to the device when reloading the `--peer` options.
+const SCAN_DURATION: Duration = Duration::from_secs(1);
+
+/// How often to report the capture statistics in the status bar.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The device to scan when reloading the `--peer` options, since the reload
+/// function cannot capture the arguments.
+static SCAN_DEVICE: OnceLock<String> = OnceLock::new();
+
+r_extcap::configs! {
+    fn configs;
+
+    CONFIG_DEVICE: StringConfig {
+        call: "device",
+        display: "Device",
+        tooltip: "The sniffer device to connect to, as host:port, or \"simulated\"",
+        placeholder: device::SIMULATED,
+    },
+    CONFIG_PEER: SelectorConfig {
+        call: "peer",
+        display: "Peripheral",
+        tooltip: "Only capture the advertisements from this peripheral",
+        reload: Reload {
+            label: String::from("Scan"),
+            reload_fn: scan_peers,
+        },
+        default_options: [
+            ConfigOptionValue::builder()
+                .value("any")
+                .display("Any peripheral")
+                .default(true)
+                .build(),
+        ],
+    },
+}
+
+r_extcap::controls! {
+    channel: SelectorControl {
+        display: String::from("Channel"),
+        tooltip: Some(String::from("Advertising channel to capture")),
+        options: vec![
+            SelectorControlOption::builder().value("0").display("All").default(true).build(),
+            SelectorControlOption::builder().value("37").display("37").build(),
+            SelectorControlOption::builder().value("38").display("38").build(),
+            SelectorControlOption::builder().value("39").display("39").build(),
+        ],
+    },
+    min_rssi: SelectorControl {
+        display: String::from("Minimum RSSI"),
+        tooltip: Some(String::from("Drop the packets received with a weaker signal")),
+        options: vec![
+            SelectorControlOption::builder().value("-128").display("Any").default(true).build(),
+            SelectorControlOption::builder().value("-70").display("-70 dBm").build(),
+            SelectorControlOption::builder().value("-50").display("-50 dBm").build(),
+        ],
+    },
+    pause: ButtonControl {
+        display: String::from("Pause"),
+        tooltip: Some(String::from("Pause or resume the capture")),
+    },
+    logger: LoggerControl {
+        display: String::from("Log"),
+        tooltip: Some(String::from("Show capture log")),
+    },
+}
+
+static METADATA: LazyLock<Metadata> = LazyLock::new(|| r_extcap::cargo_metadata!());
+
+static INTERFACE: LazyLock<Interface> = LazyLock::new(|| {
+    Interface::builder()
+        .value("ble-sniffer".into())
+        .display("Bluetooth LE sniffer (Rust example)".into())
+        .dlt(Dlt::new(
+            DataLink::BLUETOOTH_LE_LL_WITH_PHDR,
+            "Bluetooth LE link layer with pseudo-header",
+        ))
+        .build()
+});
+
+static CONTROLS: LazyLock<Controls> = LazyLock::new(Controls::new);
+
+/// The capture settings that can be changed from the toolbar while capturing.
+#[derive(Debug)]
+struct Settings {
+    /// The advertising channel index to capture, or 0 for all channels.
+    channel: AtomicU8,
+    min_rssi: AtomicI8,
+    paused: AtomicBool,
+}
+
+impl Settings {
+    /// Whether `packet`, created by [`Advertisement::to_packet`], should be
+    /// written with the current settings.
+    fn accepts(&self, packet: &[u8]) -> bool {
+        let channel = self.channel.load(Ordering::Relaxed);
+        let (Some(&packet_channel), Some(&rssi)) = (packet.first(), packet.get(1)) else {
+            return false;
+        };
+        !self.paused.load(Ordering::Relaxed)
+            && (channel == 0 || rf_channel(channel) == Some(packet_channel))
+            && rssi as i8 >= self.min_rssi.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            channel: AtomicU8::new(0),
+            min_rssi: AtomicI8::new(i8::MIN),
+            paused: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Reload function for [`CONFIG_PEER`], listing the peripherals currently
+/// seen by the device.
+fn scan_peers() -> Vec<ConfigOptionValue> {
+    let mut options = vec![ConfigOptionValue::builder()
+        .value("any")
+        .display("Any peripheral")
+        .default(true)
+        .build()];
+    let device = SCAN_DEVICE.get().map_or(device::SIMULATED, String::as_str);
+    // The reload function is synchronous, but runs within the Tokio runtime
+    // started by `main`.
+    let scan = tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current().block_on(device::scan(device, SCAN_DURATION))
+    });
+    match scan {
+        Ok(addresses) => options.extend(addresses.into_iter().map(|address| {
+            ConfigOptionValue::builder()
+                .value(address.to_string())
+                .display(address.to_string())
+                .build()
+        })),
+        Err(e) => warn!("Unable to scan {device}: {e}"),
+    }
+    options
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+    let args = AppArgs::parse();
+    debug!("Args: {args:?}");
+    // Run with RUST_LOG=debug to log the environment given by Wireshark.
+    args.extcap.capture_invocation().log();
+    if let Some(result) = args.install.run() {
+        return Ok(result?);
+    }
+    let interfaces = [&*INTERFACE];
+    let configs = configs();
+    let step = match args.extcap.run() {
+        Err(ExtcapError::NotExtcapInput) => {
+            let help = ExtcapHelp::builder()
+                .metadata(&METADATA)
+                .interfaces(&interfaces)
+                .configs(&configs)
+                .build();
+            eprintln!("{help}");
+            return Ok(());
+        }
+        step => step?,
+    };
+    match step {
+        ExtcapStep::Interfaces(step) => step.list_interfaces_for_version(
+            args.extcap.wireshark_version(),
+            &METADATA,
+            &interfaces,
+            &CONTROLS.all(),
+        ),
+        ExtcapStep::Dlts(step) => step.print_from_interfaces(&interfaces)?,
+        ExtcapStep::Config(step) => step.list_configs(&configs),
+        ExtcapStep::ReloadConfig(step) => {
+            // Wireshark passes the values entered for the other configs along
+            // with the reload request, so the scan uses the selected device.
+            debug!("Reloading {} for {}", step.config, step.interface);
+            SCAN_DEVICE.get_or_init(|| args.device.clone());
+            step.reload_from_configs(&configs)?;
+        }
+        ExtcapStep::FilterValidation(_) => {}
+        ExtcapStep::Capture(step) => capture(&args, step).await?,
+        _ => anyhow::bail!("Unsupported extcap step"),
+    }
+    Ok(())
+}
+
+async fn capture(args: &AppArgs, mut step: CaptureStep<'_>) -> anyhow::Result<()> {
+    step.install_panic_hook();
+    let peer = match args.peer.as_str() {
+        "any" => None,
+        peer => Some(peer.parse::<Address>()?),
+    };
+    // The device serves one client at a time, so report a second capture on
+    // the same device clearly instead of failing to connect.
+    let _lock = match InstanceLock::acquire(&args.device) {
+        Ok(lock) => lock,
+        Err(e) => step.abort_capture(e),
+    };
+    let mut device = match device::connect(&args.device).await {
+        Ok(device) => device,
+        Err(e) => step.abort_capture(format!("Cannot connect to {}: {e}", args.device)),
+    };
+    // Wireshark terminates the extcap when the user stops the capture, so
+    // anything that must happen before exiting belongs in this callback.
+    step.on_capture_end(|| info!("Capture ended"));
+    let shutdown = step.shutdown_signal().clone();
+
+    let settings = Arc::new(Settings::default());
+    let hub = step.spawn_control_hub_async();
+    if let Some(hub) = &hub {
+        // Subscribe before the handshake, so that the initial values of the
+        // controls are applied too.
+        tokio::spawn(handle_controls(
+            hub.subscribe(),
+            hub.sender(),
+            Arc::clone(&settings),
+        ));
+        hub.wait_for_init(Duration::from_secs(30)).await?;
+        hub.send(
+            CONTROLS
+                .logger
+                .clear_and_add_log(format!("Connected to {}", args.device).into()),
+        )
+        .await?;
+    }
+
+    let accept_settings = Arc::clone(&settings);
+    let mut sink = PcapSink::new(step.take_fifo(), INTERFACE.dlt.data_link_type)?
+        .with_middleware(move |packet: &mut SinkPacket<'_>| {
+            if accept_settings.accepts(&packet.data) {
+                Verdict::Pass
+            } else {
+                Verdict::Drop
+            }
+        })
+        .with_middleware(move |packet: &mut SinkPacket<'_>| {
+            match (peer, Advertisement::packet_address(&packet.data)) {
+                (Some(peer), Some(address)) if peer != address => Verdict::Drop,
+                _ => Verdict::Pass,
+            }
+        });
+
+    let mut stats_interval = tokio::time::interval(STATS_INTERVAL);
+    loop {
+        tokio::select! {
+            advertisement = Advertisement::read(&mut device) => {
+                let Some(advertisement) = advertisement? else {
+                    anyhow::bail!("Device {} disconnected", args.device);
+                };
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
+                if let Err(e) = sink.write_packet(timestamp, &advertisement.to_packet()) {
+                    if shutdown.is_triggered() {
+                        break;
+                    }
+                    return Err(e.into());
+                }
+            }
+            _ = stats_interval.tick() => {
+                let stats = sink.stats();
+                debug!("Capture stats: {stats:?}");
+                if let Some(hub) = &hub {
+                    hub.status_message(&format!(
+                        "{} packets captured, {} filtered out",
+                        stats.written_packets, stats.dropped_packets
+                    ))
+                    .await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the changes made in the toolbar to `settings`, until the control
+/// pipe is closed.
+async fn handle_controls(
+    mut packets: broadcast::Receiver<ControlPacket<'static>>,
+    sender: ControlHubSender,
+    settings: Arc<Settings>,
+) -> anyhow::Result<()> {
+    loop {
+        let packet = match packets.recv().await {
+            Ok(packet) => packet,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Skipped {skipped} control packets");
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let event = ControlEvent::resolve(packet, &CONTROLS.all());
+        let log = match event {
+            ControlEvent::SelectionChanged { control, value }
+                if control == CONTROLS.channel.control_number =>
+            {
+                settings.channel.store(value.parse()?, Ordering::Relaxed);
+                format!("Channel = {value}")
+            }
+            ControlEvent::SelectionChanged { control, value }
+                if control == CONTROLS.min_rssi.control_number =>
+            {
+                settings.min_rssi.store(value.parse()?, Ordering::Relaxed);
+                format!("Minimum RSSI = {value}")
+            }
+            ControlEvent::ButtonPressed { control } if control == CONTROLS.pause.control_number => {
+                let paused = !settings.paused.fetch_xor(true, Ordering::Relaxed);
+                let label = if paused { "Resume" } else { "Pause" };
+                sender.send(CONTROLS.pause.set_label(label)).await?;
+                String::from(if paused {
+                    "Capture paused"
+                } else {
+                    "Capture resumed"
+                })
+            }
+            event => {
+                debug!("Ignoring control event {event:?}");
+                continue;
+            }
+        };
+        sender.send(CONTROLS.logger.add_log(log.into())).await?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::Ordering;
+
+    use clap::CommandFactory;
+
+    use super::{device::Advertisement, AppArgs, Settings};
+
+    #[test]
+    fn test_parse() {
+        AppArgs::command().debug_assert();
+    }
+
+    #[test]
+    fn settings_accepts() {
+        let packet = Advertisement {
+            channel: 38,
+            rssi: -60,
+            address: "C0:FF:EE:00:00:01".parse().unwrap(),
+            data: vec![],
+        }
+        .to_packet();
+        let settings = Settings::default();
+        assert!(settings.accepts(&packet));
+        settings.channel.store(37, Ordering::Relaxed);
+        assert!(!settings.accepts(&packet));
+        settings.channel.store(38, Ordering::Relaxed);
+        assert!(settings.accepts(&packet));
+        settings.min_rssi.store(-50, Ordering::Relaxed);
+        assert!(!settings.accepts(&packet));
+        settings.min_rssi.store(-70, Ordering::Relaxed);
+        settings.paused.store(true, Ordering::Relaxed);
+        assert!(!settings.accepts(&packet));
+    }
+}
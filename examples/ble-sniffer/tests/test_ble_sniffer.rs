@@ -0,0 +1,163 @@
+use std::{
+    fs::File, io::Read, net::TcpListener, os::unix::process::ExitStatusExt, path::Path,
+    process::Command, thread::JoinHandle, time::Duration,
+};
+
+use assert_cmd::prelude::{CommandCargoExt, OutputAssertExt};
+use indoc::indoc;
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use pcap_file::{pcap::PcapReader, DataLink};
+use predicates::prelude::*;
+use r_extcap::testing::TempFifo;
+use wait_timeout::ChildExt;
+
+#[test]
+fn interfaces() {
+    let mut cmd = Command::cargo_bin("extcap-ble-sniffer").unwrap();
+    cmd.args(["--extcap-interfaces"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(indoc! {"
+            extcap {version=0.1.0}{help=https://github.com/mauricelam/r-extcap}{display=Async Bluetooth LE sniffer extcap example for Rust}
+            interface {value=ble-sniffer}{display=Bluetooth LE sniffer (Rust example)}
+            control {number=0}{type=selector}{display=Channel}{tooltip=Advertising channel to capture}
+            value {control=0}{value=0}{display=All}{default=true}
+            value {control=0}{value=37}{display=37}
+            value {control=0}{value=38}{display=38}
+            value {control=0}{value=39}{display=39}
+            control {number=1}{type=selector}{display=Minimum RSSI}{tooltip=Drop the packets received with a weaker signal}
+            value {control=1}{value=-128}{display=Any}{default=true}
+            value {control=1}{value=-70}{display=-70 dBm}
+            value {control=1}{value=-50}{display=-50 dBm}
+            control {number=2}{type=button}{display=Pause}{tooltip=Pause or resume the capture}
+            control {number=3}{type=button}{role=logger}{display=Log}{tooltip=Show capture log}
+            "}
+        ));
+}
+
+#[test]
+fn print_dlt() {
+    let mut cmd = Command::cargo_bin("extcap-ble-sniffer").unwrap();
+    cmd.args(["--extcap-interface", "ble-sniffer", "--extcap-dlts"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff(indoc! {"
+            dlt {number=256}{name=BLUETOOTH_LE_LL_WITH_PHDR}{display=Bluetooth LE link layer with pseudo-header}
+            "}
+        ));
+}
+
+#[test]
+fn config_reload_scans_device() {
+    let mut cmd = Command::cargo_bin("extcap-ble-sniffer").unwrap();
+    cmd.args(["--extcap-interface", "ble-sniffer", "--extcap-config"]);
+    cmd.args(["--extcap-reload-option", "peer", "--device", "simulated"]);
+    cmd.assert().success().stdout(predicate::str::diff(indoc! {"
+            value {arg=1}{value=any}{display=Any peripheral}{default=true}
+            value {arg=1}{value=C0:FF:EE:00:00:01}{display=C0:FF:EE:00:00:01}{default=false}
+            value {arg=1}{value=C0:FF:EE:00:00:02}{display=C0:FF:EE:00:00:02}{default=false}
+            value {arg=1}{value=C0:FF:EE:00:00:03}{display=C0:FF:EE:00:00:03}{default=false}
+            "}));
+}
+
+#[test]
+fn capture_until_terminated() {
+    let capture_fifo = TempFifo::new("capture-fifo").unwrap();
+
+    let mut cmd = Command::cargo_bin("extcap-ble-sniffer").unwrap();
+    cmd.args(["--extcap-interface", "ble-sniffer", "--capture"]);
+    cmd.args(["--fifo", capture_fifo.path().to_string_lossy().as_ref()]);
+    cmd.args(["--peer", "C0:FF:EE:00:00:02"]);
+    let mut child_proc = cmd.spawn().unwrap();
+
+    let mut reader = PcapReader::new(capture_fifo.open_reader().unwrap()).unwrap();
+    assert_eq!(
+        reader.header().datalink,
+        DataLink::BLUETOOTH_LE_LL_WITH_PHDR
+    );
+    for _ in 0..2 {
+        let packet = reader.next_packet().unwrap().unwrap();
+        // The advertiser address follows the pseudo-header, the access
+        // address and the PDU header, in little-endian.
+        assert_eq!(&packet.data[16..22], &[0x02, 0, 0, 0xEE, 0xFF, 0xC0]);
+    }
+
+    // Stopping the capture in Wireshark sends SIGTERM to the extcap.
+    signal::kill(
+        Pid::from_raw(child_proc.id().try_into().unwrap()),
+        Signal::SIGTERM,
+    )
+    .unwrap();
+    let status = child_proc
+        .wait_timeout(Duration::from_secs(5))
+        .unwrap()
+        .expect("Capture did not stop after SIGTERM");
+    assert_eq!(status.signal(), Some(Signal::SIGTERM as i32));
+}
+
+#[test]
+fn capture_unreachable_device() {
+    let capture_fifo = TempFifo::new("capture-fifo").unwrap();
+    let fifo_reader = spawn_fifo_reader(capture_fifo.path());
+
+    let mut cmd = assert_cmd::Command::cargo_bin("extcap-ble-sniffer").unwrap();
+    cmd.args(["--extcap-interface", "ble-sniffer", "--capture"]);
+    cmd.args(["--fifo", capture_fifo.path().to_string_lossy().as_ref()]);
+    // Port 0 is never listening, so the connection is refused.
+    cmd.args(["--device", "127.0.0.1:0"]);
+    cmd.timeout(Duration::from_secs(5));
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Cannot connect to 127.0.0.1:0"));
+    fifo_reader.join().unwrap();
+}
+
+/// Reads `capture_fifo` to the end in a thread, like Wireshark does, so that
+/// the extcap does not block when opening it.
+fn spawn_fifo_reader(capture_fifo: &Path) -> JoinHandle<()> {
+    let capture_fifo = capture_fifo.to_owned();
+    std::thread::spawn(move || {
+        let mut data = Vec::new();
+        File::open(capture_fifo)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+    })
+}
+
+#[test]
+fn capture_device_in_use() {
+    // A device that accepts the connection but never sends anything.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let device = listener.local_addr().unwrap().to_string();
+
+    let first_fifo = TempFifo::new("first-fifo").unwrap();
+    let first_reader = spawn_fifo_reader(first_fifo.path());
+    let mut first = Command::cargo_bin("extcap-ble-sniffer").unwrap();
+    first.args(["--extcap-interface", "ble-sniffer", "--capture"]);
+    first.args(["--fifo", first_fifo.path().to_string_lossy().as_ref()]);
+    first.args(["--device", &device]);
+    let mut first_proc = first.spawn().unwrap();
+    // The first capture holds the lock once it connects to the device.
+    let _connection = listener.accept().unwrap();
+
+    let second_fifo = TempFifo::new("second-fifo").unwrap();
+    let second_reader = spawn_fifo_reader(second_fifo.path());
+    let mut second = assert_cmd::Command::cargo_bin("extcap-ble-sniffer").unwrap();
+    second.args(["--extcap-interface", "ble-sniffer", "--capture"]);
+    second.args(["--fifo", second_fifo.path().to_string_lossy().as_ref()]);
+    second.args(["--device", &device]);
+    second.timeout(Duration::from_secs(5));
+    second
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(format!("Device {device} is busy")));
+    second_reader.join().unwrap();
+
+    first_proc.kill().unwrap();
+    first_proc.wait().unwrap();
+    first_reader.join().unwrap();
+}
@@ -6,44 +6,40 @@ use pcap_file::DataLink;
 use r_extcap::{
     config::*,
     controls::*,
+    encap::Encapsulation,
     interface::{Dlt, Interface, Metadata},
+    FilterValidationStep,
 };
-use std::{
-    fmt::Display,
-    num::ParseIntError,
-    time::Duration,
-};
+use std::{fmt::Display, net::Ipv4Addr, time::Duration};
 
-lazy_static! {
-    pub static ref CONFIG_DELAY: IntegerConfig = IntegerConfig::builder()
-        .config_number(0)
-        .call("delay")
-        .display("Time delay")
-        .tooltip("Time delay between packages")
-        .range(1..=15)
-        .default_value(5)
-        .build();
-    pub static ref CONFIG_MESSAGE: StringConfig = StringConfig::builder()
-        .config_number(1)
-        .call("message")
-        .display("Message")
-        .tooltip("Package message content")
-        .required(true)
-        .placeholder("Please enter a message here ...")
-        .build();
-    pub static ref CONFIG_VERIFY: BooleanConfig = BooleanConfig::builder()
-        .config_number(2)
-        .call("verify")
-        .display("Verify")
-        .tooltip("Verify package content")
-        .default_value(true)
-        .build();
-    pub static ref CONFIG_REMOTE: SelectorConfig = SelectorConfig::builder()
-        .config_number(3)
-        .call("remote")
-        .display("Remote Channel")
-        .tooltip("Remote Channel Selector")
-        .reload(Reload {
+r_extcap::configs! {
+    pub fn configs;
+
+    pub CONFIG_DELAY: IntegerConfig {
+        call: "delay",
+        display: "Time delay",
+        tooltip: "Time delay between packages",
+        range: 1..=15,
+        default_value: 5,
+    },
+    pub CONFIG_MESSAGE: StringConfig {
+        call: "message",
+        display: "Message",
+        tooltip: "Package message content",
+        required: true,
+        placeholder: "Please enter a message here ...",
+    },
+    pub CONFIG_VERIFY: BooleanConfig {
+        call: "verify",
+        display: "Verify",
+        tooltip: "Verify package content",
+        default_value: true,
+    },
+    pub CONFIG_REMOTE: SelectorConfig {
+        call: "remote",
+        display: "Remote Channel",
+        tooltip: "Remote Channel Selector",
+        reload: Reload {
             label: String::from("Load interfaces..."),
             reload_fn: || {
                 vec![
@@ -66,90 +62,81 @@ lazy_static! {
                         .build(),
                 ]
             }
-        })
-        .default_options([
+        },
+        default_options: [
             ConfigOptionValue::builder()
                 .value("if1")
                 .display("Remote1")
                 .default(true)
                 .build(),
             ConfigOptionValue::builder().value("if2").display("Remote2").build(),
-        ])
-        .build();
-    pub static ref CONFIG_FAKE_IP: StringConfig = StringConfig::builder()
-        .config_number(4)
-        .call("fake_ip")
-        .display("Fake IP Address")
-        .tooltip( "Use this ip address as sender")
-        .validation(r"\b(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\b")
-        .build();
-    pub static ref CONFIG_LTEST: LongConfig = LongConfig::builder()
-        .config_number(5)
-        .call("ltest")
-        .display("Long Test")
-        .tooltip("Long Test Value")
-        .default_value(123123123123123123)
-        .group("Numeric Values")
-        .build();
-    pub static ref CONFIG_D1TEST: DoubleConfig = DoubleConfig::builder()
-        .config_number(6)
-        .call("d1test")
-        .display("Double 1 Test")
-        .tooltip("Double Test Value")
-        .default_value(123.456)
-        .group("Numeric Values")
-        .build();
-    pub static ref CONFIG_D2TEST: DoubleConfig = DoubleConfig::builder()
-        .config_number(7)
-        .call("d2test")
-        .display("Double 2 Test")
-        .tooltip("Double Test Value")
-        .default_value(123456.0)
-        .group("Numeric Values")
-        .build();
-    pub static ref CONFIG_PASSWORD: PasswordConfig = PasswordConfig::builder()
-        .config_number(8)
-        .call("password")
-        .display("Password")
-        .tooltip("Package message password")
-        .build();
-    pub static ref CONFIG_TIMESTAMP: TimestampConfig = TimestampConfig::builder()
-        .config_number(9)
-        .call("ts")
-        .display("Start Time")
-        .tooltip("Capture start time")
-        .group("Time / Log")
-        .build();
-    pub static ref CONFIG_LOGFILE: FileSelectConfig = FileSelectConfig::builder()
-        .config_number(10)
-        .call("logfile")
-        .display("Log File Test")
-        .tooltip("The Log File Test")
-        .group("Time / Log")
-        .file_extension_filter("Text files (*.txt);;XML files (*.xml)")
-        .build();
-    pub static ref CONFIG_RADIO: RadioConfig = RadioConfig::builder()
-        .config_number(11)
-        .call("radio")
-        .display("Radio Test")
-        .tooltip("Radio Test Value")
-        .group("Selection")
-        .options([
+        ],
+    },
+    pub CONFIG_FAKE_IP: StringConfig {
+        call: "fake_ip",
+        display: "Fake IP Address",
+        tooltip: "Use this ip address as sender",
+        validation: r"\b(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\b",
+    },
+    pub CONFIG_LTEST: LongConfig {
+        call: "ltest",
+        display: "Long Test",
+        tooltip: "Long Test Value",
+        default_value: 123123123123123123,
+        group: "Numeric Values",
+    },
+    pub CONFIG_D1TEST: DoubleConfig {
+        call: "d1test",
+        display: "Double 1 Test",
+        tooltip: "Double Test Value",
+        default_value: 123.456,
+        group: "Numeric Values",
+    },
+    pub CONFIG_D2TEST: DoubleConfig {
+        call: "d2test",
+        display: "Double 2 Test",
+        tooltip: "Double Test Value",
+        default_value: 123456.0,
+        group: "Numeric Values",
+    },
+    pub CONFIG_PASSWORD: PasswordConfig {
+        call: "password",
+        display: "Password",
+        tooltip: "Package message password",
+    },
+    pub CONFIG_TIMESTAMP: TimestampConfig {
+        call: "ts",
+        display: "Start Time",
+        tooltip: "Capture start time",
+        group: "Time / Log",
+    },
+    pub CONFIG_LOGFILE: FileSelectConfig {
+        call: "logfile",
+        display: "Log File Test",
+        tooltip: "The Log File Test",
+        group: "Time / Log",
+        file_extension_filter: "Text files (*.txt);;XML files (*.xml)",
+    },
+    pub CONFIG_RADIO: RadioConfig {
+        call: "radio",
+        display: "Radio Test",
+        tooltip: "Radio Test Value",
+        group: "Selection",
+        options: [
             ConfigOptionValue::builder().value("r1").display("Radio1").build(),
             ConfigOptionValue::builder()
                 .value("r2")
                 .display("Radio2")
                 .default(true)
                 .build(),
-        ])
-        .build();
-    pub static ref CONFIG_MULTI: MultiCheckConfig = MultiCheckConfig::builder()
-        .config_number(12)
-        .call("multi")
-        .display("MultiCheck Test")
-        .tooltip("MultiCheck Test Value")
-        .group("Selection")
-        .options([
+        ],
+    },
+    pub CONFIG_MULTI: MultiCheckConfig {
+        call: "multi",
+        display: "MultiCheck Test",
+        tooltip: "MultiCheck Test Value",
+        group: "Selection",
+        options: [
             MultiCheckValue::builder()
                 .value("m1")
                 .display("Checkable Parent 1")
@@ -193,14 +180,15 @@ lazy_static! {
                         .build(),
                 ])
                 .build(),
-        ]).build();
-
-    pub static ref METADATA: Metadata = Metadata {
-        help_url: "http://www.wireshark.org".into(),
-        display_description: "Rust Example extcap interface".into(),
-        ..r_extcap::cargo_metadata!()
-    };
+        ],
+    },
+}
 
+lazy_static! {
+    pub static ref METADATA: Metadata = r_extcap::cargo_metadata!(
+        help_url = "http://www.wireshark.org",
+        display_description = "Rust Example extcap interface",
+    );
     pub static ref INTERFACE1: Interface = Interface {
         value: "rs-example1".into(),
         display: "Rust Example interface 1 for extcap".into(),
@@ -209,8 +197,9 @@ lazy_static! {
             name: "USER0".into(),
             display: "Demo Implementation for Extcap".into(),
         },
+        extra_dlts: vec![],
+        extra_attributes: vec![],
     };
-
     pub static ref INTERFACE2: Interface = Interface {
         value: "rs-example2".into(),
         display: "Rust Example interface 2 for extcap".into(),
@@ -219,18 +208,21 @@ lazy_static! {
             name: "USER1".into(),
             display: "Demo Implementation for Extcap".into(),
         },
+        extra_dlts: vec![],
+        extra_attributes: vec![],
     };
+    pub static ref CONTROLS: Controls = Controls::new();
+}
 
-    pub static ref CONTROL_MESSAGE: StringControl = StringControl {
-        control_number: 0,
+r_extcap::controls! {
+    message: StringControl {
         display: String::from("Message"),
         tooltip: Some(String::from("Package message content. Must start with a capital letter.")),
         placeholder: Some(String::from("Enter package message content here ...")),
         validation: Some(String::from(r"^[A-Z]+")),
         default_value: None,
-    };
-    pub static ref CONTROL_DELAY: SelectorControl = SelectorControl {
-        control_number: 1,
+    },
+    delay: SelectorControl {
         display: String::from("Time delay"),
         tooltip: Some(String::from("Time delay between packets")),
         options: vec![
@@ -241,33 +233,28 @@ lazy_static! {
             SelectorControlOption::builder().value("5").display("5s").default(true).build(),
             SelectorControlOption::builder().value("60").display( "60s").build(),
         ],
-    };
-    pub static ref CONTROL_VERIFY: BooleanControl = BooleanControl {
-        control_number: 2,
+    },
+    verify: BooleanControl {
         display: String::from("Verify"),
         tooltip: Some(String::from("Verify package control")),
         default_value: false,
-    };
-    pub static ref CONTROL_BUTTON: ButtonControl = ButtonControl {
-        control_number: 3,
+    },
+    button: ButtonControl {
         display: String::from("Turn on"),
         tooltip: Some(String::from("Turn on or off")),
-    };
-    pub static ref CONTROL_HELP: HelpButtonControl = HelpButtonControl {
-        control_number: 4,
+    },
+    help: HelpButtonControl {
         display: String::from("Help"),
         tooltip: Some(String::from("Show help")),
-    };
-    pub static ref CONTROL_RESTORE: RestoreButtonControl = RestoreButtonControl {
-        control_number: 5,
+    },
+    restore: RestoreButtonControl {
         display: String::from("Restore"),
         tooltip: Some(String::from("Restore default values")),
-    };
-    pub static ref CONTROL_LOGGER: LoggerControl = LoggerControl {
-        control_number: 6,
+    },
+    logger: LoggerControl {
         display: String::from("Log"),
         tooltip: Some(String::from("Show capture log")),
-    };
+    },
 }
 
 #[derive(Clone, Copy, Debug, ValueEnum)]
@@ -289,6 +276,9 @@ pub struct AppArgs {
     #[command(flatten)]
     pub extcap: r_extcap::ExtcapArgs,
 
+    #[command(flatten)]
+    pub install: r_extcap::install::InstallArgs,
+
     /// Demonstrates a verification bool flag
     #[arg(long)]
     pub verify: bool,
@@ -326,14 +316,40 @@ pub struct AppArgs {
 }
 
 pub struct CaptureState {
-    pub initialized: bool,
-    pub message: String,
-    pub delay: u8,
-    pub verify: bool,
+    pub controls: ControlState,
     pub button: bool,
     pub button_disabled: bool,
 }
 
+impl CaptureState {
+    /// Creates the capture state, using the values given on the command line
+    /// as the initial values of the controls.
+    pub fn new(args: &AppArgs) -> Self {
+        let mut controls =
+            ControlState::new(&[&CONTROLS.message, &CONTROLS.delay, &CONTROLS.verify]);
+        controls.update(&CONTROLS.message.set_value(&args.message));
+        controls.update(&CONTROLS.delay.set_value(&args.delay.to_string()));
+        controls.update(&CONTROLS.verify.set_checked(args.verify));
+        Self {
+            controls,
+            button: false,
+            button_disabled: false,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        self.controls.get(&CONTROLS.message)
+    }
+
+    pub fn delay(&self) -> u8 {
+        self.controls.get(&CONTROLS.delay).parse().unwrap_or(5)
+    }
+
+    pub fn verify(&self) -> bool {
+        self.controls.get(&CONTROLS.verify)
+    }
+}
+
 pub fn create_out_packet(
     remote: Remote,
     data_packet: usize,
@@ -362,46 +378,29 @@ pub fn pcap_fake_packet(
     message: &[u8],
     fake_ip: &str,
     iterate_counter: usize,
-) -> Result<Vec<u8>, ParseIntError> {
-    // ETH
-    let (dest_value, src_value) = if iterate_counter % 2 == 0 {
-        (0x2900_u16, 0x3400_u16)
+) -> anyhow::Result<Vec<u8>> {
+    // Alternate the MAC addresses, so that the packets look like they go back
+    // and forth between two hosts.
+    let (dest_mac, src_mac) = if iterate_counter % 2 == 0 {
+        (
+            [0x00, 0x29, 0x00, 0x29, 0x00, 0x29],
+            [0x00, 0x34, 0x00, 0x34, 0x00, 0x34],
+        )
     } else {
-        (0x3400_u16, 0x2900_u16)
+        (
+            [0x00, 0x34, 0x00, 0x34, 0x00, 0x34],
+            [0x00, 0x29, 0x00, 0x29, 0x00, 0x29],
+        )
     };
-
-    let result = [
-        &dest_value.to_le_bytes()[..],
-        &dest_value.to_le_bytes(),
-        &dest_value.to_le_bytes(),
-        &src_value.to_le_bytes(),
-        &src_value.to_le_bytes(),
-        &src_value.to_le_bytes(),
-        &8_u16.to_le_bytes(), // protocol (ip)
-        &[0x45_u8],           // IP version
-        &[0],
-        &((message.len() + 20) as u16).to_be_bytes(),
-        &0_u16.to_be_bytes(), // Identification
-        &[0x40],              // Don't fragment
-        &[0],                 // Fragment offset
-        &[0x40_u8],
-        &[0xFE_u8],           // (2 = unspecified)
-        &0_u16.to_be_bytes(), // Checksum
-        &fake_ip
-            .split('.')
-            .map(|p| p.parse::<u8>())
-            .collect::<Result<Vec<u8>, ParseIntError>>()?,
-        &[0x7f, 0x00, 0x00, 0x01], // Dest IP
-        message,
-    ]
-    .concat()
-    .to_vec();
-
-    Ok(result)
+    // 0xFE: Reserved for experimentation and testing
+    let mut encap = Encapsulation::ip(fake_ip.parse()?, Ipv4Addr::LOCALHOST, 0xFE)
+        .mac_addresses(src_mac, dest_mac);
+    Ok(encap.encapsulate(message)?)
 }
 
-pub fn validate_capture_filter(filter: &str) {
+pub fn validate_capture_filter(filter_validation_step: &FilterValidationStep) {
+    let filter = filter_validation_step.filter;
     if filter != "filter" && filter != "valid" {
-        println!("Illegal capture filter");
+        filter_validation_step.reject("Illegal capture filter");
     }
 }
@@ -1,13 +1,12 @@
 use clap::Parser;
 use log::debug;
-use pcap_file::{
-    pcap::{PcapHeader, PcapPacket, PcapWriter},
-    DataLink,
-};
+use pcap_file::pcap::PcapPacket;
 use r_extcap::{
-    controls::synchronous::{ExtcapControlSender, ExtcapControlSenderTrait},
+    controls::synchronous::{ControlSession, ExtcapControlSenderTrait},
     controls::*,
-    ExtcapStep,
+    help::ExtcapHelp,
+    shutdown::ShutdownSignal,
+    ExtcapError, ExtcapStep,
 };
 use std::{
     io::{stdout, Write},
@@ -19,23 +18,23 @@ mod common;
 use common::*;
 
 fn control_write_defaults(
-    extcap_control: &mut ExtcapControlSender,
+    extcap_control: &mut ControlSession,
     message: &str,
     delay: u8,
     verify: bool,
 ) -> anyhow::Result<()> {
-    CONTROL_MESSAGE.set_value(message).send(extcap_control)?;
-    CONTROL_BUTTON
-        .set_label(&delay.to_string())
-        .send(extcap_control)?;
-    CONTROL_VERIFY.set_checked(verify).send(extcap_control)?;
+    extcap_control.send(CONTROLS.message.set_value(message))?;
+    extcap_control.send(CONTROLS.button.set_label(&delay.to_string()))?;
+    extcap_control.send(CONTROLS.verify.set_checked(verify))?;
 
     for i in 1..16 {
-        CONTROL_DELAY
-            .add_value(&i.to_string(), Some(&format!("{i} sec")))
-            .send(extcap_control)?;
+        extcap_control.send(
+            CONTROLS
+                .delay
+                .add_value(&i.to_string(), Some(&format!("{i} sec"))),
+        )?;
     }
-    CONTROL_DELAY.remove_value("60").send(extcap_control)?;
+    extcap_control.send(CONTROLS.delay.remove_value("60"))?;
     Ok(())
 }
 
@@ -44,47 +43,38 @@ fn main() -> anyhow::Result<()> {
     debug!("argv: {:?}", std::env::args());
     let args = AppArgs::parse();
     debug!("Args: {args:?}");
-    if !args.extcap.capture {
-        if let Some(filter) = args.extcap.extcap_capture_filter {
-            validate_capture_filter(&filter);
-            std::process::exit(0);
-        }
+    if let Some(result) = args.install.run() {
+        return Ok(result?);
     }
     debug!("Running app");
-    match args.extcap.run()? {
+    let step = match args.extcap.run() {
+        Err(ExtcapError::NotExtcapInput) => {
+            let interfaces = [&*INTERFACE1, &*INTERFACE2];
+            let (configs, controls) = (configs(), CONTROLS.all());
+            let help = ExtcapHelp::builder()
+                .metadata(&METADATA)
+                .interfaces(&interfaces)
+                .configs(&configs)
+                .controls(&controls)
+                .build();
+            eprintln!("{help}");
+            return Ok(());
+        }
+        step => step?,
+    };
+    match step {
         ExtcapStep::Interfaces(interfaces_step) => {
-            interfaces_step.list_interfaces(
+            interfaces_step.list_interfaces_for_version(
+                args.extcap.wireshark_version(),
                 &METADATA,
                 &[&*INTERFACE1, &*INTERFACE2],
-                &[
-                    &*CONTROL_MESSAGE,
-                    &*CONTROL_DELAY,
-                    &*CONTROL_VERIFY,
-                    &*CONTROL_BUTTON,
-                    &*CONTROL_HELP,
-                    &*CONTROL_RESTORE,
-                    &*CONTROL_LOGGER,
-                ],
+                &CONTROLS.all(),
             );
         }
         ExtcapStep::Dlts(dlts_step) => {
             dlts_step.print_from_interfaces(&[&*INTERFACE1, &*INTERFACE2])?;
         }
-        ExtcapStep::Config(config_step) => config_step.list_configs(&[
-            &*CONFIG_DELAY,
-            &*CONFIG_MESSAGE,
-            &*CONFIG_VERIFY,
-            &*CONFIG_REMOTE,
-            &*CONFIG_FAKE_IP,
-            &*CONFIG_LTEST,
-            &*CONFIG_D1TEST,
-            &*CONFIG_D2TEST,
-            &*CONFIG_PASSWORD,
-            &*CONFIG_TIMESTAMP,
-            &*CONFIG_LOGFILE,
-            &*CONFIG_RADIO,
-            &*CONFIG_MULTI,
-        ]),
+        ExtcapStep::Config(config_step) => config_step.list_configs(&configs()),
         ExtcapStep::ReloadConfig(reload_config_step) => {
             if reload_config_step.config == CONFIG_REMOTE.call {
                 reload_config_step.reload_options(&CONFIG_REMOTE)?;
@@ -95,17 +85,15 @@ fn main() -> anyhow::Result<()> {
                 ));
             }
         }
-        ExtcapStep::Capture(capture_step) => {
+        ExtcapStep::FilterValidation(filter_validation_step) => {
+            validate_capture_filter(&filter_validation_step);
+        }
+        ExtcapStep::Capture(mut capture_step) => {
             anyhow::ensure!(args.delay <= 5, "Value for delay {} too high", args.delay);
-            let mut app_state = CaptureState {
-                initialized: false,
-                message: args.message.clone(),
-                delay: args.delay,
-                verify: args.verify,
-                button: false,
-                button_disabled: false,
-            };
-            let mut counter = 1;
+            r_extcap::config::check_required_args(&configs(), std::env::args())?;
+            capture_step.install_panic_hook();
+            capture_step.on_capture_end(|| debug!("Capture ended"));
+            let mut app_state = CaptureState::new(&args);
             const DATA: &[u8] = b"\
             Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor \
             incididunt ut labore et dolore magna aliqua. Ut enim ad minim veniam, quis nost \
@@ -113,55 +101,58 @@ fn main() -> anyhow::Result<()> {
             aute irure dolor in reprehenderit in voluptate velit esse cillum dolore eu fugi \
             at nulla pariatur. Excepteur sint occaecat cupidatat non proident, sunt in culp \
             a qui officia deserunt mollit anim id est laborum.";
-            let mut controls = (
-                capture_step.spawn_channel_control_reader(),
-                capture_step.new_control_sender(),
-            );
-            if let (Some(control_reader), Some(control_sender)) = &mut controls {
-                let packet = control_reader.read_packet()?;
-                assert_eq!(packet.command, ControlCommand::Initialized);
+            let mut controls = capture_step.control_session();
+            let handshake = controls.wait_for_init(Duration::from_secs(30))?;
+            handshake.apply_to(&mut app_state.controls);
 
-                CONTROL_LOGGER
-                    .clear_and_add_log(format!("Log started at {:?}", SystemTime::now()).into())
-                    .send(control_sender)?;
-                control_write_defaults(
-                    control_sender,
-                    &app_state.message,
-                    app_state.delay,
-                    app_state.verify,
-                )?;
-            }
+            controls.send(
+                CONTROLS
+                    .logger
+                    .clear_and_add_log(format!("Log started at {:?}", SystemTime::now()).into()),
+            )?;
+            control_write_defaults(
+                &mut controls,
+                &app_state.message(),
+                app_state.delay(),
+                app_state.verify(),
+            )?;
 
-            let pcap_header = PcapHeader {
-                datalink: DataLink::ETHERNET,
-                endianness: pcap_file::Endianness::Big,
-                ..Default::default()
-            };
-            let mut pcap_writer = PcapWriter::with_header(capture_step.fifo, pcap_header)?;
+            let interface = capture_step.resolve_interface(&[&*INTERFACE1, &*INTERFACE2])?;
+            let mut pcap_writer = capture_step.pcap_writer_for(interface)?;
             let mut data_packet = 0;
             let data_total = DATA.len() / 20 + 1;
 
-            for i in 0..usize::MAX {
-                if let (Some(control_reader), Some(control_sender)) = &mut controls {
-                    if let Some(control_packet) = control_reader.try_read_packet() {
-                        handle_control_packet(&control_packet, control_sender, &mut app_state)?;
+            // Unlike sleeping, waiting for the next tick is interrupted by the
+            // control packets, so that they are handled right away.
+            let mut pacer = Pacer::new(Duration::from_secs(app_state.delay().into()))
+                .with_shutdown(ShutdownSignal::global());
+            'capture: for i in 0..usize::MAX {
+                loop {
+                    match pacer.wait(controls.reader()) {
+                        PacerEvent::Tick => break,
+                        PacerEvent::Control(control_packet) => {
+                            handle_control_packet(&control_packet, &mut controls, &mut app_state)?;
+                            pacer.set_interval(Duration::from_secs(app_state.delay().into()));
+                        }
+                        PacerEvent::Shutdown => break 'capture,
                     }
+                }
 
-                    CONTROL_LOGGER
-                        .add_log(format!("Received packet #{counter}").into())
-                        .send(control_sender)?;
-                    counter += 1;
+                controls.send(
+                    CONTROLS
+                        .logger
+                        .add_log(format!("Received packet #{}", i + 1).into()),
+                )?;
 
-                    debug!(
-                        "Extcap out control. btn disabled = {}",
-                        app_state.button_disabled
-                    );
+                debug!(
+                    "Extcap out control. btn disabled = {}",
+                    app_state.button_disabled
+                );
 
-                    if app_state.button_disabled {
-                        CONTROL_BUTTON.set_enabled(true).send(control_sender)?;
-                        control_sender.info_message("Turn action finished.")?;
-                        app_state.button_disabled = false;
-                    }
+                if app_state.button_disabled {
+                    controls.send(CONTROLS.button.set_enabled(true))?;
+                    controls.info_message("Turn action finished.")?;
+                    app_state.button_disabled = false;
                 }
 
                 if data_packet * 20 > DATA.len() {
@@ -174,8 +165,8 @@ fn main() -> anyhow::Result<()> {
                     data_packet,
                     data_total,
                     data_sub,
-                    app_state.message.as_bytes(),
-                    app_state.verify,
+                    app_state.message().as_bytes(),
+                    app_state.verify(),
                 );
                 let packet = pcap_fake_packet(&out, &args.fake_ip, i)?;
 
@@ -185,9 +176,9 @@ fn main() -> anyhow::Result<()> {
                     &packet,
                 ))?;
                 stdout().flush()?;
-                std::thread::sleep(Duration::from_secs(app_state.delay.into()));
             }
         }
+        _ => anyhow::bail!("Unsupported extcap step"),
     }
     debug!("App run finished");
 
@@ -196,39 +187,33 @@ fn main() -> anyhow::Result<()> {
 
 fn handle_control_packet(
     control_packet: &ControlPacket<'_>,
-    control_sender: &mut ExtcapControlSender,
+    control_sender: &mut ControlSession,
     app_state: &mut CaptureState,
 ) -> anyhow::Result<()> {
     debug!("Read control packet: {control_packet:?}");
     let mut log: Option<String> = None;
     match control_packet.command {
-        ControlCommand::Initialized => app_state.initialized = true,
+        ControlCommand::Initialized => {}
         ControlCommand::Set => {
-            if control_packet.control_number == CONTROL_MESSAGE.control_number {
-                let msg = String::from_utf8(control_packet.payload.to_vec())?;
-                log = Some(format!("Message = {}", msg));
-                app_state.message = msg;
-            } else if control_packet.control_number == CONTROL_DELAY.control_number {
-                app_state.delay =
-                    std::str::from_utf8(control_packet.payload.as_ref())?.parse::<u8>()?;
-                log = Some(format!("Time delay = {}", app_state.delay));
-            } else if control_packet.control_number == CONTROL_VERIFY.control_number {
-                // Only read this after initialized
-                if app_state.initialized {
-                    app_state.verify = control_packet.payload[0] != 0_u8;
-                    log = Some(format!("Verify = {:?}", app_state.verify));
+            if app_state.controls.update(control_packet) {
+                if control_packet.control_number == CONTROLS.message.control_number {
+                    log = Some(format!("Message = {}", app_state.message()));
+                } else if control_packet.control_number == CONTROLS.delay.control_number {
+                    log = Some(format!("Time delay = {}", app_state.delay()));
+                } else if control_packet.control_number == CONTROLS.verify.control_number {
+                    log = Some(format!("Verify = {:?}", app_state.verify()));
                     control_sender.status_message("Verify changed")?;
                 }
-            } else if control_packet.control_number == CONTROL_BUTTON.control_number {
-                CONTROL_BUTTON.set_enabled(false).send(control_sender)?;
+            } else if control_packet.control_number == CONTROLS.button.control_number {
+                control_sender.send(CONTROLS.button.set_enabled(false))?;
                 debug!("Got button control event. button={}", app_state.button);
                 app_state.button_disabled = true;
                 if app_state.button {
-                    CONTROL_BUTTON.set_label("Turn on").send(control_sender)?;
+                    control_sender.send(CONTROLS.button.set_label("Turn on"))?;
                     app_state.button = false;
                     log = Some(String::from("Button turned off"));
                 } else {
-                    CONTROL_BUTTON.set_label("Turn off").send(control_sender)?;
+                    control_sender.send(CONTROLS.button.set_label("Turn off"))?;
                     app_state.button = true;
                     log = Some(String::from("Button turned on"));
                 }
@@ -242,7 +227,7 @@ fn handle_control_packet(
         _ => panic!("Unexpected control command {:?}", control_packet.command),
     }
     if let Some(log) = log {
-        CONTROL_LOGGER.add_log(log.into()).send(control_sender)?;
+        control_sender.send(CONTROLS.logger.add_log(log.into()))?;
     }
     debug!("Read control packet Loop end");
     Ok(())
@@ -1,9 +1,6 @@
 use clap::Parser;
 use log::debug;
-use pcap_file::{
-    pcap::{PcapHeader, PcapPacket, PcapWriter},
-    DataLink,
-};
+use pcap_file::pcap::PcapPacket;
 use r_extcap::{
     controls::asynchronous::{ExtcapControlSender, ExtcapControlSenderTrait},
     controls::*,
@@ -24,26 +21,31 @@ async fn control_write_defaults(
     delay: u8,
     verify: bool,
 ) -> anyhow::Result<()> {
-    CONTROL_MESSAGE
+    CONTROLS
+        .message
         .set_value(message)
         .send_async(extcap_control)
         .await?;
-    CONTROL_BUTTON
+    CONTROLS
+        .button
         .set_label(&delay.to_string())
         .send_async(extcap_control)
         .await?;
-    CONTROL_VERIFY
+    CONTROLS
+        .verify
         .set_checked(verify)
         .send_async(extcap_control)
         .await?;
 
     for i in 1..16 {
-        CONTROL_DELAY
+        CONTROLS
+            .delay
             .add_value(&i.to_string(), Some(&format!("{i} sec")))
             .send_async(extcap_control)
             .await?;
     }
-    CONTROL_DELAY
+    CONTROLS
+        .delay
         .remove_value("60")
         .send_async(extcap_control)
         .await?;
@@ -56,47 +58,23 @@ async fn main() -> anyhow::Result<()> {
     debug!("argv: {:?}", std::env::args());
     let args = AppArgs::parse();
     debug!("Args: {args:?}");
-    if !args.extcap.capture {
-        if let Some(filter) = args.extcap.extcap_capture_filter {
-            validate_capture_filter(&filter);
-            std::process::exit(0);
-        }
+    if let Some(result) = args.install.run() {
+        return Ok(result?);
     }
     debug!("Running app");
     match args.extcap.run()? {
         ExtcapStep::Interfaces(interfaces_step) => {
-            interfaces_step.list_interfaces(
+            interfaces_step.list_interfaces_for_version(
+                args.extcap.wireshark_version(),
                 &METADATA,
                 &[&*INTERFACE1, &*INTERFACE2],
-                &[
-                    &*CONTROL_MESSAGE,
-                    &*CONTROL_DELAY,
-                    &*CONTROL_VERIFY,
-                    &*CONTROL_BUTTON,
-                    &*CONTROL_HELP,
-                    &*CONTROL_RESTORE,
-                    &*CONTROL_LOGGER,
-                ],
+                &CONTROLS.all(),
             );
         }
         ExtcapStep::Dlts(dlts_step) => {
             dlts_step.print_from_interfaces(&[&*INTERFACE1, &*INTERFACE2])?;
         }
-        ExtcapStep::Config(config_step) => config_step.list_configs(&[
-            &*CONFIG_DELAY,
-            &*CONFIG_MESSAGE,
-            &*CONFIG_VERIFY,
-            &*CONFIG_REMOTE,
-            &*CONFIG_FAKE_IP,
-            &*CONFIG_LTEST,
-            &*CONFIG_D1TEST,
-            &*CONFIG_D2TEST,
-            &*CONFIG_PASSWORD,
-            &*CONFIG_TIMESTAMP,
-            &*CONFIG_LOGFILE,
-            &*CONFIG_RADIO,
-            &*CONFIG_MULTI,
-        ]),
+        ExtcapStep::Config(config_step) => config_step.list_configs(&configs()),
         ExtcapStep::ReloadConfig(reload_config_step) => {
             if reload_config_step.config == CONFIG_REMOTE.call {
                 reload_config_step.reload_options(&CONFIG_REMOTE)?;
@@ -107,16 +85,12 @@ async fn main() -> anyhow::Result<()> {
                 ));
             }
         }
-        ExtcapStep::Capture(capture_step) => {
+        ExtcapStep::FilterValidation(filter_validation_step) => {
+            validate_capture_filter(&filter_validation_step);
+        }
+        ExtcapStep::Capture(mut capture_step) => {
             anyhow::ensure!(args.delay <= 5, "Value for delay {} too high", args.delay);
-            let mut app_state = CaptureState {
-                initialized: false,
-                message: args.message.clone(),
-                delay: args.delay,
-                verify: args.verify,
-                button: false,
-                button_disabled: false,
-            };
+            let mut app_state = CaptureState::new(&args);
             let mut counter = 1;
             const DATA: &[u8] = b"\
             Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do eiusmod tempor \
@@ -130,31 +104,27 @@ async fn main() -> anyhow::Result<()> {
                 capture_step.new_control_sender_async().await,
             );
             if let (Some(control_reader), Some(control_sender)) = &mut controls {
-                let packet = control_reader
-                    .read_packet()
-                    .await
-                    .ok_or_else(|| anyhow::anyhow!("Unable to read packet"))?;
-                assert_eq!(packet.command, ControlCommand::Initialized);
+                let handshake =
+                    ControlHandshake::wait_for_init_async(control_reader, Duration::from_secs(30))
+                        .await?;
+                handshake.apply_to(&mut app_state.controls);
 
-                CONTROL_LOGGER
+                CONTROLS
+                    .logger
                     .clear_and_add_log(format!("Log started at {:?}", SystemTime::now()).into())
                     .send_async(control_sender)
                     .await?;
                 control_write_defaults(
                     control_sender,
-                    &app_state.message,
-                    app_state.delay,
-                    app_state.verify,
+                    &app_state.message(),
+                    app_state.delay(),
+                    app_state.verify(),
                 )
                 .await?;
             }
 
-            let pcap_header = PcapHeader {
-                datalink: DataLink::ETHERNET,
-                endianness: pcap_file::Endianness::Big,
-                ..Default::default()
-            };
-            let mut pcap_writer = PcapWriter::with_header(capture_step.fifo, pcap_header)?;
+            let interface = capture_step.resolve_interface(&[&*INTERFACE1, &*INTERFACE2])?;
+            let mut pcap_writer = capture_step.pcap_writer_for(interface)?;
             let mut data_packet = 0;
             let data_total = DATA.len() / 20 + 1;
 
@@ -165,7 +135,8 @@ async fn main() -> anyhow::Result<()> {
                             .await?;
                     }
 
-                    CONTROL_LOGGER
+                    CONTROLS
+                        .logger
                         .add_log(format!("Received packet #{counter}").into())
                         .send_async(control_sender)
                         .await?;
@@ -177,7 +148,8 @@ async fn main() -> anyhow::Result<()> {
                     );
 
                     if app_state.button_disabled {
-                        CONTROL_BUTTON
+                        CONTROLS
+                            .button
                             .set_enabled(true)
                             .send_async(control_sender)
                             .await?;
@@ -196,8 +168,8 @@ async fn main() -> anyhow::Result<()> {
                     data_packet,
                     data_total,
                     data_sub,
-                    app_state.message.as_bytes(),
-                    app_state.verify,
+                    app_state.message().as_bytes(),
+                    app_state.verify(),
                 );
                 let packet = pcap_fake_packet(&out, &args.fake_ip, i)?;
 
@@ -207,9 +179,10 @@ async fn main() -> anyhow::Result<()> {
                     &packet,
                 ))?;
                 stdout().flush()?;
-                std::thread::sleep(Duration::from_secs(app_state.delay.into()));
+                std::thread::sleep(Duration::from_secs(app_state.delay().into()));
             }
         }
+        _ => anyhow::bail!("Unsupported extcap step"),
     }
     debug!("App run finished");
 
@@ -224,39 +197,36 @@ async fn handle_control_packet(
     debug!("Read control packet: {control_packet:?}");
     let mut log: Option<String> = None;
     match control_packet.command {
-        ControlCommand::Initialized => app_state.initialized = true,
+        ControlCommand::Initialized => {}
         ControlCommand::Set => {
-            if control_packet.control_number == CONTROL_MESSAGE.control_number {
-                let msg = String::from_utf8(control_packet.payload.to_vec())?;
-                log = Some(format!("Message = {}", msg));
-                app_state.message = msg;
-            } else if control_packet.control_number == CONTROL_DELAY.control_number {
-                app_state.delay =
-                    std::str::from_utf8(control_packet.payload.as_ref())?.parse::<u8>()?;
-                log = Some(format!("Time delay = {}", app_state.delay));
-            } else if control_packet.control_number == CONTROL_VERIFY.control_number {
-                // Only read this after initialized
-                if app_state.initialized {
-                    app_state.verify = control_packet.payload[0] != 0_u8;
-                    log = Some(format!("Verify = {:?}", app_state.verify));
+            if app_state.controls.update(control_packet) {
+                if control_packet.control_number == CONTROLS.message.control_number {
+                    log = Some(format!("Message = {}", app_state.message()));
+                } else if control_packet.control_number == CONTROLS.delay.control_number {
+                    log = Some(format!("Time delay = {}", app_state.delay()));
+                } else if control_packet.control_number == CONTROLS.verify.control_number {
+                    log = Some(format!("Verify = {:?}", app_state.verify()));
                     control_sender.status_message("Verify changed").await?;
                 }
-            } else if control_packet.control_number == CONTROL_BUTTON.control_number {
-                CONTROL_BUTTON
+            } else if control_packet.control_number == CONTROLS.button.control_number {
+                CONTROLS
+                    .button
                     .set_enabled(false)
                     .send_async(control_sender)
                     .await?;
                 debug!("Got button control event. button={}", app_state.button);
                 app_state.button_disabled = true;
                 if app_state.button {
-                    CONTROL_BUTTON
+                    CONTROLS
+                        .button
                         .set_label("Turn on")
                         .send_async(control_sender)
                         .await?;
                     app_state.button = false;
                     log = Some(String::from("Button turned off"));
                 } else {
-                    CONTROL_BUTTON
+                    CONTROLS
+                        .button
                         .set_label("Turn off")
                         .send_async(control_sender)
                         .await?;
@@ -273,7 +243,8 @@ async fn handle_control_packet(
         _ => panic!("Unexpected control command {:?}", control_packet.command),
     }
     if let Some(log) = log {
-        CONTROL_LOGGER
+        CONTROLS
+            .logger
             .add_log(log.into())
             .send_async(control_sender)
             .await?;
@@ -25,6 +25,9 @@ async fn main() -> anyhow::Result<()> {
         ExtcapStep::ReloadConfig(_reload_config_step) => {
             unimplemented!()
         }
+        ExtcapStep::FilterValidation(_filter_validation_step) => {
+            unimplemented!()
+        }
         ExtcapStep::Capture(capture_step) => {
             let read_control = async {
                 let mut control_reader = capture_step.spawn_channel_control_reader_async().unwrap();
@@ -42,6 +45,7 @@ async fn main() -> anyhow::Result<()> {
             }
             println!("Finished reading control pipe");
         }
+        _ => unimplemented!(),
     }
     debug!("App run finished");
     Ok(())
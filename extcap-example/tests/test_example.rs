@@ -94,6 +94,19 @@ fn print_dlt() {
     ));
 }
 
+#[test]
+fn validate_capture_filter() {
+    let mut cmd = Command::cargo_bin("extcap-example").unwrap();
+    cmd.args(["--extcap-interface", "rs-example1", "--extcap-capture-filter", "valid"]);
+    cmd.assert().success().stdout(predicate::str::is_empty());
+
+    let mut cmd = Command::cargo_bin("extcap-example").unwrap();
+    cmd.args(["--extcap-interface", "rs-example1", "--extcap-capture-filter", "udp"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::diff("Illegal capture filter\n"));
+}
+
 #[test]
 fn capture() {
     let tempdir = tempfile::tempdir().unwrap();
@@ -0,0 +1,420 @@
+//! Describing a whole extcap as one value, and combining several of them into
+//! a single program.
+//!
+//! An [`ExtcapApp`] declares the interfaces, configs and toolbar controls of
+//! an extcap together with its capture, so that
+//! [`ExtcapArgs::run_app`][crate::ExtcapArgs::run_app] can handle every step
+//! Wireshark requests.
+//!
+//! Vendors often ship one program for several unrelated kinds of hardware,
+//! like a BLE sniffer and an 802.15.4 sniffer. Implementing `ExtcapApp` for
+//! each of them and combining them with [`MultiApp`] keeps them independent:
+//! the interface values of each app are prefixed with its namespace, and each
+//! request from Wireshark is routed to the app that owns the interface.
+//!
+//! ```
+//! use r_extcap::{
+//!     app::{BoxError, ExtcapApp, MultiApp},
+//!     controls::ButtonControl,
+//!     interface::{Dlt, Interface},
+//!     CaptureStep,
+//! };
+//!
+//! r_extcap::controls! {
+//!     struct SnifferControls {
+//!         start: ButtonControl {
+//!             display: String::from("Start"),
+//!             tooltip: None,
+//!         },
+//!     }
+//! }
+//!
+//! struct Sniffer {
+//!     interface: Interface,
+//!     controls: SnifferControls,
+//! }
+//!
+//! impl ExtcapApp for Sniffer {
+//!     fn interfaces(&self) -> Vec<&Interface> {
+//!         vec![&self.interface]
+//!     }
+//!
+//!     fn controls(&self) -> Vec<&dyn r_extcap::controls::ToolbarControl> {
+//!         self.controls.all().to_vec()
+//!     }
+//!
+//!     fn capture(&self, step: CaptureStep) -> Result<(), BoxError> {
+//!         // `step.interface` is "sniffer", without the namespace.
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let sniffer = |first_control_number| Sniffer {
+//!     interface: Interface::builder()
+//!         .value("sniffer".into())
+//!         .display("Sniffer".into())
+//!         .dlt(Dlt::user(0, "Sniffer"))
+//!         .build(),
+//!     controls: SnifferControls::with_first_number(first_control_number),
+//! };
+//! let app = MultiApp::new().with_app("ble", sniffer(0));
+//! // Give the second app the control numbers after the ones of the first.
+//! let app = app.with_app("zigbee", sniffer(SnifferControls::COUNT as u8));
+//! let values: Vec<_> = app.interfaces().iter().map(|i| i.value.clone()).collect();
+//! assert_eq!(values, ["ble-sniffer", "zigbee-sniffer"]);
+//!
+//! # use clap::Parser;
+//! # #[derive(Parser)]
+//! # struct AppArgs {
+//! #     #[command(flatten)]
+//! #     extcap: r_extcap::ExtcapArgs,
+//! # }
+//! # let args = AppArgs::parse_from(["app", "--extcap-interfaces"]);
+//! args.extcap.run_app(&app, &r_extcap::cargo_metadata!())?;
+//! # Ok::<(), r_extcap::app::AppError>(())
+//! ```
+
+use std::collections::BTreeSet;
+
+use thiserror::Error;
+
+use crate::{
+    config::ConfigTrait, controls::ToolbarControl, interface::Interface, CaptureStep, ExtcapError,
+    FilterValidationStep, PrintDltError, ReloadConfigError,
+};
+
+/// The error type returned by [`ExtcapApp::capture`], which can hold any
+/// error, including an `anyhow::Error`.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The separator between the namespace of an app in a [`MultiApp`] and the
+/// values of its interfaces.
+pub const NAMESPACE_SEPARATOR: char = '-';
+
+/// An extcap implementation, run with
+/// [`ExtcapArgs::run_app`][crate::ExtcapArgs::run_app].
+///
+/// Only [`interfaces`][Self::interfaces] and [`capture`][Self::capture] are
+/// required. The other methods default to an extcap without configs or
+/// toolbar controls, which accepts any capture filter.
+pub trait ExtcapApp {
+    /// The interfaces provided by this app, listed in `--extcap-interfaces`.
+    fn interfaces(&self) -> Vec<&Interface>;
+
+    /// The configs of `interface`, which is the [`Interface::value`] of one of
+    /// the [`interfaces`][Self::interfaces]. These are listed in
+    /// `--extcap-config`, and used to reload the options of a
+    /// [`SelectorConfig`][crate::config::SelectorConfig].
+    fn configs(&self, interface: &str) -> Vec<&dyn ConfigTrait> {
+        let _ = interface;
+        Vec::new()
+    }
+
+    /// The toolbar controls of this app. Wireshark shows a single toolbar
+    /// for all of the interfaces of an extcap.
+    fn controls(&self) -> Vec<&dyn ToolbarControl> {
+        Vec::new()
+    }
+
+    /// Validates the capture filter entered by the user, calling
+    /// [`FilterValidationStep::reject`] if it is invalid.
+    fn validate_capture_filter(&self, step: &FilterValidationStep) {
+        let _ = step;
+    }
+
+    /// Captures packets from [`CaptureStep::interface`], writing them to the
+    /// fifo.
+    fn capture(&self, step: CaptureStep) -> Result<(), BoxError>;
+}
+
+/// Error returned by [`ExtcapArgs::run_app`][crate::ExtcapArgs::run_app].
+#[derive(Debug, Error)]
+pub enum AppError {
+    /// The arguments are invalid, or are not extcap arguments at all. See
+    /// [`ExtcapError::NotExtcapInput`].
+    #[error(transparent)]
+    Extcap(#[from] ExtcapError),
+    /// Error printing the DLTs of the interface.
+    #[error(transparent)]
+    PrintDlt(#[from] PrintDltError),
+    /// Error reloading the options of a config.
+    #[error(transparent)]
+    ReloadConfig(#[from] ReloadConfigError),
+    /// Error returned by [`ExtcapApp::capture`].
+    #[error(transparent)]
+    Capture(BoxError),
+}
+
+/// An app in a [`MultiApp`].
+struct SubApp {
+    namespace: String,
+    app: Box<dyn ExtcapApp>,
+    /// The interfaces of `app`, with the namespace added to their values.
+    interfaces: Vec<Interface>,
+}
+
+/// Combines several [`ExtcapApp`]s into one, so that they can be shipped as a
+/// single program. See the [module documentation][self] for an example.
+///
+/// Each app is added with a namespace, which is prepended to the values of
+/// its interfaces with a [`NAMESPACE_SEPARATOR`], so that the interfaces of
+/// different apps never clash. Requests for an interface are routed to the
+/// app that owns it, with the namespace removed again, so an app works the
+/// same on its own and in a `MultiApp`.
+///
+/// Configs belong to a single interface, so their numbers cannot clash.
+/// Toolbar controls are shared by all of the interfaces of the program, and
+/// Wireshark sends the control packets with the numbers as declared, so each
+/// app needs its own range of control numbers. Create the controls declared
+/// with [`controls!`][crate::controls!] using `with_first_number` to choose
+/// the range. [`with_app`][Self::with_app] checks that the ranges do not
+/// overlap.
+#[derive(Default)]
+pub struct MultiApp {
+    apps: Vec<SubApp>,
+}
+
+impl MultiApp {
+    /// Creates a `MultiApp` without any apps.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `app`, prefixing the values of its interfaces with `namespace`.
+    ///
+    /// Panics if `namespace` is already used, or if any control of `app` has
+    /// the same number as a control of an app added before.
+    #[must_use]
+    pub fn with_app(mut self, namespace: impl Into<String>, app: impl ExtcapApp + 'static) -> Self {
+        let namespace = namespace.into();
+        assert!(
+            self.apps
+                .iter()
+                .all(|sub_app| sub_app.namespace != namespace),
+            "Namespace {namespace:?} is already used"
+        );
+        let used_numbers: BTreeSet<u8> = self
+            .controls()
+            .iter()
+            .map(|control| control.control_number())
+            .collect();
+        if let Some(control) = app
+            .controls()
+            .iter()
+            .find(|control| used_numbers.contains(&control.control_number()))
+        {
+            panic!(
+                "Control number {} of {namespace:?} is already used by another app. Create \
+                the controls with `with_first_number` to give each app its own range.",
+                control.control_number()
+            );
+        }
+        let interfaces = app
+            .interfaces()
+            .into_iter()
+            .map(|interface| Interface {
+                value: format!("{namespace}{NAMESPACE_SEPARATOR}{}", interface.value).into(),
+                ..interface.clone()
+            })
+            .collect();
+        self.apps.push(SubApp {
+            namespace,
+            app: Box::new(app),
+            interfaces,
+        });
+        self
+    }
+
+    /// The first control number after the controls of the apps added so far,
+    /// to pass to `with_first_number` when creating the controls of the next
+    /// app.
+    pub fn next_control_number(&self) -> u8 {
+        self.controls()
+            .iter()
+            .map(|control| control.control_number() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Finds the app that owns `interface`, a namespaced interface value,
+    /// and returns it together with the interface value without the
+    /// namespace.
+    fn route<'i>(&self, interface: &'i str) -> Option<(&dyn ExtcapApp, &'i str)> {
+        self.apps.iter().find_map(|sub_app| {
+            let value = interface
+                .strip_prefix(sub_app.namespace.as_str())?
+                .strip_prefix(NAMESPACE_SEPARATOR)?;
+            sub_app
+                .app
+                .interfaces()
+                .iter()
+                .any(|interface| interface.value == value)
+                .then_some((&*sub_app.app, value))
+        })
+    }
+}
+
+impl ExtcapApp for MultiApp {
+    fn interfaces(&self) -> Vec<&Interface> {
+        self.apps
+            .iter()
+            .flat_map(|sub_app| &sub_app.interfaces)
+            .collect()
+    }
+
+    fn configs(&self, interface: &str) -> Vec<&dyn ConfigTrait> {
+        self.route(interface)
+            .map(|(app, interface)| app.configs(interface))
+            .unwrap_or_default()
+    }
+
+    fn controls(&self) -> Vec<&dyn ToolbarControl> {
+        self.apps
+            .iter()
+            .flat_map(|sub_app| sub_app.app.controls())
+            .collect()
+    }
+
+    fn validate_capture_filter(&self, step: &FilterValidationStep) {
+        if let Some((app, interface)) = self.route(step.interface) {
+            app.validate_capture_filter(&FilterValidationStep {
+                interface,
+                filter: step.filter,
+            });
+        }
+    }
+
+    fn capture(&self, mut step: CaptureStep) -> Result<(), BoxError> {
+        let (app, interface) = self
+            .route(step.interface)
+            .ok_or_else(|| ExtcapError::UnknownInterface(step.interface.to_owned()))?;
+        step.interface = interface;
+        step.context.interface = interface.to_owned();
+        app.capture(step)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BoxError, ExtcapApp, MultiApp};
+    use crate::{
+        config::{ConfigTrait, StringConfig},
+        controls::{ButtonControl, ToolbarControl},
+        interface::{Dlt, Interface},
+        CaptureStep,
+    };
+
+    crate::controls! {
+        struct TestControls {
+            start: ButtonControl {
+                display: String::from("Start"),
+                tooltip: None,
+            },
+            stop: ButtonControl {
+                display: String::from("Stop"),
+                tooltip: None,
+            },
+        }
+    }
+
+    struct TestApp {
+        interfaces: Vec<Interface>,
+        config: StringConfig,
+        controls: TestControls,
+    }
+
+    impl TestApp {
+        fn new(interfaces: &[&'static str], first_control_number: u8) -> Self {
+            Self {
+                interfaces: interfaces
+                    .iter()
+                    .map(|value| {
+                        Interface::builder()
+                            .value((*value).into())
+                            .display((*value).into())
+                            .dlt(Dlt::user(0, "Test"))
+                            .build()
+                    })
+                    .collect(),
+                config: StringConfig::builder()
+                    .config_number(0)
+                    .call(format!("{}-config", interfaces[0]))
+                    .display("Config")
+                    .build(),
+                controls: TestControls::with_first_number(first_control_number),
+            }
+        }
+    }
+
+    impl ExtcapApp for TestApp {
+        fn interfaces(&self) -> Vec<&Interface> {
+            self.interfaces.iter().collect()
+        }
+
+        fn configs(&self, interface: &str) -> Vec<&dyn ConfigTrait> {
+            if self.interfaces.iter().any(|i| i.value == interface) {
+                vec![&self.config]
+            } else {
+                vec![]
+            }
+        }
+
+        fn controls(&self) -> Vec<&dyn ToolbarControl> {
+            self.controls.all().to_vec()
+        }
+
+        fn capture(&self, _step: CaptureStep) -> Result<(), BoxError> {
+            Ok(())
+        }
+    }
+
+    fn multi_app() -> MultiApp {
+        let app = MultiApp::new().with_app("ble", TestApp::new(&["sniffer", "hci"], 0));
+        let next = app.next_control_number();
+        app.with_app("zigbee", TestApp::new(&["sniffer"], next))
+    }
+
+    #[test]
+    fn namespaced_interfaces() {
+        let app = multi_app();
+        let values: Vec<_> = app.interfaces().iter().map(|i| i.value.clone()).collect();
+        assert_eq!(values, ["ble-sniffer", "ble-hci", "zigbee-sniffer"]);
+        let numbers: Vec<_> = app
+            .controls()
+            .iter()
+            .map(|control| control.control_number())
+            .collect();
+        assert_eq!(numbers, [0, 1, 2, 3]);
+        assert_eq!(app.next_control_number(), 4);
+    }
+
+    #[test]
+    fn routes_configs() {
+        let app = multi_app();
+        let calls = |interface| -> Vec<String> {
+            app.configs(interface)
+                .iter()
+                .map(|config| config.call().to_owned())
+                .collect()
+        };
+        assert_eq!(calls("ble-hci"), ["sniffer-config"]);
+        assert_eq!(calls("zigbee-sniffer"), ["sniffer-config"]);
+        assert!(calls("sniffer").is_empty());
+        assert!(calls("zigbee-hci").is_empty());
+        assert_eq!(app.route("ble-hci").unwrap().1, "hci");
+    }
+
+    #[test]
+    #[should_panic(expected = "Control number 1 of \"zigbee\" is already used")]
+    fn overlapping_controls() {
+        let _ = MultiApp::new()
+            .with_app("ble", TestApp::new(&["sniffer"], 0))
+            .with_app("zigbee", TestApp::new(&["sniffer"], 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "exceed the maximum of 254")]
+    fn control_numbers_out_of_range() {
+        let _ = TestControls::with_first_number(254);
+    }
+}
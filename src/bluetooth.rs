@@ -0,0 +1,280 @@
+//! Framing helpers for Bluetooth sniffers.
+//!
+//! BLE sniffer dongles usually report the radio metadata of each packet, like
+//! the channel and RSSI, separately from the packet bytes. Wireshark expects
+//! that metadata in a pseudo-header in front of the packet, whose layout
+//! depends on the link type:
+//!
+//! * [`LePseudoHeader`] for [`DataLink::BLUETOOTH_LE_LL_WITH_PHDR`], for
+//!   packets sniffed over the air.
+//! * [`hci_h4_with_phdr`] for [`DataLink::BLUETOOTH_HCI_H4_WITH_PHDR`], for
+//!   HCI traffic between a host and its controller.
+//!
+//! [`DataLink::BLUETOOTH_LE_LL_WITH_PHDR`]: pcap_file::DataLink::BLUETOOTH_LE_LL_WITH_PHDR
+//! [`DataLink::BLUETOOTH_HCI_H4_WITH_PHDR`]: pcap_file::DataLink::BLUETOOTH_HCI_H4_WITH_PHDR
+
+use typed_builder::TypedBuilder;
+
+/// The direction of an HCI packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HciDirection {
+    /// Sent from the host to the controller, like commands.
+    Sent = 0,
+    /// Received by the host from the controller, like events.
+    Received = 1,
+}
+
+/// Prepends the pseudo-header of
+/// [`DataLink::BLUETOOTH_HCI_H4_WITH_PHDR`][pcap_file::DataLink::BLUETOOTH_HCI_H4_WITH_PHDR]
+/// to `h4_packet`, which is an HCI packet starting with its H4 packet type
+/// byte.
+///
+/// ```
+/// use r_extcap::bluetooth::{hci_h4_with_phdr, HciDirection};
+///
+/// // HCI_Reset command.
+/// assert_eq!(
+///     hci_h4_with_phdr(HciDirection::Sent, &[0x01, 0x03, 0x0c, 0x00]),
+///     [0, 0, 0, 0, 0x01, 0x03, 0x0c, 0x00],
+/// );
+/// ```
+pub fn hci_h4_with_phdr(direction: HciDirection, h4_packet: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(4 + h4_packet.len());
+    // Unlike the rest of the Bluetooth headers, the direction is big-endian.
+    packet.extend_from_slice(&(direction as u32).to_be_bytes());
+    packet.extend_from_slice(h4_packet);
+    packet
+}
+
+/// Returns the RF channel (0 to 39, in order of frequency) of the BLE channel
+/// index `channel_index` (0 to 36 for data channels, 37 to 39 for primary
+/// advertising channels), or `None` if the index is out of range.
+///
+/// Sniffers usually report the channel index, while
+/// [`LePseudoHeader::rf_channel`] is the RF channel.
+///
+/// ```
+/// use r_extcap::bluetooth::rf_channel;
+///
+/// assert_eq!(rf_channel(37), Some(0));
+/// assert_eq!(rf_channel(0), Some(1));
+/// assert_eq!(rf_channel(11), Some(13));
+/// assert_eq!(rf_channel(40), None);
+/// ```
+pub fn rf_channel(channel_index: u8) -> Option<u8> {
+    match channel_index {
+        0..=10 => Some(channel_index + 1),
+        11..=36 => Some(channel_index + 2),
+        37 => Some(0),
+        38 => Some(12),
+        39 => Some(39),
+        _ => None,
+    }
+}
+
+/// The type of the PDU following a [`LePseudoHeader`], which Wireshark needs to
+/// dissect PDUs that do not identify themselves.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LePduType {
+    /// An advertising PDU on a primary advertising channel, or a data PDU of
+    /// unknown direction.
+    #[default]
+    AdvertisingOrData = 0,
+    /// An auxiliary advertising PDU on a secondary advertising channel.
+    AuxiliaryAdvertising = 1,
+    /// A data PDU sent by the central.
+    DataCentralToPeripheral = 2,
+    /// A data PDU sent by the peripheral.
+    DataPeripheralToCentral = 3,
+    /// A connected isochronous PDU.
+    ConnectedIsochronous = 4,
+    /// A broadcast isochronous PDU.
+    BroadcastIsochronous = 5,
+}
+
+/// The PHY a BLE packet was received on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LePhy {
+    /// LE 1M.
+    #[default]
+    Le1M = 0,
+    /// LE 2M.
+    Le2M = 1,
+    /// LE Coded.
+    LeCoded = 2,
+}
+
+/// The pseudo-header of
+/// [`DataLink::BLUETOOTH_LE_LL_WITH_PHDR`][pcap_file::DataLink::BLUETOOTH_LE_LL_WITH_PHDR]
+/// packets, which carries the radio metadata of a BLE link layer packet.
+///
+/// Optional fields that are `None` are marked as invalid in the flags of the
+/// header, so that Wireshark does not show them.
+///
+/// ```
+/// use r_extcap::bluetooth::{rf_channel, LePseudoHeader};
+///
+/// let header = LePseudoHeader::builder()
+///     .rf_channel(rf_channel(37).unwrap())
+///     .signal_power(-60)
+///     .crc_valid(true)
+///     .dewhitened(true)
+///     .build();
+/// // Access address, PDU header, payload and CRC, as received.
+/// let packet = [0xd6, 0xbe, 0x89, 0x8e, 0x40, 0x00, 0x12, 0x34, 0x56];
+/// let data = header.encode(&packet);
+/// assert_eq!(data.len(), LePseudoHeader::LEN + packet.len());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, TypedBuilder)]
+pub struct LePseudoHeader {
+    /// The RF channel the packet was received on, from 0 to 39. See
+    /// [`rf_channel`] to convert from a channel index.
+    pub rf_channel: u8,
+    /// The signal power (RSSI) in dBm.
+    #[builder(default, setter(strip_option))]
+    pub signal_power: Option<i8>,
+    /// The noise power in dBm.
+    #[builder(default, setter(strip_option))]
+    pub noise_power: Option<i8>,
+    /// The number of bits by which the access address of the packet differs
+    /// from [`reference_access_address`][Self::reference_access_address].
+    #[builder(default, setter(strip_option))]
+    pub access_address_offenses: Option<u8>,
+    /// The access address the sniffer was looking for, which may differ from
+    /// the access address of the packet if bit errors are tolerated.
+    #[builder(default, setter(strip_option))]
+    pub reference_access_address: Option<u32>,
+    /// Whether the packet was dewhitened by the sniffer.
+    #[builder(default)]
+    pub dewhitened: bool,
+    /// Whether the packet was decrypted by the sniffer.
+    #[builder(default)]
+    pub decrypted: bool,
+    /// Whether the packet may have been received on an adjacent channel.
+    #[builder(default)]
+    pub channel_aliased: bool,
+    /// The type of the PDU.
+    #[builder(default)]
+    pub pdu_type: LePduType,
+    /// Whether the CRC of the packet is valid, or `None` if it was not checked.
+    #[builder(default, setter(strip_option))]
+    pub crc_valid: Option<bool>,
+    /// Whether the MIC of the decrypted packet is valid, or `None` if it was
+    /// not checked.
+    #[builder(default, setter(strip_option))]
+    pub mic_valid: Option<bool>,
+    /// The PHY the packet was received on.
+    #[builder(default)]
+    pub phy: LePhy,
+}
+
+impl LePseudoHeader {
+    /// The length of the encoded pseudo-header.
+    pub const LEN: usize = 10;
+
+    const FLAG_DEWHITENED: u16 = 0x0001;
+    const FLAG_SIGNAL_POWER_VALID: u16 = 0x0002;
+    const FLAG_NOISE_POWER_VALID: u16 = 0x0004;
+    const FLAG_DECRYPTED: u16 = 0x0008;
+    const FLAG_REFERENCE_ACCESS_ADDRESS_VALID: u16 = 0x0010;
+    const FLAG_ACCESS_ADDRESS_OFFENSES_VALID: u16 = 0x0020;
+    const FLAG_CHANNEL_ALIASED: u16 = 0x0040;
+    const PDU_TYPE_SHIFT: u16 = 7;
+    const FLAG_CRC_CHECKED: u16 = 0x0400;
+    const FLAG_CRC_VALID: u16 = 0x0800;
+    const FLAG_MIC_CHECKED: u16 = 0x1000;
+    const FLAG_MIC_VALID: u16 = 0x2000;
+    const PHY_SHIFT: u16 = 14;
+
+    /// The flags field of the header.
+    fn flags(&self) -> u16 {
+        let flag = |set: bool, flag: u16| if set { flag } else { 0 };
+        flag(self.dewhitened, Self::FLAG_DEWHITENED)
+            | flag(self.signal_power.is_some(), Self::FLAG_SIGNAL_POWER_VALID)
+            | flag(self.noise_power.is_some(), Self::FLAG_NOISE_POWER_VALID)
+            | flag(self.decrypted, Self::FLAG_DECRYPTED)
+            | flag(
+                self.reference_access_address.is_some(),
+                Self::FLAG_REFERENCE_ACCESS_ADDRESS_VALID,
+            )
+            | flag(
+                self.access_address_offenses.is_some(),
+                Self::FLAG_ACCESS_ADDRESS_OFFENSES_VALID,
+            )
+            | flag(self.channel_aliased, Self::FLAG_CHANNEL_ALIASED)
+            | (self.pdu_type as u16) << Self::PDU_TYPE_SHIFT
+            | flag(self.crc_valid.is_some(), Self::FLAG_CRC_CHECKED)
+            | flag(self.crc_valid == Some(true), Self::FLAG_CRC_VALID)
+            | flag(self.mic_valid.is_some(), Self::FLAG_MIC_CHECKED)
+            | flag(self.mic_valid == Some(true), Self::FLAG_MIC_VALID)
+            | (self.phy as u16) << Self::PHY_SHIFT
+    }
+
+    /// Encodes the pseudo-header. All fields are little-endian.
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut bytes = [0; Self::LEN];
+        bytes[0] = self.rf_channel;
+        bytes[1] = self.signal_power.unwrap_or_default() as u8;
+        bytes[2] = self.noise_power.unwrap_or_default() as u8;
+        bytes[3] = self.access_address_offenses.unwrap_or_default();
+        bytes[4..8].copy_from_slice(
+            &self
+                .reference_access_address
+                .unwrap_or_default()
+                .to_le_bytes(),
+        );
+        bytes[8..10].copy_from_slice(&self.flags().to_le_bytes());
+        bytes
+    }
+
+    /// Returns the packet data consisting of this pseudo-header followed by
+    /// `packet`, which is the link layer packet starting with the access
+    /// address.
+    pub fn encode(&self, packet: &[u8]) -> Vec<u8> {
+        let mut data = Vec::with_capacity(Self::LEN + packet.len());
+        data.extend_from_slice(&self.to_bytes());
+        data.extend_from_slice(packet);
+        data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{rf_channel, LePduType, LePhy, LePseudoHeader};
+
+    #[test]
+    fn le_pseudo_header() {
+        assert_eq!(
+            LePseudoHeader::builder().rf_channel(0).build().to_bytes(),
+            [0; LePseudoHeader::LEN]
+        );
+        let header = LePseudoHeader::builder()
+            .rf_channel(12)
+            .signal_power(-60)
+            .noise_power(-95)
+            .access_address_offenses(1)
+            .reference_access_address(0x8e89bed6)
+            .dewhitened(true)
+            .pdu_type(LePduType::DataPeripheralToCentral)
+            .crc_valid(false)
+            .mic_valid(true)
+            .phy(LePhy::LeCoded)
+            .build();
+        // Flags: dewhitened, signal, noise, reference access address and
+        // access address offenses valid (0x37), PDU type 3 (0x180), CRC
+        // checked (0x400), MIC checked and valid (0x3000), LE Coded (0x8000).
+        assert_eq!(
+            header.to_bytes(),
+            [12, 0xc4, 0xa1, 1, 0xd6, 0xbe, 0x89, 0x8e, 0xb7, 0xb5]
+        );
+    }
+
+    #[test]
+    fn rf_channels() {
+        let channels: Vec<_> = (0..40).map(|index| rf_channel(index).unwrap()).collect();
+        let mut sorted = channels.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..40).collect::<Vec<_>>());
+        assert_eq!(channels[36], 38);
+    }
+}
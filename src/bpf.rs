@@ -0,0 +1,110 @@
+//! Helpers for applying the capture filter given in
+//! [`--extcap-capture-filter`][crate::ExtcapArgs::extcap_capture_filter].
+//!
+//! Wireshark passes the capture filter to the extcap as an opaque string, in
+//! the same BPF syntax used by `tcpdump`. Extcaps that cannot push the filter
+//! down to the underlying hardware can use [`FilterMatcher`] to filter the
+//! packets themselves before writing them to the fifo, or wrap it in a
+//! [`CaptureFilter`][crate::sink::CaptureFilter] to filter everything written
+//! to a sink.
+//!
+//! The filter is compiled using libpcap, so this module requires libpcap (or
+//! Npcap on Windows) to be available on the system. This module is only
+//! available with the `bpf` feature.
+
+use pcap::{BpfProgram, Capture, Linktype};
+use pcap_file::DataLink;
+use thiserror::Error;
+
+/// Error creating a [`FilterMatcher`].
+#[derive(Debug, Error)]
+pub enum FilterError {
+    /// The filter could not be compiled, typically because of a syntax error
+    /// or because it is not applicable to the data link type. The contained
+    /// error message is suitable for showing to the user.
+    #[error("{0}")]
+    Compile(pcap::Error),
+}
+
+/// A compiled capture filter that can be matched against packets.
+///
+/// ```no_run
+/// use pcap_file::DataLink;
+/// use r_extcap::bpf::FilterMatcher;
+///
+/// let matcher = FilterMatcher::new("udp port 53", DataLink::ETHERNET).unwrap();
+/// # let packet = [0_u8; 64];
+/// if matcher.matches(&packet) {
+///     // Write the packet to the fifo
+/// }
+/// ```
+pub struct FilterMatcher {
+    program: BpfProgram,
+}
+
+impl FilterMatcher {
+    /// Compiles the `filter` for packets of the given `datalink` type. An empty
+    /// filter matches all packets.
+    pub fn new(filter: &str, datalink: DataLink) -> Result<Self, FilterError> {
+        let linktype = Linktype(u32::from(datalink) as i32);
+        let capture = Capture::dead(linktype).map_err(FilterError::Compile)?;
+        let program = capture
+            .compile(filter, true)
+            .map_err(FilterError::Compile)?;
+        Ok(Self { program })
+    }
+
+    /// Returns whether the packet, which should start with the header for the
+    /// data link type given in [`new`][Self::new], matches the filter.
+    pub fn matches(&self, packet: &[u8]) -> bool {
+        self.program.filter(packet)
+    }
+}
+
+// Safety: the compiled program is owned exclusively by the matcher, and is
+// only read when matching, so it can be moved to another thread.
+unsafe impl Send for FilterMatcher {}
+
+impl std::fmt::Debug for FilterMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FilterMatcher").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pcap_file::DataLink;
+
+    use super::{FilterError, FilterMatcher};
+
+    /// An Ethernet + IPv4 frame with the given IP protocol number and an empty
+    /// payload.
+    fn ipv4_frame(protocol: u8) -> Vec<u8> {
+        let mut frame = vec![0_u8; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&[0x45, 0, 0, 20, 0, 0, 0, 0, 64, protocol, 0, 0]);
+        frame.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        frame
+    }
+
+    #[test]
+    fn matches_protocol() {
+        let matcher = FilterMatcher::new("udp", DataLink::ETHERNET).unwrap();
+        assert!(matcher.matches(&ipv4_frame(17)));
+        assert!(!matcher.matches(&ipv4_frame(6)));
+    }
+
+    #[test]
+    fn empty_filter_matches_all() {
+        let matcher = FilterMatcher::new("", DataLink::ETHERNET).unwrap();
+        assert!(matcher.matches(&ipv4_frame(6)));
+    }
+
+    #[test]
+    fn invalid_filter() {
+        assert!(matches!(
+            FilterMatcher::new("invalid filter ((", DataLink::ETHERNET),
+            Err(FilterError::Compile(_))
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+//! CAN frames in the format of
+//! [`DataLink::CAN_SOCKETCAN`][pcap_file::DataLink::CAN_SOCKETCAN].
+//!
+//! The packets of this link type look like the `can_frame` and `canfd_frame`
+//! structs of Linux SocketCAN, except that the CAN ID is big-endian instead
+//! of in host byte order. [`CanFrame`] converts frames read from a SocketCAN
+//! socket, or built from the output of other CAN adapters, into that format.
+//! On Linux, [`SocketCanSource`][crate::sources::socketcan::SocketCanSource]
+//! captures from a SocketCAN interface directly.
+//!
+//! ```
+//! use r_extcap::can::{CanFrame, CanId, FdFlags};
+//!
+//! let frame = CanFrame::new(CanId::Standard(0x123), &[0xde, 0xad]).unwrap();
+//! assert_eq!(frame.encode(), [0, 0, 0x01, 0x23, 2, 0, 0, 0, 0xde, 0xad]);
+//!
+//! let fd_frame = CanFrame::new_fd(
+//!     CanId::Extended(0x1234567),
+//!     &[0; 12],
+//!     FdFlags { bitrate_switch: true, ..Default::default() },
+//! )
+//! .unwrap();
+//! assert_eq!(fd_frame.encode()[..8], [0x81, 0x23, 0x45, 0x67, 12, 0x05, 0, 0]);
+//! ```
+
+use thiserror::Error;
+
+/// The identifier of a CAN frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CanId {
+    /// An 11-bit standard identifier.
+    Standard(u16),
+    /// A 29-bit extended identifier.
+    Extended(u32),
+}
+
+/// The flags of a CAN FD frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FdFlags {
+    /// The data phase of the frame used the higher bit rate (BRS).
+    pub bitrate_switch: bool,
+    /// The transmitting node was error passive (ESI).
+    pub error_state_indicator: bool,
+}
+
+impl FdFlags {
+    const BRS: u8 = 0x01;
+    const ESI: u8 = 0x02;
+    /// Marks the frame as a CAN FD frame. SocketCAN does not always set it,
+    /// but Wireshark relies on it to tell CAN FD frames apart.
+    const FDF: u8 = 0x04;
+
+    fn from_bits(bits: u8) -> Self {
+        Self {
+            bitrate_switch: bits & Self::BRS != 0,
+            error_state_indicator: bits & Self::ESI != 0,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        let mut bits = Self::FDF;
+        if self.bitrate_switch {
+            bits |= Self::BRS;
+        }
+        if self.error_state_indicator {
+            bits |= Self::ESI;
+        }
+        bits
+    }
+}
+
+/// Error creating a [`CanFrame`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvalidCanFrame {
+    /// The identifier does not fit in 11 bits for a standard ID, or 29 bits
+    /// for an extended ID.
+    #[error("CAN ID {0:#x} is out of range")]
+    IdOutOfRange(u32),
+    /// The data is longer than 8 bytes for a classic CAN frame, or 64 bytes
+    /// for a CAN FD frame.
+    #[error("CAN frame data is too long ({0} bytes)")]
+    DataTooLong(usize),
+}
+
+/// A classic CAN or CAN FD frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CanFrame {
+    can_id: u32,
+    data: Vec<u8>,
+    fd: Option<FdFlags>,
+}
+
+impl CanFrame {
+    /// The flag in [`can_id`][Self::can_id] for extended identifiers.
+    pub const EFF_FLAG: u32 = 0x8000_0000;
+    /// The flag in [`can_id`][Self::can_id] for remote transmission requests.
+    pub const RTR_FLAG: u32 = 0x4000_0000;
+    /// The flag in [`can_id`][Self::can_id] for error frames.
+    pub const ERR_FLAG: u32 = 0x2000_0000;
+
+    /// The length of a classic CAN frame read from a SocketCAN socket.
+    const CAN_MTU: usize = 16;
+    /// The length of a CAN FD frame read from a SocketCAN socket.
+    const CANFD_MTU: usize = 72;
+    /// The length of the header before the data.
+    const HEADER_LEN: usize = 8;
+
+    fn with_id(id: CanId, data: &[u8], fd: Option<FdFlags>) -> Result<Self, InvalidCanFrame> {
+        let max_len = if fd.is_some() { 64 } else { 8 };
+        if data.len() > max_len {
+            return Err(InvalidCanFrame::DataTooLong(data.len()));
+        }
+        let can_id = match id {
+            CanId::Standard(id) if id <= 0x7ff => u32::from(id),
+            CanId::Extended(id) if id <= 0x1fff_ffff => id | Self::EFF_FLAG,
+            CanId::Standard(id) => return Err(InvalidCanFrame::IdOutOfRange(id.into())),
+            CanId::Extended(id) => return Err(InvalidCanFrame::IdOutOfRange(id)),
+        };
+        Ok(Self {
+            can_id,
+            data: data.to_vec(),
+            fd,
+        })
+    }
+
+    /// Creates a classic CAN data frame.
+    pub fn new(id: CanId, data: &[u8]) -> Result<Self, InvalidCanFrame> {
+        Self::with_id(id, data, None)
+    }
+
+    /// Creates a CAN FD frame.
+    pub fn new_fd(id: CanId, data: &[u8], flags: FdFlags) -> Result<Self, InvalidCanFrame> {
+        Self::with_id(id, data, Some(flags))
+    }
+
+    /// Parses a `can_frame` or `canfd_frame` as read from a raw SocketCAN
+    /// socket, telling them apart by length like the kernel does. Returns
+    /// `None` if `frame` has neither length.
+    pub fn from_socketcan(frame: &[u8]) -> Option<Self> {
+        let fd = match frame.len() {
+            Self::CAN_MTU => None,
+            Self::CANFD_MTU => Some(FdFlags::from_bits(frame[5])),
+            _ => return None,
+        };
+        let can_id = u32::from_ne_bytes(frame[0..4].try_into().unwrap());
+        let len = usize::from(frame[4]).min(frame.len() - Self::HEADER_LEN);
+        Some(Self {
+            can_id,
+            data: frame[Self::HEADER_LEN..Self::HEADER_LEN + len].to_vec(),
+            fd,
+        })
+    }
+
+    /// The CAN ID in SocketCAN's format, which is the identifier combined with
+    /// [`EFF_FLAG`][Self::EFF_FLAG], [`RTR_FLAG`][Self::RTR_FLAG] and
+    /// [`ERR_FLAG`][Self::ERR_FLAG].
+    pub fn can_id(&self) -> u32 {
+        self.can_id
+    }
+
+    /// The data of the frame.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The flags of the frame if it is a CAN FD frame, or `None` for a classic
+    /// CAN frame.
+    pub fn fd_flags(&self) -> Option<FdFlags> {
+        self.fd
+    }
+
+    /// Encodes the frame as a
+    /// [`DataLink::CAN_SOCKETCAN`][pcap_file::DataLink::CAN_SOCKETCAN] packet.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(Self::HEADER_LEN + self.data.len());
+        packet.extend_from_slice(&self.can_id.to_be_bytes());
+        packet.push(self.data.len() as u8);
+        packet.push(self.fd.map_or(0, FdFlags::bits));
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(&self.data);
+        packet
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CanFrame, CanId, FdFlags, InvalidCanFrame};
+
+    #[test]
+    fn invalid_frames() {
+        assert_eq!(
+            CanFrame::new(CanId::Standard(0x800), &[]),
+            Err(InvalidCanFrame::IdOutOfRange(0x800))
+        );
+        assert_eq!(
+            CanFrame::new(CanId::Extended(0x2000_0000), &[]),
+            Err(InvalidCanFrame::IdOutOfRange(0x2000_0000))
+        );
+        assert_eq!(
+            CanFrame::new(CanId::Standard(1), &[0; 9]),
+            Err(InvalidCanFrame::DataTooLong(9))
+        );
+        assert!(CanFrame::new_fd(CanId::Standard(1), &[0; 64], FdFlags::default()).is_ok());
+    }
+
+    #[test]
+    fn from_socketcan() {
+        let mut frame = [0_u8; 16];
+        frame[0..4].copy_from_slice(&(0x1abc_u32 | CanFrame::EFF_FLAG).to_ne_bytes());
+        frame[4] = 3;
+        frame[8..11].copy_from_slice(&[1, 2, 3]);
+        let frame = CanFrame::from_socketcan(&frame).unwrap();
+        assert_eq!(
+            frame,
+            CanFrame::new(CanId::Extended(0x1abc), &[1, 2, 3]).unwrap()
+        );
+        assert_eq!(frame.encode(), [0x80, 0, 0x1a, 0xbc, 3, 0, 0, 0, 1, 2, 3]);
+
+        let mut fd_frame = [0_u8; 72];
+        fd_frame[0..4].copy_from_slice(&0x42_u32.to_ne_bytes());
+        fd_frame[4] = 16;
+        fd_frame[5] = 0x02;
+        let fd_frame = CanFrame::from_socketcan(&fd_frame).unwrap();
+        assert_eq!(
+            fd_frame.fd_flags(),
+            Some(FdFlags {
+                bitrate_switch: false,
+                error_state_indicator: true
+            })
+        );
+        assert_eq!(fd_frame.data(), [0; 16]);
+        assert_eq!(fd_frame.encode()[..8], [0, 0, 0, 0x42, 16, 0x06, 0, 0]);
+
+        assert_eq!(CanFrame::from_socketcan(&[0; 20]), None);
+    }
+}
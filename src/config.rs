@@ -5,28 +5,102 @@
 //! only. Those config options are specified on the command line when running
 //! the actual capture.
 
+use log::warn;
 use std::any::Any;
 use std::fmt::Debug;
 use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use thiserror::Error;
 use typed_builder::TypedBuilder;
 
-pub use crate::{ExtcapFormatter, PrintSentence};
+use crate::AttributeKey;
+pub use crate::{EscapedValue, ExtcapFormatter, PrintSentence};
 
 macro_rules! generate_config_ext {
-    ($config_type:ty) => {
+    ($config_type:ty $(, $required:ident)?) => {
         impl ConfigTrait for $config_type {
+            fn config_number(&self) -> u8 {
+                self.config_number
+            }
+
             fn call(&self) -> &str {
                 &self.call
             }
 
+            fn display(&self) -> &str {
+                &self.display
+            }
+
+            fn group(&self) -> Option<&str> {
+                self.group.as_deref()
+            }
+
+            fn required(&self) -> bool {
+                false $(|| self.$required)?
+            }
+
             fn as_any(&self) -> &dyn Any {
                 self
             }
+
+            #[cfg(feature = "serde")]
+            fn to_json(&self) -> serde_json::Value {
+                serde_json::to_value(self).expect("Configs can be serialized to JSON")
+            }
+        }
+    };
+}
+
+macro_rules! generate_validate_range {
+    ($config_type:ty) => {
+        impl $config_type {
+            /// Checks that [`default_value`][Self::default_value] is within
+            /// the [`range`][Self::range], if any. Wireshark does not check
+            /// this, and shows the out of range default to the user. A
+            /// warning is logged when the config is printed for Wireshark.
+            pub fn validate(&self) -> Result<(), DefaultOutOfRangeError> {
+                check_default_in_range(&self.range, &self.default_value)
+            }
         }
     };
 }
 
 /// Defines a reload operation for [`SelectorConfig`].
+///
+/// ## Rescanning devices
+///
+/// Wireshark caches the interfaces listed in `--extcap-interfaces`, and only
+/// asks for them again when the user refreshes the interfaces, for example
+/// with Capture > Refresh Interfaces. To let the user pick a device plugged
+/// in after Wireshark started, list the devices in a selector config, and
+/// rescan them in the reload function. Since [`ConfigOptionValue`] converts
+/// from an [`Interface`][crate::interface::Interface], the same discovery
+/// function can list both the interfaces and the options.
+///
+/// ```
+/// use r_extcap::config::*;
+/// use r_extcap::interface::{Dlt, Interface};
+///
+/// fn discover_devices() -> Vec<Interface> {
+///     // Scan for the connected devices.
+///     vec![Interface::builder()
+///         .value("/dev/ttyUSB0".into())
+///         .display("Sniffer on ttyUSB0".into())
+///         .dlt(Dlt::user(0, "Sniffer"))
+///         .build()]
+/// }
+///
+/// let device = SelectorConfig::builder()
+///     .config_number(0)
+///     .call("device")
+///     .display("Device")
+///     .reload(Reload {
+///         label: String::from("Rescan"),
+///         reload_fn: || ConfigOptionValue::list(&discover_devices()),
+///     })
+///     .default_options(ConfigOptionValue::list(&discover_devices()))
+///     .build();
+/// ```
 pub struct Reload {
     /// The label for the reload button displayed next to the selector config.
     pub label: String,
@@ -42,6 +116,18 @@ impl std::fmt::Debug for Reload {
     }
 }
 
+/// Only the label is serialized, since the reload function is only meaningful
+/// to this program.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Reload {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("Reload", 1)?;
+        state.serialize_field("label", &self.label)?;
+        state.end()
+    }
+}
+
 /// A selector config UI element that allows the user to select an option from a
 /// drop-down list. The list of options should have default=true on exactly one
 /// item.
@@ -73,6 +159,7 @@ impl std::fmt::Debug for Reload {
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct SelectorConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -105,23 +192,61 @@ pub struct SelectorConfig {
     /// The default list of options presented by this selector.
     #[builder(setter(into))]
     pub default_options: Vec<ConfigOptionValue>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
+}
+
+impl<N, C, D, T, R, G, S> SelectorConfigBuilder<(N, C, D, T, R, G, (), S)> {
+    /// Sets [`default_options`][SelectorConfig::default_options] from an
+    /// iterator of anything that converts into a [`ConfigOptionValue`], like
+    /// `(value, display)` or `(value, display, default)` tuples.
+    ///
+    /// ```
+    /// use r_extcap::config::*;
+    ///
+    /// # let devices = ["ttyUSB0", "ttyUSB1"];
+    /// let selector = SelectorConfig::builder()
+    ///     .config_number(0)
+    ///     .call("device")
+    ///     .display("Device")
+    ///     .options_from(devices.iter().map(|device| (*device, format!("/dev/{device}"))))
+    ///     .build();
+    /// assert_eq!(selector.default_options.len(), 2);
+    /// ```
+    // The tuple is the field state of the builder generated by `TypedBuilder`,
+    // with one element per field in declaration order.
+    #[allow(clippy::type_complexity)]
+    pub fn options_from<O: Into<ConfigOptionValue>>(
+        self,
+        options: impl IntoIterator<Item = O>,
+    ) -> SelectorConfigBuilder<(N, C, D, T, R, G, (Vec<ConfigOptionValue>,), S)> {
+        self.default_options(ConfigOptionValue::list(options))
+    }
 }
 
 impl PrintSentence for SelectorConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
-        write!(f, "{{type=selector}}")?;
+        AttributeKey::Type.write(f, "selector")?;
         if let Some(Reload { label, .. }) = &self.reload {
-            write!(f, "{{reload=true}}")?;
-            write!(f, "{{placeholder={label}}}")?;
+            AttributeKey::Reload.write(f, "true")?;
+            AttributeKey::Placeholder.write(f, label)?;
         }
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
         writeln!(f)?;
         for opt in self.default_options.iter() {
@@ -163,6 +288,7 @@ generate_config_ext!(SelectorConfig);
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct RadioConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -185,20 +311,30 @@ pub struct RadioConfig {
     /// The default list of options presented by this config.
     #[builder(setter(into))]
     pub options: Vec<ConfigOptionValue>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for RadioConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         if let Some(group) = &self.group {
-            write!(f, "{{group={}}}", group)?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        AttributeKey::Type.write(f, "radio")?;
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
-        write!(f, "{{type=radio}}")?;
         writeln!(f)?;
         for opt in self.options.iter() {
             write!(f, "{}", ExtcapFormatter(&(opt, self.config_number)))?;
@@ -256,6 +392,7 @@ generate_config_ext!(RadioConfig);
 /// multi: Vec<String>,
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct MultiCheckConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -278,20 +415,30 @@ pub struct MultiCheckConfig {
     /// The default list of options presented by this config. This can be refreshed by the user using via the `reload` field.
     #[builder(setter(into))]
     pub options: Vec<MultiCheckValue>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for MultiCheckConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         if let Some(group) = &self.group {
-            write!(f, "{{group={}}}", group)?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        AttributeKey::Type.write(f, "multicheck")?;
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
-        write!(f, "{{type=multicheck}}")?;
         writeln!(f)?;
         for opt in self.options.iter() {
             write!(f, "{}", ExtcapFormatter(&(opt, self.config_number, None)))?;
@@ -306,15 +453,29 @@ generate_config_ext!(MultiCheckConfig);
 /// the UI that can be nested into a hierarchy using the `children` field. See
 /// the docs for [`MultiCheckConfig`] for usage details.
 #[derive(Debug, Clone, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MultiCheckValue {
     /// The value for this option, which is the value that will be passed to the
     /// extcap command line. For example, if `MultiCheckConfig.call` is `foo`,
     /// and this field is `bar`, then `--foo bar` will be passed to this extcap
     /// program during capturing.
-    #[builder(setter(into))]
+    ///
+    /// Panics: If the value contains a comma or a line break. See
+    /// [`InvalidValueError`].
+    #[builder(setter(transform = |value: impl Into<String>| checked(value.into(), validate_multicheck_value)))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "deserialize_multicheck_value")
+    )]
     pub value: String,
     /// The user-friendly label for this check box.
-    #[builder(setter(into))]
+    ///
+    /// Panics: If the label contains a line break.
+    #[builder(setter(transform = |display: impl Into<String>| checked(display.into(), validate_sentence_value)))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "deserialize_sentence_value")
+    )]
     pub display: String,
     /// The default value for this check box, whether it is checked or not.
     #[builder(default = false)]
@@ -332,13 +493,14 @@ pub struct MultiCheckValue {
 impl PrintSentence for (&MultiCheckValue, u8, Option<&MultiCheckValue>) {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let (config, config_number, parent) = self;
-        write!(f, "value {{arg={}}}", config_number)?;
-        write!(f, "{{value={}}}", config.value)?;
-        write!(f, "{{display={}}}", config.display)?;
-        write!(f, "{{default={}}}", config.default_value)?;
-        write!(f, "{{enabled={}}}", config.enabled)?;
+        f.write_str("value ")?;
+        AttributeKey::Arg.write(f, config_number)?;
+        AttributeKey::Value.write(f, EscapedValue(&config.value))?;
+        AttributeKey::Display.write(f, EscapedValue(&config.display))?;
+        AttributeKey::Default.write(f, config.default_value)?;
+        AttributeKey::Enabled.write(f, config.enabled)?;
         if let Some(parent) = parent {
-            write!(f, "{{parent={}}}", parent.value)?;
+            AttributeKey::Parent.write(f, EscapedValue(&parent.value))?;
         }
         writeln!(f)?;
         for c in config.children.iter() {
@@ -352,6 +514,18 @@ impl PrintSentence for (&MultiCheckValue, u8, Option<&MultiCheckValue>) {
     }
 }
 
+impl MultiCheckValue {
+    /// Checks that this value and all of its children can be written in the
+    /// extcap sentence and passed back on the command line. This is checked
+    /// by the builder, but needs to be checked again if the fields are
+    /// modified after building.
+    pub fn validate(&self) -> Result<(), InvalidValueError> {
+        validate_multicheck_value(&self.value)?;
+        validate_sentence_value(&self.display)?;
+        self.children.iter().try_for_each(MultiCheckValue::validate)
+    }
+}
+
 /// This provides a field for entering a numeric value of the given data type. A
 /// default value may be provided, as well as a range.
 ///
@@ -376,6 +550,7 @@ impl PrintSentence for (&MultiCheckValue, u8, Option<&MultiCheckValue>) {
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct LongConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -391,7 +566,16 @@ pub struct LongConfig {
     /// The tooltip shown on when hovering over the UI element.
     #[builder(default, setter(strip_option, into))]
     pub tooltip: Option<String>,
+    /// The placeholder string displayed if there is no value in the field.
+    #[builder(default, setter(strip_option, into))]
+    pub placeholder: Option<String>,
+    /// Whether a value is required for this config.
+    #[builder(default = false)]
+    pub required: bool,
     /// The valid range of values for this config.
+    ///
+    /// See [`validate`][Self::validate] to check that the
+    /// [`default_value`][Self::default_value] is within this range.
     #[builder(default, setter(strip_option))]
     pub range: Option<RangeInclusive<i64>>,
     /// The default value for this config.
@@ -400,30 +584,50 @@ pub struct LongConfig {
     /// is `None`, the config will be placed in a tab called "Default".
     #[builder(default, setter(strip_option, into))]
     pub group: Option<String>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for LongConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        if let Err(e) = self.validate() {
+            warn!("{e}");
+        }
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
+        }
+        if let Some(placeholder) = &self.placeholder {
+            AttributeKey::Placeholder.write(f, placeholder)?;
+        }
+        if self.required {
+            AttributeKey::Required.write(f, "true")?;
         }
         if let Some(range) = &self.range {
-            write!(f, "{{range={},{}}}", range.start(), range.end())?;
+            AttributeKey::Range.write(f, format_args!("{},{}", range.start(), range.end()))?;
         }
-        write!(f, "{{default={}}}", self.default_value)?;
-        write!(f, "{{type=long}}")?;
+        AttributeKey::Default.write(f, self.default_value)?;
+        AttributeKey::Type.write(f, "long")?;
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
         writeln!(f)?;
         Ok(())
     }
 }
 
-generate_config_ext!(LongConfig);
+generate_config_ext!(LongConfig, required);
+generate_validate_range!(LongConfig);
 
 /// This provides a field for entering a numeric value of the given data type. A
 /// default value may be provided, as well as a range.
@@ -449,6 +653,7 @@ generate_config_ext!(LongConfig);
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct IntegerConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -464,7 +669,16 @@ pub struct IntegerConfig {
     /// The tooltip shown on when hovering over the UI element.
     #[builder(default, setter(strip_option, into))]
     pub tooltip: Option<String>,
+    /// The placeholder string displayed if there is no value in the field.
+    #[builder(default, setter(strip_option, into))]
+    pub placeholder: Option<String>,
+    /// Whether a value is required for this config.
+    #[builder(default = false)]
+    pub required: bool,
     /// The valid range of values for this config.
+    ///
+    /// See [`validate`][Self::validate] to check that the
+    /// [`default_value`][Self::default_value] is within this range.
     #[builder(default, setter(strip_option))]
     pub range: Option<RangeInclusive<i32>>,
     /// The default value for this config.
@@ -473,30 +687,50 @@ pub struct IntegerConfig {
     /// is `None`, the config will be placed in a tab called "Default".
     #[builder(default, setter(strip_option, into))]
     pub group: Option<String>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for IntegerConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        if let Err(e) = self.validate() {
+            warn!("{e}");
+        }
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
+        }
+        if let Some(placeholder) = &self.placeholder {
+            AttributeKey::Placeholder.write(f, placeholder)?;
+        }
+        if self.required {
+            AttributeKey::Required.write(f, "true")?;
         }
         if let Some(range) = &self.range {
-            write!(f, "{{range={},{}}}", range.start(), range.end())?;
+            AttributeKey::Range.write(f, format_args!("{},{}", range.start(), range.end()))?;
         }
-        write!(f, "{{default={}}}", self.default_value)?;
-        write!(f, "{{type=integer}}")?;
+        AttributeKey::Default.write(f, self.default_value)?;
+        AttributeKey::Type.write(f, "integer")?;
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
         writeln!(f)?;
         Ok(())
     }
 }
 
-generate_config_ext!(IntegerConfig);
+generate_config_ext!(IntegerConfig, required);
+generate_validate_range!(IntegerConfig);
 
 /// This provides a field for entering a numeric value of the given data type. A
 /// default value may be provided, as well as a range.
@@ -522,6 +756,7 @@ generate_config_ext!(IntegerConfig);
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct UnsignedConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -537,7 +772,16 @@ pub struct UnsignedConfig {
     /// The tooltip shown on when hovering over the UI element.
     #[builder(default, setter(strip_option, into))]
     pub tooltip: Option<String>,
+    /// The placeholder string displayed if there is no value in the field.
+    #[builder(default, setter(strip_option, into))]
+    pub placeholder: Option<String>,
+    /// Whether a value is required for this config.
+    #[builder(default = false)]
+    pub required: bool,
     /// The valid range of values for this config.
+    ///
+    /// See [`validate`][Self::validate] to check that the
+    /// [`default_value`][Self::default_value] is within this range.
     #[builder(default, setter(strip_option, into))]
     pub range: Option<RangeInclusive<u32>>,
     /// The default value for this config.
@@ -546,30 +790,50 @@ pub struct UnsignedConfig {
     /// is `None`, the config will be placed in a tab called "Default".
     #[builder(default, setter(strip_option, into))]
     pub group: Option<String>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for UnsignedConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        if let Err(e) = self.validate() {
+            warn!("{e}");
+        }
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
+        }
+        if let Some(placeholder) = &self.placeholder {
+            AttributeKey::Placeholder.write(f, placeholder)?;
+        }
+        if self.required {
+            AttributeKey::Required.write(f, "true")?;
         }
         if let Some(range) = &self.range {
-            write!(f, "{{range={},{}}}", range.start(), range.end())?;
+            AttributeKey::Range.write(f, format_args!("{},{}", range.start(), range.end()))?;
         }
-        write!(f, "{{default={}}}", self.default_value)?;
-        write!(f, "{{type=unsigned}}")?;
+        AttributeKey::Default.write(f, self.default_value)?;
+        AttributeKey::Type.write(f, "unsigned")?;
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
         writeln!(f)?;
         Ok(())
     }
 }
 
-generate_config_ext!(UnsignedConfig);
+generate_config_ext!(UnsignedConfig, required);
+generate_validate_range!(UnsignedConfig);
 
 /// This provides a field for entering a numeric value of the given data type. A
 /// default value may be provided, as well as a range.
@@ -595,6 +859,7 @@ generate_config_ext!(UnsignedConfig);
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct DoubleConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -610,7 +875,16 @@ pub struct DoubleConfig {
     /// The tooltip shown on when hovering over the UI element.
     #[builder(default, setter(strip_option, into))]
     pub tooltip: Option<String>,
+    /// The placeholder string displayed if there is no value in the field.
+    #[builder(default, setter(strip_option, into))]
+    pub placeholder: Option<String>,
+    /// Whether a value is required for this config.
+    #[builder(default = false)]
+    pub required: bool,
     /// The valid range of values for this config.
+    ///
+    /// See [`validate`][Self::validate] to check that the
+    /// [`default_value`][Self::default_value] is within this range.
     #[builder(default, setter(strip_option))]
     pub range: Option<RangeInclusive<f64>>,
     /// The default value for this config.
@@ -619,30 +893,50 @@ pub struct DoubleConfig {
     /// is `None`, the config will be placed in a tab called "Default".
     #[builder(default, setter(strip_option, into))]
     pub group: Option<String>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for DoubleConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        if let Err(e) = self.validate() {
+            warn!("{e}");
+        }
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
+        }
+        if let Some(placeholder) = &self.placeholder {
+            AttributeKey::Placeholder.write(f, placeholder)?;
+        }
+        if self.required {
+            AttributeKey::Required.write(f, "true")?;
         }
         if let Some(range) = &self.range {
-            write!(f, "{{range={},{}}}", range.start(), range.end())?;
+            AttributeKey::Range.write(f, format_args!("{},{}", range.start(), range.end()))?;
         }
-        write!(f, "{{default={}}}", self.default_value)?;
-        write!(f, "{{type=double}}")?;
+        AttributeKey::Default.write(f, self.default_value)?;
+        AttributeKey::Type.write(f, "double")?;
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
         writeln!(f)?;
         Ok(())
     }
 }
 
-generate_config_ext!(DoubleConfig);
+generate_config_ext!(DoubleConfig, required);
+generate_validate_range!(DoubleConfig);
 
 /// A field for entering a text value.
 ///
@@ -670,6 +964,7 @@ generate_config_ext!(DoubleConfig);
 /// ```
 #[allow(deprecated)]
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct StringConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -719,38 +1014,43 @@ pub struct StringConfig {
 impl PrintSentence for StringConfig {
     #[allow(deprecated)]
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         if let Some(placeholder) = &self.placeholder {
-            write!(f, "{{placeholder={}}}", placeholder)?;
+            AttributeKey::Placeholder.write(f, placeholder)?;
         }
         if self.required {
-            write!(f, "{{required=true}}")?;
+            AttributeKey::Required.write(f, "true")?;
         }
         if let Some(validation) = &self.validation {
-            write!(f, "{{validation={}}}", validation)?;
+            AttributeKey::Validation.write(f, validation)?;
         }
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
         }
         if !self.save {
-            write!(f, "{{save=false}}")?;
+            AttributeKey::Save.write(f, "false")?;
         }
-        write!(f, "{{type=string}}")?;
+        AttributeKey::Type.write(f, "string")?;
         writeln!(f)?;
         Ok(())
     }
 }
 
-generate_config_ext!(StringConfig);
+generate_config_ext!(StringConfig, required);
 
 /// A field for entering text value, but with its value masked in the user
 /// interface. The value of a password field is not saved by Wireshark.
 ///
+/// Use [`Secret<String>`][crate::Secret] as the type of the corresponding
+/// command line argument, so that the password is not revealed when the
+/// arguments are logged.
+///
 /// Typically, these configs are created in a `lazy_static`, and passed to
 /// [`ConfigStep::list_configs`][crate::ConfigStep::list_configs].
 ///
@@ -770,6 +1070,7 @@ generate_config_ext!(StringConfig);
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct PasswordConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -805,31 +1106,69 @@ pub struct PasswordConfig {
 
 impl PrintSentence for PasswordConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         if let Some(placeholder) = &self.placeholder {
-            write!(f, "{{placeholder={}}}", placeholder)?;
+            AttributeKey::Placeholder.write(f, placeholder)?;
         }
         if self.required {
-            write!(f, "{{required=true}}")?;
+            AttributeKey::Required.write(f, "true")?;
         }
         if let Some(validation) = &self.validation {
-            write!(f, "{{validation={}}}", validation)?;
+            AttributeKey::Validation.write(f, validation)?;
         }
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
         }
-        write!(f, "{{type=password}}")?;
+        AttributeKey::Type.write(f, "password")?;
         writeln!(f)?;
         Ok(())
     }
 }
 
-generate_config_ext!(PasswordConfig);
+generate_config_ext!(PasswordConfig, required);
+
+#[cfg(feature = "keyring")]
+impl PasswordConfig {
+    /// The entry in the OS credential store used by
+    /// [`resolve`][Self::resolve], named after the current executable and
+    /// [`call`][Self::call].
+    pub fn keyring_entry(&self) -> crate::keyring::KeyringEntry {
+        crate::keyring::KeyringEntry::for_current_exe(&self.call)
+    }
+
+    /// Resolves the password given on the command line for this config.
+    ///
+    /// Wireshark never saves the value of a password config (it behaves as if
+    /// `{save=false}` is always set), so the user has to enter it again for
+    /// every capture. If `arg_value` is a non-empty password, it is stored in
+    /// the [OS credential store][crate::keyring] and returned. If the user
+    /// left the field empty, the previously stored password is returned
+    /// instead. For this to work, [`required`][Self::required] should be
+    /// `false`.
+    ///
+    /// Returns [`KeyringError::NotFound`][crate::keyring::KeyringError::NotFound]
+    /// if the field is empty and no password has been stored.
+    pub fn resolve(
+        &self,
+        arg_value: Option<&str>,
+    ) -> Result<crate::Secret<String>, crate::keyring::KeyringError> {
+        let entry = self.keyring_entry();
+        match arg_value {
+            Some(password) if !password.is_empty() => {
+                let password = crate::Secret::new(password.to_owned());
+                entry.set(&password)?;
+                Ok(password)
+            }
+            _ => entry.get(),
+        }
+    }
+}
 
 /// A config that is displayed as a date/time editor.
 ///
@@ -853,6 +1192,7 @@ generate_config_ext!(PasswordConfig);
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct TimestampConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -872,20 +1212,30 @@ pub struct TimestampConfig {
     /// is `None`, the config will be placed in a tab called "Default".
     #[builder(default, setter(strip_option, into))]
     pub group: Option<String>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for TimestampConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        AttributeKey::Type.write(f, "timestamp")?;
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
-        write!(f, "{{type=timestamp}}")?;
         writeln!(f)?;
         Ok(())
     }
@@ -915,6 +1265,7 @@ generate_config_ext!(TimestampConfig);
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct FileSelectConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -930,6 +1281,9 @@ pub struct FileSelectConfig {
     /// The tooltip shown on when hovering over the UI element.
     #[builder(default, setter(strip_option, into))]
     pub tooltip: Option<String>,
+    /// Whether a value is required for this config.
+    #[builder(default = false)]
+    pub required: bool,
     /// The (user-visible) name of the tab which this config belongs to. If this
     /// is `None`, the config will be placed in a tab called "Default".
     #[builder(default, setter(strip_option, into))]
@@ -952,30 +1306,118 @@ pub struct FileSelectConfig {
     /// <https://gitlab.com/wireshark/wireshark/-/commit/0d47113ddc53714ecd6d3c1b58b694321649d89e>
     #[builder(default, setter(into, strip_option))]
     pub file_extension_filter: Option<String>,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for FileSelectConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
+        }
+        if self.required {
+            AttributeKey::Required.write(f, "true")?;
         }
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
         }
-        write!(f, "{{type=fileselect}}")?;
-        write!(f, "{{mustexist={}}}", self.must_exist)?;
+        AttributeKey::Type.write(f, "fileselect")?;
+        AttributeKey::MustExist.write(f, self.must_exist)?;
         if let Some(file_extension_filter) = &self.file_extension_filter {
-            write!(f, "{{fileext={}}}", file_extension_filter)?;
+            AttributeKey::FileExt.write(f, file_extension_filter)?;
+        }
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
         writeln!(f)?;
         Ok(())
     }
 }
 
-generate_config_ext!(FileSelectConfig);
+generate_config_ext!(FileSelectConfig, required);
+
+impl FileSelectConfig {
+    /// Parses the value Wireshark passes for a file select config into a
+    /// path, normalizing it the same way on every platform:
+    ///
+    /// * A pair of matching double or single quotes around the whole value,
+    ///   added by some shells and Wireshark versions on Windows, is removed.
+    /// * On Windows, the `/` separators returned by the Qt file dialog are
+    ///   converted to `\`, and repeated separators, as produced when the path
+    ///   has been escaped, are collapsed. A leading `\\` for UNC paths is
+    ///   kept.
+    ///
+    /// On other platforms, backslashes are valid file name characters and are
+    /// kept as-is.
+    ///
+    /// ```
+    /// use r_extcap::config::FileSelectConfig;
+    /// use std::path::Path;
+    ///
+    /// assert_eq!(
+    ///     FileSelectConfig::parse_value("\"/tmp/capture log.txt\""),
+    ///     Path::new("/tmp/capture log.txt")
+    /// );
+    /// ```
+    pub fn parse_value(value: &str) -> PathBuf {
+        normalize_path(value, cfg!(windows))
+    }
+}
+
+/// A clap value parser for the argument of a [`FileSelectConfig`], which
+/// parses it using [`FileSelectConfig::parse_value`].
+///
+/// ```
+/// use clap::Parser;
+/// use std::path::PathBuf;
+///
+/// #[derive(Parser)]
+/// struct AppArgs {
+///     #[arg(long, value_parser = r_extcap::config::file_select_value_parser)]
+///     logfile: Option<PathBuf>,
+/// }
+///
+/// let args = AppArgs::parse_from(["extcap", "--logfile", "'/tmp/log.txt'"]);
+/// assert_eq!(args.logfile, Some(PathBuf::from("/tmp/log.txt")));
+/// ```
+pub fn file_select_value_parser(value: &str) -> Result<PathBuf, std::convert::Infallible> {
+    Ok(FileSelectConfig::parse_value(value))
+}
+
+fn normalize_path(value: &str, windows: bool) -> PathBuf {
+    let value = ['"', '\'']
+        .iter()
+        .find_map(|quote| {
+            value
+                .strip_prefix(*quote)
+                .and_then(|value| value.strip_suffix(*quote))
+        })
+        .unwrap_or(value);
+    if !windows {
+        return PathBuf::from(value);
+    }
+    let value = value.replace('/', "\\");
+    let (prefix, rest) = match value.strip_prefix("\\\\") {
+        Some(rest) => ("\\\\", rest),
+        None => ("", &value[..]),
+    };
+    let mut normalized = String::with_capacity(value.len());
+    normalized.push_str(prefix);
+    for c in rest.chars() {
+        if !(c == '\\' && normalized.ends_with('\\') && normalized.len() > prefix.len()) {
+            normalized.push(c);
+        }
+    }
+    PathBuf::from(normalized)
+}
 
 /// A checkbox configuration with a true/false value.
 ///
@@ -998,6 +1440,7 @@ generate_config_ext!(FileSelectConfig);
 /// );
 /// ```
 #[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
 pub struct BooleanConfig {
     /// The config number, a unique identifier for this config.
     pub config_number: u8,
@@ -1026,26 +1469,36 @@ pub struct BooleanConfig {
     /// the command line arguments if unchecked.
     #[builder(default = false)]
     pub always_include_option: bool,
+    /// Whether Wireshark saves the value of this config, and populates it
+    /// automatically the next time the interface is selected. Set this to
+    /// `false` for sensitive or volatile values. See [`StringConfig::save`]
+    /// for known issues.
+    #[builder(default = true)]
+    pub save: bool,
 }
 
 impl PrintSentence for BooleanConfig {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "arg {{number={}}}", self.config_number)?;
-        write!(f, "{{call=--{}}}", self.call)?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         if self.default_value {
-            write!(f, "{{default=true}}")?;
+            AttributeKey::Default.write(f, "true")?;
         }
         if self.always_include_option {
-            write!(f, "{{type=boolean}}")?;
+            AttributeKey::Type.write(f, "boolean")?;
         } else {
-            write!(f, "{{type=boolflag}}")?;
+            AttributeKey::Type.write(f, "boolflag")?;
         }
         if let Some(group) = &self.group {
-            write!(f, "{{group={group}}}")?;
+            AttributeKey::Group.write(f, group)?;
+        }
+        if !self.save {
+            AttributeKey::Save.write(f, "false")?;
         }
         writeln!(f)?;
         Ok(())
@@ -1054,17 +1507,111 @@ impl PrintSentence for BooleanConfig {
 
 generate_config_ext!(BooleanConfig);
 
+/// A fixed value passed to the capture through the config plumbing, like a
+/// protocol version or an internal mode that the user should not normally
+/// change.
+///
+/// Wireshark has no hidden config type, and drops configs of unknown types
+/// along with their values, so this is listed as a text field with `value` as
+/// its default. Wireshark passes the default of a config to the capture when
+/// the user did not change it, so the capture receives `--call value`. The
+/// value is never saved (`{save=false}`), so an edit made in the
+/// configuration dialog only lasts until the interface is configured again,
+/// and the capture gets the constant after a change of version instead of a
+/// stale saved value. Put the config in a separate [`group`][Self::group] to
+/// keep it out of the way.
+///
+/// Typically, these configs are created in a `lazy_static`, and passed to
+/// [`ConfigStep::list_configs`][crate::ConfigStep::list_configs].
+///
+/// ## Example
+/// ```
+/// use r_extcap::config::*;
+///
+/// let config = ConstantConfig::builder()
+///     .config_number(5)
+///     .call("protocol-version")
+///     .display("Protocol version")
+///     .value("3")
+///     .group("Advanced")
+///     .build();
+/// assert_eq!(
+///     format!("{}", ExtcapFormatter(&config)),
+///     "arg {number=5}{call=--protocol-version}{display=Protocol version}{default=3}{group=Advanced}{save=false}{type=string}\n"
+/// );
+/// ```
+#[derive(Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
+pub struct ConstantConfig {
+    /// The config number, a unique identifier for this config.
+    pub config_number: u8,
+    /// The command line option that will be sent to this extcap program. For
+    /// example, if this field is `foobar`, and the corresponding value is `42`,
+    /// then `--foobar 42` will be sent to this program during the extcap
+    /// capture.
+    #[builder(setter(into))]
+    pub call: String,
+    /// The user-friendly label for the text field.
+    #[builder(setter(into))]
+    pub display: String,
+    /// The tooltip shown on when hovering over the UI element.
+    #[builder(default, setter(strip_option, into))]
+    pub tooltip: Option<String>,
+    /// The value passed to the capture.
+    #[builder(setter(into))]
+    pub value: String,
+    /// The (user-visible) name of the tab which this config belongs to. If this
+    /// is `None`, the config will be placed in a tab called "Default".
+    #[builder(default, setter(strip_option, into))]
+    pub group: Option<String>,
+}
+
+impl PrintSentence for ConstantConfig {
+    fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("arg ")?;
+        AttributeKey::Number.write(f, self.config_number)?;
+        AttributeKey::Call.write(f, format_args!("--{}", self.call))?;
+        AttributeKey::Display.write(f, &self.display)?;
+        if let Some(tooltip) = &self.tooltip {
+            AttributeKey::Tooltip.write(f, tooltip)?;
+        }
+        AttributeKey::Default.write(f, &self.value)?;
+        if let Some(group) = &self.group {
+            AttributeKey::Group.write(f, group)?;
+        }
+        AttributeKey::Save.write(f, "false")?;
+        AttributeKey::Type.write(f, "string")?;
+        writeln!(f)?;
+        Ok(())
+    }
+}
+
+generate_config_ext!(ConstantConfig);
+
 /// An option for [`SelectorConfig`] and [`RadioConfig`].
 #[derive(Clone, Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConfigOptionValue {
     /// The value of this option. If this option is selected, the value will be
     /// passed to the command line. For example, if [`SelectorConfig.call`] is
     /// `foo`, and this field is `bar`, then `--foo bar` will be passed to this
     /// extcap program.
-    #[builder(setter(into))]
+    ///
+    /// Panics: If the value contains a line break.
+    #[builder(setter(transform = |value: impl Into<String>| checked(value.into(), validate_sentence_value)))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "deserialize_sentence_value")
+    )]
     value: String,
     /// The user-friendly label for this option.
-    #[builder(setter(into))]
+    ///
+    /// Panics: If the label contains a line break.
+    #[builder(setter(transform = |display: impl Into<String>| checked(display.into(), validate_sentence_value)))]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "deserialize_sentence_value")
+    )]
     display: String,
     /// Whether this option is selected as the default. For each config there
     /// should only be one selected default.
@@ -1073,33 +1620,928 @@ pub struct ConfigOptionValue {
 }
 
 impl ConfigOptionValue {
+    /// Converts each item of `options` into a `ConfigOptionValue`, for
+    /// building lists of options that are only known at runtime.
+    ///
+    /// Panics: If a value or label contains a line break.
+    ///
+    /// ```
+    /// use r_extcap::config::{ConfigOptionValue, ExtcapFormatter};
+    ///
+    /// let options = ConfigOptionValue::list([("udp", "UDP", true), ("tcp", "TCP", false)]);
+    /// assert_eq!(
+    ///     ExtcapFormatter(&(&options[0], 3)).to_string(),
+    ///     "value {arg=3}{value=udp}{display=UDP}{default=true}\n"
+    /// );
+    /// ```
+    pub fn list<O: Into<ConfigOptionValue>>(options: impl IntoIterator<Item = O>) -> Vec<Self> {
+        options.into_iter().map(Into::into).collect()
+    }
+
     /// Prints out the extcap sentence to stdout for Wireshark's consumption.
     pub fn print_sentence(&self, number: u8) {
         (self, number).print_sentence()
     }
 }
 
+/// Creates an option that is not the default from a `(value, display)` pair.
+///
+/// Panics: If the value or label contains a line break.
+impl<V: Into<String>, D: Into<String>> From<(V, D)> for ConfigOptionValue {
+    fn from((value, display): (V, D)) -> Self {
+        Self::builder().value(value).display(display).build()
+    }
+}
+
+/// Creates an option from a `(value, display, default)` tuple.
+///
+/// Panics: If the value or label contains a line break.
+impl<V: Into<String>, D: Into<String>> From<(V, D, bool)> for ConfigOptionValue {
+    fn from((value, display, default): (V, D, bool)) -> Self {
+        Self::builder()
+            .value(value)
+            .display(display)
+            .default(default)
+            .build()
+    }
+}
+
+/// Creates an option that is not the default from an interface, using
+/// [`Interface::value`][crate::interface::Interface::value] as the value and
+/// [`Interface::display`][crate::interface::Interface::display] as the
+/// label. See [`Reload`] for an example.
+///
+/// Panics: If the value or label contains a line break.
+impl From<&crate::interface::Interface> for ConfigOptionValue {
+    fn from(interface: &crate::interface::Interface) -> Self {
+        (&*interface.value, &*interface.display).into()
+    }
+}
+
 impl PrintSentence for (&ConfigOptionValue, u8) {
     fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let (config, arg_number) = self;
-        write!(f, "value {{arg={}}}", arg_number)?;
-        write!(f, "{{value={}}}", config.value)?;
-        write!(f, "{{display={}}}", config.display)?;
-        write!(f, "{{default={}}}", config.default)?;
+        f.write_str("value ")?;
+        AttributeKey::Arg.write(f, arg_number)?;
+        AttributeKey::Value.write(f, EscapedValue(&config.value))?;
+        AttributeKey::Display.write(f, EscapedValue(&config.display))?;
+        AttributeKey::Default.write(f, config.default)?;
         writeln!(f)?;
         Ok(())
     }
 }
 
+fn check_default_in_range<T: PartialOrd + std::fmt::Display>(
+    range: &Option<RangeInclusive<T>>,
+    default_value: &T,
+) -> Result<(), DefaultOutOfRangeError> {
+    match range {
+        Some(range) if !range.contains(default_value) => Err(DefaultOutOfRangeError {
+            default_value: default_value.to_string(),
+            start: range.start().to_string(),
+            end: range.end().to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Error returned by the `validate` method of the numeric configs, like
+/// [`IntegerConfig::validate`], when the default value is outside of the
+/// range. The values are formatted as they appear in the extcap sentence.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Default value {default_value} is outside of the range {start}..={end}")]
+pub struct DefaultOutOfRangeError {
+    /// The default value of the config.
+    pub default_value: String,
+    /// The start of the range.
+    pub start: String,
+    /// The end of the range.
+    pub end: String,
+}
+
+/// Error for an option value or label that cannot be used in an extcap
+/// sentence. Braces and backslashes are escaped automatically using
+/// [`EscapedValue`], but the characters here cannot be escaped.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvalidValueError {
+    /// Extcap sentences are separated by line breaks, so values cannot
+    /// contain them.
+    #[error("{0:?} contains a line break, which is not allowed in an extcap sentence")]
+    LineBreak(String),
+    /// Wireshark passes the checked values of a [`MultiCheckConfig`] to the
+    /// extcap as a single comma-separated argument, so the values cannot
+    /// contain commas.
+    #[error("MultiCheckValue value {0:?} contains a comma, which is used to separate the checked values")]
+    Comma(String),
+}
+
+fn validate_sentence_value(value: &str) -> Result<(), InvalidValueError> {
+    if value.contains(['\n', '\r']) {
+        return Err(InvalidValueError::LineBreak(value.to_owned()));
+    }
+    Ok(())
+}
+
+fn validate_multicheck_value(value: &str) -> Result<(), InvalidValueError> {
+    validate_sentence_value(value)?;
+    if value.contains(',') {
+        return Err(InvalidValueError::Comma(value.to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_sentence_value<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+    validate_sentence_value(&value).map_err(serde::de::Error::custom)?;
+    Ok(value)
+}
+
+#[cfg(feature = "serde")]
+fn deserialize_multicheck_value<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let value = <String as serde::Deserialize>::deserialize(deserializer)?;
+    validate_multicheck_value(&value).map_err(serde::de::Error::custom)?;
+    Ok(value)
+}
+
+/// Validates a value given to a builder, panicking with the error message if
+/// the value is invalid.
+fn checked(value: String, validate: fn(&str) -> Result<(), InvalidValueError>) -> String {
+    if let Err(e) = validate(&value) {
+        panic!("{e}");
+    }
+    value
+}
+
 /// Represents a config, also known as `arg` in an extcap sentence`, which is a
 /// UI element shown in Wireshark that allows the user to customize the capture.
 pub trait ConfigTrait: PrintSentence + Any {
+    /// The config number, a unique identifier for this config.
+    ///
+    /// The default implementation reads the `number` attribute from the
+    /// sentence of this config, or returns 0 if there is none. The configs in
+    /// this crate return their [`config_number`][StringConfig::config_number]
+    /// field directly.
+    fn config_number(&self) -> u8 {
+        let sentence = ExtcapFormatter(self).to_string();
+        sentence
+            .split_once("{number=")
+            .and_then(|(_, rest)| rest.split_once('}'))
+            .and_then(|(number, _)| number.parse().ok())
+            .unwrap_or(0)
+    }
+
     /// The command line option that will be sent to this extcap program. For
     /// example, if this field is `foobar`, and the corresponding value is `42`,
     /// then `--foobar 42` will be sent to this program during the extcap
     /// capture.
     fn call(&self) -> &str;
 
+    /// The user-friendly label for this config. The default implementation
+    /// returns the [`call`][Self::call].
+    fn display(&self) -> &str {
+        self.call()
+    }
+
+    /// The tab this config is placed on in the extcap configuration window,
+    /// or `None` if it is placed on the default tab. The default
+    /// implementation returns `None`.
+    fn group(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether Wireshark requires a value for this config before starting the
+    /// capture. See [`check_required_args`].
+    fn required(&self) -> bool {
+        false
+    }
+
     /// Returns this trait as an `Any` type.
     fn as_any(&self) -> &dyn Any;
+
+    /// Serializes this config into JSON, for tools that inspect the declared
+    /// model. See [`model`][crate::model].
+    ///
+    /// The configs in this crate serialize all of their fields. The default
+    /// implementation only includes the fields available on this trait.
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "config_number": self.config_number(),
+            "call": self.call(),
+            "display": self.display(),
+            "group": self.group(),
+        })
+    }
+}
+
+/// A dependency between configs, where a config is required only when another
+/// config is set, or set to a particular value. For example, a `port` config
+/// that is required when `mode` is `tcp`.
+///
+/// Wireshark cannot show or hide configs depending on the values of other
+/// configs, so it cannot enforce this when the user starts the capture.
+/// Instead, the dependencies are checked in the capture step using
+/// [`check_args`][Self::check_args]. If the check fails, returning the error
+/// from `main` prints it to stderr, which Wireshark shows to the user in an
+/// error dialog. Declaring the dependencies next to the configs keeps them in
+/// sync.
+///
+/// ```
+/// use r_extcap::config::*;
+///
+/// r_extcap::configs! {
+///     pub fn configs;
+///
+///     pub CONFIG_MODE: SelectorConfig {
+///         call: "mode",
+///         display: "Mode",
+///         default_options: [
+///             ConfigOptionValue::builder().value("udp").display("UDP").default(true).build(),
+///             ConfigOptionValue::builder().value("tcp").display("TCP").build(),
+///         ],
+///     },
+///     pub CONFIG_PORT: UnsignedConfig {
+///         call: "port",
+///         display: "Port",
+///         range: 1..=65535,
+///         default_value: 8080,
+///     },
+/// }
+///
+/// fn dependencies() -> Vec<ConfigDependency> {
+///     vec![ConfigDependency::when_equals(&*CONFIG_MODE, "tcp").requires(&*CONFIG_PORT)]
+/// }
+///
+/// let args = ["extcap", "--capture", "--mode", "tcp"];
+/// let error = ConfigDependency::check_args(&dependencies(), args).unwrap_err();
+/// assert_eq!(error.to_string(), "\"Port\" is required when \"Mode\" is \"tcp\"");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigDependency {
+    config: ConfigName,
+    condition: DependencyCondition,
+    required: Vec<ConfigName>,
+}
+
+/// The condition on the value of a config for a [`ConfigDependency`] to apply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DependencyCondition {
+    /// The config is given on the command line, and its value is not `false`.
+    Set,
+    /// The config is given on the command line with the given value.
+    Equals(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ConfigName {
+    call: String,
+    display: String,
+}
+
+impl ConfigName {
+    fn of(config: &dyn ConfigTrait) -> Self {
+        Self {
+            call: config.call().to_owned(),
+            display: config.display().to_owned(),
+        }
+    }
+}
+
+impl ConfigDependency {
+    /// Creates a dependency that applies when `config` is set. See
+    /// [`DependencyCondition::Set`].
+    pub fn when_set(config: &dyn ConfigTrait) -> Self {
+        Self::when(config, DependencyCondition::Set)
+    }
+
+    /// Creates a dependency that applies when `config` is set to `value`.
+    pub fn when_equals(config: &dyn ConfigTrait, value: impl Into<String>) -> Self {
+        Self::when(config, DependencyCondition::Equals(value.into()))
+    }
+
+    /// Creates a dependency that applies when `config` matches `condition`.
+    pub fn when(config: &dyn ConfigTrait, condition: DependencyCondition) -> Self {
+        Self {
+            config: ConfigName::of(config),
+            condition,
+            required: Vec::new(),
+        }
+    }
+
+    /// Adds `config` to the configs required when this dependency applies.
+    #[must_use]
+    pub fn requires(mut self, config: &dyn ConfigTrait) -> Self {
+        self.required.push(ConfigName::of(config));
+        self
+    }
+
+    /// Checks this dependency against the command line arguments of the
+    /// capture, returning an error for the first required config that is
+    /// missing. `args` is typically [`std::env::args`].
+    pub fn check(&self, args: &[impl AsRef<str>]) -> Result<(), ConfigDependencyError> {
+        let value = arg_value(args, &self.config.call);
+        let applies = match (&self.condition, value) {
+            (DependencyCondition::Set, Some(value)) => value != "false",
+            (DependencyCondition::Equals(expected), Some(value)) => value == expected,
+            (_, None) => false,
+        };
+        if !applies {
+            return Ok(());
+        }
+        match self
+            .required
+            .iter()
+            .find(|required| arg_value(args, &required.call).is_none())
+        {
+            Some(missing) => Err(ConfigDependencyError {
+                missing: missing.display.clone(),
+                config: self.config.display.clone(),
+                condition: self.condition.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks all of the `dependencies` against the command line arguments.
+    /// See [`check`][Self::check].
+    pub fn check_args(
+        dependencies: &[ConfigDependency],
+        args: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Result<(), ConfigDependencyError> {
+        let args: Vec<_> = args.into_iter().collect();
+        dependencies.iter().try_for_each(|d| d.check(&args))
+    }
+}
+
+/// Returns the value given for `--{call}` in `args`, either as `--call value`
+/// or `--call=value`. Flags given without a value, like boolean configs, have
+/// an empty value.
+fn arg_value<'a>(args: &'a [impl AsRef<str>], call: &str) -> Option<&'a str> {
+    let mut iter = args.iter().map(AsRef::as_ref).peekable();
+    while let Some(arg) = iter.next() {
+        let Some(rest) = arg.strip_prefix("--").and_then(|a| a.strip_prefix(call)) else {
+            continue;
+        };
+        if let Some(value) = rest.strip_prefix('=') {
+            return Some(value);
+        }
+        if rest.is_empty() {
+            return Some(iter.next_if(|next| !next.starts_with("--")).unwrap_or(""));
+        }
+    }
+    None
+}
+
+/// Checks that the command line arguments of the capture contain a non-empty
+/// value for each of the [`required`][ConfigTrait::required] `configs`. `args`
+/// is typically [`std::env::args`].
+///
+/// Wireshark does not start the capture when a required config is empty, but
+/// the extcap can also be run from tshark or the command line, where a
+/// missing config would otherwise surface as a generic argument parsing
+/// error, or not at all. Returning the error from `main` prints it to stderr,
+/// which Wireshark shows to the user in an error dialog.
+///
+/// ```
+/// use r_extcap::config::*;
+///
+/// let message = StringConfig::builder()
+///     .config_number(0)
+///     .call("message")
+///     .display("Message")
+///     .required(true)
+///     .build();
+/// let configs: [&dyn ConfigTrait; 1] = [&message];
+/// assert!(check_required_args(&configs, ["--message", "hello"]).is_ok());
+/// assert_eq!(
+///     check_required_args(&configs, ["--capture"]).unwrap_err().to_string(),
+///     "Missing required config \"Message\"",
+/// );
+/// ```
+pub fn check_required_args(
+    configs: &[&dyn ConfigTrait],
+    args: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<(), MissingConfigsError> {
+    let args: Vec<_> = args.into_iter().collect();
+    let missing: Vec<String> = configs
+        .iter()
+        .filter(|c| c.required() && arg_value(&args, c.call()).map_or(true, str::is_empty))
+        .map(|c| c.display().to_owned())
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(MissingConfigsError(missing))
+    }
+}
+
+/// Returns the sentences declaring `configs`, as printed in the
+/// `--extcap-config` step, including the `value` sentences of the options.
+/// The output only depends on the configs, so it can be compared with a
+/// golden file in tests, to catch accidental changes to the configuration
+/// dialog like renumbered configs.
+///
+/// ```
+/// use r_extcap::config::*;
+///
+/// let message = StringConfig::builder()
+///     .config_number(0)
+///     .call("message")
+///     .display("Message")
+///     .build();
+/// assert_eq!(
+///     to_sentences(&[&message]),
+///     "arg {number=0}{call=--message}{display=Message}{type=string}\n",
+/// );
+/// ```
+pub fn to_sentences(configs: &[&dyn ConfigTrait]) -> String {
+    configs
+        .iter()
+        .map(|config| ExtcapFormatter(*config).to_string())
+        .collect()
+}
+
+/// Error returned by [`check_required_args`], containing the display names of
+/// the required configs that are missing.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error(
+    "Missing required config{} {}",
+    if .0.len() == 1 { "" } else { "s" },
+    .0.iter().map(|c| format!("{c:?}")).collect::<Vec<_>>().join(", ")
+)]
+pub struct MissingConfigsError(pub Vec<String>);
+
+/// Error returned by [`ConfigDependency::check`] when a required config is
+/// missing.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("{missing:?} is required when {config:?} {}", condition_description(.condition))]
+pub struct ConfigDependencyError {
+    /// The display name of the missing config.
+    pub missing: String,
+    /// The display name of the config that the missing config depends on.
+    pub config: String,
+    /// The condition of the dependency.
+    pub condition: DependencyCondition,
+}
+
+fn condition_description(condition: &DependencyCondition) -> String {
+    match condition {
+        DependencyCondition::Set => String::from("is set"),
+        DependencyCondition::Equals(value) => format!("is {value:?}"),
+    }
+}
+
+/// Declares a list of configs, assigning their config numbers sequentially in
+/// declaration order starting from 0.
+///
+/// This expands to a static for each config, initialized lazily using the
+/// config's builder, and a function that returns all of the configs in
+/// declaration order, suitable for passing to
+/// [`ConfigStep::list_configs`][crate::ConfigStep::list_configs]. Each config
+/// is written as a list of `setter: value` pairs for its builder, starting with
+/// `call`.
+///
+/// Optionally, a struct declaration can be given after the function. The
+/// struct derives [`clap::Args`] and contains a field for each config followed
+/// by `=> field: Type`, parsed from the command line option given in `call`.
+/// It can be flattened into the app's arguments with `#[command(flatten)]`.
+/// The struct is declared in a module named `__configs_args`, so there can be
+/// only one such struct in each module.
+///
+/// ```
+/// use r_extcap::config::*;
+///
+/// r_extcap::configs! {
+///     /// Returns the configs of this extcap.
+///     pub fn configs;
+///
+///     /// The values of the configs passed on the command line.
+///     #[derive(Debug)]
+///     pub struct ConfigValues;
+///
+///     pub CONFIG_DELAY: IntegerConfig {
+///         call: "delay",
+///         display: "Time delay",
+///         range: 1..=15,
+///         default_value: 5,
+///     } => #[arg(default_value_t = 5)] delay: i32,
+///     pub CONFIG_VERBOSE: BooleanConfig {
+///         call: "verbose",
+///         display: "Verbose",
+///     },
+/// }
+///
+/// assert_eq!(CONFIG_DELAY.config_number, 0);
+/// assert_eq!(CONFIG_VERBOSE.config_number, 1);
+/// assert_eq!(configs().len(), 2);
+///
+/// use r_extcap::clap::{Args, FromArgMatches};
+/// let command = ConfigValues::augment_args(r_extcap::clap::Command::new("extcap"));
+/// let matches = command.get_matches_from(["extcap", "--delay", "3"]);
+/// assert_eq!(ConfigValues::from_arg_matches(&matches).unwrap().delay, 3);
+/// ```
+#[macro_export]
+macro_rules! configs {
+    (@statics [$($n:tt)*]) => {};
+    (
+        @statics [$($n:tt)*]
+        [$(#[$meta:meta])*] $vis:vis $name:ident $ty:ident [$call:literal]
+        [$($setter:ident : $value:expr),*]
+        $($rest:tt)*
+    ) => {
+        $(#[$meta])*
+        #[allow(clippy::identity_op)]
+        $vis static $name: ::std::sync::LazyLock<$ty> = ::std::sync::LazyLock::new(|| {
+            $ty::builder()
+                .config_number($($n)*)
+                .call($call)
+                $(.$setter($value))*
+                .build()
+        });
+        $crate::configs!(@statics [$($n)* + 1] $($rest)*);
+    };
+    (
+        $(#[$fn_meta:meta])*
+        $fn_vis:vis fn $fn_name:ident;
+
+        $(#[$struct_meta:meta])*
+        $struct_vis:vis struct $struct_name:ident;
+
+        $(
+            $(#[$meta:meta])*
+            $vis:vis $name:ident : $ty:ident {
+                call: $call:literal
+                $(, $setter:ident : $value:expr)* $(,)?
+            }
+            $(=> $(#[$field_meta:meta])* $field:ident : $field_ty:ty)?
+        ),* $(,)?
+    ) => {
+        $crate::configs! {
+            $(#[$fn_meta])*
+            $fn_vis fn $fn_name;
+            $(
+                $(#[$meta])*
+                $vis $name : $ty { call: $call $(, $setter : $value)* }
+            ),*
+        }
+
+        // The code generated by `derive(Args)` refers to `clap` by name, so
+        // it is expanded in a module that imports the re-exported `clap`.
+        #[doc(hidden)]
+        mod __configs_args {
+            #[allow(unused_imports)]
+            use super::*;
+            use $crate::clap;
+
+            $(#[$struct_meta])*
+            #[derive(clap::Args)]
+            pub struct $struct_name {
+                $($(
+                    $(#[$field_meta])*
+                    #[arg(long = $call)]
+                    pub $field: $field_ty,
+                )?)*
+            }
+        }
+        $struct_vis use __configs_args::$struct_name;
+    };
+    (
+        $(#[$fn_meta:meta])*
+        $fn_vis:vis fn $fn_name:ident;
+
+        $(
+            $(#[$meta:meta])*
+            $vis:vis $name:ident : $ty:ident {
+                call: $call:literal
+                $(, $setter:ident : $value:expr)* $(,)?
+            }
+        ),* $(,)?
+    ) => {
+        $crate::configs!(
+            @statics [0]
+            $([$(#[$meta])*] $vis $name $ty [$call] [$($setter : $value),*])*
+        );
+
+        $(#[$fn_meta])*
+        $fn_vis fn $fn_name() -> Vec<&'static dyn $crate::config::ConfigTrait> {
+            vec![$(&*$name),*]
+        }
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        BooleanConfig, ConfigDependency, ConfigOptionValue, ConfigTrait, DoubleConfig,
+        ExtcapFormatter, FileSelectConfig, IntegerConfig, InvalidValueError, MissingConfigsError,
+        MultiCheckValue, PasswordConfig, PrintSentence, SelectorConfig, StringConfig,
+        UnsignedConfig,
+    };
+
+    #[test]
+    fn config_trait_metadata() {
+        let config: Box<dyn ConfigTrait> = Box::new(
+            BooleanConfig::builder()
+                .config_number(4)
+                .call("verify")
+                .display("Verify")
+                .group("Advanced")
+                .build(),
+        );
+        assert_eq!(config.config_number(), 4);
+        assert_eq!(config.call(), "verify");
+        assert_eq!(config.display(), "Verify");
+        assert_eq!(config.group(), Some("Advanced"));
+    }
+
+    #[test]
+    fn config_trait_defaults() {
+        struct CustomConfig;
+
+        impl PrintSentence for CustomConfig {
+            fn format_sentence(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                writeln!(
+                    f,
+                    "arg {{number=7}}{{call=--custom}}{{display=Custom}}{{type=string}}"
+                )
+            }
+        }
+
+        impl ConfigTrait for CustomConfig {
+            fn call(&self) -> &str {
+                "custom"
+            }
+
+            fn as_any(&self) -> &dyn std::any::Any {
+                self
+            }
+        }
+
+        let config: &dyn ConfigTrait = &CustomConfig;
+        assert_eq!(config.config_number(), 7);
+        assert_eq!(config.display(), "custom");
+        assert_eq!(config.group(), None);
+    }
+
+    #[test]
+    fn default_outside_range() {
+        let mut config = IntegerConfig::builder()
+            .config_number(0)
+            .call("delay")
+            .display("Delay")
+            .range(1..=15)
+            .default_value(0)
+            .build();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Default value 0 is outside of the range 1..=15"
+        );
+        config.default_value = 15;
+        assert_eq!(config.validate(), Ok(()));
+        config.range = None;
+        config.default_value = -1;
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn save_false() {
+        let config = BooleanConfig::builder()
+            .config_number(0)
+            .call("verify")
+            .display("Verify")
+            .save(false)
+            .build();
+        assert_eq!(
+            ExtcapFormatter(&config).to_string(),
+            "arg {number=0}{call=--verify}{display=Verify}{type=boolflag}{save=false}\n"
+        );
+        let config = IntegerConfig::builder()
+            .config_number(1)
+            .call("delay")
+            .display("Delay")
+            .default_value(1)
+            .build();
+        assert!(!ExtcapFormatter(&config).to_string().contains("save"));
+    }
+
+    #[test]
+    fn options_from() {
+        let devices = vec![
+            (String::from("ttyUSB0"), String::from("USB serial 0")),
+            (String::from("ttyUSB1"), String::from("USB serial 1")),
+        ];
+        let config = SelectorConfig::builder()
+            .config_number(2)
+            .call("device")
+            .display("Device")
+            .options_from(devices)
+            .build();
+        assert_eq!(
+            ExtcapFormatter(&config).to_string(),
+            concat!(
+                "arg {number=2}{call=--device}{display=Device}{type=selector}\n",
+                "value {arg=2}{value=ttyUSB0}{display=USB serial 0}{default=false}\n",
+                "value {arg=2}{value=ttyUSB1}{display=USB serial 1}{default=false}\n",
+            )
+        );
+    }
+
+    #[test]
+    fn normalize_path() {
+        use std::path::Path;
+
+        assert_eq!(
+            super::normalize_path(r#""C:/Users/me/capture.log""#, true),
+            Path::new(r"C:\Users\me\capture.log")
+        );
+        assert_eq!(
+            super::normalize_path(r"C:\\Users\\me\\capture.log", true),
+            Path::new(r"C:\Users\me\capture.log")
+        );
+        assert_eq!(
+            super::normalize_path(r"'\\server\share\log.txt'", true),
+            Path::new(r"\\server\share\log.txt")
+        );
+        assert_eq!(
+            super::normalize_path(r"/tmp/a\b.txt", false),
+            Path::new(r"/tmp/a\b.txt")
+        );
+        assert_eq!(
+            super::normalize_path(r#""/tmp/unmatched'"#, false),
+            Path::new(r#""/tmp/unmatched'"#)
+        );
+    }
+
+    #[test]
+    fn option_value_escaped() {
+        let value = ConfigOptionValue::builder()
+            .value("{a}")
+            .display(r"C:\path")
+            .build();
+        assert_eq!(
+            ExtcapFormatter(&(&value, 3)).to_string(),
+            "value {arg=3}{value=\\{a\\}}{display=C:\\\\path}{default=false}\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_option_value() {
+        let value: ConfigOptionValue =
+            serde_json::from_str(r#"{"value":"if1","display":"Remote1","default":true}"#).unwrap();
+        assert_eq!(
+            ExtcapFormatter(&(&value, 3)).to_string(),
+            "value {arg=3}{value=if1}{display=Remote1}{default=true}\n"
+        );
+        let error = serde_json::from_str::<MultiCheckValue>(
+            r#"{"value":"a,b","display":"A","default_value":false,"enabled":true,"children":[]}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("contains a comma"));
+    }
+
+    #[test]
+    #[should_panic(expected = "contains a comma")]
+    fn multicheck_value_comma() {
+        MultiCheckValue::builder().value("a,b").display("A").build();
+    }
+
+    #[test]
+    fn config_dependency() {
+        let verbose = BooleanConfig::builder()
+            .config_number(0)
+            .call("verbose")
+            .display("Verbose")
+            .build();
+        let level = IntegerConfig::builder()
+            .config_number(1)
+            .call("level")
+            .display("Level")
+            .range(1..=3)
+            .default_value(1)
+            .build();
+        let dependencies = [ConfigDependency::when_set(&verbose).requires(&level)];
+        let check = |args: &[&str]| ConfigDependency::check_args(&dependencies, args);
+        assert!(check(&["--capture"]).is_ok());
+        assert!(check(&["--verbose", "false"]).is_ok());
+        assert!(check(&["--verbose", "--level", "2"]).is_ok());
+        assert!(check(&["--verbose=true", "--level=2"]).is_ok());
+        assert!(check(&["--verbose-output", "--capture"]).is_ok());
+        assert_eq!(
+            check(&["--verbose", "--capture"]).unwrap_err().to_string(),
+            r#""Level" is required when "Verbose" is set"#
+        );
+    }
+
+    #[test]
+    fn check_required_args() {
+        let message = StringConfig::builder()
+            .config_number(0)
+            .call("message")
+            .display("Message")
+            .required(true)
+            .build();
+        let password = PasswordConfig::builder()
+            .config_number(1)
+            .call("password")
+            .display("Password")
+            .required(true)
+            .build();
+        let verify = BooleanConfig::builder()
+            .config_number(2)
+            .call("verify")
+            .display("Verify")
+            .build();
+        let configs: [&dyn ConfigTrait; 3] = [&message, &password, &verify];
+        assert!(super::check_required_args(&configs, ["--message=a", "--password", "b"]).is_ok());
+        assert_eq!(
+            super::check_required_args(&configs, ["--message", "", "--verify"]),
+            Err(MissingConfigsError(vec![
+                String::from("Message"),
+                String::from("Password")
+            ]))
+        );
+    }
+
+    #[test]
+    fn placeholder_and_required() {
+        let port = UnsignedConfig::builder()
+            .config_number(0)
+            .call("port")
+            .display("Port")
+            .placeholder("8080")
+            .required(true)
+            .default_value(0)
+            .build();
+        assert_eq!(
+            ExtcapFormatter(&port).to_string(),
+            "arg {number=0}{call=--port}{display=Port}{placeholder=8080}{required=true}{default=0}{type=unsigned}\n"
+        );
+        assert!(port.required());
+        let ratio = DoubleConfig::builder()
+            .config_number(1)
+            .call("ratio")
+            .display("Ratio")
+            .placeholder("0.5")
+            .default_value(0.5)
+            .build();
+        assert_eq!(
+            ExtcapFormatter(&ratio).to_string(),
+            "arg {number=1}{call=--ratio}{display=Ratio}{placeholder=0.5}{default=0.5}{type=double}\n"
+        );
+        let file = FileSelectConfig::builder()
+            .config_number(2)
+            .call("file")
+            .display("File")
+            .required(true)
+            .build();
+        assert_eq!(
+            ExtcapFormatter(&file).to_string(),
+            "arg {number=2}{call=--file}{display=File}{required=true}{type=fileselect}{mustexist=true}\n"
+        );
+    }
+
+    #[test]
+    fn multicheck_validate() {
+        let mut value = MultiCheckValue::builder()
+            .value("a")
+            .display("A")
+            .children([MultiCheckValue::builder().value("b").display("B").build()])
+            .build();
+        assert_eq!(value.validate(), Ok(()));
+        value.children[0].display = "B\nC".into();
+        assert_eq!(
+            value.validate(),
+            Err(InvalidValueError::LineBreak("B\nC".into()))
+        );
+    }
+
+    #[test]
+    fn options_from_interfaces() {
+        use crate::interface::{Dlt, Interface};
+
+        let interfaces: Vec<Interface> = (0..2)
+            .map(|i| {
+                Interface::builder()
+                    .value(format!("/dev/ttyUSB{i}").into())
+                    .display(format!("Sniffer {i}").into())
+                    .dlt(Dlt::user(0, "Sniffer"))
+                    .build()
+            })
+            .collect();
+        let options = ConfigOptionValue::list(&interfaces);
+        assert_eq!(
+            options
+                .iter()
+                .map(|option| ExtcapFormatter(&(option, 1)).to_string())
+                .collect::<String>(),
+            "value {arg=1}{value=/dev/ttyUSB0}{display=Sniffer 0}{default=false}\n\
+             value {arg=1}{value=/dev/ttyUSB1}{display=Sniffer 1}{default=false}\n"
+        );
+    }
 }
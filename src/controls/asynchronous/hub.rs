@@ -0,0 +1,240 @@
+use std::{path::PathBuf, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    task::JoinHandle,
+};
+
+use super::{
+    ControlChannelError, ExtcapControlReader, ExtcapControlSender, ExtcapControlSenderTrait,
+};
+use crate::controls::{ControlHandshake, ControlPacket, HandshakeError};
+
+/// The number of inbound packets buffered for each subscriber.
+const INBOUND_CAPACITY: usize = 64;
+
+/// Owns both control pipes of a capture, so that multiple tasks can receive
+/// the control packets from Wireshark and send packets to it.
+///
+/// Unlike [`ChannelExtcapControlReader`][super::ChannelExtcapControlReader],
+/// which has a single consumer, each call to [`subscribe`][Self::subscribe]
+/// returns a receiver that gets its own copy of every inbound packet. Outbound
+/// packets sent through any [`ControlHubSender`] are queued and written by a
+/// single task, so that packets from different tasks are never interleaved.
+///
+/// The hub also records the `Initialized` handshake, which can be awaited
+/// with [`wait_for_init`][Self::wait_for_init] from any task.
+///
+/// ```no_run
+/// # use r_extcap::controls::asynchronous::{ControlHub, ExtcapControlSenderTrait as _};
+/// # use std::time::Duration;
+/// # async fn example(capture_step: &r_extcap::CaptureStep<'_>) -> anyhow::Result<()> {
+/// let hub = capture_step.spawn_control_hub_async().unwrap();
+/// let mut packets = hub.subscribe();
+/// let sender = hub.sender();
+/// tokio::spawn(async move {
+///     while let Ok(packet) = packets.recv().await {
+///         // Handle the control packet
+///     }
+/// });
+/// let handshake = hub.wait_for_init(Duration::from_secs(30)).await?;
+/// sender.status_message("Capturing").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ControlHub {
+    inbound: broadcast::Sender<ControlPacket<'static>>,
+    sender: ControlHubSender,
+    handshake: watch::Receiver<Option<ControlHandshake>>,
+    reader_handle: JoinHandle<Result<(), ControlChannelError>>,
+    writer_handle: JoinHandle<Result<(), tokio::io::Error>>,
+}
+
+impl ControlHub {
+    /// Spawns the tasks that read the control packets from `in_path`, given
+    /// in `--extcap-control-in`, and write the outbound packets to
+    /// `out_path`, given in `--extcap-control-out`.
+    ///
+    /// This must be called within a Tokio runtime.
+    pub fn spawn(in_path: PathBuf, out_path: PathBuf) -> Self {
+        let (inbound, _) = broadcast::channel(INBOUND_CAPACITY);
+        let (handshake_tx, handshake) = watch::channel(None);
+        let reader_inbound = inbound.clone();
+        let reader_handle = tokio::task::spawn(async move {
+            let mut reader = ExtcapControlReader::new(&in_path).await;
+            let mut handshake = Some(ControlHandshake::default());
+            loop {
+                let packet = reader.read_control_packet().await?;
+                if let Some(mut pending) = handshake.take() {
+                    if pending.record(&packet) {
+                        handshake_tx.send_replace(Some(pending));
+                    } else {
+                        handshake = Some(pending);
+                    }
+                }
+                // Sending only fails if there are no subscribers, in which
+                // case the packet is dropped.
+                let _ = reader_inbound.send(packet);
+            }
+        });
+        let (outbound, mut outbound_rx) = mpsc::channel::<ControlPacket<'static>>(10);
+        let writer_handle = tokio::task::spawn(async move {
+            let mut sender = ExtcapControlSender::new(&out_path).await;
+            while let Some(packet) = outbound_rx.recv().await {
+                // Write the packets queued in the meantime with the same write.
+                sender
+                    .batch(|batch| {
+                        batch.send(packet);
+                        while let Ok(packet) = outbound_rx.try_recv() {
+                            batch.send(packet);
+                        }
+                    })
+                    .await?;
+            }
+            Ok(())
+        });
+        Self {
+            inbound,
+            sender: ControlHubSender(outbound),
+            handshake,
+            reader_handle,
+            writer_handle,
+        }
+    }
+
+    /// Returns a receiver for the control packets sent by Wireshark from now
+    /// on, including the packets of the handshake if it is not complete yet.
+    ///
+    /// Up to 64 packets are buffered for each receiver. Receivers that fall
+    /// further behind skip the oldest packets, and get
+    /// [`RecvError::Lagged`][broadcast::error::RecvError::Lagged].
+    ///
+    /// To receive the packets as [`ControlEvent`][crate::controls::ControlEvent]s,
+    /// pass them to [`ControlEvent::resolve`][crate::controls::ControlEvent::resolve].
+    pub fn subscribe(&self) -> broadcast::Receiver<ControlPacket<'static>> {
+        self.inbound.subscribe()
+    }
+
+    /// Returns a handle for sending control packets to Wireshark, which can be
+    /// cloned and moved into other tasks.
+    pub fn sender(&self) -> ControlHubSender {
+        self.sender.clone()
+    }
+
+    /// Waits for the `Initialized` packet, returning the initial control
+    /// values sent before it. See [`ControlHandshake`].
+    ///
+    /// This can be called any number of times, from any task. Once the
+    /// handshake is complete, it returns immediately.
+    pub async fn wait_for_init(
+        &self,
+        timeout: Duration,
+    ) -> Result<ControlHandshake, HandshakeError> {
+        let mut handshake = self.handshake.clone();
+        tokio::time::timeout(timeout, async {
+            loop {
+                if let Some(handshake) = &*handshake.borrow_and_update() {
+                    return Ok(handshake.clone());
+                }
+                handshake
+                    .changed()
+                    .await
+                    .map_err(|_| HandshakeError::Disconnected)?;
+            }
+        })
+        .await
+        .map_err(|_| HandshakeError::Timeout(timeout))?
+    }
+
+    /// Whether the reader and writer tasks are still running. They stop when
+    /// Wireshark closes either control pipe.
+    pub fn is_running(&self) -> bool {
+        !self.reader_handle.is_finished() && !self.writer_handle.is_finished()
+    }
+}
+
+impl Drop for ControlHub {
+    fn drop(&mut self) {
+        // The writer task stops once all of the senders are dropped, after
+        // writing the queued packets.
+        self.reader_handle.abort();
+    }
+}
+
+#[async_trait]
+impl ExtcapControlSenderTrait for &ControlHub {
+    async fn send(self, packet: ControlPacket<'_>) -> Result<(), tokio::io::Error> {
+        self.sender.send(packet).await
+    }
+}
+
+/// A handle for sending control packets through a [`ControlHub`].
+#[derive(Clone, Debug)]
+pub struct ControlHubSender(pub(crate) mpsc::Sender<ControlPacket<'static>>);
+
+#[async_trait]
+impl ExtcapControlSenderTrait for &ControlHubSender {
+    /// Queues `packet` to be written to the control pipe. Returns a
+    /// `BrokenPipe` error if the pipe has been closed.
+    async fn send(self, packet: ControlPacket<'_>) -> Result<(), tokio::io::Error> {
+        self.0
+            .send(packet.into_owned())
+            .await
+            .map_err(|_| tokio::io::Error::from(tokio::io::ErrorKind::BrokenPipe))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::time::Duration;
+
+    use super::ControlHub;
+    use crate::controls::{
+        asynchronous::{ExtcapControlReader, ExtcapControlSender, ExtcapControlSenderTrait as _},
+        BooleanControl, ControlCommand, ControlPacket,
+    };
+    use crate::testing::TempFifo;
+
+    #[tokio::test]
+    async fn fan_out() {
+        let in_fifo = TempFifo::new("hub-in").unwrap();
+        let out_fifo = TempFifo::new("hub-out").unwrap();
+        let (in_path, out_path) = (in_fifo.path().to_owned(), out_fifo.path().to_owned());
+        let hub = ControlHub::spawn(in_path.clone(), out_path.clone());
+        let mut first = hub.subscribe();
+        let mut second = hub.subscribe();
+
+        // Plays the part of Wireshark.
+        let mut wireshark_out = ExtcapControlSender::new(&in_path).await;
+        let mut wireshark_in = ExtcapControlReader::new(&out_path).await;
+        let verify = BooleanControl::builder()
+            .control_number(1)
+            .display("Verify")
+            .build();
+        wireshark_out.send(verify.set_checked(true)).await.unwrap();
+        wireshark_out
+            .send(ControlPacket::new(0, ControlCommand::Initialized))
+            .await
+            .unwrap();
+
+        let handshake = hub.wait_for_init(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(handshake.initial_value(&verify), Some(true));
+        for receiver in [&mut first, &mut second] {
+            assert_eq!(receiver.recv().await.unwrap(), verify.set_checked(true));
+            assert_eq!(
+                receiver.recv().await.unwrap().command,
+                ControlCommand::Initialized
+            );
+        }
+
+        let sender = hub.sender();
+        tokio::spawn(async move { sender.status_message("hello").await })
+            .await
+            .unwrap()
+            .unwrap();
+        let packet = wireshark_in.read_control_packet().await.unwrap();
+        assert_eq!(&packet.payload[..], b"hello");
+        assert!(hub.is_running());
+    }
+}
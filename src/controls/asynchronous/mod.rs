@@ -10,6 +10,9 @@
 //!   that provides simpler, but less flexible, handling of the communication
 //!   using a Tokio channel.
 //!
+//! [`ControlHub`] owns both pipes, and broadcasts the inbound packets to any
+//! number of tasks while queueing the outbound packets from all of them.
+//!
 //! See Wireshark's [Adding Capture Interfaces And Log Sources Using
 //! Extcap](https://www.wireshark.org/docs/wsdg_html_chunked/ChCaptureExtcap.html#_messages)
 //! section 8.2.3.2.1 for a description of the protocol format.
@@ -17,7 +20,14 @@
 use async_trait::async_trait;
 use log::debug;
 use nom_derive::Parse;
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 #[cfg(target_os = "windows")]
 use tokio::fs::File;
@@ -32,10 +42,15 @@ use tokio::{
     task::JoinHandle,
 };
 
+mod hub;
 pub mod util;
+pub use hub::{ControlHub, ControlHubSender};
 use util::AsyncReadExt as _;
 
-use crate::controls::{ControlCommand, ControlPacket};
+use crate::controls::{
+    ControlBatch, ControlCommand, ControlCommandMask, ControlEvent, ControlNumber, ControlPacket,
+    ControlPipeError, LoggerControl, OpenPolicy, SendErrorPolicy, ToolbarControl,
+};
 
 /// Error type returned for control packet read operations.
 #[derive(Debug, Error)]
@@ -47,6 +62,10 @@ pub enum ReadControlError {
     /// Error parsing the incoming data into the [`ControlPacket`] format.
     #[error("Error parsing control packet: {0}")]
     ParseError(String),
+
+    /// No control packet arrived within the given timeout.
+    #[error("Timed out after {0:?} waiting for a control packet")]
+    Timeout(Duration),
 }
 
 /// Error associated with [`ChannelExtcapControlReader`].
@@ -101,6 +120,7 @@ pub struct ChannelExtcapControlReader {
     pub join_handle: JoinHandle<Result<(), ControlChannelError>>,
     /// The channel to receive control packets from.
     pub read_channel: mpsc::Receiver<ControlPacket<'static>>,
+    subscription: Arc<AtomicU16>,
 }
 
 impl ChannelExtcapControlReader {
@@ -109,18 +129,43 @@ impl ChannelExtcapControlReader {
     /// `in_path`.
     pub fn spawn(in_path: PathBuf) -> Self {
         let (tx, rx) = mpsc::channel::<ControlPacket<'static>>(10);
+        let subscription = Arc::new(AtomicU16::new(ControlCommandMask::ALL.bits()));
+        let task_subscription = Arc::clone(&subscription);
         let join_handle = tokio::task::spawn(async move {
             let mut reader = ExtcapControlReader::new(&in_path).await;
             loop {
-                tx.send(reader.read_control_packet().await?).await?;
+                let packet = reader.read_control_packet().await?;
+                if is_subscribed(&task_subscription, &packet) {
+                    tx.send(packet).await?;
+                }
             }
         });
         Self {
             join_handle,
             read_channel: rx,
+            subscription,
         }
     }
 
+    /// Only forwards the control packets with a command in `mask` to this
+    /// reader. Other packets are dropped as they are read from the pipe,
+    /// instead of being queued in the channel and discarded by the caller.
+    /// Packets already in the channel are not affected.
+    ///
+    /// By default, all packets are forwarded.
+    ///
+    /// Note that Wireshark only sends `Initialized` and `Set` packets, so
+    /// those are the only commands worth subscribing to.
+    pub fn subscribe(&self, mask: ControlCommandMask) {
+        self.subscription.store(mask.bits(), Ordering::Relaxed);
+    }
+
+    /// The commands forwarded to this reader. See
+    /// [`subscribe`][Self::subscribe].
+    pub fn subscription(&self) -> ControlCommandMask {
+        ControlCommandMask::from_bits(self.subscription.load(Ordering::Relaxed))
+    }
+
     /// Try to read a buffered control packet, or return `None` if there are no
     /// incoming control packets.
     pub async fn try_read_packet(&mut self) -> Option<ControlPacket<'static>> {
@@ -138,6 +183,35 @@ impl ChannelExtcapControlReader {
     pub async fn read_packet(&mut self) -> Option<ControlPacket<'static>> {
         self.read_channel.recv().await
     }
+
+    /// Try to read a buffered control packet like
+    /// [`try_read_packet`][Self::try_read_packet], resolved into a
+    /// [`ControlEvent`] using the registered `controls`.
+    pub async fn try_read_event(
+        &mut self,
+        controls: &[&dyn ToolbarControl],
+    ) -> Option<ControlEvent> {
+        self.try_read_packet()
+            .await
+            .map(|packet| ControlEvent::resolve(packet, controls))
+    }
+
+    /// Reads a control packet like [`read_packet`][Self::read_packet],
+    /// resolved into a [`ControlEvent`] using the registered `controls`.
+    pub async fn read_event(&mut self, controls: &[&dyn ToolbarControl]) -> Option<ControlEvent> {
+        self.read_packet()
+            .await
+            .map(|packet| ControlEvent::resolve(packet, controls))
+    }
+}
+
+fn is_subscribed(subscription: &AtomicU16, packet: &ControlPacket<'_>) -> bool {
+    let subscribed = ControlCommandMask::from_bits(subscription.load(Ordering::Relaxed))
+        .contains(packet.command);
+    if !subscribed {
+        debug!("Dropping control packet outside of the subscription: {packet:?}");
+    }
+    subscribed
 }
 
 /// A reader for the Extcap control pipe.
@@ -157,23 +231,49 @@ impl ExtcapControlReader {
     ///
     /// * `in_path`: The path of the extcap control pipe passed with
     ///   `--extcap-control-in`.
-    #[cfg(not(target_os = "windows"))]
+    ///
+    /// To give up if Wireshark does not open its end of the pipe within a
+    /// timeout, use [`open`][Self::open].
     pub async fn new(in_path: &Path) -> Self {
-        Self {
-            in_file: tokio::net::unix::pipe::OpenOptions::new()
-                .open_receiver(in_path)
-                .unwrap(),
+        Self::open(in_path, &OpenPolicy::default()).await.unwrap()
+    }
+
+    /// Creates a new instance of [`ExtcapControlReader`], waiting for Wireshark
+    /// to open its end of the pipe according to the given `policy`.
+    ///
+    /// When a timeout is set, the pipe is considered opened once Wireshark
+    /// sends its first control packet, which it does right after opening the
+    /// pipe.
+    ///
+    /// * `in_path`: The path of the extcap control pipe passed with
+    ///   `--extcap-control-in`.
+    #[cfg(not(target_os = "windows"))]
+    pub async fn open(in_path: &Path, policy: &OpenPolicy) -> Result<Self, ControlPipeError> {
+        let in_file = tokio::net::unix::pipe::OpenOptions::new().open_receiver(in_path)?;
+        if let Some(timeout) = policy.timeout {
+            tokio::time::timeout(timeout, in_file.readable())
+                .await
+                .map_err(|_| ControlPipeError::Timeout(timeout))??;
         }
+        Ok(Self { in_file })
     }
 
-    /// Creates a new instance of [`ExtcapControlReader`].
+    /// Creates a new instance of [`ExtcapControlReader`], waiting for Wireshark
+    /// to open its end of the pipe according to the given `policy`.
     ///
     /// * `in_path`: The path of the extcap control pipe passed with
     ///   `--extcap-control-in`.
     #[cfg(target_os = "windows")]
-    pub async fn new(in_path: &Path) -> Self {
-        Self {
-            in_file: File::open(in_path).await.unwrap(),
+    pub async fn open(in_path: &Path, policy: &OpenPolicy) -> Result<Self, ControlPipeError> {
+        let deadline = policy.deadline(Instant::now());
+        loop {
+            match File::open(in_path).await {
+                Ok(in_file) => return Ok(Self { in_file }),
+                Err(e) if deadline.is_some() && e.kind() == std::io::ErrorKind::NotFound => {
+                    tokio::time::sleep(policy.next_wait(deadline)?).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
         }
     }
 
@@ -181,6 +281,10 @@ impl ExtcapControlReader {
     /// control packet pipe is expected to stay open for the entire duration of
     /// the extcap program, if the pipe is closed prematurely in this function
     /// here, `UnexpectedEof` will be returned.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
     pub async fn read_control_packet(
         &mut self,
     ) -> Result<ControlPacket<'static>, ReadControlError> {
@@ -208,9 +312,38 @@ impl ExtcapControlReader {
         debug!("Parsed incoming control message: {packet:?}");
         Ok(packet)
     }
-}
 
-const UNUSED_CONTROL_NUMBER: u8 = 255;
+    /// Read one control packet like
+    /// [`read_control_packet`][Self::read_control_packet], but give up with
+    /// [`ReadControlError::Timeout`] if no packet arrives within `timeout`.
+    ///
+    /// This can be used to wait a short time for the `Initialized` packet, and
+    /// continue the capture without controls if Wireshark does not send it.
+    /// On Unix, once a packet starts to arrive, the rest of it is read without
+    /// a timeout, so that packets are never split.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub async fn read_control_packet_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<ControlPacket<'static>, ReadControlError> {
+        #[cfg(not(target_os = "windows"))]
+        {
+            tokio::time::timeout(timeout, self.in_file.readable())
+                .await
+                .map_err(|_| ReadControlError::Timeout(timeout))??;
+            self.read_control_packet().await
+        }
+        // Files do not support waiting for readiness, so the read itself is
+        // cancelled on timeout.
+        #[cfg(target_os = "windows")]
+        tokio::time::timeout(timeout, self.read_control_packet())
+            .await
+            .map_err(|_| ReadControlError::Timeout(timeout))?
+    }
+}
 
 /// Sender for extcap control packets. These control packets controls the UI
 /// generated by Wireshark. This trait also provides convenience functions for
@@ -227,7 +360,7 @@ pub trait ExtcapControlSenderTrait: Send + Sync + Sized {
     /// the screen until the user dismisses the popup.
     async fn info_message(self, message: &str) -> Result<(), tokio::io::Error> {
         self.send(ControlPacket::new_with_payload(
-            UNUSED_CONTROL_NUMBER,
+            ControlNumber::UNUSED,
             ControlCommand::InformationMessage,
             message.as_bytes(),
         ))
@@ -238,7 +371,7 @@ pub trait ExtcapControlSenderTrait: Send + Sync + Sized {
     /// screen until the user dismisses the popup.
     async fn warning_message(self, message: &str) -> Result<(), tokio::io::Error> {
         self.send(ControlPacket::new_with_payload(
-            UNUSED_CONTROL_NUMBER,
+            ControlNumber::UNUSED,
             ControlCommand::WarningMessage,
             message.as_bytes(),
         ))
@@ -249,7 +382,7 @@ pub trait ExtcapControlSenderTrait: Send + Sync + Sized {
     /// screen until the user dismisses the popup.
     async fn error_message(self, message: &str) -> Result<(), tokio::io::Error> {
         self.send(ControlPacket::new_with_payload(
-            UNUSED_CONTROL_NUMBER,
+            ControlNumber::UNUSED,
             ControlCommand::ErrorMessage,
             message.as_bytes(),
         ))
@@ -262,7 +395,7 @@ pub trait ExtcapControlSenderTrait: Send + Sync + Sized {
     /// bar for a few seconds, or until another message overwrites it.
     async fn status_message(self, message: &str) -> Result<(), tokio::io::Error> {
         self.send(ControlPacket::new_with_payload(
-            UNUSED_CONTROL_NUMBER,
+            ControlNumber::UNUSED,
             ControlCommand::StatusbarMessage,
             message.as_bytes(),
         ))
@@ -273,47 +406,197 @@ pub trait ExtcapControlSenderTrait: Send + Sync + Sized {
 /// A sender for the extcap control packets. `out_file` should be the file given
 /// by the `--extcap-control-out` flag.
 pub struct ExtcapControlSender {
+    /// The control pipe, or `None` if Wireshark has closed it.
     #[cfg(not(target_os = "windows"))]
-    out_file: Sender,
+    out_file: Option<Sender>,
     #[cfg(target_os = "windows")]
-    out_file: File,
+    out_file: Option<File>,
+    out_path: PathBuf,
+    error_policy: SendErrorPolicy,
+    /// Reused to serialize packets without allocating.
+    buffer: ControlBatch,
 }
 
 impl ExtcapControlSender {
-    #[cfg(not(target_os = "windows"))]
     /// Creates a new instance of [`ExtcapControlSender`].
     ///
     /// * `out_path`: The path specified by the `--extcap-control-out` flag.
+    ///
+    /// To give up if Wireshark does not open its end of the pipe within a
+    /// timeout, use [`open`][Self::open].
     pub async fn new(out_path: &Path) -> Self {
-        use std::time::Duration;
+        Self::open(out_path, &OpenPolicy::default()).await.unwrap()
+    }
 
-        for i in 0..50 {
+    #[cfg(not(target_os = "windows"))]
+    /// Creates a new instance of [`ExtcapControlSender`], waiting for Wireshark
+    /// to open its end of the pipe according to the given `policy`.
+    ///
+    /// * `out_path`: The path specified by the `--extcap-control-out` flag.
+    pub async fn open(out_path: &Path, policy: &OpenPolicy) -> Result<Self, ControlPipeError> {
+        let deadline = policy.deadline(Instant::now());
+        loop {
             match tokio::net::unix::pipe::OpenOptions::new().open_sender(out_path) {
-                Ok(out_file) => return Self { out_file },
-                Err(e) => {
-                    if let Some(libc::ENXIO) = e.raw_os_error() {
-                        // This seems sketchy, but the docs for pipe::Sender says "The file is a
-                        // FIFO, but no process has it open for reading. Sleep for a while and try
-                        // again"
-                        // https://docs.rs/tokio/latest/tokio/net/unix/pipe/struct.Sender.html
-                        tokio::time::sleep(Duration::from_millis(i * 100)).await;
-                    } else {
-                        panic!("{e:?}");
-                    }
+                Ok(out_file) => return Ok(Self::with_file(out_file, out_path)),
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                    // This seems sketchy, but the docs for pipe::Sender says "The file is a
+                    // FIFO, but no process has it open for reading. Sleep for a while and try
+                    // again"
+                    // https://docs.rs/tokio/latest/tokio/net/unix/pipe/struct.Sender.html
+                    tokio::time::sleep(policy.next_wait(deadline)?).await;
                 }
+                Err(e) => return Err(e.into()),
             };
         }
-        panic!("Failed waiting for extcap-control-out to be opened");
     }
 
     #[cfg(target_os = "windows")]
-    /// Creates a new instance of [`ExtcapControlSender`].
+    /// Creates a new instance of [`ExtcapControlSender`], waiting for Wireshark
+    /// to open its end of the pipe according to the given `policy`.
     ///
     /// * `out_path`: The path specified by the `--extcap-control-out` flag.
-    pub async fn new(out_path: &Path) -> Self {
+    pub async fn open(out_path: &Path, policy: &OpenPolicy) -> Result<Self, ControlPipeError> {
+        let deadline = policy.deadline(Instant::now());
+        loop {
+            match File::create(out_path).await {
+                Ok(out_file) => return Ok(Self::with_file(out_file, out_path)),
+                Err(e) if deadline.is_some() && e.kind() == std::io::ErrorKind::NotFound => {
+                    tokio::time::sleep(policy.next_wait(deadline)?).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn with_file(out_file: Sender, out_path: &Path) -> Self {
         Self {
-            out_file: File::create(out_path).await.unwrap(),
+            out_file: Some(out_file),
+            out_path: out_path.to_owned(),
+            error_policy: SendErrorPolicy::default(),
+            buffer: ControlBatch::default(),
+        }
+    }
+
+    #[cfg(target_os = "windows")]
+    fn with_file(out_file: File, out_path: &Path) -> Self {
+        Self {
+            out_file: Some(out_file),
+            out_path: out_path.to_owned(),
+            error_policy: SendErrorPolicy::default(),
+            buffer: ControlBatch::default(),
+        }
+    }
+
+    /// Sets what this sender does when Wireshark closes the control pipe. See
+    /// [`SendErrorPolicy`].
+    #[must_use]
+    pub fn with_error_policy(mut self, error_policy: SendErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Whether the control pipe is open. This is `false` after a send failed
+    /// because Wireshark closed the pipe, until it is reopened with
+    /// [`SendErrorPolicy::TryReopen`].
+    pub fn is_connected(&self) -> bool {
+        self.out_file.is_some()
+    }
+
+    /// Opens the control pipe again without waiting, failing if Wireshark
+    /// does not have it open for reading.
+    async fn reopen(&mut self) {
+        #[cfg(not(target_os = "windows"))]
+        let out_file = tokio::net::unix::pipe::OpenOptions::new().open_sender(&self.out_path);
+        #[cfg(target_os = "windows")]
+        let out_file = File::create(&self.out_path).await;
+        self.out_file = out_file.ok();
+    }
+}
+
+impl ExtcapControlSender {
+    /// Sends all of the packets added to the batch in `add_packets` with a
+    /// single write. See [`ControlBatch`].
+    ///
+    /// ```no_run
+    /// # use r_extcap::controls::{asynchronous::ExtcapControlSender, ButtonControl, EnableableControl as _};
+    /// # async fn example(sender: &mut ExtcapControlSender, start: &ButtonControl, stop: &ButtonControl) {
+    /// sender
+    ///     .batch(|batch| {
+    ///         batch.send(start.set_enabled(false));
+    ///         batch.send(stop.set_enabled(true));
+    ///     })
+    ///     .await
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub async fn batch(
+        &mut self,
+        add_packets: impl FnOnce(&mut ControlBatch),
+    ) -> Result<(), tokio::io::Error> {
+        self.write_buffered(|batch| {
+            add_packets(batch);
+            if !batch.is_empty() {
+                debug!("Sending {} extcap control messages", batch.len());
+            }
+        })
+        .await
+    }
+
+    /// Serializes the packets added in `add_packets` into the reused buffer
+    /// and writes them, if any.
+    async fn write_buffered(
+        &mut self,
+        add_packets: impl FnOnce(&mut ControlBatch),
+    ) -> Result<(), tokio::io::Error> {
+        let mut batch = std::mem::take(&mut self.buffer);
+        batch.clear();
+        add_packets(&mut batch);
+        let result = if batch.is_empty() {
+            Ok(())
+        } else {
+            self.write_bytes(batch.bytes()).await
+        };
+        let result = result.and_then(|()| batch.rejected().map_or(Ok(()), Err));
+        self.buffer = batch;
+        result
+    }
+
+    /// Adds `log` to the `logger`. This is the same as sending
+    /// [`LoggerControl::add_log`], but does not allocate, which is useful for
+    /// logging each captured packet.
+    pub async fn add_log(
+        &mut self,
+        logger: &LoggerControl,
+        log: &str,
+    ) -> Result<(), tokio::io::Error> {
+        self.write_buffered(|batch| {
+            batch.add_log(logger, log);
+        })
+        .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(len = bytes.len()), err)
+    )]
+    async fn write_bytes(&mut self, bytes: &[u8]) -> Result<(), tokio::io::Error> {
+        if self.out_file.is_none() && self.error_policy == SendErrorPolicy::TryReopen {
+            self.reopen().await;
+        }
+        let Some(out_file) = &mut self.out_file else {
+            return self.error_policy.disconnected_result();
+        };
+        let result = async {
+            out_file.write_all(bytes).await?;
+            out_file.flush().await
         }
+        .await;
+        let (result, connected) = self.error_policy.handle(result);
+        if !connected {
+            self.out_file = None;
+        }
+        result
     }
 }
 
@@ -321,10 +604,10 @@ impl ExtcapControlSender {
 impl<'a> ExtcapControlSenderTrait for &'a mut ExtcapControlSender {
     async fn send(self, packet: ControlPacket<'_>) -> Result<(), tokio::io::Error> {
         debug!("Sending extcap control message: {packet:#?}");
-        self.out_file.write_all(&packet.to_header_bytes()).await?;
-        self.out_file.write_all(&packet.payload).await?;
-        self.out_file.flush().await?;
-        Ok(())
+        self.write_buffered(|batch| {
+            batch.send(packet);
+        })
+        .await
     }
 }
 
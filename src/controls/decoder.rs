@@ -0,0 +1,140 @@
+use nom_derive::Parse as _;
+use thiserror::Error;
+
+use super::ControlPacket;
+
+/// The size of the header of a control packet, up to and including the
+/// command.
+const HEADER_LEN: usize = 6;
+
+/// Error returned by [`ControlPacketDecoder::push_bytes`] when the incoming
+/// bytes are not valid control packets.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Error parsing control packet: {0}")]
+pub struct DecodeError(String);
+
+/// Decodes control packets from a stream of bytes that may arrive in arbitrary
+/// chunks.
+///
+/// The readers in the [`synchronous`][super::synchronous] and
+/// [`asynchronous`][super::asynchronous] modules own the control pipe and read
+/// from it directly. Integrations that already do their own I/O on the pipe,
+/// like a custom event loop, can instead feed whatever bytes they read into
+/// this decoder, which buffers incomplete packets until the rest arrives.
+///
+/// ```
+/// use r_extcap::controls::{ControlCommand, ControlPacket, ControlPacketDecoder};
+///
+/// let packet = ControlPacket::new_with_payload(1, ControlCommand::Set, &b"on"[..]);
+/// let bytes = packet.to_bytes();
+///
+/// let mut decoder = ControlPacketDecoder::new();
+/// assert!(decoder.push_bytes(&bytes[..4]).unwrap().is_empty());
+/// assert_eq!(decoder.push_bytes(&bytes[4..]).unwrap(), [packet]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ControlPacketDecoder {
+    buffer: Vec<u8>,
+}
+
+impl ControlPacketDecoder {
+    /// Creates a decoder with an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `bytes` to the buffer, and returns all of the packets that are
+    /// now complete, in the order they were received. The bytes of an
+    /// incomplete packet at the end are kept until the next call.
+    ///
+    /// If the bytes are not a valid control packet, for example because the
+    /// sync pipe indication is not `T`, the stream cannot be resynchronized.
+    /// A [`DecodeError`] is returned and the buffer is cleared, and packets
+    /// decoded from the same call before the invalid one are discarded.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Result<Vec<ControlPacket<'static>>, DecodeError> {
+        self.buffer.extend_from_slice(bytes);
+        let mut packets = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let rest = &self.buffer[consumed..];
+            if rest.len() < HEADER_LEN {
+                break;
+            }
+            let message_length = u32::from_be_bytes([0, rest[1], rest[2], rest[3]]);
+            if message_length < 2 {
+                self.buffer.clear();
+                return Err(DecodeError(format!(
+                    "Message length {message_length} is shorter than the header"
+                )));
+            }
+            let error = match ControlPacket::parse(rest) {
+                Ok((remaining, packet)) => {
+                    consumed = self.buffer.len() - remaining.len();
+                    packets.push(packet.into_owned());
+                    continue;
+                }
+                Err(nom::Err::Incomplete(_)) => break,
+                Err(e) => e.to_string(),
+            };
+            self.buffer.clear();
+            return Err(DecodeError(error));
+        }
+        self.buffer.drain(..consumed);
+        Ok(packets)
+    }
+
+    /// The number of bytes buffered for a packet that is not yet complete.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ControlPacketDecoder;
+    use crate::controls::{ControlCommand, ControlPacket};
+
+    #[test]
+    fn byte_by_byte() {
+        let packets = [
+            ControlPacket::new(0, ControlCommand::Initialized),
+            ControlPacket::new_with_payload(3, ControlCommand::Set, &b"hello"[..]),
+        ];
+        let bytes = [packets[0].to_bytes(), packets[1].to_bytes()].concat();
+        let mut decoder = ControlPacketDecoder::new();
+        let mut decoded = Vec::new();
+        for byte in bytes {
+            decoded.extend(decoder.push_bytes(&[byte]).unwrap());
+        }
+        assert_eq!(decoded, packets);
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn multiple_packets_in_one_chunk() {
+        let first = ControlPacket::new_with_payload(1, ControlCommand::Add, &b"a"[..]);
+        let second = ControlPacket::new_with_payload(1, ControlCommand::Add, &b"bc"[..]);
+        let second_bytes = second.to_bytes();
+        let mut decoder = ControlPacketDecoder::new();
+        let chunk = [&first.to_bytes()[..], &second_bytes[..3]].concat();
+        assert_eq!(decoder.push_bytes(&chunk).unwrap(), [first]);
+        assert_eq!(decoder.buffered_len(), 3);
+        assert_eq!(decoder.push_bytes(&second_bytes[3..]).unwrap(), [second]);
+    }
+
+    #[test]
+    fn unknown_command() {
+        let unknown = ControlPacket::new_with_payload(1, ControlCommand::Unknown(200), &b"x"[..]);
+        let set = ControlPacket::new(1, ControlCommand::Set);
+        let bytes = [unknown.to_bytes(), set.to_bytes()].concat();
+        let mut decoder = ControlPacketDecoder::new();
+        assert_eq!(decoder.push_bytes(&bytes).unwrap(), [unknown, set]);
+    }
+
+    #[test]
+    fn invalid_sync_byte() {
+        let mut decoder = ControlPacketDecoder::new();
+        assert!(decoder.push_bytes(b"X\0\0\x02\x01\x01").is_err());
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+}
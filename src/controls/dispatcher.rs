@@ -0,0 +1,244 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use super::{
+    ControlCommand, ControlEvent, ControlKind, ControlPacket, ControlState, ToolbarControl,
+};
+
+/// The maximum time between the first and the last packet of the burst of
+/// `Set` packets Wireshark sends when the restore button is pressed.
+const RESTORE_BURST_WINDOW: Duration = Duration::from_millis(100);
+
+type RestoreHook = Box<dyn FnMut(&ControlState) + Send>;
+
+/// Resolves the control packets sent by Wireshark into [`ControlEvent`]s,
+/// keeping a [`ControlState`] of the registered controls up to date.
+///
+/// A [`RestoreButtonControl`][super::RestoreButtonControl] has no packets of
+/// its own. When the user presses it, Wireshark resets the toolbar and sends a
+/// `Set` packet with the default value of each boolean, selector and string
+/// control. The dispatcher recognizes this burst of packets, resets its state
+/// to the default values, and calls the hook given to
+/// [`on_restore`][Self::on_restore], so that the extcap can reset its own
+/// state as well.
+///
+/// ```
+/// use r_extcap::controls::{BooleanControl, ControlDispatcher};
+///
+/// let verify = BooleanControl::builder()
+///     .control_number(0)
+///     .display("Verify")
+///     .build();
+/// let repeat = BooleanControl::builder()
+///     .control_number(1)
+///     .display("Repeat")
+///     .build();
+/// let mut dispatcher = ControlDispatcher::new(&[&verify, &repeat])
+///     .on_restore(|_state| println!("Restored the default values"));
+///
+/// dispatcher.dispatch(verify.set_checked(true));
+/// assert!(dispatcher.state().get(&verify));
+///
+/// // The burst sent by Wireshark when the restore button is pressed.
+/// dispatcher.dispatch(verify.set_checked(false));
+/// dispatcher.dispatch(repeat.set_checked(false));
+/// assert!(!dispatcher.state().get(&verify));
+/// ```
+///
+/// With a single boolean, selector or string control, the burst cannot be
+/// told apart from the user changing that control back to its default value,
+/// which calls the hook as well.
+pub struct ControlDispatcher {
+    kinds: HashMap<u8, ControlKind>,
+    /// The payload of the `Set` packet Wireshark sends for each value control
+    /// when the restore button is pressed.
+    defaults: HashMap<u8, Vec<u8>>,
+    state: ControlState,
+    burst: HashSet<u8>,
+    burst_start: Option<Instant>,
+    on_restore: Option<RestoreHook>,
+}
+
+impl ControlDispatcher {
+    /// Creates a dispatcher for the given `controls`. Packets for other
+    /// controls are resolved to [`ControlEvent::Unknown`].
+    pub fn new(controls: &[&dyn ToolbarControl]) -> Self {
+        let defaults = controls
+            .iter()
+            .filter(|control| {
+                matches!(
+                    control.kind(),
+                    ControlKind::Boolean | ControlKind::Selector | ControlKind::String
+                )
+            })
+            .map(|control| {
+                // A string control without a default value is restored to an
+                // empty string.
+                let payload = control
+                    .default_packets()
+                    .into_iter()
+                    .find(|packet| packet.command == ControlCommand::Set)
+                    .map(|packet| packet.payload.into_owned())
+                    .unwrap_or_default();
+                (control.control_number(), payload)
+            })
+            .collect();
+        Self {
+            kinds: controls
+                .iter()
+                .map(|control| (control.control_number(), control.kind()))
+                .collect(),
+            defaults,
+            state: ControlState::new(controls),
+            burst: HashSet::new(),
+            burst_start: None,
+            on_restore: None,
+        }
+    }
+
+    /// Sets the hook called after the restore button is pressed, with the
+    /// state reset to the default values.
+    #[must_use]
+    pub fn on_restore(mut self, on_restore: impl FnMut(&ControlState) + Send + 'static) -> Self {
+        self.on_restore = Some(Box::new(on_restore));
+        self
+    }
+
+    /// The current values of the registered controls.
+    pub fn state(&self) -> &ControlState {
+        &self.state
+    }
+
+    /// The current values of the registered controls, for example to apply
+    /// the initial values with
+    /// [`ControlHandshake::apply_to`][super::ControlHandshake::apply_to].
+    pub fn state_mut(&mut self) -> &mut ControlState {
+        &mut self.state
+    }
+
+    /// Updates the state with `packet` and resolves it into a
+    /// [`ControlEvent`]. If `packet` completes the burst sent when the restore
+    /// button is pressed, the [restore hook][Self::on_restore] is called
+    /// before returning.
+    ///
+    /// In debug builds, packets that do not make sense for the registered
+    /// control, according to [`ControlPacket::validate_for`], are logged as a
+    /// warning. They are ignored by the state in all builds.
+    pub fn dispatch(&mut self, packet: ControlPacket<'static>) -> ControlEvent {
+        #[cfg(debug_assertions)]
+        if let Err(e) = self.state.validate(&packet) {
+            log::warn!("Invalid control packet from Wireshark: {e}");
+        }
+        self.state.update(&packet);
+        if self.completes_restore(&packet) {
+            debug!("Restoring the default control values");
+            self.state.reset();
+            if let Some(on_restore) = &mut self.on_restore {
+                on_restore(&self.state);
+            }
+        }
+        let kind = self.kinds.get(&packet.control_number).copied();
+        ControlEvent::resolve_kind(packet, kind)
+    }
+
+    /// Whether `packet` completes a burst of `Set` packets restoring every
+    /// value control to its default.
+    fn completes_restore(&mut self, packet: &ControlPacket<'_>) -> bool {
+        let is_default = packet.command == ControlCommand::Set
+            && self
+                .defaults
+                .get(&packet.control_number)
+                .is_some_and(|default| default[..] == packet.payload[..]);
+        if !is_default {
+            self.burst.clear();
+            return false;
+        }
+        let now = Instant::now();
+        let in_burst = self
+            .burst_start
+            .is_some_and(|start| now - start <= RESTORE_BURST_WINDOW);
+        if !in_burst || self.burst.is_empty() {
+            self.burst.clear();
+            self.burst_start = Some(now);
+        }
+        self.burst.insert(packet.control_number);
+        if self.burst.len() == self.defaults.len() {
+            self.burst.clear();
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::ControlDispatcher;
+    use crate::controls::{
+        BooleanControl, ButtonControl, ControlCommand, ControlEvent, ControlPacket,
+        SelectorControl, SelectorControlOption, ToolbarControl,
+    };
+
+    #[test]
+    fn on_restore() {
+        let verify = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .default_value(true)
+            .build();
+        let delay = SelectorControl::builder()
+            .control_number(1)
+            .display("Delay")
+            .options(vec![
+                SelectorControlOption::builder()
+                    .value("1")
+                    .display("1s")
+                    .default(true)
+                    .build(),
+                SelectorControlOption::builder()
+                    .value("5")
+                    .display("5s")
+                    .build(),
+            ])
+            .build();
+        let button = ButtonControl::builder()
+            .control_number(2)
+            .display("Turn on")
+            .build();
+        let restored = Arc::new(Mutex::new(Vec::new()));
+        let hook_restored = Arc::clone(&restored);
+        let mut dispatcher =
+            ControlDispatcher::new(&[&verify as &dyn ToolbarControl, &delay, &button])
+                .on_restore(move |state| hook_restored.lock().unwrap().push(state.clone()));
+
+        assert_eq!(
+            dispatcher.dispatch(verify.set_checked(false)),
+            ControlEvent::SetBool {
+                control: 0,
+                value: false
+            }
+        );
+        dispatcher.dispatch(delay.set_value("5").into_owned());
+        // Changing a single control back to its default is not a restore.
+        dispatcher.dispatch(verify.set_checked(true));
+        assert_eq!(
+            dispatcher.dispatch(ControlPacket::new(2, ControlCommand::Set)),
+            ControlEvent::ButtonPressed { control: 2 }
+        );
+        assert!(restored.lock().unwrap().is_empty());
+        assert_eq!(dispatcher.state().get(&delay), "5");
+
+        dispatcher.dispatch(verify.set_checked(true));
+        dispatcher.dispatch(delay.set_value("1").into_owned());
+        let restored = restored.lock().unwrap();
+        assert_eq!(restored.len(), 1);
+        assert!(restored[0].get(&verify));
+        assert_eq!(restored[0].get(&delay), "1");
+        assert_eq!(dispatcher.state().get(&delay), "1");
+    }
+}
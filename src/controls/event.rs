@@ -0,0 +1,209 @@
+use log::debug;
+
+use super::{ControlCommand, ControlPacket, ToolbarControl};
+
+/// The kind of a toolbar control, which determines how the packets Wireshark
+/// sends for it are interpreted by [`ControlEvent::resolve`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ControlKind {
+    /// A [`BooleanControl`][super::BooleanControl].
+    Boolean,
+    /// A [`ButtonControl`][super::ButtonControl].
+    Button,
+    /// A [`SelectorControl`][super::SelectorControl].
+    Selector,
+    /// A [`StringControl`][super::StringControl].
+    String,
+    /// A control that Wireshark does not send values for, like the
+    /// [`LoggerControl`][super::LoggerControl], or a custom control.
+    Other,
+}
+
+/// A control packet received from Wireshark, interpreted according to the
+/// control it is for.
+///
+/// Matching on events is simpler than matching on the command and control
+/// number of each [`ControlPacket`], and parses the payload into the type of
+/// the control.
+///
+/// ```
+/// use r_extcap::controls::{
+///     BooleanControl, ButtonControl, ControlEvent, ControlPacket, ToolbarControl,
+/// };
+///
+/// let verify = BooleanControl::builder()
+///     .control_number(0)
+///     .display("Verify")
+///     .build();
+/// let button = ButtonControl::builder()
+///     .control_number(1)
+///     .display("Turn on")
+///     .build();
+/// let controls: [&dyn ToolbarControl; 2] = [&verify, &button];
+///
+/// # let packet = verify.set_checked(true).into_owned();
+/// match ControlEvent::resolve(packet, &controls) {
+///     ControlEvent::SetBool { control: 0, value } => println!("Verify: {value}"),
+///     ControlEvent::ButtonPressed { control: 1 } => println!("Turned on"),
+///     _ => {}
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ControlEvent {
+    /// Wireshark has sent the initial values of the controls. See
+    /// [`ControlHandshake`][super::ControlHandshake].
+    Initialized,
+    /// The user changed the text of a [`StringControl`][super::StringControl].
+    SetString {
+        /// The control number.
+        control: u8,
+        /// The new text.
+        value: String,
+    },
+    /// The user toggled a [`BooleanControl`][super::BooleanControl].
+    SetBool {
+        /// The control number.
+        control: u8,
+        /// Whether the checkbox is checked.
+        value: bool,
+    },
+    /// The user pressed a [`ButtonControl`][super::ButtonControl].
+    ButtonPressed {
+        /// The control number.
+        control: u8,
+    },
+    /// The user selected an option of a
+    /// [`SelectorControl`][super::SelectorControl].
+    SelectionChanged {
+        /// The control number.
+        control: u8,
+        /// The value of the selected option.
+        value: String,
+    },
+    /// A packet for a control that is not registered, with a command that
+    /// does not apply to the control, or with a malformed payload.
+    Unknown(ControlPacket<'static>),
+}
+
+impl ControlEvent {
+    /// Interprets `packet` according to the control with the same control
+    /// number in `controls`, using its [`kind`][ToolbarControl::kind].
+    pub fn resolve(packet: ControlPacket<'static>, controls: &[&dyn ToolbarControl]) -> Self {
+        let kind = controls
+            .iter()
+            .find(|control| control.control_number() == packet.control_number)
+            .map(|control| control.kind());
+        Self::resolve_kind(packet, kind)
+    }
+
+    /// Interprets `packet` as a packet for a control of the given `kind`, or
+    /// for an unregistered control if `kind` is `None`.
+    pub(crate) fn resolve_kind(packet: ControlPacket<'static>, kind: Option<ControlKind>) -> Self {
+        if packet.command == ControlCommand::Initialized {
+            return Self::Initialized;
+        }
+        let control = packet.control_number;
+        let event = match (kind, packet.command) {
+            (Some(ControlKind::Boolean), ControlCommand::Set) => {
+                packet.payload.first().map(|b| Self::SetBool {
+                    control,
+                    value: *b != 0,
+                })
+            }
+            (Some(ControlKind::Button), ControlCommand::Set) => {
+                Some(Self::ButtonPressed { control })
+            }
+            (Some(ControlKind::Selector), ControlCommand::Set) => {
+                String::from_utf8(packet.payload.to_vec())
+                    .ok()
+                    .map(|value| Self::SelectionChanged { control, value })
+            }
+            (Some(ControlKind::String), ControlCommand::Set) => {
+                String::from_utf8(packet.payload.to_vec())
+                    .ok()
+                    .map(|value| Self::SetString { control, value })
+            }
+            _ => None,
+        };
+        event.unwrap_or_else(|| {
+            debug!("Unknown control packet: {packet:?}");
+            Self::Unknown(packet)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ControlEvent;
+    use crate::controls::{
+        BooleanControl, ButtonControl, ControlCommand, ControlPacket, EnableableControl as _,
+        LoggerControl, SelectorControl, StringControl, ToolbarControl,
+    };
+
+    #[test]
+    fn resolve() {
+        let verify = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .build();
+        let button = ButtonControl::builder()
+            .control_number(1)
+            .display("Turn on")
+            .build();
+        let delay = SelectorControl::builder()
+            .control_number(2)
+            .display("Delay")
+            .build();
+        let message = StringControl {
+            control_number: 3,
+            ..Default::default()
+        };
+        let logger = LoggerControl::builder()
+            .control_number(4)
+            .display("Log")
+            .build();
+        let controls: [&dyn ToolbarControl; 5] = [&verify, &button, &delay, &message, &logger];
+        let resolve =
+            |packet: ControlPacket<'_>| ControlEvent::resolve(packet.into_owned(), &controls);
+
+        assert_eq!(
+            resolve(ControlPacket::new(0, ControlCommand::Initialized)),
+            ControlEvent::Initialized
+        );
+        assert_eq!(
+            resolve(verify.set_checked(true)),
+            ControlEvent::SetBool {
+                control: 0,
+                value: true
+            }
+        );
+        assert_eq!(
+            resolve(ControlPacket::new(1, ControlCommand::Set)),
+            ControlEvent::ButtonPressed { control: 1 }
+        );
+        assert_eq!(
+            resolve(delay.set_value("5")),
+            ControlEvent::SelectionChanged {
+                control: 2,
+                value: String::from("5")
+            }
+        );
+        assert_eq!(
+            resolve(message.set_value("hello")),
+            ControlEvent::SetString {
+                control: 3,
+                value: String::from("hello")
+            }
+        );
+        for packet in [
+            logger.add_log("log".into()),
+            verify.set_enabled(false),
+            ControlPacket::new(0, ControlCommand::Set),
+            ControlPacket::new(9, ControlCommand::Set),
+            ControlPacket::new(0, ControlCommand::Unknown(10)),
+        ] {
+            let packet = packet.into_owned();
+            assert_eq!(resolve(packet.clone()), ControlEvent::Unknown(packet));
+        }
+    }
+}
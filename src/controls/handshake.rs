@@ -0,0 +1,177 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::debug;
+use thiserror::Error;
+
+use super::{ControlCommand, ControlPacket, ControlState, ControlWithValue};
+
+/// Error waiting for the `Initialized` control packet in
+/// [`ControlHandshake::wait_for_init`].
+#[derive(Debug, Error)]
+pub enum HandshakeError {
+    /// Wireshark did not send the `Initialized` packet within the timeout.
+    #[error("Timed out after {0:?} waiting for the Initialized control packet")]
+    Timeout(Duration),
+
+    /// The control pipe was closed before the `Initialized` packet was
+    /// received.
+    #[error("Control pipe closed before the Initialized control packet was received")]
+    Disconnected,
+}
+
+/// The result of the initial handshake on the control pipe.
+///
+/// When a capture starts, Wireshark sends a [`Set`][ControlCommand::Set]
+/// packet for each control whose value differs from its default, followed by
+/// an [`Initialized`][ControlCommand::Initialized] packet. `ControlHandshake`
+/// consumes those packets and records the initial values, so the capture can
+/// start with the values the user selected in the toolbar.
+///
+/// ```ignore
+/// let control_reader = capture_step.spawn_channel_control_reader().unwrap();
+/// let handshake = ControlHandshake::wait_for_init(&control_reader, Duration::from_secs(5))?;
+/// let verify = handshake
+///     .initial_value(&*CONTROL_VERIFY)
+///     .unwrap_or(CONTROL_VERIFY.default_value);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ControlHandshake {
+    initial_values: HashMap<u8, Vec<u8>>,
+}
+
+impl ControlHandshake {
+    /// Records a packet received before the handshake is complete. Returns
+    /// `true` if `packet` is the `Initialized` packet, which completes the
+    /// handshake.
+    ///
+    /// This is useful when reading from a control reader not supported by
+    /// [`wait_for_init`][Self::wait_for_init].
+    pub fn record(&mut self, packet: &ControlPacket<'_>) -> bool {
+        match packet.command {
+            ControlCommand::Initialized => return true,
+            ControlCommand::Set => {
+                self.initial_values
+                    .insert(packet.control_number, packet.payload.to_vec());
+            }
+            _ => debug!("Ignoring control packet before initialization: {packet:?}"),
+        }
+        false
+    }
+
+    /// Returns the initial value of `control` sent by Wireshark, or `None` if
+    /// the control has its default value.
+    pub fn initial_value<C: ControlWithValue>(&self, control: &C) -> Option<C::Value> {
+        self.initial_values
+            .get(&control.control_number())
+            .and_then(|payload| control.parse_value(payload))
+    }
+
+    /// Updates `state` with the initial values received in this handshake.
+    pub fn apply_to(&self, state: &mut ControlState) {
+        for (control_number, payload) in &self.initial_values {
+            state.update(&ControlPacket::new_with_payload(
+                *control_number,
+                ControlCommand::Set,
+                &payload[..],
+            ));
+        }
+    }
+
+    /// Reads control packets from `reader` until the `Initialized` packet
+    /// arrives, recording the initial control values sent before it.
+    ///
+    /// Returns [`HandshakeError::Timeout`] if the `Initialized` packet does not
+    /// arrive within `timeout`.
+    #[cfg(feature = "sync")]
+    pub fn wait_for_init(
+        reader: &super::synchronous::ChannelExtcapControlReader,
+        timeout: Duration,
+    ) -> Result<Self, HandshakeError> {
+        use std::{sync::mpsc::RecvTimeoutError, time::Instant};
+
+        let deadline = Instant::now() + timeout;
+        let mut handshake = Self::default();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match reader.read_channel.recv_timeout(remaining) {
+                Ok(packet) => {
+                    if handshake.record(&packet) {
+                        return Ok(handshake);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Err(HandshakeError::Timeout(timeout)),
+                Err(RecvTimeoutError::Disconnected) => return Err(HandshakeError::Disconnected),
+            }
+        }
+    }
+
+    /// Reads control packets from `reader` until the `Initialized` packet
+    /// arrives, recording the initial control values sent before it.
+    ///
+    /// Returns [`HandshakeError::Timeout`] if the `Initialized` packet does not
+    /// arrive within `timeout`.
+    #[cfg(feature = "async")]
+    pub async fn wait_for_init_async(
+        reader: &mut super::asynchronous::ChannelExtcapControlReader,
+        timeout: Duration,
+    ) -> Result<Self, HandshakeError> {
+        let mut handshake = Self::default();
+        tokio::time::timeout(timeout, async {
+            loop {
+                let packet = reader
+                    .read_packet()
+                    .await
+                    .ok_or(HandshakeError::Disconnected)?;
+                if handshake.record(&packet) {
+                    return Ok(());
+                }
+            }
+        })
+        .await
+        .map_err(|_| HandshakeError::Timeout(timeout))??;
+        Ok(handshake)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ControlHandshake;
+    use crate::controls::{BooleanControl, ControlCommand, ControlPacket, SelectorControl};
+
+    #[test]
+    fn record_initial_values() {
+        let verify = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .build();
+        let delay = SelectorControl::builder()
+            .control_number(1)
+            .display("Delay")
+            .build();
+        let mut handshake = ControlHandshake::default();
+        assert!(!handshake.record(&verify.set_checked(true)));
+        assert!(handshake.record(&ControlPacket::new(0, ControlCommand::Initialized)));
+        assert_eq!(handshake.initial_value(&verify), Some(true));
+        assert_eq!(handshake.initial_value(&delay), None);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn wait_for_init_timeout() {
+        use std::{sync::mpsc, time::Duration};
+
+        use super::HandshakeError;
+        use crate::controls::synchronous::ChannelExtcapControlReader;
+
+        let (tx, rx) = mpsc::sync_channel(10);
+        let reader = ChannelExtcapControlReader::spawn_with(move || Ok(rx.recv().ok()));
+        tx.send(ControlPacket::new(1, ControlCommand::Set)).unwrap();
+        assert!(matches!(
+            ControlHandshake::wait_for_init(&reader, Duration::from_millis(50)),
+            Err(HandshakeError::Timeout(_))
+        ));
+        tx.send(ControlPacket::new(0, ControlCommand::Initialized))
+            .unwrap();
+        assert!(ControlHandshake::wait_for_init(&reader, Duration::from_millis(50)).is_ok());
+    }
+}
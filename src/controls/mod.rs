@@ -9,18 +9,12 @@
 //!    things like displaying status bar and dialog messages, as well as for
 //!    Wireshark to send events like `Initialized`.
 
-use std::borrow::Cow;
+use std::{borrow::Cow, time::Duration};
 
-use nom::number::streaming::be_u24;
-use nom_derive::Nom;
+use thiserror::Error;
 use typed_builder::TypedBuilder;
 
-use crate::PrintSentence;
-
-#[cfg(feature = "async")]
-use asynchronous::ExtcapControlSenderTrait as _;
-#[cfg(feature = "sync")]
-use synchronous::ExtcapControlSenderTrait as _;
+use crate::{AttributeKey, PrintSentence, WiresharkVersion};
 
 #[cfg(feature = "async")]
 pub mod asynchronous;
@@ -28,6 +22,39 @@ pub mod asynchronous;
 #[cfg(feature = "sync")]
 pub mod synchronous;
 
+mod decoder;
+mod dispatcher;
+mod event;
+mod handshake;
+#[cfg(unix)]
+pub mod nonblocking;
+mod pacer;
+mod packet;
+mod progress;
+mod rate_limit;
+#[cfg(feature = "serde")]
+pub mod replay;
+mod state;
+
+pub use decoder::{ControlPacketDecoder, DecodeError};
+pub use dispatcher::ControlDispatcher;
+pub use event::{ControlEvent, ControlKind};
+pub use handshake::{ControlHandshake, HandshakeError};
+pub use pacer::{Pacer, PacerEvent};
+pub use packet::{
+    ControlCommand, ControlCommandMask, ControlNumber, ControlPacket, InvalidControlNumber,
+    PayloadTooLarge,
+};
+pub use progress::StartupProgress;
+#[cfg(feature = "async")]
+pub use progress::{AsyncProgressHandle, AsyncProgressSender};
+#[cfg(feature = "sync")]
+pub use progress::{ProgressHandle, ProgressSender};
+#[cfg(any(feature = "sync", feature = "async"))]
+pub use rate_limit::RateLimitedSender;
+pub use rate_limit::{RateLimit, RateLimitStats};
+pub use state::ControlState;
+
 /// A `ToolbarControl` that can be enabled or disabled.
 pub trait EnableableControl: ToolbarControl {
     /// Sets whether the control is enabled or disabled.
@@ -64,25 +91,62 @@ pub trait ControlWithLabel: ToolbarControl {
     }
 }
 
-/// A checkbox which lets the user set a true / false value.
-///
-/// The extcap utility can set a default value at startup, change the value
-/// using [`set_checked`][Self::set_checked], and receive value changes from an
-/// [`ExtcapControlReader`][asynchronous::ExtcapControlReader]. When starting a
-/// capture Wireshark will send the value if different from the default value.
-#[derive(Debug, TypedBuilder)]
-pub struct BooleanControl {
-    /// The control number, a unique identifier for this control.
-    pub control_number: u8,
-    /// The user-visible label for the check box.
-    #[builder(setter(into))]
-    pub display: String,
-    /// Tooltip shown when hovering over the UI element.
-    #[builder(default, setter(strip_option, into))]
-    pub tooltip: Option<String>,
-    /// Whether the control should be checked or unchecked by default
-    #[builder(default = false)]
-    pub default_value: bool,
+/// A [`ToolbarControl`] that holds a value, which Wireshark sends in a
+/// [`Set`][ControlCommand::Set] control packet when the user changes it.
+pub trait ControlWithValue: ToolbarControl {
+    /// The type of the value held by this control.
+    type Value;
+
+    /// Parses the value from the payload of a `Set` control packet sent by
+    /// Wireshark. Returns `None` if the payload is malformed.
+    fn parse_value(&self, payload: &[u8]) -> Option<Self::Value>;
+
+    /// The value of this control before Wireshark or the extcap sets it.
+    fn default_value(&self) -> Self::Value;
+}
+
+/// Declares a control struct with the `control_number` field shared by all
+/// controls, which the builder checks with [`checked_control_number`].
+macro_rules! control_struct {
+    ($(#[$meta:meta])* pub struct $name:ident { $($fields:tt)* }) => {
+        $(#[$meta])*
+        pub struct $name {
+            /// The control number, a unique identifier for this control.
+            ///
+            /// # Panics
+            ///
+            /// The builder panics if the number is [reserved][ControlNumber::UNUSED].
+            /// Use [`ControlNumber::new`] to check numbers that are not constants.
+            #[builder(setter(
+                doc = "Sets the control number.\n\n# Panics\n\nIf the number is 255, which is reserved for packets that are not for a control. Use `ControlNumber::new` to check numbers that are not constants.",
+                transform = |number: u8| checked_control_number(number)
+            ))]
+            pub control_number: u8,
+            $($fields)*
+        }
+    };
+}
+
+control_struct! {
+    /// A checkbox which lets the user set a true / false value.
+    ///
+    /// The extcap utility can set a default value at startup, change the value
+    /// using [`set_checked`][Self::set_checked], and receive value changes from an
+    /// [`ExtcapControlReader`][asynchronous::ExtcapControlReader]. When starting a
+    /// capture Wireshark will send the value if different from the default value.
+    #[derive(Debug, TypedBuilder)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
+    pub struct BooleanControl {
+        /// The user-visible label for the check box.
+        #[builder(setter(into))]
+        pub display: String,
+        /// Tooltip shown when hovering over the UI element.
+        #[builder(default, setter(strip_option, into))]
+        pub tooltip: Option<String>,
+        /// Whether the control should be checked or unchecked by default
+        #[builder(default = false)]
+        pub default_value: bool,
+    }
 }
 
 impl EnableableControl for BooleanControl {}
@@ -99,14 +163,27 @@ impl BooleanControl {
     }
 }
 
+impl ControlWithValue for BooleanControl {
+    type Value = bool;
+
+    fn parse_value(&self, payload: &[u8]) -> Option<bool> {
+        payload.first().map(|b| *b != 0)
+    }
+
+    fn default_value(&self) -> bool {
+        self.default_value
+    }
+}
+
 impl PrintSentence for BooleanControl {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "control {{number={}}}", self.control_number())?;
-        write!(f, "{{type=boolean}}")?;
-        write!(f, "{{display={}}}", self.display)?;
-        write!(f, "{{default={}}}", self.default_value)?;
+        f.write_str("control ")?;
+        AttributeKey::Number.write(f, self.control_number())?;
+        AttributeKey::Type.write(f, "boolean")?;
+        AttributeKey::Display.write(f, &self.display)?;
+        AttributeKey::Default.write(f, self.default_value)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={}}}", tooltip)?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         writeln!(f)
     }
@@ -116,28 +193,50 @@ impl ToolbarControl for BooleanControl {
     fn control_number(&self) -> u8 {
         self.control_number
     }
+
+    fn kind(&self) -> ControlKind {
+        ControlKind::Boolean
+    }
+
+    fn default_packets(&self) -> Vec<ControlPacket<'static>> {
+        vec![self.set_checked(self.default_value)]
+    }
+
+    fn supported_commands(&self) -> &'static [ControlCommand] {
+        &[
+            ControlCommand::Set,
+            ControlCommand::Enable,
+            ControlCommand::Disable,
+        ]
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Controls can be serialized to JSON")
+    }
 }
 
-/// Button that sends a signal when pressed. The button is only enabled when
-/// capturing.
-///
-/// The extcap utility can set the button text at startup using the `display`
-/// field, change the button text using
-/// [`set_label`][ControlWithLabel::set_label], and receive button press signals
-/// from an [`ExtcapControlReader`][asynchronous::ExtcapControlReader].
-///
-/// The button is disabled and the button text is restored to the default text
-/// when not capturing.
-#[derive(Debug, TypedBuilder)]
-pub struct ButtonControl {
-    /// The control number, a unique identifier for this control.
-    pub control_number: u8,
-    /// The user-visible label for the button.
-    #[builder(setter(into))]
-    pub display: String,
-    /// Tooltip shown when hovering over the UI element.
-    #[builder(default, setter(strip_option, into))]
-    pub tooltip: Option<String>,
+control_struct! {
+    /// Button that sends a signal when pressed. The button is only enabled when
+    /// capturing.
+    ///
+    /// The extcap utility can set the button text at startup using the `display`
+    /// field, change the button text using
+    /// [`set_label`][ControlWithLabel::set_label], and receive button press signals
+    /// from an [`ExtcapControlReader`][asynchronous::ExtcapControlReader].
+    ///
+    /// The button is disabled and the button text is restored to the default text
+    /// when not capturing.
+    #[derive(Debug, TypedBuilder)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
+    pub struct ButtonControl {
+        /// The user-visible label for the button.
+        #[builder(setter(into))]
+        pub display: String,
+        /// Tooltip shown when hovering over the UI element.
+        #[builder(default, setter(strip_option, into))]
+        pub tooltip: Option<String>,
+    }
 }
 
 impl EnableableControl for ButtonControl {}
@@ -147,162 +246,301 @@ impl ToolbarControl for ButtonControl {
     fn control_number(&self) -> u8 {
         self.control_number
     }
+
+    fn kind(&self) -> ControlKind {
+        ControlKind::Button
+    }
+
+    fn default_packets(&self) -> Vec<ControlPacket<'static>> {
+        vec![self.set_label(&self.display).into_owned()]
+    }
+
+    fn supported_commands(&self) -> &'static [ControlCommand] {
+        &[
+            ControlCommand::Set,
+            ControlCommand::Enable,
+            ControlCommand::Disable,
+        ]
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Controls can be serialized to JSON")
+    }
 }
 
 impl PrintSentence for ButtonControl {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "control {{number={}}}", self.control_number())?;
-        write!(f, "{{type=button}}")?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("control ")?;
+        AttributeKey::Number.write(f, self.control_number())?;
+        AttributeKey::Type.write(f, "button")?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={}}}", tooltip)?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         writeln!(f)
     }
 }
 
-/// A logger mechanism where the extcap utility can send log entries to be
-/// presented in a log window. This communication is unidirectional from this
-/// extcap program to Wireshark.
-///
-/// A button will be displayed in the toolbar which will open the log window
-/// when clicked.
-#[derive(Debug, TypedBuilder)]
-pub struct LoggerControl {
-    /// The control number, a unique identifier for this control.
-    pub control_number: u8,
-    /// Label of the button that opens the log window.
-    #[builder(setter(into))]
-    pub display: String,
-    /// Tooltip shown when hovering over the UI element.
-    #[builder(default, setter(strip_option, into))]
-    pub tooltip: Option<String>,
+control_struct! {
+    /// A logger mechanism where the extcap utility can send log entries to be
+    /// presented in a log window. This communication is unidirectional from this
+    /// extcap program to Wireshark.
+    ///
+    /// A button will be displayed in the toolbar which will open the log window
+    /// when clicked.
+    #[derive(Debug, TypedBuilder)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
+    pub struct LoggerControl {
+        /// Label of the button that opens the log window.
+        #[builder(setter(into))]
+        pub display: String,
+        /// Tooltip shown when hovering over the UI element.
+        #[builder(default, setter(strip_option, into))]
+        pub tooltip: Option<String>,
+    }
 }
 
 impl LoggerControl {
     /// Clear the log and add the given log the entry to the window.
     pub fn clear_and_add_log<'a>(&self, log: Cow<'a, str>) -> ControlPacket<'a> {
-        ControlPacket::new_with_payload(
-            self.control_number(),
-            ControlCommand::Set,
-            format!("{}\n", log).into_bytes(),
-        )
+        ControlPacket::new_with_payload(self.control_number(), ControlCommand::Set, log_line(log))
     }
 
     /// Add the log entry to the log window.
     pub fn add_log<'a>(&self, log: Cow<'a, str>) -> ControlPacket<'a> {
-        ControlPacket::new_with_payload(
-            self.control_number(),
-            ControlCommand::Add,
-            format!("{}\n", log).into_bytes(),
-        )
+        ControlPacket::new_with_payload(self.control_number(), ControlCommand::Add, log_line(log))
     }
+
+    /// Add the text to the log window, split into as many packets as needed
+    /// to keep each payload within [`ControlPacket::MAX_PAYLOAD_LEN`]. This
+    /// is useful for large logs, like a trace dumped by the device.
+    ///
+    /// The text is split after line breaks where possible, so that each line
+    /// is added whole. Lines too long for a single packet are split at a
+    /// character boundary, which Wireshark appends to the same line. A line
+    /// break is appended to the last packet, unless the text already ends
+    /// with one.
+    ///
+    /// The packets can be sent with either the synchronous or the asynchronous
+    /// senders, preferably in a single batch:
+    ///
+    /// ```no_run
+    /// # use r_extcap::controls::{LoggerControl, synchronous::ExtcapControlSender};
+    /// # fn example(sender: &mut ExtcapControlSender, logger: &LoggerControl, trace: &str) {
+    /// sender
+    ///     .batch(|batch| {
+    ///         for packet in logger.add_log_chunked(trace) {
+    ///             batch.send(packet);
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn add_log_chunked<'a>(&self, text: &'a str) -> Vec<ControlPacket<'a>> {
+        let mut chunks = log_chunks(text, ControlPacket::MAX_PAYLOAD_LEN).peekable();
+        let mut packets = Vec::new();
+        while let Some(chunk) = chunks.next() {
+            let payload = if chunks.peek().is_some() || chunk.ends_with('\n') {
+                Cow::Borrowed(chunk.as_bytes())
+            } else {
+                Cow::Owned(log_line(Cow::Borrowed(chunk)))
+            };
+            packets.push(ControlPacket::new_with_payload(
+                self.control_number(),
+                ControlCommand::Add,
+                payload,
+            ));
+        }
+        packets
+    }
+}
+
+/// Checks the control number given to the builder of a control.
+fn checked_control_number(number: u8) -> u8 {
+    match ControlNumber::new(number) {
+        Ok(number) => number.get(),
+        Err(e) => panic!("Invalid control number passed to the control builder: {e}"),
+    }
+}
+
+/// Appends the line break to `log`, reusing its buffer if it is owned.
+fn log_line(log: Cow<'_, str>) -> Vec<u8> {
+    let mut line = match log {
+        Cow::Owned(log) => log.into_bytes(),
+        Cow::Borrowed(log) => {
+            let mut line = Vec::with_capacity(log.len() + 1);
+            line.extend_from_slice(log.as_bytes());
+            line
+        }
+    };
+    line.push(b'\n');
+    line
+}
+
+/// Splits `log` into chunks of at most `max_len` bytes, leaving room in the
+/// last one for the line break unless it already ends with one. Chunks end
+/// after a line break where possible, or else at a character boundary.
+fn log_chunks(log: &str, max_len: usize) -> impl Iterator<Item = &str> {
+    let mut rest = Some(log);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        if current.len() < max_len || (current.len() == max_len && current.ends_with('\n')) {
+            rest = None;
+            return Some(current);
+        }
+        // Keep at least one byte for the last chunk, so that the remainder
+        // is never empty.
+        let mut end = max_len.min(current.len() - 1);
+        while !current.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Some(line_end) = current[..end].rfind('\n') {
+            end = line_end + 1;
+        }
+        let (chunk, remaining) = current.split_at(end);
+        rest = Some(remaining);
+        Some(chunk)
+    })
 }
 
 impl ToolbarControl for LoggerControl {
     fn control_number(&self) -> u8 {
         self.control_number
     }
+
+    fn supported_commands(&self) -> &'static [ControlCommand] {
+        &[ControlCommand::Set, ControlCommand::Add]
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Controls can be serialized to JSON")
+    }
 }
 
 impl PrintSentence for LoggerControl {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "control {{number={}}}", self.control_number())?;
-        write!(f, "{{type=button}}")?;
-        write!(f, "{{role=logger}}")?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("control ")?;
+        AttributeKey::Number.write(f, self.control_number())?;
+        AttributeKey::Type.write(f, "button")?;
+        AttributeKey::Role.write(f, "logger")?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         writeln!(f)
     }
 }
 
-/// A button in the toolbar that opens the help URL when clicked. The URL it
-/// opens is defined in [`Metadata::help_url`][crate::interface::Metadata::help_url].
-#[derive(Debug, TypedBuilder)]
-pub struct HelpButtonControl {
-    /// The control number, a unique identifier for this control.
-    pub control_number: u8,
-    /// Label of the button that opens the help URL.
-    #[builder(setter(into))]
-    pub display: String,
-    /// Tooltip shown when hovering over the UI element.
-    #[builder(default, setter(strip_option, into))]
-    pub tooltip: Option<String>,
+control_struct! {
+    /// A button in the toolbar that opens the help URL when clicked. The URL it
+    /// opens is defined in [`Metadata::help_url`][crate::interface::Metadata::help_url].
+    #[derive(Debug, TypedBuilder)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
+    pub struct HelpButtonControl {
+        /// Label of the button that opens the help URL.
+        #[builder(setter(into))]
+        pub display: String,
+        /// Tooltip shown when hovering over the UI element.
+        #[builder(default, setter(strip_option, into))]
+        pub tooltip: Option<String>,
+    }
 }
 
 impl ToolbarControl for HelpButtonControl {
     fn control_number(&self) -> u8 {
         self.control_number
     }
+
+    fn supported_commands(&self) -> &'static [ControlCommand] {
+        &[]
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Controls can be serialized to JSON")
+    }
 }
 
 impl PrintSentence for HelpButtonControl {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "control {{number={}}}", self.control_number())?;
-        write!(f, "{{type=button}}")?;
-        write!(f, "{{role=help}}")?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("control ")?;
+        AttributeKey::Number.write(f, self.control_number())?;
+        AttributeKey::Type.write(f, "button")?;
+        AttributeKey::Role.write(f, "help")?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         writeln!(f)
     }
 }
 
-/// This button will restore all control values to default. The button is only
-/// enabled when not capturing.
-#[derive(Debug, TypedBuilder)]
-pub struct RestoreButtonControl {
-    /// The control number, a unique identifier for this control.
-    pub control_number: u8,
-    /// Label of the button.
-    #[builder(setter(into))]
-    pub display: String,
-    /// Tooltip shown when hovering over the UI element.
-    #[builder(default, setter(strip_option, into))]
-    pub tooltip: Option<String>,
+control_struct! {
+    /// This button will restore all control values to default. The button is only
+    /// enabled when not capturing.
+    #[derive(Debug, TypedBuilder)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
+    pub struct RestoreButtonControl {
+        /// Label of the button.
+        #[builder(setter(into))]
+        pub display: String,
+        /// Tooltip shown when hovering over the UI element.
+        #[builder(default, setter(strip_option, into))]
+        pub tooltip: Option<String>,
+    }
 }
 
 impl ToolbarControl for RestoreButtonControl {
     fn control_number(&self) -> u8 {
         self.control_number
     }
+
+    fn supported_commands(&self) -> &'static [ControlCommand] {
+        &[]
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Controls can be serialized to JSON")
+    }
 }
 
 impl PrintSentence for RestoreButtonControl {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "control {{number={}}}", self.control_number())?;
-        write!(f, "{{type=button}}")?;
-        write!(f, "{{role=restore}}")?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("control ")?;
+        AttributeKey::Number.write(f, self.control_number())?;
+        AttributeKey::Type.write(f, "button")?;
+        AttributeKey::Role.write(f, "restore")?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={tooltip}}}")?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         writeln!(f)
     }
 }
 
-/// A dropdown selector with fixed values which can be selected.
-///
-/// Default values can be provided using the `options` field. When starting
-/// a capture, Wireshark will send the value as a command line flag if the
-/// selected value is different from the default value.
-#[derive(Debug, TypedBuilder)]
-pub struct SelectorControl {
-    /// The control number, a unique identifier for this control.
-    pub control_number: u8,
-    /// The user-visible label of this selector, displayed next to the drop down
-    /// box.
-    #[builder(setter(into))]
-    pub display: String,
-    /// Tooltip shown when hovering over the UI element.
-    #[builder(default, setter(strip_option, into))]
-    pub tooltip: Option<String>,
-    /// The list of options available for selection in this selector.
-    #[builder(default, setter(into))]
-    pub options: Vec<SelectorControlOption>,
+control_struct! {
+    /// A dropdown selector with fixed values which can be selected.
+    ///
+    /// Default values can be provided using the `options` field. When starting
+    /// a capture, Wireshark will send the value as a command line flag if the
+    /// selected value is different from the default value.
+    #[derive(Debug, TypedBuilder)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
+    pub struct SelectorControl {
+        /// The user-visible label of this selector, displayed next to the drop down
+        /// box.
+        #[builder(setter(into))]
+        pub display: String,
+        /// Tooltip shown when hovering over the UI element.
+        #[builder(default, setter(strip_option, into))]
+        pub tooltip: Option<String>,
+        /// The list of options available for selection in this selector.
+        #[builder(default, setter(into))]
+        pub options: Vec<SelectorControlOption>,
+    }
 }
 
 impl SelectorControl {
@@ -318,7 +556,13 @@ impl SelectorControl {
     /// Add an option to the selector dynamically.
     pub fn add_value<'a>(&self, value: &'a str, display: Option<&'a str>) -> ControlPacket<'a> {
         let payload_bytes: Cow<'a, [u8]> = match display {
-            Some(d) => Cow::Owned(format!("{}\0{}", value, d).as_bytes().to_vec()),
+            Some(d) => {
+                let mut payload = Vec::with_capacity(value.len() + 1 + d.len());
+                payload.extend_from_slice(value.as_bytes());
+                payload.push(0);
+                payload.extend_from_slice(d.as_bytes());
+                Cow::Owned(payload)
+            }
             None => Cow::Borrowed(value.as_bytes()),
         };
         ControlPacket::new_with_payload(self.control_number(), ControlCommand::Add, payload_bytes)
@@ -345,22 +589,73 @@ impl SelectorControl {
     }
 }
 
+impl EnableableControl for SelectorControl {}
+
+/// The value is the [`SelectorControlOption::value`] of the selected option.
+impl ControlWithValue for SelectorControl {
+    type Value = String;
+
+    fn parse_value(&self, payload: &[u8]) -> Option<String> {
+        String::from_utf8(payload.to_vec()).ok()
+    }
+
+    /// The option marked as [`default`][SelectorControlOption::default], or
+    /// the first option if none is marked, or an empty string if there are no
+    /// options.
+    fn default_value(&self) -> String {
+        self.options
+            .iter()
+            .find(|option| option.default)
+            .or_else(|| self.options.first())
+            .map(|option| option.value.clone())
+            .unwrap_or_default()
+    }
+}
+
 impl ToolbarControl for SelectorControl {
     fn control_number(&self) -> u8 {
         self.control_number
     }
+
+    fn kind(&self) -> ControlKind {
+        ControlKind::Selector
+    }
+
+    fn default_packets(&self) -> Vec<ControlPacket<'static>> {
+        if self.options.is_empty() {
+            return Vec::new();
+        }
+        vec![ControlPacket::new_with_payload(
+            self.control_number,
+            ControlCommand::Set,
+            self.default_value().into_bytes(),
+        )]
+    }
+
+    fn supported_commands(&self) -> &'static [ControlCommand] {
+        &[
+            ControlCommand::Set,
+            ControlCommand::Add,
+            ControlCommand::Remove,
+            ControlCommand::Enable,
+            ControlCommand::Disable,
+        ]
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Controls can be serialized to JSON")
+    }
 }
 
 impl PrintSentence for SelectorControl {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "control {{number={}}}{{type=selector}}",
-            self.control_number()
-        )?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("control ")?;
+        AttributeKey::Number.write(f, self.control_number())?;
+        AttributeKey::Type.write(f, "selector")?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={}}}", tooltip)?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         writeln!(f)?;
         for value in self.options.iter() {
@@ -372,6 +667,7 @@ impl PrintSentence for SelectorControl {
 
 /// An option in a [`SelectorControl`].
 #[derive(Clone, Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SelectorControlOption {
     /// The value that is sent in the payload of the [`ControlPacket`] when this
     /// option is selected.
@@ -394,54 +690,52 @@ impl SelectorControlOption {
         f: &mut std::fmt::Formatter<'_>,
         control: &C,
     ) -> std::fmt::Result {
-        write!(
-            f,
-            "value {{control={}}}{{value={}}}{{display={}}}",
-            control.control_number(),
-            self.value,
-            self.display,
-        )?;
+        f.write_str("value ")?;
+        AttributeKey::Control.write(f, control.control_number())?;
+        AttributeKey::Value.write(f, &self.value)?;
+        AttributeKey::Display.write(f, &self.display)?;
         if self.default {
-            write!(f, "{{default=true}}")?;
+            AttributeKey::Default.write(f, "true")?;
         }
         writeln!(f)?;
         Ok(())
     }
 }
 
-/// A text field toolbar control element.
-///
-/// Maximum length is accepted by a `StringControl` is 32767 bytes.
-///
-/// The default string value can be set at startup, and the value can be changed
-/// dynamically while capturing. When the value changes or is different form the
-/// default, its value will be sent as a [`ControlPacket`] during capture.
-#[derive(Debug, Default, TypedBuilder)]
-pub struct StringControl {
-    /// The control number, a unique identifier for this control.
-    pub control_number: u8,
-    /// A user-visible label for this control.
-    #[builder(setter(into))]
-    pub display: String,
-    /// An optional tooltip that is shown when hovering on the UI element.
-    #[builder(setter(into, strip_option))]
-    pub tooltip: Option<String>,
-    /// An optional placeholder that is shown when this control is empty.
-    #[builder(setter(into, strip_option))]
-    pub placeholder: Option<String>,
-    /// An optional regular expression string that validates the value on the
-    /// field. If the value does not match the regular expression, the text
-    /// field will appear red and its value will not be sent in a
-    /// [`ControlPacket`].
+control_struct! {
+    /// A text field toolbar control element.
+    ///
+    /// Maximum length is accepted by a `StringControl` is 32767 bytes.
     ///
-    /// Despite what the Wireshark documentation says, back slashes in the the
-    /// regular expression string do not have to be escaped, just remember to
-    /// use a Rust raw string when defining them. (e.g. r"\d\d\d\d").
-    #[builder(setter(into, strip_option))]
-    pub validation: Option<String>,
-    /// The default value
-    #[builder(default, setter(into, strip_option))]
-    pub default_value: Option<String>,
+    /// The default string value can be set at startup, and the value can be changed
+    /// dynamically while capturing. When the value changes or is different form the
+    /// default, its value will be sent as a [`ControlPacket`] during capture.
+    #[derive(Debug, Default, TypedBuilder)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize), serde(tag = "type"))]
+    pub struct StringControl {
+        /// A user-visible label for this control.
+        #[builder(setter(into))]
+        pub display: String,
+        /// An optional tooltip that is shown when hovering on the UI element.
+        #[builder(setter(into, strip_option))]
+        pub tooltip: Option<String>,
+        /// An optional placeholder that is shown when this control is empty.
+        #[builder(setter(into, strip_option))]
+        pub placeholder: Option<String>,
+        /// An optional regular expression string that validates the value on the
+        /// field. If the value does not match the regular expression, the text
+        /// field will appear red and its value will not be sent in a
+        /// [`ControlPacket`].
+        ///
+        /// Despite what the Wireshark documentation says, back slashes in the the
+        /// regular expression string do not have to be escaped, just remember to
+        /// use a Rust raw string when defining them. (e.g. r"\d\d\d\d").
+        #[builder(setter(into, strip_option))]
+        pub validation: Option<String>,
+        /// The default value
+        #[builder(default, setter(into, strip_option))]
+        pub default_value: Option<String>,
+    }
 }
 
 impl StringControl {
@@ -461,36 +755,214 @@ impl StringControl {
     }
 }
 
+impl EnableableControl for StringControl {}
+
+impl ControlWithValue for StringControl {
+    type Value = String;
+
+    fn parse_value(&self, payload: &[u8]) -> Option<String> {
+        String::from_utf8(payload.to_vec()).ok()
+    }
+
+    fn default_value(&self) -> String {
+        self.default_value.clone().unwrap_or_default()
+    }
+}
+
 impl ToolbarControl for StringControl {
     fn control_number(&self) -> u8 {
         self.control_number
     }
+
+    fn kind(&self) -> ControlKind {
+        ControlKind::String
+    }
+
+    fn default_packets(&self) -> Vec<ControlPacket<'static>> {
+        self.default_value
+            .iter()
+            .map(|value| self.set_value(value).into_owned())
+            .collect()
+    }
+
+    fn supported_commands(&self) -> &'static [ControlCommand] {
+        &[
+            ControlCommand::Set,
+            ControlCommand::Enable,
+            ControlCommand::Disable,
+        ]
+    }
+
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("Controls can be serialized to JSON")
+    }
 }
 
 impl PrintSentence for StringControl {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "control {{number={}}}{{type=string}}",
-            self.control_number()
-        )?;
-        write!(f, "{{display={}}}", self.display)?;
+        f.write_str("control ")?;
+        AttributeKey::Number.write(f, self.control_number())?;
+        AttributeKey::Type.write(f, "string")?;
+        AttributeKey::Display.write(f, &self.display)?;
         if let Some(tooltip) = &self.tooltip {
-            write!(f, "{{tooltip={}}}", tooltip)?;
+            AttributeKey::Tooltip.write(f, tooltip)?;
         }
         if let Some(placeholder) = &self.placeholder {
-            write!(f, "{{placeholder={}}}", placeholder)?;
+            AttributeKey::Placeholder.write(f, placeholder)?;
         }
         if let Some(validation) = &self.validation {
-            write!(f, "{{validation={}}}", validation)?;
+            AttributeKey::Validation.write(f, validation)?;
         }
         if let Some(default_value) = &self.default_value {
-            write!(f, "{{default={}}}", default_value)?;
+            AttributeKey::Default.write(f, default_value)?;
         }
         writeln!(f)
     }
 }
 
+/// Declares a set of toolbar controls, assigning their control numbers
+/// sequentially in declaration order starting from 0.
+///
+/// This expands to a struct with a field for each control, a `new()`
+/// constructor (also available through `Default`), and an `all()` method that
+/// returns an array of `&dyn ToolbarControl` in declaration order, suitable for
+/// passing to [`InterfacesStep::list_interfaces`][crate::InterfacesStep::list_interfaces].
+/// The struct also implements [`ControlPanel`], which can send the default
+/// state of all of the controls to Wireshark.
+/// Each control is written like a struct expression without the
+/// `control_number` field. If the struct declaration is omitted, the struct is
+/// named `Controls`.
+///
+/// ```
+/// use lazy_static::lazy_static;
+/// use r_extcap::controls::*;
+///
+/// r_extcap::controls! {
+///     pub struct AppControls {
+///         message: StringControl {
+///             display: String::from("Message"),
+///             tooltip: None,
+///             placeholder: None,
+///             validation: None,
+///             default_value: None,
+///         },
+///         verify: BooleanControl {
+///             display: String::from("Verify"),
+///             tooltip: None,
+///             default_value: false,
+///         },
+///     }
+/// }
+///
+/// lazy_static! {
+///     static ref CONTROLS: AppControls = AppControls::new();
+/// }
+///
+/// assert_eq!(CONTROLS.message.control_number, 0);
+/// assert_eq!(CONTROLS.verify.control_number, 1);
+/// assert_eq!(CONTROLS.all().len(), 2);
+/// ```
+#[macro_export]
+macro_rules! controls {
+    (
+        $(#[$struct_meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field:ident : $ty:ident { $($body:tt)* }
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$struct_meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                pub $field: $ty,
+            )*
+        }
+
+        impl $name {
+            /// The number of controls declared.
+            pub const COUNT: usize = [$(stringify!($field)),*].len();
+
+            /// Creates the controls, numbered sequentially in declaration
+            /// order starting from 0.
+            pub fn new() -> Self {
+                Self::with_first_number(0)
+            }
+
+            /// Creates the controls, numbered sequentially in declaration
+            /// order starting from `first`. This gives each app combined in a
+            /// `MultiApp` its own range of control numbers.
+            ///
+            /// Panics if the last control number is greater than
+            /// `ControlNumber::MAX`.
+            pub fn with_first_number(first: u8) -> Self {
+                #[allow(non_camel_case_types)]
+                enum ControlNumber {
+                    $($field),*
+                }
+                let number = |index: u8| {
+                    let max = $crate::controls::ControlNumber::MAX.get();
+                    first
+                        .checked_add(index)
+                        .filter(|number| *number <= max)
+                        .unwrap_or_else(|| {
+                            panic!("Control numbers starting from {first} exceed the maximum of {max}")
+                        })
+                };
+                Self {
+                    $(
+                        $field: $ty {
+                            control_number: number(ControlNumber::$field as u8),
+                            $($body)*
+                        },
+                    )*
+                }
+            }
+
+            /// Returns all of the controls in declaration order.
+            pub fn all(&self) -> [&dyn $crate::controls::ToolbarControl; Self::COUNT] {
+                [$(&self.$field),*]
+            }
+        }
+
+        impl ::std::default::Default for $name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl $crate::controls::ControlPanel for $name {
+            fn controls(&self) -> ::std::vec::Vec<&dyn $crate::controls::ToolbarControl> {
+                self.all().to_vec()
+            }
+        }
+
+        const _: () = assert!(
+            $name::COUNT <= $crate::controls::ControlNumber::MAX.get() as usize + 1,
+            "At most 255 toolbar controls can be declared, since control number 255 is reserved"
+        );
+    };
+    (
+        $(
+            $(#[$field_meta:meta])*
+            $field:ident : $ty:ident { $($body:tt)* }
+        ),* $(,)?
+    ) => {
+        $crate::controls! {
+            /// The toolbar controls of this extcap.
+            pub struct Controls {
+                $(
+                    $(#[$field_meta])*
+                    $field: $ty { $($body)* }
+                ),*
+            }
+        }
+    };
+}
+
 /// Controls provided by this extcap utility to use in a toolbar in the UI.
 /// These controls are bidirectional and can be used to control the extcap
 /// utility while capturing.
@@ -511,145 +983,467 @@ impl PrintSentence for StringControl {
 pub trait ToolbarControl: PrintSentence {
     /// The control number, a unique identifier for this control.
     fn control_number(&self) -> u8;
+
+    /// The commands that can be sent to or received from this control. Used by
+    /// [`ControlPacket::validate_for`] to detect packets that do not make sense
+    /// for this type of control, like a `Remove` for a [`ButtonControl`].
+    ///
+    /// The default implementation allows all commands that apply to a control.
+    fn supported_commands(&self) -> &'static [ControlCommand] {
+        &[
+            ControlCommand::Set,
+            ControlCommand::Add,
+            ControlCommand::Remove,
+            ControlCommand::Enable,
+            ControlCommand::Disable,
+        ]
+    }
+
+    /// The kind of this control, used to interpret the packets Wireshark
+    /// sends for it in [`ControlEvent::resolve`].
+    ///
+    /// The default implementation returns [`ControlKind::Other`], so all
+    /// packets for the control are resolved to [`ControlEvent::Unknown`].
+    fn kind(&self) -> ControlKind {
+        ControlKind::Other
+    }
+
+    /// The packets that reset this control in Wireshark to its declared
+    /// default state, like the default value of a [`BooleanControl`] or the
+    /// label of a [`ButtonControl`]. See [`ControlPanel::send_defaults`].
+    ///
+    /// The default implementation returns no packets.
+    fn default_packets(&self) -> Vec<ControlPacket<'static>> {
+        Vec::new()
+    }
+
+    /// The oldest Wireshark version that understands this control. Controls
+    /// are left out for older versions by
+    /// [`InterfacesStep::list_interfaces_for_version`][crate::InterfacesStep::list_interfaces_for_version].
+    ///
+    /// The default implementation returns
+    /// [`WiresharkVersion::INTERFACE_TOOLBAR`], which introduced all of the
+    /// controls in this crate.
+    fn min_wireshark_version(&self) -> WiresharkVersion {
+        WiresharkVersion::INTERFACE_TOOLBAR
+    }
+
+    /// Serializes this control into JSON, for tools that inspect the declared
+    /// model. See [`model`][crate::model].
+    ///
+    /// The controls in this crate serialize all of their fields. The default
+    /// implementation only includes the control number.
+    #[cfg(feature = "serde")]
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({ "control_number": self.control_number() })
+    }
 }
 
-/// Control packets for the extcap interface. This is used for communication of
-/// control data between Wireshark and this extcap program.
-///
-/// Reference:
-/// <https://www.wireshark.org/docs/wsdg_html_chunked/ChCaptureExtcap.html#_messages>
-#[derive(Debug, Nom, Clone, PartialEq, Eq)]
-pub struct ControlPacket<'a> {
-    /// The common sync pipe indication. This protocol uses the value "T".
-    #[nom(Verify = "*sync_pipe_indication == b'T'")]
-    pub sync_pipe_indication: u8,
-    /// Length of `payload` + 2 bytes for `control_number` and `command`.
-    #[nom(Parse = "be_u24")]
-    pub message_length: u32,
-    /// Unique number to identify the control, as previously returned in the
-    /// `{control}` declarations returned in the
-    /// [`--extcap-interfaces`][crate::ExtcapArgs::extcap_interfaces] phase. This
-    /// number also gives the order of the controls in the interface toolbar.
-    /// The control number, a unique identifier for this control.
-    pub control_number: u8,
-    /// The command associated with this packet. See [`ControlCommand`] for
-    /// details.
-    pub command: ControlCommand,
-    /// Payload specific to the [`command`][Self::command]. For example, the
-    /// payload for [`StatusbarMessage`][ControlCommand::StatusbarMessage] is
-    /// the message string.
-    #[nom(Map = "Cow::from", Take = "(message_length - 2) as usize")]
-    pub payload: Cow<'a, [u8]>,
-}
-
-impl<'a> ControlPacket<'a> {
-    /// Creates a new control packet with a payload.
-    #[must_use]
-    pub fn new_with_payload<CowSlice: Into<Cow<'a, [u8]>>>(
-        control_number: u8,
-        command: ControlCommand,
-        payload: CowSlice,
-    ) -> Self {
-        let payload = payload.into();
-        ControlPacket {
-            sync_pipe_indication: b'T',
-            message_length: (payload.len() + 2) as u32,
-            control_number,
-            command,
-            payload,
-        }
-    }
-
-    /// Creates a new control packet with an empty payload.
-    #[must_use]
-    pub fn new(control_number: u8, command: ControlCommand) -> Self {
-        let empty_slice: &'static [u8] = &[];
-        Self::new_with_payload(control_number, command, empty_slice)
-    }
-
-    /// Outputs the serialzied bytes of the header to send back to wireshark.
-    pub fn to_header_bytes(&self) -> [u8; 6] {
-        let mut bytes = [0_u8; 6];
-        bytes[0] = self.sync_pipe_indication;
-        bytes[1..4].copy_from_slice(&self.message_length.to_be_bytes()[1..]);
-        bytes[4] = self.control_number;
-        bytes[5] = self.command as u8;
-        bytes
-    }
-
-    /// Turns the given ControlPacket into a ControlPacket with fully owned data
-    /// and 'static lifetime.
-    pub fn into_owned(self) -> ControlPacket<'static> {
-        ControlPacket {
-            payload: match self.payload {
-                Cow::Borrowed(v) => Cow::Owned(v.to_vec()),
-                Cow::Owned(v) => Cow::Owned(v),
-            },
-            ..self
-        }
+/// A set of toolbar controls, like the struct declared by the
+/// [`controls!`][crate::controls!] macro, which implements this trait.
+#[cfg_attr(feature = "async", async_trait::async_trait)]
+pub trait ControlPanel: Sync {
+    /// Returns all of the controls in the panel, in declaration order.
+    fn controls(&self) -> Vec<&dyn ToolbarControl>;
+
+    /// The [default packets][ToolbarControl::default_packets] of all of the
+    /// controls in the panel, in declaration order.
+    fn default_packets(&self) -> Vec<ControlPacket<'static>> {
+        self.controls()
+            .iter()
+            .flat_map(|control| control.default_packets())
+            .collect()
     }
 
-    /// Sends this control packet to Wireshark using the given `sender`.
+    /// Sends the [default packets][Self::default_packets] of all of the
+    /// controls in a single [batch][ControlBatch], to synchronize the toolbar
+    /// with the declared defaults. This should be called after Wireshark sends
+    /// the [`Initialized`][ControlCommand::Initialized] packet, for example
+    /// after [`ControlHandshake::wait_for_init`].
     #[cfg(feature = "sync")]
-    pub fn send(self, sender: &mut synchronous::ExtcapControlSender) -> std::io::Result<()> {
-        sender.send(self)
+    fn send_defaults(&self, sender: &mut synchronous::ExtcapControlSender) -> std::io::Result<()> {
+        let packets = self.default_packets();
+        sender.batch(|batch| {
+            for packet in packets {
+                batch.send(packet);
+            }
+        })
     }
 
-    /// Sends this control packet to Wireshark using the given `sender`.
+    /// Same as [`send_defaults`][Self::send_defaults], but using an
+    /// [`asynchronous::ExtcapControlSender`].
     #[cfg(feature = "async")]
-    pub async fn send_async(
-        self,
+    async fn send_defaults_async(
+        &self,
         sender: &mut asynchronous::ExtcapControlSender,
-    ) -> tokio::io::Result<()> {
-        sender.send(self).await
-    }
-}
-
-/// The control command for the control packet. Note that a `ControlCommand` is
-/// not valid for all control types, for example, the `Remove` command is
-/// applicable only to [`SelectorControls`][SelectorControl], and `Initialized`
-/// is only sent by Wireshark to this extcap program.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Nom)]
-#[repr(u8)]
-pub enum ControlCommand {
-    /// Sent by Wireshark to indicate that this extcap has been initialized and
-    /// is ready to accept packets.
-    Initialized = 0,
-    /// Either sent by Wireshark to indicate that the user has interacted with
-    /// one of the controls, or sent by the extcap program to change the value
-    /// on a given control.
+    ) -> std::io::Result<()> {
+        let packets = self.default_packets();
+        sender
+            .batch(|batch| {
+                for packet in packets {
+                    batch.send(packet);
+                }
+            })
+            .await
+    }
+}
+
+/// A batch of control packets that are written to the control pipe together.
+/// Created by `ExtcapControlSender::batch` in the [`synchronous`] and
+/// [`asynchronous`] modules.
+///
+/// The packets are serialized into a single buffer, which is written and
+/// flushed once, so Wireshark sees all of the updates at the same time and in
+/// order. This is useful for sending the initial state of many controls.
+///
+/// The senders also keep a batch to serialize single packets into, so that
+/// sending does not allocate once the buffer has grown to the size of the
+/// largest packet.
+///
+/// Packets with a payload longer than [`ControlPacket::MAX_PAYLOAD_LEN`]
+/// would corrupt the control pipe, so they are left out of the batch, and the
+/// sender returns an `InvalidInput` error after writing the other packets.
+#[derive(Debug, Default)]
+pub struct ControlBatch {
+    bytes: Vec<u8>,
+    len: usize,
+    rejected: Option<PayloadTooLarge>,
+}
+
+impl ControlBatch {
+    /// Adds `packet` to the end of the batch.
+    pub fn send(&mut self, packet: ControlPacket<'_>) -> &mut Self {
+        if packet.payload.len() > ControlPacket::MAX_PAYLOAD_LEN {
+            let error = PayloadTooLarge(packet.payload.len());
+            log::warn!(
+                "Dropping control packet for control {}: {error}",
+                packet.control_number
+            );
+            self.rejected = Some(error);
+            return self;
+        }
+        self.bytes.extend_from_slice(&packet.to_header_bytes());
+        self.bytes.extend_from_slice(&packet.payload);
+        self.len += 1;
+        self
+    }
+
+    /// Adds a packet adding `log` to the `logger`, like
+    /// [`LoggerControl::add_log`]. The line break is appended while writing
+    /// the packet, without allocating a new string.
     ///
-    /// Used by control types: [`BooleanControl`], [`ButtonControl`],
-    /// [`LoggerControl`], [`SelectorControl`], and [`StringControl`].
-    Set = 1,
-    /// Sent by the extcap program to add a value to the given logger or
-    /// selector.
+    /// Logs longer than [`ControlPacket::MAX_PAYLOAD_LEN`] are split into
+    /// multiple packets, like [`LoggerControl::add_log_chunked`], in which
+    /// case the line break is only appended if the log doesn't end with one.
+    pub fn add_log(&mut self, logger: &LoggerControl, log: &str) -> &mut Self {
+        self.add_log_chunked(logger, log, ControlPacket::MAX_PAYLOAD_LEN)
+    }
+
+    fn add_log_chunked(&mut self, logger: &LoggerControl, log: &str, max_len: usize) -> &mut Self {
+        let mut chunks = log_chunks(log, max_len).peekable();
+        while let Some(chunk) = chunks.next() {
+            let last = chunks.peek().is_none();
+            self.write_log_packet(logger, chunk, last && !chunk.ends_with('\n'));
+        }
+        self
+    }
+
+    fn write_log_packet(
+        &mut self,
+        logger: &LoggerControl,
+        log: &str,
+        line_break: bool,
+    ) -> &mut Self {
+        let header = ControlPacket {
+            message_length: (log.len() + 2 + usize::from(line_break)) as u32,
+            ..ControlPacket::new(logger.control_number, ControlCommand::Add)
+        }
+        .to_header_bytes();
+        self.bytes.extend_from_slice(&header);
+        self.bytes.extend_from_slice(log.as_bytes());
+        if line_break {
+            self.bytes.push(b'\n');
+        }
+        self.len += 1;
+        self
+    }
+
+    /// The number of packets in the batch.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the batch has no packets.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The serialized packets in the batch.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The error for the last packet left out of the batch because its
+    /// payload is too long, as an `InvalidInput` error.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub(crate) fn rejected(&self) -> Option<std::io::Error> {
+        self.rejected
+            .map(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))
+    }
+
+    /// Removes all packets, keeping the allocated buffer for reuse.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub(crate) fn clear(&mut self) {
+        self.bytes.clear();
+        self.len = 0;
+        self.rejected = None;
+    }
+}
+
+/// Error returned by [`ControlPacket::validate_for`] when a packet does not
+/// make sense for the given control.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvalidPacketError {
+    /// The packet is addressed to a different control number.
+    #[error("Packet for control {actual} does not belong to control {expected}")]
+    WrongControl {
+        /// The control number of the control the packet was validated for.
+        expected: u8,
+        /// The control number in the packet.
+        actual: u8,
+    },
+
+    /// The control does not support the command of the packet. See
+    /// [`ToolbarControl::supported_commands`].
+    #[error("Control {control_number} does not support the {command:?} command")]
+    UnsupportedCommand {
+        /// The control number of the packet.
+        control_number: u8,
+        /// The unsupported command.
+        command: ControlCommand,
+    },
+}
+
+impl ControlPacket<'_> {
+    /// Checks that this packet makes sense for `control`, which means that the
+    /// control numbers match and the command is one of the control's
+    /// [`supported_commands`][ToolbarControl::supported_commands].
     ///
-    /// Used by control types: [`LoggerControl`] and [`SelectorControl`].
-    Add = 2,
-    /// Sent by the extcap program to remove a value from the given selector.
+    /// Commands that are not addressed to a control, like
+    /// [`Initialized`][ControlCommand::Initialized] and the messages shown by
+    /// Wireshark, are always valid.
     ///
-    /// Used by control types: [`SelectorControl`].
-    Remove = 3,
-    /// Sent by the extcap program to enable a given control.
+    /// The packets returned by the methods on the controls in this crate are
+    /// always valid. This is useful to check packets that are constructed
+    /// manually. [`ControlState::update`] ignores packets with unsupported
+    /// commands.
     ///
-    /// Used by control types: [`BooleanControl`], [`ButtonControl`],
-    /// [`SelectorControl`], and [`StringControl`].
-    Enable = 4,
-    /// Sent by the extcap program to disable a given control.
+    /// ```
+    /// use r_extcap::controls::{
+    ///     ButtonControl, ControlCommand, ControlPacket, ControlWithLabel as _,
+    ///     InvalidPacketError,
+    /// };
     ///
-    /// Used by control types: [`BooleanControl`], [`ButtonControl`],
-    /// [`SelectorControl`], and [`StringControl`].
-    Disable = 5,
-    /// Sent by the extcap program to show a message in the status bar.
-    StatusbarMessage = 6,
-    /// Sent by the extcap program to show a message in an information dialog
-    /// popup.
-    InformationMessage = 7,
-    /// Sent by the extcap program to show a message in a warning dialog popup.
-    WarningMessage = 8,
-    /// Sent by the extcap program to show a message in an error dialog popup.
-    ErrorMessage = 9,
+    /// let button = ButtonControl::builder()
+    ///     .control_number(1)
+    ///     .display("Turn on")
+    ///     .build();
+    /// assert_eq!(button.set_label("Turn off").validate_for(&button), Ok(()));
+    /// assert_eq!(
+    ///     ControlPacket::new(1, ControlCommand::Remove).validate_for(&button),
+    ///     Err(InvalidPacketError::UnsupportedCommand {
+    ///         control_number: 1,
+    ///         command: ControlCommand::Remove,
+    ///     }),
+    /// );
+    /// ```
+    pub fn validate_for(&self, control: &dyn ToolbarControl) -> Result<(), InvalidPacketError> {
+        self.validate_for_commands(control.control_number(), control.supported_commands())
+    }
+
+    /// Same as [`validate_for`][Self::validate_for], for a control given by
+    /// its control number and supported commands.
+    pub(crate) fn validate_for_commands(
+        &self,
+        control_number: u8,
+        supported_commands: &[ControlCommand],
+    ) -> Result<(), InvalidPacketError> {
+        if !self.command.is_control_command() {
+            return Ok(());
+        }
+        if self.control_number != control_number {
+            return Err(InvalidPacketError::WrongControl {
+                expected: control_number,
+                actual: self.control_number,
+            });
+        }
+        if !supported_commands.contains(&self.command) {
+            return Err(InvalidPacketError::UnsupportedCommand {
+                control_number: self.control_number,
+                command: self.command,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Error opening one of the control pipes given in `--extcap-control-in` or
+/// `--extcap-control-out`.
+#[derive(Debug, Error)]
+pub enum ControlPipeError {
+    /// Wireshark did not open its end of the control pipe within the timeout
+    /// given in [`OpenPolicy::timeout`]. This typically means Wireshark has
+    /// crashed or was killed before the capture started.
+    #[error("Timed out after {0:?} waiting for Wireshark to open the control pipe")]
+    Timeout(Duration),
+
+    /// IO error opening the control pipe.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Policy for opening the control pipes, used in `ExtcapControlReader::open`
+/// and `ExtcapControlSender::open` in the [`synchronous`] and [`asynchronous`]
+/// modules.
+///
+/// Opening a control pipe waits until Wireshark opens the other end. By
+/// default this waits forever, which can cause the extcap to hang if Wireshark
+/// crashes. Set a [`timeout`][Self::timeout] to give up with
+/// [`ControlPipeError::Timeout`] instead.
+///
+/// ```
+/// use r_extcap::controls::OpenPolicy;
+/// use std::time::Duration;
+///
+/// let policy = OpenPolicy::builder()
+///     .timeout(Duration::from_secs(5))
+///     .retry_interval(Duration::from_millis(50))
+///     .build();
+/// ```
+#[derive(Clone, Debug, TypedBuilder)]
+pub struct OpenPolicy {
+    /// The maximum time to wait for Wireshark to open its end of the pipe, or
+    /// `None` to wait forever.
+    #[builder(default, setter(strip_option))]
+    pub timeout: Option<Duration>,
+    /// The time to wait between attempts to open or poll the pipe while
+    /// Wireshark has not opened its end yet.
+    #[builder(default = Duration::from_millis(100))]
+    pub retry_interval: Duration,
+}
+
+impl Default for OpenPolicy {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+/// What an `ExtcapControlSender` in the [`synchronous`] or [`asynchronous`]
+/// module does when Wireshark closes the control pipe, for example because
+/// the toolbar was closed or Wireshark crashed. Set it using the sender's
+/// `with_error_policy` method.
+///
+/// Only broken pipe errors are handled by this policy. Other IO errors are
+/// always returned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SendErrorPolicy {
+    /// Return an error for the failed packet and every packet sent after it.
+    #[default]
+    FailFast,
+    /// Drop the failed packet and every packet sent after it, returning
+    /// `Ok(())`, so that the capture can continue without the toolbar.
+    IgnoreAfterBroken,
+    /// Drop the failed packet, and try to open the pipe again before each
+    /// subsequent packet. Packets are dropped while the pipe cannot be
+    /// opened.
+    TryReopen,
+}
+
+impl SendErrorPolicy {
+    /// Handles the result of sending a packet. Returns the result to return
+    /// from `send`, and whether the pipe is still connected.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub(crate) fn handle(self, result: std::io::Result<()>) -> (std::io::Result<()>, bool) {
+        match result {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+                log::warn!("Control pipe closed by Wireshark");
+                match self {
+                    SendErrorPolicy::FailFast => (Err(e), false),
+                    _ => (Ok(()), false),
+                }
+            }
+            result => (result, true),
+        }
+    }
+
+    /// The result of sending a packet while the pipe is disconnected.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub(crate) fn disconnected_result(self) -> std::io::Result<()> {
+        match self {
+            SendErrorPolicy::FailFast => Err(std::io::ErrorKind::BrokenPipe.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl OpenPolicy {
+    /// Returns the time at which an open attempt started at `start` should
+    /// give up, or `None` if it should wait forever.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub(crate) fn deadline(&self, start: std::time::Instant) -> Option<std::time::Instant> {
+        self.timeout.map(|timeout| start + timeout)
+    }
+
+    /// Returns the time to wait before the next attempt, or
+    /// [`ControlPipeError::Timeout`] if `deadline` has passed.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    pub(crate) fn next_wait(
+        &self,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Duration, ControlPipeError> {
+        match deadline {
+            None => Ok(self.retry_interval),
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                if remaining.is_zero() {
+                    Err(ControlPipeError::Timeout(self.timeout.unwrap_or_default()))
+                } else {
+                    Ok(remaining.min(self.retry_interval))
+                }
+            }
+        }
+    }
+}
+
+/// Returns the sentences declaring `controls`, as printed in the
+/// `--extcap-interfaces` step, including the `value` sentences of the
+/// selectors. The output only depends on the controls, so it can be compared
+/// with a golden file in tests, to catch accidental changes to the toolbar
+/// like renumbered controls.
+///
+/// ```
+/// use r_extcap::controls::*;
+///
+/// let verify = BooleanControl::builder()
+///     .control_number(0)
+///     .display("Verify")
+///     .build();
+/// let button = ButtonControl::builder()
+///     .control_number(1)
+///     .display("Reset")
+///     .build();
+/// assert_eq!(
+///     to_sentences(&[&verify, &button]),
+///     "control {number=0}{type=boolean}{display=Verify}{default=false}\n\
+///      control {number=1}{type=button}{display=Reset}\n",
+/// );
+/// ```
+pub fn to_sentences(controls: &[&dyn ToolbarControl]) -> String {
+    controls
+        .iter()
+        .map(|control| crate::ExtcapFormatter(*control).to_string())
+        .collect()
 }
 
 #[cfg(test)]
@@ -670,4 +1464,244 @@ mod test {
         assert_eq!(packet, parsed_packet);
         assert!(rem.is_empty());
     }
+
+    #[test]
+    fn parse_unknown_command() {
+        let bytes = b"T\0\0\x04\x02\x2aok";
+        let (rem, packet) = ControlPacket::parse(bytes).unwrap();
+        assert!(rem.is_empty());
+        assert_eq!(packet.control_number, 2);
+        assert_eq!(packet.command, super::ControlCommand::Unknown(42));
+        assert_eq!(&*packet.payload, b"ok");
+        assert_eq!(packet.to_bytes(), bytes);
+    }
+
+    #[test]
+    #[should_panic(expected = "Control number 255 is reserved")]
+    fn reserved_control_number() {
+        super::ButtonControl::builder()
+            .control_number(super::ControlNumber::UNUSED)
+            .display("Button")
+            .build();
+    }
+
+    #[test]
+    fn payloads() {
+        use super::{LoggerControl, SelectorControl};
+
+        let logger = LoggerControl::builder()
+            .control_number(0)
+            .display("Log")
+            .build();
+        assert_eq!(&*logger.add_log("borrowed".into()).payload, b"borrowed\n");
+        assert_eq!(
+            &*logger
+                .clear_and_add_log(String::from("owned").into())
+                .payload,
+            b"owned\n"
+        );
+        let selector = SelectorControl::builder()
+            .control_number(1)
+            .display("Delay")
+            .build();
+        assert_eq!(
+            &*selector.add_value("1", Some("1 sec")).payload,
+            b"1\x001 sec"
+        );
+        assert_eq!(&*selector.add_value("1", None).payload, b"1");
+    }
+
+    #[test]
+    #[cfg(any(feature = "sync", feature = "async"))]
+    fn batch_add_log() {
+        use super::{ControlBatch, LoggerControl};
+
+        let logger = LoggerControl::builder()
+            .control_number(2)
+            .display("Log")
+            .build();
+        let mut batch = ControlBatch::default();
+        batch.add_log(&logger, "first").add_log(&logger, "second");
+        assert_eq!(batch.len(), 2);
+        assert_eq!(
+            batch.bytes(),
+            [
+                logger.add_log("first".into()).to_bytes(),
+                logger.add_log("second".into()).to_bytes(),
+            ]
+            .concat()
+        );
+        batch.clear();
+        assert!(batch.is_empty());
+        assert!(batch.bytes().is_empty());
+    }
+
+    #[test]
+    fn log_chunks() {
+        use super::{log_chunks, LoggerControl};
+
+        let chunks: Vec<_> = log_chunks("one\ntwo\nthree", 9).collect();
+        assert_eq!(chunks, ["one\ntwo\n", "three"]);
+        // A line too long for a chunk is split at a character boundary.
+        let chunks: Vec<_> = log_chunks("a\nbcdéf", 4).collect();
+        assert_eq!(chunks, ["a\n", "bcd", "éf"]);
+        // A trailing line break is kept in the last chunk instead of
+        // producing an empty one.
+        let chunks: Vec<_> = log_chunks("abc\n", 4).collect();
+        assert_eq!(chunks, ["abc\n"]);
+        let chunks: Vec<_> = log_chunks("abcd", 4).collect();
+        assert_eq!(chunks, ["abc", "d"]);
+
+        let logger = LoggerControl::builder()
+            .control_number(2)
+            .display("Log")
+            .build();
+        assert_eq!(
+            logger.add_log_chunked("short"),
+            [logger.add_log("short".into())]
+        );
+        let mut text = "a".repeat(ControlPacket::MAX_PAYLOAD_LEN - 1);
+        text.push('\n');
+        let packets = logger.add_log_chunked(&text);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].payload, text.as_bytes());
+    }
+
+    #[test]
+    #[cfg(any(feature = "sync", feature = "async"))]
+    fn batch_oversized() {
+        use super::{ControlBatch, ControlCommand, LoggerControl};
+
+        let logger = LoggerControl::builder()
+            .control_number(2)
+            .display("Log")
+            .build();
+        let mut batch = ControlBatch::default();
+        // The chunks are split at character boundaries.
+        batch.add_log_chunked(&logger, "ééab", 3);
+        let packet = |payload: &'static [u8]| {
+            ControlPacket::new_with_payload(2, ControlCommand::Add, payload).to_bytes()
+        };
+        assert_eq!(batch.len(), 3);
+        assert_eq!(
+            batch.bytes(),
+            [
+                packet("é".as_bytes()),
+                packet("éa".as_bytes()),
+                packet(b"b\n")
+            ]
+            .concat()
+        );
+        batch.clear();
+
+        // A trailing line break is not doubled.
+        batch.add_log_chunked(&logger, "abc\n", 4);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch.bytes(), packet(b"abc\n"));
+        batch.clear();
+
+        let payload = vec![0; ControlPacket::MAX_PAYLOAD_LEN + 1];
+        batch
+            .send(ControlPacket::new_with_payload(
+                2,
+                ControlCommand::Set,
+                payload,
+            ))
+            .add_log(&logger, "ok");
+        assert_eq!(batch.len(), 1);
+        let error = batch.rejected().unwrap();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        batch.clear();
+        assert!(batch.rejected().is_none());
+    }
+
+    #[test]
+    fn validate_for() {
+        use super::{
+            ButtonControl, ControlCommand, EnableableControl as _, InvalidPacketError,
+            LoggerControl, SelectorControl,
+        };
+
+        let button = ButtonControl::builder()
+            .control_number(0)
+            .display("Turn on")
+            .build();
+        let logger = LoggerControl::builder()
+            .control_number(1)
+            .display("Log")
+            .build();
+        let selector = SelectorControl::builder()
+            .control_number(2)
+            .display("Delay")
+            .options(vec![])
+            .build();
+        assert_eq!(button.set_enabled(false).validate_for(&button), Ok(()));
+        assert_eq!(logger.add_log("log".into()).validate_for(&logger), Ok(()));
+        assert_eq!(selector.clear().validate_for(&selector), Ok(()));
+        assert_eq!(
+            ControlPacket::new(0, ControlCommand::WarningMessage).validate_for(&logger),
+            Ok(())
+        );
+        assert_eq!(
+            ControlPacket::new(1, ControlCommand::Enable).validate_for(&logger),
+            Err(InvalidPacketError::UnsupportedCommand {
+                control_number: 1,
+                command: ControlCommand::Enable,
+            })
+        );
+        assert_eq!(
+            button.set_enabled(true).validate_for(&selector),
+            Err(InvalidPacketError::WrongControl {
+                expected: 2,
+                actual: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn default_packets() {
+        use super::{
+            BooleanControl, ButtonControl, ControlPanel as _, ControlWithLabel as _, LoggerControl,
+        };
+
+        crate::controls! {
+            struct Panel {
+                verify: BooleanControl {
+                    display: String::from("Verify"),
+                    tooltip: None,
+                    default_value: true,
+                },
+                log: LoggerControl {
+                    display: String::from("Log"),
+                    tooltip: None,
+                },
+                start: ButtonControl {
+                    display: String::from("Start"),
+                    tooltip: None,
+                },
+            }
+        }
+
+        let panel = Panel::new();
+        assert_eq!(
+            panel.default_packets(),
+            [
+                panel.verify.set_checked(true),
+                panel.start.set_label("Start"),
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let packet = ControlPacket::new_with_payload(
+            2,
+            super::ControlCommand::StatusbarMessage,
+            &b"Capturing"[..],
+        );
+        let json = serde_json::to_string(&packet).unwrap();
+        let parsed: ControlPacket = serde_json::from_str(&json).unwrap();
+        assert_eq!(packet, parsed);
+    }
 }
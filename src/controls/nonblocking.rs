@@ -0,0 +1,203 @@
+//! Non-blocking reader for control packets, for extcaps that run their own
+//! poll loop instead of a thread per pipe or an async runtime.
+//!
+//! [`NonblockingControlReader`] puts the control pipe in non-blocking mode and
+//! exposes its file descriptor, so that it can be registered with `poll`,
+//! [`polling`](https://docs.rs/polling), or, with the `mio` feature,
+//! [`mio`](https://docs.rs/mio). When the pipe is readable, call
+//! [`read_packets`][NonblockingControlReader::read_packets] to get the packets
+//! received so far, without blocking. This allows a single thread to multiplex
+//! the device I/O of a capture with the control pipe.
+//!
+//! ```ignore
+//! use r_extcap::controls::nonblocking::NonblockingControlReader;
+//!
+//! let mut reader = NonblockingControlReader::open(in_path)?;
+//! let mut poll = mio::Poll::new()?;
+//! poll.registry()
+//!     .register(&mut reader, mio::Token(0), mio::Interest::READABLE)?;
+//! let mut events = mio::Events::with_capacity(16);
+//! loop {
+//!     poll.poll(&mut events, None)?;
+//!     for packet in reader.read_packets()? {
+//!         // Handle the control packet
+//!     }
+//! }
+//! ```
+
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsFd, AsRawFd, BorrowedFd, RawFd},
+    },
+    path::Path,
+};
+
+use log::debug;
+use thiserror::Error;
+
+use super::{ControlPacket, ControlPacketDecoder, DecodeError};
+
+/// Error type returned for control packet read operations.
+#[derive(Debug, Error)]
+pub enum ReadControlError {
+    /// Error reading the incoming control pipe.
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    /// Error parsing the incoming data into the [`ControlPacket`] format.
+    #[error(transparent)]
+    ParseError(#[from] DecodeError),
+}
+
+/// A reader for the control pipe that never blocks. See the [module
+/// documentation][self] for details.
+#[derive(Debug)]
+pub struct NonblockingControlReader {
+    in_file: File,
+    decoder: ControlPacketDecoder,
+    connected: bool,
+}
+
+impl NonblockingControlReader {
+    /// Opens the control pipe given in `--extcap-control-in` in non-blocking
+    /// mode. Unlike the other readers, this returns immediately, even if
+    /// Wireshark has not opened its end of the pipe yet.
+    pub fn open(in_path: &Path) -> std::io::Result<Self> {
+        let in_file = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(in_path)?;
+        Ok(Self::with_file(in_file))
+    }
+
+    /// Creates a reader for a control pipe that is already open, setting it to
+    /// non-blocking mode.
+    pub fn from_file(in_file: File) -> std::io::Result<Self> {
+        let fd = in_file.as_raw_fd();
+        // Safety: `fd` is a valid file descriptor owned by `in_file`.
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(Self::with_file(in_file))
+    }
+
+    fn with_file(in_file: File) -> Self {
+        Self {
+            in_file,
+            decoder: ControlPacketDecoder::new(),
+            connected: false,
+        }
+    }
+
+    /// Reads all of the data currently available in the pipe, and returns the
+    /// complete packets in it, which may be empty. Incomplete packets are kept
+    /// until the rest of the packet is read in a later call.
+    ///
+    /// Before Wireshark opens its end of the pipe, this returns no packets.
+    /// Once some data has been received, `UnexpectedEof` is returned when
+    /// Wireshark closes the pipe.
+    pub fn read_packets(&mut self) -> Result<Vec<ControlPacket<'static>>, ReadControlError> {
+        let mut packets = Vec::new();
+        let mut buffer = [0_u8; 4096];
+        loop {
+            match self.in_file.read(&mut buffer) {
+                Ok(0) if self.connected && packets.is_empty() => {
+                    Err(std::io::Error::from(ErrorKind::UnexpectedEof))?
+                }
+                Ok(0) => break,
+                Ok(len) => {
+                    self.connected = true;
+                    packets.extend(self.decoder.push_bytes(&buffer[..len])?);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => Err(e)?,
+            }
+        }
+        debug!("Read incoming control messages: {packets:?}");
+        Ok(packets)
+    }
+}
+
+impl AsFd for NonblockingControlReader {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.in_file.as_fd()
+    }
+}
+
+impl AsRawFd for NonblockingControlReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.in_file.as_raw_fd()
+    }
+}
+
+#[cfg(feature = "mio")]
+impl mio::event::Source for NonblockingControlReader {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.as_raw_fd()).deregister(registry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::File,
+        io::{ErrorKind, Write},
+        os::unix::io::FromRawFd,
+    };
+
+    use super::{NonblockingControlReader, ReadControlError};
+    use crate::controls::{ControlCommand, ControlPacket};
+
+    fn pipe() -> (File, File) {
+        let mut fds = [0; 2];
+        // Safety: `fds` has room for the two file descriptors.
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        // Safety: `pipe` returned two new file descriptors owned by no one else.
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) }
+    }
+
+    #[test]
+    fn read_without_blocking() {
+        let (read_end, mut write_end) = pipe();
+        let mut reader = NonblockingControlReader::from_file(read_end).unwrap();
+        assert!(reader.read_packets().unwrap().is_empty());
+
+        let packet = ControlPacket::new_with_payload(1, ControlCommand::Set, &b"on"[..]);
+        let bytes = packet.to_bytes();
+        write_end.write_all(&bytes[..3]).unwrap();
+        assert!(reader.read_packets().unwrap().is_empty());
+        write_end.write_all(&bytes[3..]).unwrap();
+        assert_eq!(reader.read_packets().unwrap(), [packet]);
+
+        drop(write_end);
+        assert!(matches!(
+            reader.read_packets(),
+            Err(ReadControlError::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof
+        ));
+    }
+}
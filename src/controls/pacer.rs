@@ -0,0 +1,239 @@
+use std::time::{Duration, Instant};
+
+use super::ControlPacket;
+use crate::shutdown::ShutdownSignal;
+
+/// How often a wait checks the [`ShutdownSignal`], which has no way to wake
+/// up a waiting thread.
+#[cfg(any(feature = "sync", feature = "async"))]
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Paces a capture loop that emits a packet every `interval`, like a demo or
+/// replay extcap, without delaying the handling of control packets.
+///
+/// Unlike `thread::sleep`, waiting for the next tick returns early when a
+/// control packet arrives or the capture is shut down, so that the loop can
+/// handle it right away. The ticks are scheduled at a fixed rate, so the time
+/// spent handling packets does not add up. If the loop falls behind by more
+/// than an interval, the missed ticks are skipped instead of fired in a burst.
+///
+/// ```no_run
+/// # use r_extcap::controls::{Pacer, PacerEvent, synchronous::ControlSession};
+/// # use std::time::Duration;
+/// # fn example(controls: &ControlSession) {
+/// let mut pacer = Pacer::new(Duration::from_secs(1));
+/// loop {
+///     match pacer.wait(controls.reader()) {
+///         PacerEvent::Tick => { /* Write the next packet */ }
+///         PacerEvent::Control(packet) => { /* Handle the control packet */ }
+///         PacerEvent::Shutdown => break,
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Pacer {
+    interval: Duration,
+    next_tick: Instant,
+    shutdown: Option<ShutdownSignal>,
+}
+
+/// The reason a wait of a [`Pacer`] returned.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PacerEvent {
+    /// The next tick is due.
+    Tick,
+    /// A control packet arrived before the next tick.
+    Control(ControlPacket<'static>),
+    /// The [shutdown signal][Pacer::with_shutdown] was triggered.
+    Shutdown,
+}
+
+impl Pacer {
+    /// Creates a pacer that ticks every `interval`. The first tick is
+    /// immediate.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next_tick: Instant::now(),
+            shutdown: None,
+        }
+    }
+
+    /// Returns [`PacerEvent::Shutdown`] from the waits once `signal` is
+    /// triggered, like [`ShutdownSignal::global`].
+    #[must_use]
+    pub fn with_shutdown(mut self, signal: &ShutdownSignal) -> Self {
+        self.shutdown = Some(signal.clone());
+        self
+    }
+
+    /// The time between ticks.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Changes the time between ticks, for example when the user changes a
+    /// delay control. The next tick is rescheduled to one new `interval`
+    /// after the last tick.
+    pub fn set_interval(&mut self, interval: Duration) {
+        let last_tick = self
+            .next_tick
+            .checked_sub(self.interval)
+            .unwrap_or(self.next_tick);
+        self.next_tick = last_tick + interval;
+        self.interval = interval;
+    }
+
+    #[cfg(any(feature = "sync", feature = "async"))]
+    fn is_shutdown(&self) -> bool {
+        self.shutdown
+            .as_ref()
+            .is_some_and(ShutdownSignal::is_triggered)
+    }
+
+    /// The time until the next tick, or `None` if it is due, in which case the
+    /// tick after it is scheduled.
+    #[cfg(any(feature = "sync", feature = "async", test))]
+    fn remaining(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        if now < self.next_tick {
+            return Some(self.next_tick - now);
+        }
+        self.next_tick += self.interval;
+        if self.next_tick < now {
+            // Skip the missed ticks.
+            self.next_tick = now + self.interval;
+        }
+        None
+    }
+
+    /// Blocks until the next tick, a control packet from `reader`, or the
+    /// shutdown signal, whichever comes first. Control packets and the
+    /// shutdown signal take precedence over a tick that is due.
+    ///
+    /// If `reader` is `None` or disconnected, this only waits for the tick and
+    /// the shutdown signal.
+    #[cfg(feature = "sync")]
+    pub fn wait(
+        &mut self,
+        reader: Option<&super::synchronous::ChannelExtcapControlReader>,
+    ) -> PacerEvent {
+        use std::sync::mpsc::RecvTimeoutError;
+
+        loop {
+            if self.is_shutdown() {
+                return PacerEvent::Shutdown;
+            }
+            if let Some(packet) = reader.and_then(|reader| reader.try_read_packet()) {
+                return PacerEvent::Control(packet);
+            }
+            let Some(remaining) = self.remaining() else {
+                return PacerEvent::Tick;
+            };
+            let timeout = match self.shutdown {
+                Some(_) => remaining.min(SHUTDOWN_POLL_INTERVAL),
+                None => remaining,
+            };
+            match reader.map(|reader| reader.read_channel.recv_timeout(timeout)) {
+                Some(Ok(packet)) => return PacerEvent::Control(packet),
+                Some(Err(RecvTimeoutError::Timeout)) => {}
+                Some(Err(RecvTimeoutError::Disconnected)) | None => std::thread::sleep(timeout),
+            }
+        }
+    }
+
+    /// The async version of [`wait`][Self::wait].
+    #[cfg(feature = "async")]
+    pub async fn wait_async(
+        &mut self,
+        mut reader: Option<&mut super::asynchronous::ChannelExtcapControlReader>,
+    ) -> PacerEvent {
+        loop {
+            if self.is_shutdown() {
+                return PacerEvent::Shutdown;
+            }
+            if let Some(reader) = reader.as_deref_mut() {
+                if let Ok(packet) = reader.read_channel.try_recv() {
+                    return PacerEvent::Control(packet);
+                }
+            }
+            let Some(remaining) = self.remaining() else {
+                return PacerEvent::Tick;
+            };
+            let timeout = match self.shutdown {
+                Some(_) => remaining.min(SHUTDOWN_POLL_INTERVAL),
+                None => remaining,
+            };
+            let sleep = tokio::time::sleep(timeout);
+            match reader.as_deref_mut() {
+                // Once the channel is closed, `recv` returns `None` and only
+                // the sleep is awaited.
+                Some(reader) => tokio::select! {
+                    Some(packet) = reader.read_channel.recv() => {
+                        return PacerEvent::Control(packet);
+                    }
+                    _ = sleep => {}
+                },
+                None => sleep.await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Pacer;
+
+    #[test]
+    fn skips_missed_ticks() {
+        let mut pacer = Pacer::new(Duration::from_millis(50));
+        assert_eq!(pacer.remaining(), None);
+        assert!(pacer.remaining().is_some());
+        std::thread::sleep(Duration::from_millis(120));
+        assert_eq!(pacer.remaining(), None);
+        // The ticks missed while sleeping are not fired in a burst.
+        assert!(pacer.remaining().unwrap() > Duration::from_millis(40));
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn wait() {
+        use std::time::Instant;
+
+        use super::PacerEvent;
+        use crate::{
+            controls::{synchronous::ChannelExtcapControlReader, ControlCommand, ControlPacket},
+            shutdown::{ShutdownReason, ShutdownSignal},
+        };
+
+        let shutdown = ShutdownSignal::default();
+        let mut pacer = Pacer::new(Duration::from_millis(100)).with_shutdown(&shutdown);
+        assert_eq!(pacer.wait(None), PacerEvent::Tick);
+        let mut packets = vec![
+            Ok(None),
+            Ok(Some(ControlPacket::new(1, ControlCommand::Set))),
+        ];
+        let reader = ChannelExtcapControlReader::spawn_with(move || packets.pop().unwrap());
+        assert_eq!(
+            pacer.wait(Some(&reader)),
+            PacerEvent::Control(ControlPacket::new(1, ControlCommand::Set))
+        );
+        assert_eq!(pacer.wait(Some(&reader)), PacerEvent::Tick);
+        let start = Instant::now();
+        assert_eq!(pacer.wait(Some(&reader)), PacerEvent::Tick);
+        assert!(start.elapsed() >= Duration::from_millis(90));
+
+        let trigger = shutdown.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            trigger.trigger(ShutdownReason::Terminated);
+        });
+        pacer.set_interval(Duration::from_secs(10));
+        let start = Instant::now();
+        assert_eq!(pacer.wait(None), PacerEvent::Shutdown);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
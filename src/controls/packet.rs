@@ -0,0 +1,449 @@
+//! The control packets exchanged with Wireshark through the control pipes.
+//! This module only needs `alloc`, so the packet format is available without
+//! the `std` feature.
+
+use alloc::{borrow::Cow, vec::Vec};
+
+#[cfg(feature = "std")]
+use nom::{
+    combinator::map,
+    number::streaming::{be_u24, be_u8},
+};
+#[cfg(feature = "std")]
+use nom_derive::Nom;
+
+#[cfg(feature = "async")]
+use super::asynchronous::ExtcapControlSenderTrait as _;
+#[cfg(feature = "sync")]
+use super::synchronous::ExtcapControlSenderTrait as _;
+#[cfg(all(doc, feature = "std"))]
+use super::{BooleanControl, ButtonControl, LoggerControl, SelectorControl, StringControl};
+
+/// Control packets for the extcap interface. This is used for communication of
+/// control data between Wireshark and this extcap program.
+///
+/// Reference:
+/// <https://www.wireshark.org/docs/wsdg_html_chunked/ChCaptureExtcap.html#_messages>
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Nom))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ControlPacket<'a> {
+    /// The common sync pipe indication. This protocol uses the value "T".
+    #[cfg_attr(feature = "std", nom(Verify = "*sync_pipe_indication == b'T'"))]
+    pub sync_pipe_indication: u8,
+    /// Length of `payload` + 2 bytes for `control_number` and `command`.
+    #[cfg_attr(feature = "std", nom(Parse = "be_u24"))]
+    pub message_length: u32,
+    /// Unique number to identify the control, as previously returned in the
+    /// `{control}` declarations returned in the
+    /// [`--extcap-interfaces`][crate::ExtcapArgs::extcap_interfaces] phase. This
+    /// number also gives the order of the controls in the interface toolbar.
+    /// The control number, a unique identifier for this control.
+    pub control_number: u8,
+    /// The command associated with this packet. See [`ControlCommand`] for
+    /// details.
+    #[cfg_attr(feature = "std", nom(Parse = "map(be_u8, ControlCommand::from)"))]
+    pub command: ControlCommand,
+    /// Payload specific to the [`command`][Self::command]. For example, the
+    /// payload for [`StatusbarMessage`][ControlCommand::StatusbarMessage] is
+    /// the message string.
+    #[cfg_attr(
+        feature = "std",
+        nom(Map = "Cow::from", Take = "(message_length - 2) as usize")
+    )]
+    pub payload: Cow<'a, [u8]>,
+}
+
+impl<'a> ControlPacket<'a> {
+    /// The maximum length of the payload. The `message_length` in the header
+    /// is 24 bits long, and includes the control number and command.
+    pub const MAX_PAYLOAD_LEN: usize = 0xFF_FFFF - 2;
+
+    /// Creates a new control packet with a payload.
+    ///
+    /// The payload must be at most [`MAX_PAYLOAD_LEN`][Self::MAX_PAYLOAD_LEN]
+    /// bytes long, otherwise the header cannot represent its length and the
+    /// senders in this crate reject the packet. Use
+    /// [`try_new_with_payload`][Self::try_new_with_payload] to check the
+    /// length when creating the packet.
+    #[must_use]
+    pub fn new_with_payload<CowSlice: Into<Cow<'a, [u8]>>>(
+        control_number: u8,
+        command: ControlCommand,
+        payload: CowSlice,
+    ) -> Self {
+        debug_assert!(
+            !(command.is_control_command() && control_number == ControlNumber::UNUSED),
+            "{command:?} packets must be for a control, but control number {control_number} is reserved"
+        );
+        let payload = payload.into();
+        ControlPacket {
+            sync_pipe_indication: b'T',
+            message_length: (payload.len() + 2) as u32,
+            control_number,
+            command,
+            payload,
+        }
+    }
+
+    /// Creates a new control packet with a payload, or returns an error if
+    /// the payload is longer than [`MAX_PAYLOAD_LEN`][Self::MAX_PAYLOAD_LEN].
+    pub fn try_new_with_payload<CowSlice: Into<Cow<'a, [u8]>>>(
+        control_number: u8,
+        command: ControlCommand,
+        payload: CowSlice,
+    ) -> Result<Self, PayloadTooLarge> {
+        let payload = payload.into();
+        if payload.len() > Self::MAX_PAYLOAD_LEN {
+            return Err(PayloadTooLarge(payload.len()));
+        }
+        Ok(Self::new_with_payload(control_number, command, payload))
+    }
+
+    /// Creates a new control packet with an empty payload.
+    #[must_use]
+    pub fn new(control_number: u8, command: ControlCommand) -> Self {
+        let empty_slice: &'static [u8] = &[];
+        Self::new_with_payload(control_number, command, empty_slice)
+    }
+
+    /// Outputs the serialzied bytes of the header to send back to wireshark.
+    pub fn to_header_bytes(&self) -> [u8; 6] {
+        let mut bytes = [0_u8; 6];
+        bytes[0] = self.sync_pipe_indication;
+        bytes[1..4].copy_from_slice(&self.message_length.to_be_bytes()[1..]);
+        bytes[4] = self.control_number;
+        bytes[5] = self.command.into();
+        bytes
+    }
+
+    /// Outputs the serialized bytes of the whole packet, which is the header
+    /// followed by the payload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [&self.to_header_bytes()[..], &self.payload].concat()
+    }
+
+    /// Turns the given ControlPacket into a ControlPacket with fully owned data
+    /// and 'static lifetime.
+    pub fn into_owned(self) -> ControlPacket<'static> {
+        ControlPacket {
+            payload: match self.payload {
+                Cow::Borrowed(v) => Cow::Owned(v.to_vec()),
+                Cow::Owned(v) => Cow::Owned(v),
+            },
+            ..self
+        }
+    }
+
+    /// Sends this control packet to Wireshark using the given `sender`.
+    #[cfg(feature = "sync")]
+    pub fn send(self, sender: &mut super::synchronous::ExtcapControlSender) -> std::io::Result<()> {
+        sender.send(self)
+    }
+
+    /// Sends this control packet to Wireshark using the given `sender`.
+    #[cfg(feature = "async")]
+    pub async fn send_async(
+        self,
+        sender: &mut super::asynchronous::ExtcapControlSender,
+    ) -> tokio::io::Result<()> {
+        sender.send(self).await
+    }
+}
+
+/// The control command for the control packet. Note that a `ControlCommand` is
+/// not valid for all control types, for example, the `Remove` command is
+/// applicable only to [`SelectorControls`][SelectorControl], and `Initialized`
+/// is only sent by Wireshark to this extcap program.
+///
+/// Commands are a single byte in the control packets. A byte that does not
+/// correspond to a known command, for example one added in a later version of
+/// Wireshark, is parsed as [`Unknown`][Self::Unknown], so that the packet can
+/// be skipped instead of failing the whole control pipe.
+///
+/// ```
+/// use r_extcap::controls::ControlCommand;
+///
+/// assert_eq!(ControlCommand::from(1), ControlCommand::Set);
+/// assert_eq!(ControlCommand::from(42), ControlCommand::Unknown(42));
+/// assert_eq!(u8::from(ControlCommand::ErrorMessage), 9);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ControlCommand {
+    /// Sent by Wireshark to indicate that this extcap has been initialized and
+    /// is ready to accept packets.
+    Initialized,
+    /// Either sent by Wireshark to indicate that the user has interacted with
+    /// one of the controls, or sent by the extcap program to change the value
+    /// on a given control.
+    ///
+    /// Used by control types: [`BooleanControl`], [`ButtonControl`],
+    /// [`LoggerControl`], [`SelectorControl`], and [`StringControl`].
+    Set,
+    /// Sent by the extcap program to add a value to the given logger or
+    /// selector.
+    ///
+    /// Used by control types: [`LoggerControl`] and [`SelectorControl`].
+    Add,
+    /// Sent by the extcap program to remove a value from the given selector.
+    ///
+    /// Used by control types: [`SelectorControl`].
+    Remove,
+    /// Sent by the extcap program to enable a given control.
+    ///
+    /// Used by control types: [`BooleanControl`], [`ButtonControl`],
+    /// [`SelectorControl`], and [`StringControl`].
+    Enable,
+    /// Sent by the extcap program to disable a given control.
+    ///
+    /// Used by control types: [`BooleanControl`], [`ButtonControl`],
+    /// [`SelectorControl`], and [`StringControl`].
+    Disable,
+    /// Sent by the extcap program to show a message in the status bar.
+    StatusbarMessage,
+    /// Sent by the extcap program to show a message in an information dialog
+    /// popup.
+    InformationMessage,
+    /// Sent by the extcap program to show a message in a warning dialog popup.
+    WarningMessage,
+    /// Sent by the extcap program to show a message in an error dialog popup.
+    ErrorMessage,
+    /// A command that is not known to this crate, with its value. Packets
+    /// with unknown commands are resolved to [`ControlEvent::Unknown`].
+    ///
+    /// This is only created for values that do not correspond to any of the
+    /// other variants.
+    ///
+    /// [`ControlEvent::Unknown`]: super::ControlEvent::Unknown
+    Unknown(u8),
+}
+
+impl From<u8> for ControlCommand {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => Self::Initialized,
+            1 => Self::Set,
+            2 => Self::Add,
+            3 => Self::Remove,
+            4 => Self::Enable,
+            5 => Self::Disable,
+            6 => Self::StatusbarMessage,
+            7 => Self::InformationMessage,
+            8 => Self::WarningMessage,
+            9 => Self::ErrorMessage,
+            value => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<ControlCommand> for u8 {
+    fn from(command: ControlCommand) -> Self {
+        command.value()
+    }
+}
+
+impl ControlCommand {
+    /// The value of this command in the control packets.
+    pub const fn value(self) -> u8 {
+        match self {
+            Self::Initialized => 0,
+            Self::Set => 1,
+            Self::Add => 2,
+            Self::Remove => 3,
+            Self::Enable => 4,
+            Self::Disable => 5,
+            Self::StatusbarMessage => 6,
+            Self::InformationMessage => 7,
+            Self::WarningMessage => 8,
+            Self::ErrorMessage => 9,
+            Self::Unknown(value) => value,
+        }
+    }
+
+    /// Whether this command is addressed to a specific control, as opposed to
+    /// `Initialized` and the messages shown by Wireshark, which are not tied
+    /// to any control.
+    pub fn is_control_command(self) -> bool {
+        matches!(
+            self,
+            Self::Set | Self::Add | Self::Remove | Self::Enable | Self::Disable
+        )
+    }
+}
+
+/// A set of [`ControlCommand`]s, used to subscribe to some of the control
+/// packets sent by Wireshark.
+///
+/// ```
+/// use r_extcap::controls::{ControlCommand, ControlCommandMask};
+///
+/// let mask = ControlCommandMask::from(ControlCommand::Initialized) | ControlCommand::Set.into();
+/// assert!(mask.contains(ControlCommand::Set));
+/// assert!(!mask.contains(ControlCommand::StatusbarMessage));
+/// assert!(!mask.contains(ControlCommand::Unknown(42)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ControlCommandMask(u16);
+
+impl ControlCommandMask {
+    /// Contains all of the commands.
+    pub const ALL: Self =
+        Self(((1 << (ControlCommand::ErrorMessage.value() + 1)) - 1) | Self::UNKNOWN_BIT);
+    /// Contains none of the commands.
+    pub const NONE: Self = Self(0);
+    /// The bit shared by all of the [`Unknown`][ControlCommand::Unknown]
+    /// commands, which do not have a bit of their own.
+    const UNKNOWN_BIT: u16 = 1 << 15;
+
+    const fn bit(command: ControlCommand) -> u16 {
+        match command {
+            ControlCommand::Unknown(_) => Self::UNKNOWN_BIT,
+            command => 1 << command.value(),
+        }
+    }
+
+    /// Returns this mask with `command` added.
+    #[must_use]
+    pub const fn with(self, command: ControlCommand) -> Self {
+        Self(self.0 | Self::bit(command))
+    }
+
+    /// Whether `command` is in this mask.
+    pub const fn contains(self, command: ControlCommand) -> bool {
+        self.0 & Self::bit(command) != 0
+    }
+
+    /// The bits of this mask, where bit `n` is set if the command with the
+    /// value `n` is in the mask. Bit 15 is set if the
+    /// [`Unknown`][ControlCommand::Unknown] commands are in the mask.
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Creates a mask from its [`bits`][Self::bits]. Bits that do not
+    /// correspond to a command are ignored.
+    pub const fn from_bits(bits: u16) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+}
+
+impl Default for ControlCommandMask {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl From<ControlCommand> for ControlCommandMask {
+    fn from(command: ControlCommand) -> Self {
+        Self::NONE.with(command)
+    }
+}
+
+impl core::ops::BitOr for ControlCommandMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl FromIterator<ControlCommand> for ControlCommandMask {
+    fn from_iter<T: IntoIterator<Item = ControlCommand>>(iter: T) -> Self {
+        iter.into_iter().fold(Self::NONE, Self::with)
+    }
+}
+
+/// A control number that can be declared for a toolbar control.
+///
+/// Control numbers are a single byte in the control packets, but 255 is
+/// reserved for packets that are not for a control, like status bar messages,
+/// so the valid range is `0..=254`.
+///
+/// ```
+/// use r_extcap::controls::ControlNumber;
+///
+/// assert_eq!(ControlNumber::new(3).unwrap().get(), 3);
+/// assert!(ControlNumber::new(ControlNumber::UNUSED).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ControlNumber(u8);
+
+impl ControlNumber {
+    /// The largest valid control number.
+    pub const MAX: Self = Self(254);
+    /// The number used in packets that are not for a control, like
+    /// [`StatusbarMessage`][ControlCommand::StatusbarMessage].
+    pub const UNUSED: u8 = 255;
+
+    /// Creates a control number, or returns an error if `number` is
+    /// [reserved][Self::UNUSED].
+    pub const fn new(number: u8) -> Result<Self, InvalidControlNumber> {
+        if number == Self::UNUSED {
+            Err(InvalidControlNumber(number))
+        } else {
+            Ok(Self(number))
+        }
+    }
+
+    /// Returns the number.
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl TryFrom<u8> for ControlNumber {
+    type Error = InvalidControlNumber;
+
+    fn try_from(number: u8) -> Result<Self, Self::Error> {
+        Self::new(number)
+    }
+}
+
+impl From<ControlNumber> for u8 {
+    fn from(number: ControlNumber) -> Self {
+        number.0
+    }
+}
+
+impl core::fmt::Display for ControlNumber {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Error for a control number that is reserved. See [`ControlNumber`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidControlNumber(pub u8);
+
+impl core::fmt::Display for InvalidControlNumber {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Control number {} is reserved for packets that are not for a control",
+            self.0
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for InvalidControlNumber {}
+
+/// Error for a control packet whose payload is longer than
+/// [`ControlPacket::MAX_PAYLOAD_LEN`]. Contains the length of the payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PayloadTooLarge(pub usize);
+
+impl core::fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Control packet payload of {} bytes exceeds the maximum of {} bytes",
+            self.0,
+            ControlPacket::MAX_PAYLOAD_LEN
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PayloadTooLarge {}
@@ -0,0 +1,272 @@
+use std::time::Duration;
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::{borrow::Cow, time::Instant};
+
+#[cfg(any(feature = "sync", feature = "async"))]
+use log::debug;
+use typed_builder::TypedBuilder;
+
+use super::LoggerControl;
+#[cfg(any(feature = "sync", feature = "async"))]
+use super::{log_line, ControlCommand, ControlNumber, ControlPacket};
+
+/// Reports the progress of a slow setup step, like connecting to a device or
+/// flashing firmware, so that Wireshark does not look frozen while the capture
+/// starts.
+///
+/// While the setup runs, a background thread or task periodically shows the
+/// message with the elapsed time in the status bar, like `Connecting to
+/// device… 3s`, and optionally adds it to a [`LoggerControl`].
+///
+/// ```no_run
+/// # use r_extcap::controls::{StartupProgress, synchronous::ExtcapControlSender};
+/// # fn connect() {}
+/// # fn example(sender: Option<ExtcapControlSender>) {
+/// let progress = StartupProgress::builder()
+///     .message("Connecting to device")
+///     .build()
+///     .begin(sender);
+/// connect();
+/// let sender = progress.end();
+/// # }
+/// ```
+///
+/// The sender is moved into the background thread or task, and given back by
+/// `end`. Since `Option<T>` is a no-op sender when it is `None`, the sender
+/// for an optional `--extcap-control-out` can be passed directly.
+#[derive(Clone, Debug, TypedBuilder)]
+pub struct StartupProgress {
+    /// The message to show, without the elapsed time.
+    #[builder(setter(into))]
+    pub message: String,
+    /// How often the message is updated.
+    #[builder(default = Duration::from_secs(1))]
+    pub interval: Duration,
+    /// The control number of the logger to also add the messages to, if any.
+    #[builder(default, setter(transform = |logger: &LoggerControl| Some(logger.control_number)))]
+    pub logger: Option<u8>,
+}
+
+impl StartupProgress {
+    /// The packets to send after `elapsed` time.
+    #[cfg(any(feature = "sync", feature = "async"))]
+    fn packets(&self, elapsed: Duration) -> Vec<ControlPacket<'static>> {
+        let text = if elapsed.as_secs() == 0 {
+            format!("{}…", self.message)
+        } else {
+            format!("{}… {}s", self.message, elapsed.as_secs())
+        };
+        let mut packets = Vec::with_capacity(2);
+        if let Some(logger) = self.logger {
+            packets.push(ControlPacket::new_with_payload(
+                logger,
+                ControlCommand::Add,
+                log_line(Cow::Borrowed(&text)),
+            ));
+        }
+        packets.push(ControlPacket::new_with_payload(
+            ControlNumber::UNUSED,
+            ControlCommand::StatusbarMessage,
+            text.into_bytes(),
+        ));
+        packets
+    }
+
+    /// Starts showing the progress from a background thread, using `sender`
+    /// until [`end`][ProgressHandle::end] is called or the handle is dropped.
+    #[cfg(feature = "sync")]
+    pub fn begin<S: ProgressSender>(self, mut sender: S) -> ProgressHandle<S> {
+        use std::sync::mpsc::{self, RecvTimeoutError};
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            let start = Instant::now();
+            loop {
+                for packet in self.packets(start.elapsed()) {
+                    if let Err(e) = sender.send_progress(packet) {
+                        debug!("Cannot send startup progress: {e}");
+                    }
+                }
+                match stopped.recv_timeout(self.interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return sender,
+                }
+            }
+        });
+        ProgressHandle { stop, thread }
+    }
+
+    /// Starts showing the progress from a background task, using `sender`
+    /// until [`end`][AsyncProgressHandle::end] is called or the handle is
+    /// dropped.
+    ///
+    /// This must be called within a Tokio runtime.
+    #[cfg(feature = "async")]
+    pub fn begin_async<S: AsyncProgressSender>(self, mut sender: S) -> AsyncProgressHandle<S> {
+        let (stop, mut stopped) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::task::spawn(async move {
+            let start = Instant::now();
+            loop {
+                for packet in self.packets(start.elapsed()) {
+                    if let Err(e) = sender.send_progress(packet).await {
+                        debug!("Cannot send startup progress: {e}");
+                    }
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(self.interval) => {}
+                    _ = &mut stopped => return sender,
+                }
+            }
+        });
+        AsyncProgressHandle { stop, task }
+    }
+}
+
+/// A control sender that can be moved into the thread started by
+/// [`StartupProgress::begin`].
+///
+/// This is implemented for every sender `S` where `&mut S` implements
+/// [`ExtcapControlSenderTrait`][super::synchronous::ExtcapControlSenderTrait],
+/// including `Option<ExtcapControlSender>`. Bounding on this trait instead of
+/// on `&mut S` directly lets the compiler infer `S` from the argument.
+#[cfg(feature = "sync")]
+pub trait ProgressSender: Send + 'static {
+    /// Sends a control packet to Wireshark.
+    fn send_progress(&mut self, packet: ControlPacket<'_>) -> std::io::Result<()>;
+}
+
+#[cfg(feature = "sync")]
+impl<S> ProgressSender for S
+where
+    S: Send + 'static,
+    for<'a> &'a mut S: super::synchronous::ExtcapControlSenderTrait,
+{
+    fn send_progress(&mut self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+        use super::synchronous::ExtcapControlSenderTrait as _;
+        self.send(packet)
+    }
+}
+
+/// The progress started by [`StartupProgress::begin`]. Dropping the handle
+/// stops the progress without waiting for the background thread.
+#[cfg(feature = "sync")]
+pub struct ProgressHandle<S> {
+    stop: std::sync::mpsc::Sender<()>,
+    thread: std::thread::JoinHandle<S>,
+}
+
+#[cfg(feature = "sync")]
+impl<S> ProgressHandle<S> {
+    /// Stops the progress, and returns the sender given to
+    /// [`StartupProgress::begin`].
+    pub fn end(self) -> S {
+        let _ = self.stop.send(());
+        self.thread
+            .join()
+            .expect("Startup progress thread panicked")
+    }
+}
+
+/// A control sender that can be moved into the task started by
+/// [`StartupProgress::begin_async`]. This is the async counterpart of
+/// [`ProgressSender`].
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+pub trait AsyncProgressSender: Send + Sync + 'static {
+    /// Sends a control packet to Wireshark.
+    async fn send_progress(&mut self, packet: ControlPacket<'static>) -> std::io::Result<()>;
+}
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl<S> AsyncProgressSender for S
+where
+    S: Send + Sync + 'static,
+    for<'a> &'a mut S: super::asynchronous::ExtcapControlSenderTrait,
+{
+    async fn send_progress(&mut self, packet: ControlPacket<'static>) -> std::io::Result<()> {
+        use super::asynchronous::ExtcapControlSenderTrait as _;
+        self.send(packet).await
+    }
+}
+
+/// The progress started by [`StartupProgress::begin_async`]. Dropping the
+/// handle stops the progress without waiting for the background task.
+#[cfg(feature = "async")]
+pub struct AsyncProgressHandle<S> {
+    stop: tokio::sync::oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<S>,
+}
+
+#[cfg(feature = "async")]
+impl<S> AsyncProgressHandle<S> {
+    /// Stops the progress, and returns the sender given to
+    /// [`StartupProgress::begin_async`].
+    pub async fn end(self) -> S {
+        let _ = self.stop.send(());
+        self.task.await.expect("Startup progress task panicked")
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod test {
+    use std::time::Duration;
+
+    use super::StartupProgress;
+    use crate::controls::{
+        synchronous::ExtcapControlSenderTrait, ControlCommand, ControlPacket, LoggerControl,
+    };
+
+    /// Collects the sent packets.
+    #[derive(Default)]
+    struct Collect(Vec<ControlPacket<'static>>);
+
+    impl ExtcapControlSenderTrait for &mut Collect {
+        fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+            self.0.push(packet.into_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn progress() {
+        let logger = LoggerControl::builder()
+            .control_number(3)
+            .display("Log")
+            .build();
+        let progress = StartupProgress::builder()
+            .message("Connecting")
+            .interval(Duration::from_millis(500))
+            .logger(&logger)
+            .build()
+            .begin(Collect::default());
+        std::thread::sleep(Duration::from_millis(1250));
+        let packets = progress.end().0;
+        let status: Vec<_> = packets
+            .iter()
+            .filter(|p| p.command == ControlCommand::StatusbarMessage)
+            .map(|p| String::from_utf8_lossy(&p.payload).into_owned())
+            .collect();
+        assert_eq!(status, ["Connecting…", "Connecting…", "Connecting… 1s"]);
+        assert_eq!(packets[0], logger.add_log("Connecting…".into()));
+    }
+
+    #[test]
+    fn no_sender() {
+        let progress = StartupProgress::builder()
+            .message("Connecting")
+            .build()
+            .begin(None::<Collect>);
+        assert!(progress.end().is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn no_sender_async() {
+        let progress = StartupProgress::builder()
+            .message("Connecting")
+            .build()
+            .begin_async(None::<crate::controls::asynchronous::ExtcapControlSender>);
+        assert!(progress.end().await.is_none());
+    }
+}
@@ -0,0 +1,383 @@
+#[cfg(any(feature = "sync", feature = "async"))]
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
+
+use typed_builder::TypedBuilder;
+
+#[cfg(all(doc, not(any(feature = "sync", feature = "async"))))]
+use super::ControlCommand;
+#[cfg(any(feature = "sync", feature = "async"))]
+use super::{ControlCommand, ControlPacket};
+
+/// The rate limit for one type of [`ControlCommand`] in a
+/// [`RateLimitedSender`], implemented as a token bucket.
+///
+/// ```
+/// use r_extcap::controls::RateLimit;
+///
+/// // Allow bursts of 10 messages, refilled at 2 messages per second.
+/// let limit = RateLimit::builder().per_second(2.0).burst(10).build();
+/// ```
+#[derive(Clone, Debug, TypedBuilder)]
+pub struct RateLimit {
+    /// The sustained number of packets allowed per second.
+    ///
+    /// If this is 0, the bucket never refills: only the first
+    /// [`burst`][Self::burst] packets are sent, and later packets are dropped,
+    /// or held until [`flush_pending`][RateLimitedSender::flush_pending] if
+    /// the limit [coalesces][Self::coalesce] packets.
+    pub per_second: f64,
+    /// The maximum number of packets that can be sent at once after a quiet
+    /// period.
+    #[builder(default = 1)]
+    pub burst: u32,
+    /// If true, a packet over the limit is held instead of dropped, and sent
+    /// once the limit allows it, unless a newer packet for the same control
+    /// replaces it first. This is useful for status bar messages and `Set`
+    /// packets, where only the latest value matters. If false, packets over
+    /// the limit are dropped, which is more suitable for log entries.
+    #[builder(default)]
+    pub coalesce: bool,
+}
+
+/// Statistics of a [`RateLimitedSender`] for one type of [`ControlCommand`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimitStats {
+    /// The number of packets sent.
+    pub sent: u64,
+    /// The number of packets dropped because they were over the limit.
+    pub dropped: u64,
+    /// The number of held packets replaced by a newer packet for the same
+    /// control. See [`RateLimit::coalesce`].
+    pub coalesced: u64,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+    /// Held packets by control number, if the limit coalesces packets.
+    pending: BTreeMap<u8, ControlPacket<'static>>,
+    stats: RateLimitStats,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl Bucket {
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.stats.sent += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A control sender that limits the rate of the packets sent for each type of
+/// [`ControlCommand`], so that frequent log entries or status messages do not
+/// overwhelm the control pipe and the Wireshark UI.
+///
+/// Commands without a [`RateLimit`] are sent unchanged. Sending a packet over
+/// the limit returns `Ok(())`, and the packet is counted in
+/// [`stats`][Self::stats]. Held packets are sent before the next packet sent
+/// through this sender, once the limit allows it. Since nothing else sends
+/// them, call [`flush_pending`][Self::flush_pending] when the capture goes
+/// idle or ends, so that the last status message or value is not lost.
+///
+/// This implements `ExtcapControlSenderTrait` in both the
+/// [`synchronous`][super::synchronous] and
+/// [`asynchronous`][super::asynchronous] modules, for the corresponding
+/// senders.
+///
+/// ```ignore
+/// let mut sender = RateLimitedSender::new(capture_step.new_control_sender())
+///     .with_limit(
+///         ControlCommand::Add,
+///         RateLimit::builder().per_second(20.0).burst(50).build(),
+///     )
+///     .with_limit(
+///         ControlCommand::StatusbarMessage,
+///         RateLimit::builder().per_second(2.0).coalesce(true).build(),
+///     );
+/// CONTROL_LOGGER.add_log(format!("Received packet {i}").into()).send(&mut sender)?;
+/// // Send the last status message held by the limit.
+/// sender.flush_pending()?;
+/// ```
+#[cfg(any(feature = "sync", feature = "async"))]
+#[derive(Debug)]
+pub struct RateLimitedSender<S> {
+    sender: S,
+    buckets: HashMap<ControlCommand, Bucket>,
+}
+
+#[cfg(any(feature = "sync", feature = "async"))]
+impl<S> RateLimitedSender<S> {
+    /// Wraps `sender`, without any limits.
+    pub fn new(sender: S) -> Self {
+        Self {
+            sender,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Limits the rate of packets with the given `command`. The bucket starts
+    /// full, allowing a burst right away.
+    #[must_use]
+    pub fn with_limit(mut self, command: ControlCommand, limit: RateLimit) -> Self {
+        let bucket = Bucket {
+            tokens: limit.burst as f64,
+            limit,
+            last_refill: Instant::now(),
+            pending: BTreeMap::new(),
+            stats: RateLimitStats::default(),
+        };
+        self.buckets.insert(command, bucket);
+        self
+    }
+
+    /// The statistics for packets with the given `command`, or all zeros if
+    /// the command is not limited.
+    pub fn stats(&self, command: ControlCommand) -> RateLimitStats {
+        self.buckets
+            .get(&command)
+            .map(|bucket| bucket.stats)
+            .unwrap_or_default()
+    }
+
+    /// The number of packets held to be sent later.
+    pub fn pending(&self) -> usize {
+        self.buckets
+            .values()
+            .map(|bucket| bucket.pending.len())
+            .sum()
+    }
+
+    /// Returns the wrapped sender.
+    pub fn into_inner(self) -> S {
+        self.sender
+    }
+
+    /// Removes the held packets that the limits now allow to be sent.
+    fn take_ready(&mut self) -> Vec<ControlPacket<'static>> {
+        let mut ready = Vec::new();
+        for bucket in self.buckets.values_mut() {
+            while !bucket.pending.is_empty() && bucket.try_take() {
+                ready.extend(bucket.pending.pop_first().map(|(_, packet)| packet));
+            }
+        }
+        ready
+    }
+
+    /// Removes all of the held packets, regardless of the limits.
+    fn take_pending(&mut self) -> Vec<ControlPacket<'static>> {
+        let mut pending = Vec::new();
+        for bucket in self.buckets.values_mut() {
+            bucket.stats.sent += bucket.pending.len() as u64;
+            pending.extend(std::mem::take(&mut bucket.pending).into_values());
+        }
+        pending
+    }
+
+    /// Returns `packet` if it is within the limit and should be sent now.
+    fn admit<'p>(&mut self, packet: ControlPacket<'p>) -> Option<ControlPacket<'p>> {
+        let Some(bucket) = self.buckets.get_mut(&packet.command) else {
+            return Some(packet);
+        };
+        if bucket.limit.coalesce {
+            // A newer packet makes the held one obsolete.
+            if bucket.pending.remove(&packet.control_number).is_some() {
+                bucket.stats.coalesced += 1;
+            }
+            if bucket.try_take() {
+                Some(packet)
+            } else {
+                bucket
+                    .pending
+                    .insert(packet.control_number, packet.into_owned());
+                None
+            }
+        } else if bucket.try_take() {
+            Some(packet)
+        } else {
+            bucket.stats.dropped += 1;
+            None
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<S> RateLimitedSender<S>
+where
+    for<'a> &'a mut S: super::synchronous::ExtcapControlSenderTrait,
+{
+    /// Sends all of the held packets now, even if they are over the limit.
+    pub fn flush_pending(&mut self) -> std::io::Result<()> {
+        use super::synchronous::ExtcapControlSenderTrait as _;
+
+        for packet in self.take_pending() {
+            self.sender.send(packet)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<S> super::synchronous::ExtcapControlSenderTrait for &mut RateLimitedSender<S>
+where
+    for<'a> &'a mut S: super::synchronous::ExtcapControlSenderTrait,
+{
+    fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+        for ready in self.take_ready() {
+            self.sender.send(ready)?;
+        }
+        match self.admit(packet) {
+            Some(packet) => self.sender.send(packet),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S> RateLimitedSender<S>
+where
+    S: Send + Sync,
+    for<'a> &'a mut S: super::asynchronous::ExtcapControlSenderTrait,
+{
+    /// Sends all of the held packets now, even if they are over the limit,
+    /// like [`flush_pending`][Self::flush_pending].
+    pub async fn flush_pending_async(&mut self) -> tokio::io::Result<()> {
+        use super::asynchronous::ExtcapControlSenderTrait as _;
+
+        for packet in self.take_pending() {
+            self.sender.send(packet).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl<S> super::asynchronous::ExtcapControlSenderTrait for &mut RateLimitedSender<S>
+where
+    S: Send + Sync,
+    for<'a> &'a mut S: super::asynchronous::ExtcapControlSenderTrait,
+{
+    async fn send(self, packet: ControlPacket<'_>) -> Result<(), tokio::io::Error> {
+        for ready in self.take_ready() {
+            self.sender.send(ready).await?;
+        }
+        match self.admit(packet) {
+            Some(packet) => self.sender.send(packet).await,
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod test {
+    use std::time::Duration;
+
+    use super::{RateLimit, RateLimitStats, RateLimitedSender};
+    use crate::controls::{synchronous::ExtcapControlSenderTrait, ControlCommand, ControlPacket};
+
+    /// Collects the sent packets.
+    #[derive(Default)]
+    struct Collect(Vec<ControlPacket<'static>>);
+
+    impl ExtcapControlSenderTrait for &mut Collect {
+        fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+            self.0.push(packet.into_owned());
+            Ok(())
+        }
+    }
+
+    fn payloads(sender: &RateLimitedSender<Collect>) -> Vec<&[u8]> {
+        sender.sender.0.iter().map(|p| &p.payload[..]).collect()
+    }
+
+    #[test]
+    fn drops_over_limit() {
+        let mut sender = RateLimitedSender::new(Collect::default()).with_limit(
+            ControlCommand::Add,
+            RateLimit::builder().per_second(0.0).burst(2).build(),
+        );
+        for i in 0..5 {
+            sender
+                .send(ControlPacket::new_with_payload(
+                    1,
+                    ControlCommand::Add,
+                    format!("{i}").into_bytes(),
+                ))
+                .unwrap();
+        }
+        sender.status_message("unlimited").unwrap();
+        assert_eq!(payloads(&sender), [&b"0"[..], b"1", b"unlimited"]);
+        assert_eq!(
+            sender.stats(ControlCommand::Add),
+            RateLimitStats {
+                sent: 2,
+                dropped: 3,
+                coalesced: 0
+            }
+        );
+    }
+
+    #[test]
+    fn coalesces_over_limit() {
+        let mut sender = RateLimitedSender::new(Collect::default()).with_limit(
+            ControlCommand::StatusbarMessage,
+            RateLimit::builder().per_second(50.0).coalesce(true).build(),
+        );
+        sender.status_message("first").unwrap();
+        sender.status_message("second").unwrap();
+        sender.status_message("third").unwrap();
+        assert_eq!(payloads(&sender), [b"first"]);
+        assert_eq!(sender.pending(), 1);
+
+        std::thread::sleep(Duration::from_millis(50));
+        sender.info_message("unlimited").unwrap();
+        assert_eq!(payloads(&sender), [&b"first"[..], b"third", b"unlimited"]);
+        assert_eq!(
+            sender.stats(ControlCommand::StatusbarMessage),
+            RateLimitStats {
+                sent: 2,
+                dropped: 0,
+                coalesced: 1
+            }
+        );
+    }
+    #[test]
+    fn flush_pending() {
+        let mut sender = RateLimitedSender::new(Collect::default()).with_limit(
+            ControlCommand::StatusbarMessage,
+            RateLimit::builder().per_second(0.0).coalesce(true).build(),
+        );
+        sender.status_message("first").unwrap();
+        sender.status_message("second").unwrap();
+        sender.status_message("last").unwrap();
+        assert_eq!(payloads(&sender), [b"first"]);
+
+        sender.flush_pending().unwrap();
+        assert_eq!(payloads(&sender), [&b"first"[..], b"last"]);
+        assert_eq!(sender.pending(), 0);
+        assert_eq!(
+            sender.stats(ControlCommand::StatusbarMessage),
+            RateLimitStats {
+                sent: 2,
+                dropped: 0,
+                coalesced: 1
+            }
+        );
+        sender.flush_pending().unwrap();
+        assert_eq!(payloads(&sender).len(), 2);
+    }
+}
@@ -0,0 +1,326 @@
+//! Records the control packets exchanged with Wireshark, and replays them
+//! later, so that the toolbar handling of an extcap can be tested without
+//! running Wireshark.
+//!
+//! A [`Recorder`] writes each packet to a file in the [JSON
+//! Lines](https://jsonlines.org/) format, together with its direction and the
+//! time since the recording started. Packets sent to Wireshark can be recorded
+//! by wrapping the sender with [`Recorder::tee`], and packets read from
+//! Wireshark using [`Recorder::record`].
+//!
+//! A [`Player`] loads a recorded session. The packets received from Wireshark
+//! can be fed back into a [`ChannelExtcapControlReader`] or a
+//! [`ControlState`], and the packets that were sent can be compared with the
+//! ones sent in the test.
+//!
+//! [`ChannelExtcapControlReader`]: super::synchronous::ChannelExtcapControlReader
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{ControlPacket, ControlState};
+
+/// The direction a recorded control packet was sent in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Sent by Wireshark to this extcap, through `--extcap-control-in`.
+    Inbound,
+    /// Sent by this extcap to Wireshark, through `--extcap-control-out`.
+    Outbound,
+}
+
+/// A control packet in a recorded session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedPacket<'a> {
+    /// The direction the packet was sent in.
+    pub direction: Direction,
+    /// The time between the start of the recording and this packet.
+    pub elapsed: Duration,
+    /// The recorded packet.
+    pub packet: ControlPacket<'a>,
+}
+
+/// Records control packets to a writer, one JSON object per line. See the
+/// [module documentation][self] for details.
+///
+/// The recorder can be shared between the threads reading and sending control
+/// packets.
+pub struct Recorder<W: Write> {
+    start: Instant,
+    writer: Mutex<W>,
+}
+
+impl Recorder<BufWriter<File>> {
+    /// Creates a recorder that writes to a new file at `path`, replacing the
+    /// file if it already exists.
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    /// Creates a recorder that writes to `writer`. The elapsed time of the
+    /// recorded packets is measured from now.
+    pub fn new(writer: W) -> Self {
+        Self {
+            start: Instant::now(),
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Records `packet`, which was sent in the given `direction`. The writer is
+    /// flushed after each packet, so that the recording is complete even if
+    /// the extcap is terminated.
+    pub fn record(&self, direction: Direction, packet: &ControlPacket<'_>) -> std::io::Result<()> {
+        let recorded = RecordedPacket {
+            direction,
+            elapsed: self.start.elapsed(),
+            packet: packet.clone(),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, &recorded)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+
+    /// Wraps `sender`, so that each packet sent through it is also recorded as
+    /// [`Outbound`][Direction::Outbound].
+    pub fn tee<S>(&self, sender: S) -> RecordingSender<'_, S, W> {
+        RecordingSender {
+            sender,
+            recorder: self,
+        }
+    }
+
+    /// Consumes this recorder and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+/// A control sender that records each packet it sends. Created using
+/// [`Recorder::tee`].
+pub struct RecordingSender<'r, S, W: Write> {
+    sender: S,
+    recorder: &'r Recorder<W>,
+}
+
+#[cfg(feature = "sync")]
+impl<S, W: Write> super::synchronous::ExtcapControlSenderTrait for &mut RecordingSender<'_, S, W>
+where
+    for<'a> &'a mut S: super::synchronous::ExtcapControlSenderTrait,
+{
+    fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+        self.recorder.record(Direction::Outbound, &packet)?;
+        self.sender.send(packet)
+    }
+}
+
+#[cfg(feature = "async")]
+#[async_trait::async_trait]
+impl<S, W> super::asynchronous::ExtcapControlSenderTrait for &mut RecordingSender<'_, S, W>
+where
+    S: Send + Sync,
+    W: Write + Send,
+    for<'a> &'a mut S: super::asynchronous::ExtcapControlSenderTrait,
+{
+    async fn send(self, packet: ControlPacket<'_>) -> Result<(), tokio::io::Error> {
+        self.recorder.record(Direction::Outbound, &packet)?;
+        self.sender.send(packet).await
+    }
+}
+
+/// Error loading a recorded session in [`Player`].
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    /// Error reading the recording.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// A line of the recording is not a valid [`RecordedPacket`].
+    #[error("Invalid recorded packet on line {line}")]
+    Parse {
+        /// The line number of the invalid packet, starting from 1.
+        line: usize,
+        /// The underlying parse error.
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// A recorded session of control packets, loaded from the output of a
+/// [`Recorder`].
+///
+/// ```
+/// use r_extcap::controls::{
+///     replay::{Direction, Player, Recorder},
+///     BooleanControl, ControlCommand, ControlPacket, ControlState,
+/// };
+///
+/// let verify = BooleanControl::builder()
+///     .control_number(0)
+///     .display("Verify")
+///     .build();
+/// let recorder = Recorder::new(Vec::new());
+/// recorder.record(Direction::Inbound, &verify.set_checked(true)).unwrap();
+/// recorder
+///     .record(Direction::Inbound, &ControlPacket::new(0, ControlCommand::Initialized))
+///     .unwrap();
+///
+/// let player = Player::from_reader(&recorder.into_inner()[..]).unwrap();
+/// let mut state = ControlState::new(&[&verify]);
+/// player.replay_into(&mut state);
+/// assert!(state.get(&verify));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Player {
+    packets: Vec<RecordedPacket<'static>>,
+}
+
+impl Player {
+    /// Loads the recording in the file at `path`.
+    pub fn open(path: &Path) -> Result<Self, ReplayError> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Loads a recording from `reader`. Empty lines are ignored.
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, ReplayError> {
+        let mut packets = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let packet: RecordedPacket =
+                serde_json::from_str(&line).map_err(|source| ReplayError::Parse {
+                    line: index + 1,
+                    source,
+                })?;
+            packets.push(packet);
+        }
+        Ok(Self { packets })
+    }
+
+    /// All of the recorded packets, in the order they were recorded.
+    pub fn packets(&self) -> &[RecordedPacket<'static>] {
+        &self.packets
+    }
+
+    /// The packets that were sent by Wireshark.
+    pub fn inbound(&self) -> impl Iterator<Item = &ControlPacket<'static>> {
+        self.packets_in(Direction::Inbound)
+    }
+
+    /// The packets that were sent by the extcap. A test can compare these with
+    /// the packets its extcap logic sends when replaying the inbound packets.
+    pub fn outbound(&self) -> impl Iterator<Item = &ControlPacket<'static>> {
+        self.packets_in(Direction::Outbound)
+    }
+
+    fn packets_in(&self, direction: Direction) -> impl Iterator<Item = &ControlPacket<'static>> {
+        self.packets
+            .iter()
+            .filter(move |recorded| recorded.direction == direction)
+            .map(|recorded| &recorded.packet)
+    }
+
+    /// Updates `state` with each inbound packet, as if they were received from
+    /// Wireshark.
+    pub fn replay_into(&self, state: &mut ControlState) {
+        for packet in self.inbound() {
+            state.update(packet);
+        }
+    }
+
+    /// Spawns a thread that sends the inbound packets to a
+    /// [`ChannelExtcapControlReader`][super::synchronous::ChannelExtcapControlReader],
+    /// which can be used in place of the reader returned by
+    /// [`CaptureStep::spawn_channel_control_reader`][crate::CaptureStep::spawn_channel_control_reader].
+    ///
+    /// If `realtime` is true, each packet is delayed until its recorded elapsed
+    /// time. Otherwise the packets are sent as fast as the reader consumes
+    /// them. The channel is closed after the last packet.
+    #[cfg(feature = "sync")]
+    pub fn spawn_channel_reader(
+        &self,
+        realtime: bool,
+    ) -> super::synchronous::ChannelExtcapControlReader {
+        let mut inbound = self
+            .packets
+            .iter()
+            .filter(|recorded| recorded.direction == Direction::Inbound)
+            .cloned()
+            .collect::<Vec<_>>()
+            .into_iter();
+        let start = Instant::now();
+        super::synchronous::ChannelExtcapControlReader::spawn_with(move || {
+            let Some(recorded) = inbound.next() else {
+                return Ok(None);
+            };
+            if realtime {
+                let target = start + recorded.elapsed;
+                std::thread::sleep(target.saturating_duration_since(Instant::now()));
+            }
+            Ok(Some(recorded.packet))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Direction, Player, Recorder, ReplayError};
+    use crate::controls::{ButtonControl, ControlCommand, ControlPacket, ControlWithLabel as _};
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn record_and_replay() {
+        use std::time::Duration;
+
+        use crate::controls::{
+            synchronous::{ExtcapControlSender, ExtcapControlSenderTrait as _},
+            ControlHandshake,
+        };
+
+        let button = ButtonControl::builder()
+            .control_number(1)
+            .display("Turn on")
+            .build();
+        let recorder = Recorder::new(Vec::new());
+        let initialized = ControlPacket::new(0, ControlCommand::Initialized);
+        recorder.record(Direction::Inbound, &initialized).unwrap();
+        // Without a control pipe, the packet is only recorded.
+        let mut sender = recorder.tee(None::<ExtcapControlSender>);
+        sender.send(button.set_label("Turn off")).unwrap();
+        let pressed = ControlPacket::new(1, ControlCommand::Set);
+        recorder.record(Direction::Inbound, &pressed).unwrap();
+
+        let player = Player::from_reader(&recorder.into_inner()[..]).unwrap();
+        assert_eq!(player.packets().len(), 3);
+        assert_eq!(
+            player.outbound().collect::<Vec<_>>(),
+            [&button.set_label("Turn off")]
+        );
+
+        let reader = player.spawn_channel_reader(false);
+        ControlHandshake::wait_for_init(&reader, Duration::from_secs(1)).unwrap();
+        assert_eq!(reader.read_packet().unwrap(), pressed);
+        assert!(reader.read_packet().is_err());
+    }
+
+    #[test]
+    fn invalid_line() {
+        let recording = "\n{\"direction\":\"Inbound\"}\n";
+        assert!(matches!(
+            Player::from_reader(recording.as_bytes()),
+            Err(ReplayError::Parse { line: 2, .. })
+        ));
+    }
+}
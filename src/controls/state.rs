@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use super::{ControlCommand, ControlPacket, ControlWithValue, InvalidPacketError, ToolbarControl};
+
+/// Stores the most recent value of each registered toolbar control.
+///
+/// The state is updated with [`update`][Self::update] for each `Set` control
+/// packet, whether it was received from Wireshark or sent by the extcap.
+/// Values can then be read back with their types using [`get`][Self::get],
+/// which falls back to the control's default value if no value has been set.
+/// The initial values sent by Wireshark when the capture starts can be applied
+/// using [`ControlHandshake::apply_to`][super::ControlHandshake::apply_to].
+///
+/// ```
+/// use r_extcap::controls::{BooleanControl, ControlState};
+///
+/// let verify = BooleanControl::builder()
+///     .control_number(0)
+///     .display("Verify")
+///     .build();
+/// let mut state = ControlState::new(&[&verify]);
+/// assert!(!state.get(&verify));
+///
+/// state.update(&verify.set_checked(true));
+/// assert!(state.get(&verify));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ControlState {
+    values: HashMap<u8, Option<Vec<u8>>>,
+    supported_commands: HashMap<u8, &'static [ControlCommand]>,
+}
+
+impl ControlState {
+    /// Creates a new state that tracks the values of the given `controls`.
+    /// Packets for other controls are ignored by [`update`][Self::update].
+    pub fn new(controls: &[&dyn ToolbarControl]) -> Self {
+        Self {
+            values: controls
+                .iter()
+                .map(|control| (control.control_number(), None))
+                .collect(),
+            supported_commands: controls
+                .iter()
+                .map(|control| (control.control_number(), control.supported_commands()))
+                .collect(),
+        }
+    }
+
+    /// Updates the state with the given control packet. Returns `true` if the
+    /// packet is a `Set` packet for a registered control, in which case the
+    /// stored value has been updated.
+    ///
+    /// Packets with a command that is not supported by the registered
+    /// control are ignored, since they may come from Wireshark. See
+    /// [`ControlPacket::validate_for`].
+    pub fn update(&mut self, packet: &ControlPacket<'_>) -> bool {
+        if let Err(e) = self.validate(packet) {
+            debug!("Ignoring control packet: {e}");
+            return false;
+        }
+        if packet.command != ControlCommand::Set {
+            return false;
+        }
+        match self.values.get_mut(&packet.control_number) {
+            Some(value) => {
+                *value = Some(packet.payload.to_vec());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Checks `packet` with [`ControlPacket::validate_for`] against the
+    /// registered control with the same control number. Packets for other
+    /// controls are valid.
+    pub(crate) fn validate(&self, packet: &ControlPacket<'_>) -> Result<(), InvalidPacketError> {
+        match self.supported_commands.get(&packet.control_number) {
+            Some(commands) => packet.validate_for_commands(packet.control_number, commands),
+            None => Ok(()),
+        }
+    }
+
+    /// Forgets the values of all registered controls, so that
+    /// [`get`][Self::get] returns their default values again.
+    pub fn reset(&mut self) {
+        for value in self.values.values_mut() {
+            *value = None;
+        }
+    }
+
+    /// Returns the most recent value of `control`, or its
+    /// [default value][ControlWithValue::default_value] if no value has been
+    /// set or the stored value cannot be parsed.
+    ///
+    /// Panics: If `control` was not registered in [`new`][Self::new].
+    pub fn get<C: ControlWithValue>(&self, control: &C) -> C::Value {
+        self.values
+            .get(&control.control_number())
+            .unwrap_or_else(|| {
+                panic!(
+                    "Control {} is not registered in the ControlState",
+                    control.control_number()
+                )
+            })
+            .as_ref()
+            .and_then(|payload| control.parse_value(payload))
+            .unwrap_or_else(|| control.default_value())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ControlState;
+    use crate::controls::{
+        BooleanControl, ControlCommand, ControlPacket, EnableableControl as _, SelectorControl,
+        SelectorControlOption,
+    };
+
+    #[test]
+    fn selector_value() {
+        let delay = SelectorControl::builder()
+            .control_number(1)
+            .display("Delay")
+            .options(vec![
+                SelectorControlOption::builder()
+                    .value("1")
+                    .display("1s")
+                    .build(),
+                SelectorControlOption::builder()
+                    .value("5")
+                    .display("5s")
+                    .default(true)
+                    .build(),
+            ])
+            .build();
+        let mut state = ControlState::new(&[&delay]);
+        assert_eq!(state.get(&delay), "5");
+        assert!(state.update(&delay.set_value("1")));
+        assert_eq!(state.get(&delay), "1");
+    }
+
+    #[test]
+    fn ignores_unregistered() {
+        let verify = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .build();
+        let mut state = ControlState::new(&[&verify]);
+        assert!(!state.update(&ControlPacket::new_with_payload(
+            3,
+            ControlCommand::Set,
+            &[1][..]
+        )));
+        assert!(!state.update(&verify.set_enabled(false)));
+        assert!(!state.get(&verify));
+    }
+
+    #[test]
+    #[should_panic]
+    fn get_unregistered() {
+        let verify = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .build();
+        ControlState::default().get(&verify);
+    }
+
+    #[test]
+    fn unsupported_command() {
+        let verify = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .build();
+        let mut state = ControlState::new(&[&verify]);
+        assert!(!state.update(&ControlPacket::new(0, ControlCommand::Remove)));
+        assert!(!state.get(&verify));
+    }
+}
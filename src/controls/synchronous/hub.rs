@@ -0,0 +1,264 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use super::{
+    ControlChannelError, ExtcapControlReader, ExtcapControlSender, ExtcapControlSenderTrait,
+};
+use crate::controls::{
+    ControlEvent, ControlHandshake, ControlPacket, HandshakeError, ToolbarControl,
+};
+
+/// Owns both control pipes of a capture, so that multiple threads can receive
+/// the control events from Wireshark and send packets to it. This is the
+/// threaded counterpart of
+/// [`asynchronous::ControlHub`][crate::controls::asynchronous::ControlHub].
+///
+/// Each call to [`subscribe`][Self::subscribe] returns a receiver that gets
+/// its own copy of every inbound packet, resolved into a [`ControlEvent`]
+/// using the controls given to [`spawn`][Self::spawn]. Outbound packets sent
+/// through any [`ControlHubSender`] are queued and written by a single thread,
+/// so that packets from different threads are never interleaved.
+///
+/// ```no_run
+/// # use r_extcap::controls::{synchronous::ExtcapControlSenderTrait as _, ControlEvent, ToolbarControl};
+/// # use std::time::Duration;
+/// # fn example(capture_step: &r_extcap::CaptureStep, controls: &[&dyn ToolbarControl]) -> anyhow::Result<()> {
+/// let hub = capture_step.spawn_control_hub(controls).unwrap();
+/// let events = hub.subscribe();
+/// let sender = hub.sender();
+/// std::thread::spawn(move || {
+///     for event in events {
+///         if let ControlEvent::ButtonPressed { .. } = event {
+///             sender.status_message("Button pressed").unwrap();
+///         }
+///     }
+/// });
+/// let handshake = hub.wait_for_init(Duration::from_secs(30))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ControlHub {
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<ControlEvent>>>>,
+    sender: ControlHubSender,
+    handshake: Arc<(Mutex<HandshakeState>, Condvar)>,
+    reader_handle: JoinHandle<Result<(), ControlChannelError>>,
+    writer_handle: JoinHandle<std::io::Result<()>>,
+}
+
+#[derive(Default)]
+struct HandshakeState {
+    handshake: Option<ControlHandshake>,
+    disconnected: bool,
+}
+
+impl ControlHub {
+    /// Spawns the threads that read the control packets from `in_path`, given
+    /// in `--extcap-control-in`, and write the outbound packets to
+    /// `out_path`, given in `--extcap-control-out`.
+    ///
+    /// `controls` are the toolbar controls of the extcap, used to resolve the
+    /// inbound packets into [`ControlEvent`]s.
+    pub fn spawn(in_path: PathBuf, out_path: PathBuf, controls: &[&dyn ToolbarControl]) -> Self {
+        let kinds: HashMap<_, _> = controls
+            .iter()
+            .map(|control| (control.control_number(), control.kind()))
+            .collect();
+        let subscribers = Arc::new(Mutex::new(Vec::<mpsc::Sender<ControlEvent>>::new()));
+        let handshake = Arc::new((Mutex::new(HandshakeState::default()), Condvar::new()));
+        let reader_subscribers = Arc::clone(&subscribers);
+        let reader_handshake = Arc::clone(&handshake);
+        let reader_handle = std::thread::spawn(move || {
+            let reader = ExtcapControlReader::new(&in_path);
+            let mut pending = Some(ControlHandshake::default());
+            let result = loop {
+                let packet = match reader.read_control_packet() {
+                    Ok(packet) => packet,
+                    Err(e) => break Err(e.into()),
+                };
+                if let Some(mut handshake) = pending.take() {
+                    if handshake.record(&packet) {
+                        let (state, condvar) = &*reader_handshake;
+                        state.lock().unwrap().handshake = Some(handshake);
+                        condvar.notify_all();
+                    } else {
+                        pending = Some(handshake);
+                    }
+                }
+                let kind = kinds.get(&packet.control_number).copied();
+                let event = ControlEvent::resolve_kind(packet, kind);
+                // Receivers that have been dropped are unsubscribed.
+                reader_subscribers
+                    .lock()
+                    .unwrap()
+                    .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+            };
+            let (state, condvar) = &*reader_handshake;
+            state.lock().unwrap().disconnected = true;
+            condvar.notify_all();
+            result
+        });
+        let (outbound, outbound_rx) = mpsc::channel::<ControlPacket<'static>>();
+        let writer_handle = std::thread::spawn(move || {
+            let mut sender = ExtcapControlSender::new(&out_path);
+            while let Ok(packet) = outbound_rx.recv() {
+                // Write the packets queued in the meantime with the same write.
+                sender.batch(|batch| {
+                    batch.send(packet);
+                    while let Ok(packet) = outbound_rx.try_recv() {
+                        batch.send(packet);
+                    }
+                })?;
+            }
+            Ok(())
+        });
+        Self {
+            subscribers,
+            sender: ControlHubSender(outbound),
+            handshake,
+            reader_handle,
+            writer_handle,
+        }
+    }
+
+    /// Returns a receiver for the control events sent by Wireshark from now
+    /// on, including the packets of the handshake if it is not complete yet.
+    ///
+    /// The events are buffered without a limit until they are received, so
+    /// that a slow subscriber does not hold up the others. Dropping the
+    /// receiver unsubscribes it.
+    pub fn subscribe(&self) -> mpsc::Receiver<ControlEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Returns a handle for sending control packets to Wireshark, which can be
+    /// cloned and moved into other threads.
+    pub fn sender(&self) -> ControlHubSender {
+        self.sender.clone()
+    }
+
+    /// Waits for the `Initialized` packet, returning the initial control
+    /// values sent before it. See [`ControlHandshake`].
+    ///
+    /// This can be called any number of times, from any thread. Once the
+    /// handshake is complete, it returns immediately.
+    pub fn wait_for_init(&self, timeout: Duration) -> Result<ControlHandshake, HandshakeError> {
+        let (state, condvar) = &*self.handshake;
+        let (state, _) = condvar
+            .wait_timeout_while(state.lock().unwrap(), timeout, |state| {
+                state.handshake.is_none() && !state.disconnected
+            })
+            .unwrap();
+        match &state.handshake {
+            Some(handshake) => Ok(handshake.clone()),
+            None if state.disconnected => Err(HandshakeError::Disconnected),
+            None => Err(HandshakeError::Timeout(timeout)),
+        }
+    }
+
+    /// Whether the reader and writer threads are still running. They stop
+    /// when Wireshark closes either control pipe.
+    pub fn is_running(&self) -> bool {
+        !self.reader_handle.is_finished() && !self.writer_handle.is_finished()
+    }
+}
+
+impl ExtcapControlSenderTrait for &ControlHub {
+    fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+        (&self.sender).send(packet)
+    }
+}
+
+/// A handle for sending control packets through a [`ControlHub`].
+#[derive(Clone, Debug)]
+pub struct ControlHubSender(mpsc::Sender<ControlPacket<'static>>);
+
+impl ExtcapControlSenderTrait for &ControlHubSender {
+    /// Queues `packet` to be written to the control pipe. Returns a
+    /// `BrokenPipe` error if the pipe has been closed.
+    fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+        self.0
+            .send(packet.into_owned())
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::time::Duration;
+
+    use super::ControlHub;
+    use crate::controls::{
+        synchronous::{
+            test::policy, ExtcapControlReader, ExtcapControlSender, ExtcapControlSenderTrait as _,
+        },
+        BooleanControl, ButtonControl, ControlCommand, ControlEvent, ControlPacket, ToolbarControl,
+    };
+    use crate::testing::TempFifo;
+
+    #[test]
+    fn fan_out() {
+        let in_fifo = TempFifo::new("hub-in").unwrap();
+        let out_fifo = TempFifo::new("hub-out").unwrap();
+        let (in_path, out_path) = (in_fifo.path().to_owned(), out_fifo.path().to_owned());
+        let verify = BooleanControl::builder()
+            .control_number(1)
+            .display("Verify")
+            .build();
+        let button = ButtonControl::builder()
+            .control_number(2)
+            .display("Turn on")
+            .build();
+        let hub = ControlHub::spawn(
+            in_path.clone(),
+            out_path.clone(),
+            &[&verify as &dyn ToolbarControl, &button],
+        );
+        let first = hub.subscribe();
+        let second = hub.subscribe();
+
+        // Plays the part of Wireshark.
+        let mut wireshark_out = ExtcapControlSender::open(&in_path, &policy()).unwrap();
+        let reader_path = out_path.clone();
+        let wireshark_in = std::thread::spawn(move || ExtcapControlReader::new(&reader_path));
+        wireshark_out.send(verify.set_checked(true)).unwrap();
+        wireshark_out
+            .send(ControlPacket::new(0, ControlCommand::Initialized))
+            .unwrap();
+        wireshark_out
+            .send(ControlPacket::new(2, ControlCommand::Set))
+            .unwrap();
+
+        let handshake = hub.wait_for_init(Duration::from_secs(5)).unwrap();
+        assert_eq!(handshake.initial_value(&verify), Some(true));
+        for receiver in [&first, &second] {
+            let events: Vec<_> = receiver.iter().take(3).collect();
+            assert_eq!(
+                events,
+                [
+                    ControlEvent::SetBool {
+                        control: 1,
+                        value: true
+                    },
+                    ControlEvent::Initialized,
+                    ControlEvent::ButtonPressed { control: 2 },
+                ]
+            );
+        }
+
+        let sender = hub.sender();
+        std::thread::spawn(move || sender.status_message("hello"))
+            .join()
+            .unwrap()
+            .unwrap();
+        let packet = wireshark_in.join().unwrap().read_control_packet().unwrap();
+        assert_eq!(&packet.payload[..], b"hello");
+        assert!(hub.is_running());
+    }
+}
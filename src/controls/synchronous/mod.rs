@@ -10,6 +10,12 @@
 //!   that provides simpler, but less flexible, handling of the communication
 //!   using a mspc channel.
 //!
+//! [`ControlSession`] bundles a channel reader and a sender, and turns control
+//! calls into no-ops when Wireshark does not provide the control pipes.
+//!
+//! [`ControlHub`] owns both pipes, and delivers the inbound events to any
+//! number of threads while queueing the outbound packets from all of them.
+//!
 //! See Wireshark's [Adding Capture Interfaces And Log Sources Using
 //! Extcap](https://www.wireshark.org/docs/wsdg_html_chunked/ChCaptureExtcap.html#_messages)
 //! section 8.2.3.2.1 for a description of the protocol format.
@@ -21,17 +27,26 @@ use std::{
     io::{Read, Write},
     path::{Path, PathBuf},
     sync::{
+        atomic::{AtomicU16, Ordering},
         mpsc::{self, SendError},
-        Mutex,
+        Arc, Mutex,
     },
     thread::JoinHandle,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
+mod hub;
+mod session;
 pub mod util;
+pub use hub::{ControlHub, ControlHubSender};
+pub use session::ControlSession;
 use util::ReadExt as _;
 
-use crate::controls::{ControlCommand, ControlPacket};
+use crate::controls::{
+    ControlBatch, ControlCommand, ControlCommandMask, ControlEvent, ControlNumber, ControlPacket,
+    ControlPipeError, LoggerControl, OpenPolicy, SendErrorPolicy, ToolbarControl,
+};
 
 /// Error type returned for control packet read operations.
 #[derive(Debug, Error)]
@@ -43,6 +58,10 @@ pub enum ReadControlError {
     /// Error parsing the incoming data into the [`ControlPacket`] format.
     #[error("Error parsing control packet: {0}")]
     ParseError(String),
+
+    /// No control packet arrived within the given timeout.
+    #[error("Timed out after {0:?} waiting for a control packet")]
+    Timeout(Duration),
 }
 
 /// Error associated with [`ChannelExtcapControlReader`].
@@ -97,6 +116,7 @@ pub struct ChannelExtcapControlReader {
     pub join_handle: JoinHandle<Result<(), ControlChannelError>>,
     /// The channel to receive control packets from.
     pub read_channel: mpsc::Receiver<ControlPacket<'static>>,
+    subscription: Arc<AtomicU16>,
 }
 
 impl ChannelExtcapControlReader {
@@ -104,19 +124,57 @@ impl ChannelExtcapControlReader {
     /// it uses to start reading the control packets from the pipe given in
     /// `in_path`.
     pub fn spawn(in_path: PathBuf) -> Self {
+        let mut reader = None;
+        Self::spawn_with(move || {
+            let reader = reader.get_or_insert_with(|| ExtcapControlReader::new(&in_path));
+            Ok(Some(reader.read_control_packet()?))
+        })
+    }
+
+    /// Spawns a thread that forwards the packets returned by `next_packet` to
+    /// the channel, until it returns `None` or an error.
+    pub(crate) fn spawn_with(
+        mut next_packet: impl FnMut() -> Result<Option<ControlPacket<'static>>, ControlChannelError>
+            + Send
+            + 'static,
+    ) -> Self {
         let (tx, rx) = mpsc::sync_channel::<ControlPacket<'static>>(10);
+        let subscription = Arc::new(AtomicU16::new(ControlCommandMask::ALL.bits()));
+        let thread_subscription = Arc::clone(&subscription);
         let join_handle = std::thread::spawn(move || {
-            let reader = ExtcapControlReader::new(&in_path);
-            loop {
-                tx.send(reader.read_control_packet()?)?;
+            while let Some(packet) = next_packet()? {
+                if is_subscribed(&thread_subscription, &packet) {
+                    tx.send(packet)?;
+                }
             }
+            Ok(())
         });
         Self {
             join_handle,
             read_channel: rx,
+            subscription,
         }
     }
 
+    /// Only forwards the control packets with a command in `mask` to this
+    /// reader. Other packets are dropped as they are read from the pipe,
+    /// instead of being queued in the channel and discarded by the caller.
+    /// Packets already in the channel are not affected.
+    ///
+    /// By default, all packets are forwarded.
+    ///
+    /// Note that Wireshark only sends `Initialized` and `Set` packets, so
+    /// those are the only commands worth subscribing to.
+    pub fn subscribe(&self, mask: ControlCommandMask) {
+        self.subscription.store(mask.bits(), Ordering::Relaxed);
+    }
+
+    /// The commands forwarded to this reader. See
+    /// [`subscribe`][Self::subscribe].
+    pub fn subscription(&self) -> ControlCommandMask {
+        ControlCommandMask::from_bits(self.subscription.load(Ordering::Relaxed))
+    }
+
     /// Try to read a buffered control packet, or return `None` if there are no
     /// incoming control packets.
     pub fn try_read_packet(&self) -> Option<ControlPacket<'static>> {
@@ -134,6 +192,33 @@ impl ChannelExtcapControlReader {
     pub fn read_packet(&self) -> Result<ControlPacket<'static>, mpsc::RecvError> {
         self.read_channel.recv()
     }
+
+    /// Try to read a buffered control packet like
+    /// [`try_read_packet`][Self::try_read_packet], resolved into a
+    /// [`ControlEvent`] using the registered `controls`.
+    pub fn try_read_event(&self, controls: &[&dyn ToolbarControl]) -> Option<ControlEvent> {
+        self.try_read_packet()
+            .map(|packet| ControlEvent::resolve(packet, controls))
+    }
+
+    /// Reads a control packet like [`read_packet`][Self::read_packet],
+    /// resolved into a [`ControlEvent`] using the registered `controls`.
+    pub fn read_event(
+        &self,
+        controls: &[&dyn ToolbarControl],
+    ) -> Result<ControlEvent, mpsc::RecvError> {
+        self.read_packet()
+            .map(|packet| ControlEvent::resolve(packet, controls))
+    }
+}
+
+fn is_subscribed(subscription: &AtomicU16, packet: &ControlPacket<'_>) -> bool {
+    let subscribed = ControlCommandMask::from_bits(subscription.load(Ordering::Relaxed))
+        .contains(packet.command);
+    if !subscribed {
+        debug!("Dropping control packet outside of the subscription: {packet:?}");
+    }
+    subscribed
 }
 
 /// A reader for the Extcap control pipe.
@@ -148,16 +233,36 @@ impl ExtcapControlReader {
     ///
     /// * `in_path`: The path of the extcap control pipe passed with
     ///   `--extcap-control-in`.
+    ///
+    /// This blocks until Wireshark opens its end of the pipe. To give up after
+    /// a timeout instead, use [`open`][Self::open].
     pub fn new(in_path: &Path) -> Self {
-        Self {
-            in_file: File::open(in_path).unwrap(),
-        }
+        Self::open(in_path, &OpenPolicy::default()).unwrap()
+    }
+
+    /// Creates a new instance of [`ExtcapControlReader`], waiting for Wireshark
+    /// to open its end of the pipe according to the given `policy`.
+    ///
+    /// When a timeout is set, the pipe is considered opened once Wireshark
+    /// sends its first control packet, which it does right after opening the
+    /// pipe.
+    ///
+    /// * `in_path`: The path of the extcap control pipe passed with
+    ///   `--extcap-control-in`.
+    pub fn open(in_path: &Path, policy: &OpenPolicy) -> Result<Self, ControlPipeError> {
+        Ok(Self {
+            in_file: open_in_file(in_path, policy)?,
+        })
     }
 
     /// Read one control packet, blocking until the packet arrives. Since the
     /// control packet pipe is expected to stay open for the entire duration of
     /// the extcap program, if the pipe is closed prematurely in this function
     /// here, `UnexpectedEof` will be returned.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
     pub fn read_control_packet(&self) -> Result<ControlPacket<'static>, ReadControlError> {
         let mut in_file = &self.in_file;
         let header_bytes = in_file
@@ -182,9 +287,184 @@ impl ExtcapControlReader {
         debug!("Parsed incoming control message: {packet:?}");
         Ok(packet)
     }
+
+    /// Read one control packet like
+    /// [`read_control_packet`][Self::read_control_packet], but give up with
+    /// [`ReadControlError::Timeout`] if no packet arrives within `timeout`.
+    ///
+    /// This can be used to wait a short time for the `Initialized` packet, and
+    /// continue the capture without controls if Wireshark does not send it.
+    /// Once a packet starts to arrive, the rest of it is read without a
+    /// timeout, so that packets are never split.
+    #[cfg(unix)]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), err)
+    )]
+    pub fn read_control_packet_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<ControlPacket<'static>, ReadControlError> {
+        use std::os::unix::io::AsRawFd;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut pollfd = libc::pollfd {
+                fd: self.in_file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let timeout_ms = remaining.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            // Safety: `pollfd` is a valid pointer to a single `pollfd` struct.
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                -1 => {
+                    let e = std::io::Error::last_os_error();
+                    if e.kind() != std::io::ErrorKind::Interrupted {
+                        return Err(e.into());
+                    }
+                }
+                0 => return Err(ReadControlError::Timeout(timeout)),
+                // Either data is available or the pipe is closed, in which case
+                // reading returns `UnexpectedEof`.
+                _ => return self.read_control_packet(),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open_in_file(in_path: &Path, policy: &OpenPolicy) -> Result<File, ControlPipeError> {
+    use std::os::unix::{fs::OpenOptionsExt, io::AsRawFd};
+
+    let Some(deadline) = policy.deadline(Instant::now()) else {
+        return Ok(File::open(in_path)?);
+    };
+    // Opening a fifo for reading in non-blocking mode succeeds even if there
+    // are no writers, so poll until Wireshark sends the first packet instead.
+    let in_file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(in_path)?;
+    loop {
+        let wait = policy.next_wait(Some(deadline))?;
+        let mut pollfd = libc::pollfd {
+            fd: in_file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = wait.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+        // Safety: `pollfd` is a valid pointer to a single `pollfd` struct.
+        if unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } == -1 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e.into());
+        }
+        if pollfd.revents & libc::POLLIN != 0 {
+            break;
+        } else if pollfd.revents != 0 {
+            // Some platforms report `POLLHUP` while there are no writers, which
+            // makes `poll` return immediately.
+            std::thread::sleep(wait);
+        }
+    }
+    set_blocking(&in_file)?;
+    Ok(in_file)
+}
+
+#[cfg(unix)]
+fn open_out_file(out_path: &Path, policy: &OpenPolicy) -> Result<File, ControlPipeError> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let Some(deadline) = policy.deadline(Instant::now()) else {
+        return Ok(File::create(out_path)?);
+    };
+    loop {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(out_path)
+        {
+            Ok(out_file) => {
+                set_blocking(&out_file)?;
+                return Ok(out_file);
+            }
+            // Opening a fifo for writing in non-blocking mode fails with
+            // `ENXIO` if no process has it open for reading yet.
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+                std::thread::sleep(policy.next_wait(Some(deadline))?);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Opens the control pipe again without waiting, failing if Wireshark does
+/// not have it open for reading.
+#[cfg(unix)]
+fn reopen_out_file(out_path: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let out_file = std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(out_path)?;
+    set_blocking(&out_file)?;
+    Ok(out_file)
+}
+
+#[cfg(unix)]
+fn set_blocking(file: &File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    // Safety: `fd` is a valid file descriptor owned by `file`.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn open_in_file(in_path: &Path, policy: &OpenPolicy) -> Result<File, ControlPipeError> {
+    open_with_retry(|| File::open(in_path), policy)
+}
+
+#[cfg(not(unix))]
+fn open_out_file(out_path: &Path, policy: &OpenPolicy) -> Result<File, ControlPipeError> {
+    open_with_retry(|| File::create(out_path), policy)
 }
 
-const UNUSED_CONTROL_NUMBER: u8 = 255;
+#[cfg(not(unix))]
+fn reopen_out_file(out_path: &Path) -> std::io::Result<File> {
+    File::create(out_path)
+}
+
+/// Retries `open` while the pipe does not exist yet, until the deadline in
+/// `policy` is reached.
+#[cfg(not(unix))]
+fn open_with_retry(
+    open: impl Fn() -> std::io::Result<File>,
+    policy: &OpenPolicy,
+) -> Result<File, ControlPipeError> {
+    let deadline = policy.deadline(Instant::now());
+    loop {
+        match open() {
+            Ok(file) => return Ok(file),
+            Err(e) if deadline.is_some() && e.kind() == std::io::ErrorKind::NotFound => {
+                std::thread::sleep(policy.next_wait(deadline)?);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
 
 /// Sender for extcap control packets. These control packets controls the UI
 /// generated by Wireshark. This trait also provides convenience functions for
@@ -200,7 +480,7 @@ pub trait ExtcapControlSenderTrait: Sized {
     /// the screen until the user dismisses the popup.
     fn info_message(self, message: &str) -> std::io::Result<()> {
         self.send(ControlPacket::new_with_payload(
-            UNUSED_CONTROL_NUMBER,
+            ControlNumber::UNUSED,
             ControlCommand::InformationMessage,
             message.as_bytes(),
         ))
@@ -210,7 +490,7 @@ pub trait ExtcapControlSenderTrait: Sized {
     /// screen until the user dismisses the popup.
     fn warning_message(self, message: &str) -> std::io::Result<()> {
         self.send(ControlPacket::new_with_payload(
-            UNUSED_CONTROL_NUMBER,
+            ControlNumber::UNUSED,
             ControlCommand::WarningMessage,
             message.as_bytes(),
         ))
@@ -220,7 +500,7 @@ pub trait ExtcapControlSenderTrait: Sized {
     /// screen until the user dismisses the popup.
     fn error_message(self, message: &str) -> std::io::Result<()> {
         self.send(ControlPacket::new_with_payload(
-            UNUSED_CONTROL_NUMBER,
+            ControlNumber::UNUSED,
             ControlCommand::ErrorMessage,
             message.as_bytes(),
         ))
@@ -232,7 +512,7 @@ pub trait ExtcapControlSenderTrait: Sized {
     /// bar for a few seconds, or until another message overwrites it.
     fn status_message(self, message: &str) -> std::io::Result<()> {
         self.send(ControlPacket::new_with_payload(
-            UNUSED_CONTROL_NUMBER,
+            ControlNumber::UNUSED,
             ControlCommand::StatusbarMessage,
             message.as_bytes(),
         ))
@@ -242,26 +522,142 @@ pub trait ExtcapControlSenderTrait: Sized {
 /// A sender for the extcap control packets. `out_file` should be the file given
 /// by the `--extcap-control-out` flag.
 pub struct ExtcapControlSender {
-    out_file: File,
+    /// The control pipe, or `None` if Wireshark has closed it.
+    out_file: Option<File>,
+    out_path: PathBuf,
+    error_policy: SendErrorPolicy,
+    /// Reused to serialize packets without allocating.
+    buffer: ControlBatch,
 }
 
 impl ExtcapControlSender {
     /// Creates a new instance of [`ExtcapControlSender`].
     ///
     /// * `out_path`: The path specified by the `--extcap-control-out` flag.
+    ///
+    /// This blocks until Wireshark opens its end of the pipe. To give up after
+    /// a timeout instead, use [`open`][Self::open].
     pub fn new(out_path: &Path) -> Self {
-        Self {
-            out_file: File::create(out_path).unwrap(),
+        Self::open(out_path, &OpenPolicy::default()).unwrap()
+    }
+
+    /// Creates a new instance of [`ExtcapControlSender`], waiting for Wireshark
+    /// to open its end of the pipe according to the given `policy`.
+    ///
+    /// * `out_path`: The path specified by the `--extcap-control-out` flag.
+    pub fn open(out_path: &Path, policy: &OpenPolicy) -> Result<Self, ControlPipeError> {
+        Ok(Self {
+            out_file: Some(open_out_file(out_path, policy)?),
+            out_path: out_path.to_owned(),
+            error_policy: SendErrorPolicy::default(),
+            buffer: ControlBatch::default(),
+        })
+    }
+
+    /// Opens a control pipe that Wireshark already has open for reading,
+    /// without waiting.
+    pub(crate) fn reopen(out_path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            out_file: Some(reopen_out_file(out_path)?),
+            out_path: out_path.to_owned(),
+            error_policy: SendErrorPolicy::default(),
+            buffer: ControlBatch::default(),
+        })
+    }
+
+    /// Sets what this sender does when Wireshark closes the control pipe. See
+    /// [`SendErrorPolicy`].
+    #[must_use]
+    pub fn with_error_policy(mut self, error_policy: SendErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
+    /// Whether the control pipe is open. This is `false` after a send failed
+    /// because Wireshark closed the pipe, until it is reopened with
+    /// [`SendErrorPolicy::TryReopen`].
+    pub fn is_connected(&self) -> bool {
+        self.out_file.is_some()
+    }
+}
+
+impl ExtcapControlSender {
+    /// Sends all of the packets added to the batch in `add_packets` with a
+    /// single write. See [`ControlBatch`].
+    ///
+    /// ```no_run
+    /// # use r_extcap::controls::{synchronous::ExtcapControlSender, ButtonControl, EnableableControl as _};
+    /// # fn example(sender: &mut ExtcapControlSender, start: &ButtonControl, stop: &ButtonControl) {
+    /// sender
+    ///     .batch(|batch| {
+    ///         batch.send(start.set_enabled(false));
+    ///         batch.send(stop.set_enabled(true));
+    ///     })
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn batch(&mut self, add_packets: impl FnOnce(&mut ControlBatch)) -> std::io::Result<()> {
+        self.write_buffered(|batch| {
+            add_packets(batch);
+            if !batch.is_empty() {
+                debug!("Sending {} extcap control messages", batch.len());
+            }
+        })
+    }
+
+    /// Serializes the packets added in `add_packets` into the reused buffer
+    /// and writes them, if any.
+    fn write_buffered(
+        &mut self,
+        add_packets: impl FnOnce(&mut ControlBatch),
+    ) -> std::io::Result<()> {
+        let mut batch = std::mem::take(&mut self.buffer);
+        batch.clear();
+        add_packets(&mut batch);
+        let result = if batch.is_empty() {
+            Ok(())
+        } else {
+            self.write_bytes(batch.bytes())
+        };
+        let result = result.and_then(|()| batch.rejected().map_or(Ok(()), Err));
+        self.buffer = batch;
+        result
+    }
+
+    /// Adds `log` to the `logger`. This is the same as sending
+    /// [`LoggerControl::add_log`], but does not allocate, which is useful for
+    /// logging each captured packet.
+    pub fn add_log(&mut self, logger: &LoggerControl, log: &str) -> std::io::Result<()> {
+        self.write_buffered(|batch| {
+            batch.add_log(logger, log);
+        })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(len = bytes.len()), err)
+    )]
+    fn write_bytes(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        if self.out_file.is_none() && self.error_policy == SendErrorPolicy::TryReopen {
+            self.out_file = reopen_out_file(&self.out_path).ok();
+        }
+        let Some(out_file) = &mut self.out_file else {
+            return self.error_policy.disconnected_result();
+        };
+        let result = out_file.write_all(bytes).and_then(|()| out_file.flush());
+        let (result, connected) = self.error_policy.handle(result);
+        if !connected {
+            self.out_file = None;
         }
+        result
     }
 }
 
 impl ExtcapControlSenderTrait for &mut ExtcapControlSender {
     fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
-        self.out_file.write_all(&packet.to_header_bytes())?;
-        self.out_file.write_all(&packet.payload)?;
-        self.out_file.flush().unwrap();
-        Ok(())
+        self.write_buffered(|batch| {
+            batch.send(packet);
+        })
     }
 }
 
@@ -296,3 +692,167 @@ where
         self.lock().unwrap().send(packet)
     }
 }
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{
+        fs::{File, OpenOptions},
+        io::{ErrorKind, Read},
+        os::unix::fs::OpenOptionsExt,
+        path::Path,
+        time::Duration,
+    };
+
+    use super::{
+        ExtcapControlReader, ExtcapControlSender, ExtcapControlSenderTrait as _, ReadControlError,
+    };
+    use crate::{
+        controls::{ControlPipeError, OpenPolicy, SendErrorPolicy},
+        testing::TempFifo,
+    };
+
+    fn open_reader(path: &Path) -> File {
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .unwrap()
+    }
+
+    pub(super) fn policy() -> OpenPolicy {
+        OpenPolicy::builder()
+            .timeout(Duration::from_millis(200))
+            .retry_interval(Duration::from_millis(20))
+            .build()
+    }
+
+    #[test]
+    fn reader_open_timeout() {
+        let fifo = TempFifo::new("reader-timeout").unwrap();
+        let path = fifo.path().to_owned();
+        let result = ExtcapControlReader::open(&path, &policy());
+        assert!(matches!(result, Err(ControlPipeError::Timeout(_))));
+    }
+
+    #[test]
+    fn sender_open_timeout() {
+        let fifo = TempFifo::new("sender-timeout").unwrap();
+        let path = fifo.path().to_owned();
+        let result = ExtcapControlSender::open(&path, &policy());
+        assert!(matches!(result, Err(ControlPipeError::Timeout(_))));
+    }
+
+    #[test]
+    fn open_within_timeout() {
+        let fifo = TempFifo::new("open").unwrap();
+        let path = fifo.path().to_owned();
+        let reader_path = path.clone();
+        let reader = std::thread::spawn(move || {
+            ExtcapControlReader::open(&reader_path, &policy())
+                .unwrap()
+                .read_control_packet()
+                .unwrap()
+        });
+        let mut sender = ExtcapControlSender::open(&path, &policy()).unwrap();
+        sender.status_message("hello").unwrap();
+        let packet = reader.join().unwrap();
+        assert_eq!(&packet.payload[..], b"hello");
+    }
+
+    #[test]
+    fn read_timeout() {
+        let fifo = TempFifo::new("read-timeout").unwrap();
+        let path = fifo.path().to_owned();
+        let reader_path = path.clone();
+        let reader = std::thread::spawn(move || ExtcapControlReader::new(&reader_path));
+        let mut sender = ExtcapControlSender::open(&path, &policy()).unwrap();
+        let reader = reader.join().unwrap();
+        assert!(matches!(
+            reader.read_control_packet_timeout(Duration::from_millis(50)),
+            Err(ReadControlError::Timeout(_))
+        ));
+        sender.status_message("hello").unwrap();
+        let packet = reader
+            .read_control_packet_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(&packet.payload[..], b"hello");
+    }
+
+    #[test]
+    fn subscribe() {
+        use super::ChannelExtcapControlReader;
+        use crate::controls::{ControlCommand, ControlCommandMask, ControlPacket};
+
+        let fifo = TempFifo::new("subscribe").unwrap();
+        let path = fifo.path().to_owned();
+        let reader = ChannelExtcapControlReader::spawn(path.clone());
+        assert_eq!(reader.subscription(), ControlCommandMask::ALL);
+        reader.subscribe(ControlCommand::Set.into());
+        let mut sender = ExtcapControlSender::open(&path, &policy()).unwrap();
+        sender.status_message("ignored").unwrap();
+        let set = ControlPacket::new_with_payload(1, ControlCommand::Set, &b"on"[..]);
+        sender.send(set.clone()).unwrap();
+        assert_eq!(reader.read_packet().unwrap(), set);
+        assert!(reader.try_read_packet().is_none());
+    }
+
+    #[test]
+    fn fail_fast_after_broken() {
+        let fifo = TempFifo::new("fail-fast").unwrap();
+        let path = fifo.path().to_owned();
+        let reader = open_reader(&path);
+        let mut sender = ExtcapControlSender::open(&path, &policy()).unwrap();
+        sender.status_message("first").unwrap();
+        drop(reader);
+        let error = sender.status_message("second").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+        assert!(!sender.is_connected());
+        let error = sender.status_message("third").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn batch() {
+        use crate::controls::{ControlCommand, ControlPacket};
+
+        let fifo = TempFifo::new("batch").unwrap();
+        let path = fifo.path().to_owned();
+        let mut reader = open_reader(&path);
+        let mut sender = ExtcapControlSender::open(&path, &policy()).unwrap();
+        let first = ControlPacket::new_with_payload(1, ControlCommand::Set, &b"on"[..]);
+        let second = ControlPacket::new(2, ControlCommand::Disable);
+        sender
+            .batch(|batch| {
+                batch.send(first.clone()).send(second.clone());
+            })
+            .unwrap();
+        let expected = [first.to_bytes(), second.to_bytes()].concat();
+        let mut bytes = vec![0; expected.len()];
+        reader.read_exact(&mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn reopen_after_broken() {
+        let fifo = TempFifo::new("reopen").unwrap();
+        let path = fifo.path().to_owned();
+        let reader = open_reader(&path);
+        let mut sender = ExtcapControlSender::open(&path, &policy())
+            .unwrap()
+            .with_error_policy(SendErrorPolicy::TryReopen);
+        sender.status_message("first").unwrap();
+        drop(reader);
+        sender.status_message("dropped").unwrap();
+        assert!(!sender.is_connected());
+        // No reader yet, so the pipe cannot be reopened.
+        sender.status_message("dropped").unwrap();
+        assert!(!sender.is_connected());
+
+        let mut reader = open_reader(&path);
+        sender.status_message("second").unwrap();
+        assert!(sender.is_connected());
+        let mut bytes = [0; 12];
+        reader.read_exact(&mut bytes).unwrap();
+        assert_eq!(&bytes[6..], b"second");
+    }
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use super::{ChannelExtcapControlReader, ExtcapControlSender, ExtcapControlSenderTrait};
+use crate::{
+    controls::{
+        ControlBatch, ControlEvent, ControlHandshake, ControlPacket, HandshakeError, LoggerControl,
+        ToolbarControl,
+    },
+    WiresharkVersion,
+};
+
+/// The control reader and sender of a capture, bundled so that extcaps do not
+/// need to check whether Wireshark supports toolbar controls before every
+/// call.
+///
+/// Wireshark does not pass the control pipes when the extcap has no toolbar
+/// controls, when running in tshark, or in versions older than
+/// [`WiresharkVersion::INTERFACE_TOOLBAR`]. In those cases the session is
+/// unavailable: sending is a no-op, no packets are read, and
+/// [`wait_for_init`][Self::wait_for_init] returns an empty handshake right
+/// away, so the capture can run with its default control values.
+///
+/// ```no_run
+/// # use r_extcap::{CaptureStep, controls::synchronous::ExtcapControlSenderTrait as _};
+/// # use std::time::Duration;
+/// # fn example(capture_step: &CaptureStep) -> anyhow::Result<()> {
+/// let mut controls = capture_step.control_session();
+/// let handshake = controls.wait_for_init(Duration::from_secs(30))?;
+/// loop {
+///     if let Some(packet) = controls.try_read_packet() {
+///         // Handle the control packet
+///     }
+///     controls.status_message("Capturing")?;
+/// }
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ControlSession {
+    reader: Option<ChannelExtcapControlReader>,
+    sender: Option<ExtcapControlSender>,
+    wireshark_version: Option<WiresharkVersion>,
+}
+
+impl ControlSession {
+    /// Creates a session from the given reader and sender, either of which may
+    /// be absent.
+    pub fn new(
+        reader: Option<ChannelExtcapControlReader>,
+        sender: Option<ExtcapControlSender>,
+    ) -> Self {
+        Self {
+            reader,
+            sender,
+            wireshark_version: None,
+        }
+    }
+
+    /// Creates a session without control pipes, where all control calls are
+    /// no-ops.
+    pub fn unavailable() -> Self {
+        Self::default()
+    }
+
+    /// Sets the version of Wireshark running the capture, as returned by
+    /// [`wireshark_version`][Self::wireshark_version].
+    #[must_use]
+    pub fn with_wireshark_version(mut self, version: Option<WiresharkVersion>) -> Self {
+        self.wireshark_version = version;
+        self
+    }
+
+    /// Whether both control pipes are present, that is, whether the toolbar
+    /// controls are shown in Wireshark.
+    pub fn is_available(&self) -> bool {
+        self.reader.is_some() && self.sender.is_some()
+    }
+
+    /// The version of Wireshark running the capture, if known.
+    pub fn wireshark_version(&self) -> Option<WiresharkVersion> {
+        self.wireshark_version
+    }
+
+    /// The underlying reader, if the `--extcap-control-in` pipe is present.
+    pub fn reader(&self) -> Option<&ChannelExtcapControlReader> {
+        self.reader.as_ref()
+    }
+
+    /// The underlying sender, if the `--extcap-control-out` pipe is present.
+    pub fn sender(&mut self) -> Option<&mut ExtcapControlSender> {
+        self.sender.as_mut()
+    }
+
+    /// Waits for the `Initialized` packet. See
+    /// [`ControlHandshake::wait_for_init`].
+    ///
+    /// If there is no control reader, this returns an empty handshake
+    /// immediately.
+    pub fn wait_for_init(&self, timeout: Duration) -> Result<ControlHandshake, HandshakeError> {
+        match &self.reader {
+            Some(reader) => ControlHandshake::wait_for_init(reader, timeout),
+            None => Ok(ControlHandshake::default()),
+        }
+    }
+
+    /// Try to read a buffered control packet, or return `None` if there are no
+    /// incoming control packets or there is no control reader.
+    pub fn try_read_packet(&self) -> Option<ControlPacket<'static>> {
+        self.reader.as_ref()?.try_read_packet()
+    }
+
+    /// Try to read a buffered control packet, resolved into a
+    /// [`ControlEvent`] using the registered `controls`. See
+    /// [`ChannelExtcapControlReader::try_read_event`].
+    pub fn try_read_event(&self, controls: &[&dyn ToolbarControl]) -> Option<ControlEvent> {
+        self.reader.as_ref()?.try_read_event(controls)
+    }
+
+    /// Sends all of the packets added to the batch in `add_packets` with a
+    /// single write. See [`ExtcapControlSender::batch`]. This is a no-op if
+    /// there is no control sender.
+    pub fn batch(&mut self, add_packets: impl FnOnce(&mut ControlBatch)) -> std::io::Result<()> {
+        match &mut self.sender {
+            Some(sender) => sender.batch(add_packets),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds `log` to the `logger` without allocating. See
+    /// [`ExtcapControlSender::add_log`]. This is a no-op if there is no
+    /// control sender.
+    pub fn add_log(&mut self, logger: &LoggerControl, log: &str) -> std::io::Result<()> {
+        match &mut self.sender {
+            Some(sender) => sender.add_log(logger, log),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ExtcapControlSenderTrait for &mut ControlSession {
+    fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+        (&mut self.sender).send(packet)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::ControlSession;
+    use crate::controls::{synchronous::ExtcapControlSenderTrait as _, BooleanControl};
+
+    #[test]
+    fn unavailable_is_no_op() {
+        let mut session = ControlSession::unavailable();
+        assert!(!session.is_available());
+        assert!(session.try_read_packet().is_none());
+        let control = BooleanControl::builder()
+            .control_number(1)
+            .display("Verify")
+            .build();
+        let handshake = session.wait_for_init(Duration::ZERO).unwrap();
+        assert_eq!(handshake.initial_value(&control), None);
+        session.send(control.set_checked(true)).unwrap();
+        session.status_message("Hello").unwrap();
+        session
+            .batch(|batch| {
+                batch.send(control.set_checked(false));
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,417 @@
+//! Fake Ethernet, IPv4, UDP and TCP headers, to make the payloads of devices
+//! that don't speak IP dissectable by Wireshark's IP-based dissectors.
+//!
+//! A common trick for extcaps is to wrap each payload in made-up headers and
+//! capture with [`DataLink::ETHERNET`][pcap_file::DataLink::ETHERNET], so
+//! that a dissector registered on a UDP or TCP port (or a "Decode As" rule)
+//! picks the payload up. [`Encapsulation`] builds these headers with correct
+//! lengths and checksums, and keeps the TCP sequence numbers of both
+//! directions consistent, so Wireshark does not flag the packets as malformed
+//! or as retransmissions.
+//!
+//! ```
+//! use r_extcap::encap::Encapsulation;
+//! use std::net::{Ipv4Addr, SocketAddrV4};
+//!
+//! let mut encap = Encapsulation::udp(
+//!     SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 5000),
+//!     SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 6000),
+//! );
+//! let request = encap.encapsulate(b"ping").unwrap();
+//! let response = encap.encapsulate_reply(b"pong").unwrap();
+//! assert_eq!(request.len(), 14 + 20 + 8 + 4);
+//! // The reply goes from port 6000 back to port 5000.
+//! assert_eq!(response[34..38], [0x17, 0x70, 0x13, 0x88]);
+//! ```
+//!
+//! `Encapsulation` is also a [`SinkMiddleware`], which wraps every packet
+//! written to the sink, using [`Direction::Inbound`] packets as replies.
+
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+use thiserror::Error;
+
+use crate::sink::{Direction, SinkMiddleware, SinkPacket, Verdict};
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV4_HEADER_LEN: usize = 20;
+const UDP_HEADER_LEN: usize = 8;
+const TCP_HEADER_LEN: usize = 20;
+const IP_PROTOCOL_TCP: u8 = 6;
+const IP_PROTOCOL_UDP: u8 = 17;
+/// The PSH and ACK flags, which are set on every TCP segment.
+const TCP_FLAGS_PSH_ACK: u8 = 0x18;
+
+/// Error returned when a payload does not fit in a single IPv4 packet.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+#[error("Payload of {len} bytes is too long to encapsulate (max {max})")]
+pub struct PayloadTooLong {
+    /// The length of the payload.
+    pub len: usize,
+    /// The maximum length of a payload with the chosen headers.
+    pub max: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transport {
+    Ip(u8),
+    Udp { src_port: u16, dst_port: u16 },
+    Tcp { src_port: u16, dst_port: u16 },
+}
+
+impl Transport {
+    fn protocol(self) -> u8 {
+        match self {
+            Self::Ip(protocol) => protocol,
+            Self::Udp { .. } => IP_PROTOCOL_UDP,
+            Self::Tcp { .. } => IP_PROTOCOL_TCP,
+        }
+    }
+
+    fn header_len(self) -> usize {
+        match self {
+            Self::Ip(_) => 0,
+            Self::Udp { .. } => UDP_HEADER_LEN,
+            Self::Tcp { .. } => TCP_HEADER_LEN,
+        }
+    }
+}
+
+/// Builds Ethernet frames carrying a payload over IPv4, and optionally UDP or
+/// TCP, between two fixed endpoints. See the [module documentation][self].
+///
+/// Packets from the first endpoint to the second are built with
+/// [`encapsulate`][Self::encapsulate], and packets in the other direction
+/// with [`encapsulate_reply`][Self::encapsulate_reply].
+#[derive(Clone, Debug)]
+pub struct Encapsulation {
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    src_ip: Ipv4Addr,
+    dst_ip: Ipv4Addr,
+    ttl: u8,
+    transport: Transport,
+    identification: u16,
+    /// The next TCP sequence number of each direction, indexed by whether
+    /// the direction is the reply.
+    sequence: [u32; 2],
+}
+
+impl Encapsulation {
+    /// The MAC address used for the source endpoint by default. It is a
+    /// locally administered address, so it doesn't belong to a real vendor.
+    pub const DEFAULT_SRC_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+    /// The MAC address used for the destination endpoint by default.
+    pub const DEFAULT_DST_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+    fn new(src_ip: Ipv4Addr, dst_ip: Ipv4Addr, transport: Transport) -> Self {
+        Self {
+            src_mac: Self::DEFAULT_SRC_MAC,
+            dst_mac: Self::DEFAULT_DST_MAC,
+            src_ip,
+            dst_ip,
+            ttl: 64,
+            transport,
+            identification: 0,
+            sequence: [1, 1],
+        }
+    }
+
+    /// Wraps payloads directly in IPv4 with the given protocol number, for
+    /// protocols that run on top of IP without UDP or TCP.
+    pub fn ip(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8) -> Self {
+        Self::new(src, dst, Transport::Ip(protocol))
+    }
+
+    /// Wraps payloads in UDP datagrams from `src` to `dst`.
+    pub fn udp(src: SocketAddrV4, dst: SocketAddrV4) -> Self {
+        let transport = Transport::Udp {
+            src_port: src.port(),
+            dst_port: dst.port(),
+        };
+        Self::new(*src.ip(), *dst.ip(), transport)
+    }
+
+    /// Wraps payloads in the segments of a TCP connection from `src` to
+    /// `dst`. The connection is assumed to be established, so there is no
+    /// handshake, and each payload advances the sequence number of its
+    /// direction.
+    pub fn tcp(src: SocketAddrV4, dst: SocketAddrV4) -> Self {
+        let transport = Transport::Tcp {
+            src_port: src.port(),
+            dst_port: dst.port(),
+        };
+        Self::new(*src.ip(), *dst.ip(), transport)
+    }
+
+    /// Sets the MAC addresses of the source and destination endpoints,
+    /// instead of [`DEFAULT_SRC_MAC`][Self::DEFAULT_SRC_MAC] and
+    /// [`DEFAULT_DST_MAC`][Self::DEFAULT_DST_MAC].
+    #[must_use]
+    pub fn mac_addresses(mut self, src: [u8; 6], dst: [u8; 6]) -> Self {
+        self.src_mac = src;
+        self.dst_mac = dst;
+        self
+    }
+
+    /// Sets the time-to-live of the IPv4 packets. Defaults to 64.
+    #[must_use]
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// The longest payload that fits in a single packet.
+    pub fn max_payload_len(&self) -> usize {
+        usize::from(u16::MAX) - IPV4_HEADER_LEN - self.transport.header_len()
+    }
+
+    /// Builds a frame carrying `payload` from the source to the destination
+    /// endpoint.
+    pub fn encapsulate(&mut self, payload: &[u8]) -> Result<Vec<u8>, PayloadTooLong> {
+        self.build(false, payload)
+    }
+
+    /// Builds a frame carrying `payload` from the destination back to the
+    /// source endpoint.
+    pub fn encapsulate_reply(&mut self, payload: &[u8]) -> Result<Vec<u8>, PayloadTooLong> {
+        self.build(true, payload)
+    }
+
+    fn build(&mut self, reply: bool, payload: &[u8]) -> Result<Vec<u8>, PayloadTooLong> {
+        let max = self.max_payload_len();
+        if payload.len() > max {
+            return Err(PayloadTooLong {
+                len: payload.len(),
+                max,
+            });
+        }
+        let (src_mac, dst_mac, src_ip, dst_ip) = if reply {
+            (self.dst_mac, self.src_mac, self.dst_ip, self.src_ip)
+        } else {
+            (self.src_mac, self.dst_mac, self.src_ip, self.dst_ip)
+        };
+        let transport_len = self.transport.header_len() + payload.len();
+        let mut packet = Vec::with_capacity(ETHERNET_HEADER_LEN + IPV4_HEADER_LEN + transport_len);
+        packet.extend_from_slice(&dst_mac);
+        packet.extend_from_slice(&src_mac);
+        packet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+
+        let ip_header = ETHERNET_HEADER_LEN;
+        packet.extend_from_slice(&[0x45, 0]);
+        packet.extend_from_slice(&((IPV4_HEADER_LEN + transport_len) as u16).to_be_bytes());
+        packet.extend_from_slice(&self.identification.to_be_bytes());
+        // Don't fragment.
+        packet.extend_from_slice(&[0x40, 0]);
+        packet.extend_from_slice(&[self.ttl, self.transport.protocol(), 0, 0]);
+        packet.extend_from_slice(&src_ip.octets());
+        packet.extend_from_slice(&dst_ip.octets());
+        let checksum = internet_checksum(packet[ip_header..].iter().copied());
+        packet[ip_header + 10..ip_header + 12].copy_from_slice(&checksum.to_be_bytes());
+        self.identification = self.identification.wrapping_add(1);
+
+        let transport_header = packet.len();
+        let checksum_offset = match self.transport {
+            Transport::Ip(_) => None,
+            Transport::Udp { src_port, dst_port } => {
+                let (src_port, dst_port) = swap_if(reply, src_port, dst_port);
+                packet.extend_from_slice(&src_port.to_be_bytes());
+                packet.extend_from_slice(&dst_port.to_be_bytes());
+                packet.extend_from_slice(&(transport_len as u16).to_be_bytes());
+                packet.extend_from_slice(&[0, 0]);
+                Some(6)
+            }
+            Transport::Tcp { src_port, dst_port } => {
+                let (src_port, dst_port) = swap_if(reply, src_port, dst_port);
+                let sequence = self.sequence[usize::from(reply)];
+                let acknowledgment = self.sequence[usize::from(!reply)];
+                packet.extend_from_slice(&src_port.to_be_bytes());
+                packet.extend_from_slice(&dst_port.to_be_bytes());
+                packet.extend_from_slice(&sequence.to_be_bytes());
+                packet.extend_from_slice(&acknowledgment.to_be_bytes());
+                packet.extend_from_slice(&[(TCP_HEADER_LEN as u8 / 4) << 4, TCP_FLAGS_PSH_ACK]);
+                packet.extend_from_slice(&u16::MAX.to_be_bytes());
+                packet.extend_from_slice(&[0, 0, 0, 0]);
+                self.sequence[usize::from(reply)] = sequence.wrapping_add(payload.len() as u32);
+                Some(16)
+            }
+        };
+        packet.extend_from_slice(payload);
+
+        if let Some(offset) = checksum_offset {
+            let pseudo_header = [
+                &src_ip.octets()[..],
+                &dst_ip.octets(),
+                &[0, self.transport.protocol()],
+                &(transport_len as u16).to_be_bytes(),
+            ]
+            .concat();
+            let mut checksum = internet_checksum(
+                pseudo_header
+                    .into_iter()
+                    .chain(packet[transport_header..].iter().copied()),
+            );
+            // A zero UDP checksum means that there is none, so send the
+            // equivalent all-ones value instead.
+            if checksum == 0 && matches!(self.transport, Transport::Udp { .. }) {
+                checksum = 0xffff;
+            }
+            let offset = transport_header + offset;
+            packet[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+        }
+        Ok(packet)
+    }
+}
+
+impl SinkMiddleware for Encapsulation {
+    fn process(&mut self, packet: &mut SinkPacket<'_>) -> Verdict {
+        let reply = packet.info.direction == Some(Direction::Inbound);
+        match self.build(reply, &packet.data) {
+            Ok(data) => {
+                packet.data = data.into();
+                Verdict::Pass
+            }
+            Err(e) => {
+                log::warn!("Dropping packet: {e}");
+                Verdict::Drop
+            }
+        }
+    }
+}
+
+fn swap_if(swap: bool, a: u16, b: u16) -> (u16, u16) {
+    if swap {
+        (b, a)
+    } else {
+        (a, b)
+    }
+}
+
+/// The Internet checksum of RFC 1071: the ones' complement of the ones'
+/// complement sum of the big-endian 16-bit words in `bytes`.
+fn internet_checksum(bytes: impl Iterator<Item = u8>) -> u16 {
+    let mut sum = 0_u32;
+    let mut high = None;
+    for byte in bytes {
+        match high.take() {
+            None => high = Some(byte),
+            Some(high) => sum += u32::from(u16::from_be_bytes([high, byte])),
+        }
+    }
+    if let Some(high) = high {
+        sum += u32::from(high) << 8;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    use super::{internet_checksum, Encapsulation, PayloadTooLong};
+    use crate::sink::{Direction, PacketInfo, SinkMiddleware, SinkPacket, Verdict};
+
+    /// Returns whether the checksum of the UDP or TCP segment in `packet` is
+    /// valid.
+    fn transport_checksum_valid(packet: &[u8], protocol: u8) -> bool {
+        let len = (packet.len() - 34) as u16;
+        let pseudo_header = [&packet[26..34], &[0, protocol], &len.to_be_bytes()].concat();
+        internet_checksum(
+            pseudo_header
+                .into_iter()
+                .chain(packet[34..].iter().copied()),
+        ) == 0
+    }
+
+    #[test]
+    fn checksum() {
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(internet_checksum(header.into_iter()), 0xb861);
+        assert_eq!(internet_checksum([0x12, 0x34, 0x56].into_iter()), !0x6834);
+    }
+
+    #[test]
+    fn udp() {
+        let mut encap = Encapsulation::udp(
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 1), 1234),
+            SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 2), 4321),
+        )
+        .ttl(32);
+        let packet = encap.encapsulate(b"hello").unwrap();
+        assert_eq!(packet[..12], [2, 0, 0, 0, 0, 2, 2, 0, 0, 0, 0, 1]);
+        assert_eq!(packet[12..14], [0x08, 0x00]);
+        assert_eq!(packet[14..24], [0x45, 0, 0, 33, 0, 0, 0x40, 0, 32, 17]);
+        assert_eq!(internet_checksum(packet[14..34].iter().copied()), 0);
+        assert_eq!(packet[34..40], [0x04, 0xd2, 0x10, 0xe1, 0, 13]);
+        assert!(transport_checksum_valid(&packet, 17));
+        assert_eq!(&packet[42..], b"hello");
+
+        let reply = encap.encapsulate_reply(b"").unwrap();
+        assert_eq!(reply[18..20], [0, 1]);
+        assert_eq!(reply[26..34], [192, 168, 0, 2, 192, 168, 0, 1]);
+        assert_eq!(reply[34..38], [0x10, 0xe1, 0x04, 0xd2]);
+        assert!(transport_checksum_valid(&reply, 17));
+    }
+
+    #[test]
+    fn tcp_sequence_numbers() {
+        let mut encap = Encapsulation::tcp(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 40000),
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80),
+        );
+        let seq_ack = |packet: &[u8]| {
+            (
+                u32::from_be_bytes(packet[38..42].try_into().unwrap()),
+                u32::from_be_bytes(packet[42..46].try_into().unwrap()),
+            )
+        };
+        let request = encap.encapsulate(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(seq_ack(&request), (1, 1));
+        assert_eq!(request[46..48], [0x50, 0x18]);
+        assert!(transport_checksum_valid(&request, 6));
+        let response = encap.encapsulate_reply(b"HTTP/1.0 200 OK\r\n").unwrap();
+        assert_eq!(seq_ack(&response), (1, 19));
+        assert!(transport_checksum_valid(&response, 6));
+        let next = encap.encapsulate(b"x").unwrap();
+        assert_eq!(seq_ack(&next), (19, 18));
+    }
+
+    #[test]
+    fn ip_and_too_long() {
+        let mut encap = Encapsulation::ip(Ipv4Addr::new(1, 2, 3, 4), Ipv4Addr::LOCALHOST, 0xfe)
+            .mac_addresses([1; 6], [2; 6]);
+        let packet = encap.encapsulate(&[7; 3]).unwrap();
+        assert_eq!(packet.len(), 14 + 20 + 3);
+        assert_eq!(packet[23], 0xfe);
+        assert_eq!(
+            encap.encapsulate(&vec![0; 65516]),
+            Err(PayloadTooLong {
+                len: 65516,
+                max: 65515
+            })
+        );
+    }
+
+    #[test]
+    fn middleware() {
+        let mut encap = Encapsulation::udp(
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 1),
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 2),
+        );
+        let mut packet = SinkPacket {
+            timestamp: Default::default(),
+            data: b"data"[..].into(),
+            info: PacketInfo::builder().direction(Direction::Inbound).build(),
+        };
+        assert_eq!(encap.process(&mut packet), Verdict::Pass);
+        assert_eq!(packet.data[26..30], [10, 0, 0, 2]);
+        assert_eq!(&packet.data[42..], b"data");
+    }
+}
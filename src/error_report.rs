@@ -0,0 +1,268 @@
+//! Formats errors for the dialog Wireshark shows when an extcap fails.
+//!
+//! When an extcap exits with a non-zero status, Wireshark shows what it wrote
+//! to stderr in an error dialog. Only the beginning of stderr is read, and the
+//! text is shown as-is, so long messages are cut off at an arbitrary point
+//! and the color codes added by some error reporters show up as garbage.
+//!
+//! [`ErrorReport`] formats an error and its chain of
+//! [sources][std::error::Error::source] into a message suited for the dialog:
+//! ANSI escape codes are removed, each source is listed on its own line, and
+//! the message is capped at [`max_len`][ErrorReport::max_len] bytes.
+//!
+//! ```
+//! use r_extcap::error_report::ErrorReport;
+//!
+//! let error = anyhow::anyhow!("No such device")
+//!     .context("Cannot open /dev/ttyUSB0")
+//!     .context("Cannot start the capture");
+//! assert_eq!(
+//!     ErrorReport::default().format(error.as_ref()),
+//!     "Cannot start the capture\n\
+//!      \n\
+//!      Caused by:\n  \
+//!      1. Cannot open /dev/ttyUSB0\n  \
+//!      2. No such device"
+//! );
+//! ```
+
+use std::{borrow::Cow, error::Error};
+
+use typed_builder::TypedBuilder;
+
+/// The default value of [`ErrorReport::max_len`].
+pub const DEFAULT_MAX_LEN: usize = 4096;
+
+const TRUNCATED: &str = "\n… (truncated)";
+
+/// Formats errors for the Wireshark error dialog. See the [module
+/// documentation][self] for details.
+#[derive(Clone, Debug, TypedBuilder)]
+pub struct ErrorReport {
+    /// The maximum length of the formatted message in bytes, including the
+    /// note added when the message is truncated.
+    #[builder(default = DEFAULT_MAX_LEN)]
+    pub max_len: usize,
+}
+
+impl Default for ErrorReport {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+impl ErrorReport {
+    /// Formats `error` followed by its chain of sources. Sources whose
+    /// message is already included at the end of the previous message, as is
+    /// common with `#[error("...: {0}")]`, are omitted.
+    pub fn format(&self, error: &(dyn Error + 'static)) -> String {
+        let mut messages = vec![strip_ansi(&error.to_string()).into_owned()];
+        let mut source = error.source();
+        while let Some(error) = source {
+            let message = strip_ansi(&error.to_string()).into_owned();
+            if !messages.last().is_some_and(|last| last.ends_with(&message)) {
+                messages.push(message);
+            }
+            source = error.source();
+        }
+        let mut report = messages.remove(0);
+        if !messages.is_empty() {
+            report.push_str("\n\nCaused by:");
+            for (i, message) in messages.iter().enumerate() {
+                report.push_str(&format!("\n  {}. {message}", i + 1));
+            }
+        }
+        self.truncate(&report).into_owned()
+    }
+
+    /// Removes the ANSI escape codes from `message` and truncates it to
+    /// [`max_len`][Self::max_len].
+    pub fn format_message(&self, message: &str) -> String {
+        self.truncate(&strip_ansi(message)).into_owned()
+    }
+
+    /// Writes `error`, formatted with [`format`][Self::format], to stderr.
+    pub fn print(&self, error: &(dyn Error + 'static)) {
+        eprintln!("{}", self.format(error));
+    }
+
+    fn truncate<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        if message.len() <= self.max_len {
+            return Cow::Borrowed(message);
+        }
+        let mut end = self.max_len.saturating_sub(TRUNCATED.len());
+        while !message.is_char_boundary(end) {
+            end -= 1;
+        }
+        Cow::Owned(format!("{}{TRUNCATED}", &message[..end]))
+    }
+}
+
+/// Reports the error of a capture to Wireshark and exits, so that the result
+/// of the capture does not need to be matched at every call site.
+///
+/// The error, and its chain of sources, is formatted with [`ErrorReport`] and
+/// written to stderr, which Wireshark shows in an error dialog once the
+/// extcap exits. The process then exits with status 1.
+///
+/// This is meant for errors that happen during the capture, after the fifo
+/// is opened. For errors before the fifo is opened, use
+/// [`CaptureStep::abort_capture`][crate::CaptureStep::abort_capture], which
+/// also keeps Wireshark from waiting for the fifo.
+///
+/// ```no_run
+/// use r_extcap::error_report::CaptureResultExt;
+///
+/// # fn capture_packets(capture_step: &mut r_extcap::CaptureStep) -> anyhow::Result<()> { Ok(()) }
+/// # fn example(mut capture_step: r_extcap::CaptureStep) {
+/// let mut sender = capture_step.new_control_sender();
+/// capture_packets(&mut capture_step).or_report(&mut sender);
+/// # }
+/// ```
+pub trait CaptureResultExt<T> {
+    /// Returns the value, or writes the error to stderr and exits the process
+    /// with status 1.
+    fn or_exit(self) -> T;
+
+    /// Like [`or_exit`][Self::or_exit], but also shows the first line of the
+    /// error in the status bar with `sender`, since Wireshark only shows the
+    /// error dialog after it notices the extcap has exited. Errors sending
+    /// the status message are ignored.
+    #[cfg(feature = "sync")]
+    fn or_report(self, sender: impl crate::controls::synchronous::ExtcapControlSenderTrait) -> T;
+}
+
+impl<T, E: Into<anyhow::Error>> CaptureResultExt<T> for Result<T, E> {
+    fn or_exit(self) -> T {
+        self.unwrap_or_else(|error| {
+            ErrorReport::default().print(error.into().as_ref());
+            std::process::exit(1)
+        })
+    }
+
+    #[cfg(feature = "sync")]
+    fn or_report(self, sender: impl crate::controls::synchronous::ExtcapControlSenderTrait) -> T {
+        self.unwrap_or_else(|error| {
+            report(&error.into(), sender);
+            std::process::exit(1)
+        })
+    }
+}
+
+/// Writes `error` to stderr and its first line to the status bar, and
+/// returns the formatted error.
+#[cfg(feature = "sync")]
+fn report(
+    error: &anyhow::Error,
+    sender: impl crate::controls::synchronous::ExtcapControlSenderTrait,
+) -> String {
+    let message = ErrorReport::default().format(error.as_ref());
+    eprintln!("{message}");
+    let _ = sender.status_message(message.lines().next().unwrap_or_default());
+    message
+}
+
+/// Removes ANSI escape sequences, like the color codes added by some error
+/// reporters, from `text`.
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains('\x1b') {
+        return Cow::Borrowed(text);
+    }
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI sequences, like colors, end with a byte in `@`..=`~`.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC sequences, like hyperlinks, end with BEL or ST (`ESC \`).
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' || (c == '\x1b' && chars.next() == Some('\\')) {
+                        break;
+                    }
+                }
+            }
+            // Other escape sequences are two characters long.
+            _ => {}
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+#[cfg(test)]
+mod test {
+    use thiserror::Error;
+
+    use super::{strip_ansi, ErrorReport};
+
+    #[derive(Debug, Error)]
+    enum DeviceError {
+        #[error("Cannot open device: {0}")]
+        Open(#[source] std::io::Error),
+    }
+
+    #[test]
+    fn skips_repeated_sources() {
+        let error = DeviceError::Open(std::io::Error::other("permission denied"));
+        assert_eq!(
+            ErrorReport::default().format(&error),
+            "Cannot open device: permission denied"
+        );
+    }
+
+    #[test]
+    fn strips_ansi() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31merror\x1b[0m: \x1b]8;;https://example.com\x07link\x1b]8;;\x1b\\"),
+            "error: link"
+        );
+        assert_eq!(strip_ansi("plain"), "plain");
+    }
+
+    #[test]
+    fn truncates() {
+        let report = ErrorReport::builder().max_len(20).build();
+        assert_eq!(report.format_message("short"), "short");
+        let message = report.format_message(&"é".repeat(20));
+        assert!(message.len() <= 20, "{message}");
+        assert_eq!(message, "éé\n… (truncated)");
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn report_to_status_bar() {
+        use crate::controls::{
+            synchronous::ExtcapControlSenderTrait, ControlCommand, ControlPacket,
+        };
+
+        struct Collect(Vec<ControlPacket<'static>>);
+
+        impl ExtcapControlSenderTrait for &mut Collect {
+            fn send(self, packet: ControlPacket<'_>) -> std::io::Result<()> {
+                self.0.push(packet.into_owned());
+                Ok(())
+            }
+        }
+
+        let error = anyhow::anyhow!("No such device").context("Cannot open /dev/ttyUSB0");
+        let mut sender = Collect(Vec::new());
+        let message = super::report(&error, &mut sender);
+        assert_eq!(
+            message,
+            "Cannot open /dev/ttyUSB0\n\nCaused by:\n  1. No such device"
+        );
+        assert_eq!(sender.0.len(), 1);
+        assert_eq!(sender.0[0].command, ControlCommand::StatusbarMessage);
+        assert_eq!(&sender.0[0].payload[..], b"Cannot open /dev/ttyUSB0");
+    }
+}
@@ -0,0 +1,258 @@
+//! Encodes packets for Wireshark's exported PDU mechanism.
+//!
+//! Packets with the [`WIRESHARK_UPPER_PDU`][pcap_file::DataLink::WIRESHARK_UPPER_PDU]
+//! link type start with a list of tags, which tell Wireshark which dissector
+//! to hand the rest of the packet to, and optionally the addresses, ports and
+//! column texts to show for it. This lets extcaps for logs, application
+//! messages or other non-network data have their payloads dissected by an
+//! existing dissector, like `json`, `syslog` or `systemd_journal`, without
+//! making up a link-layer header.
+//!
+//! ```
+//! use r_extcap::exported_pdu::{ExportedPdu, Tag};
+//! use std::net::Ipv4Addr;
+//!
+//! let packet = ExportedPdu::new("json")
+//!     .tag(Tag::Ipv4Src(Ipv4Addr::new(10, 0, 0, 1)))
+//!     .tag(Tag::ColInfoText("Sensor report"))
+//!     .encode(br#"{"temperature": 21.5}"#);
+//! // Write `packet` to a sink created with `DataLink::WIRESHARK_UPPER_PDU`.
+//! ```
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// The tag types of the exported PDU header, as defined in Wireshark's
+/// `exported_pdu.h`.
+pub mod tag_type {
+    /// Ends the list of tags.
+    pub const END_OF_OPT: u16 = 0;
+    /// The name of the dissector for the payload.
+    pub const DISSECTOR_NAME: u16 = 12;
+    /// The name of the heuristic dissector for the payload.
+    pub const HEUR_DISSECTOR_NAME: u16 = 13;
+    /// The name of the dissector table to look up the dissector in.
+    pub const DISSECTOR_TABLE_NAME: u16 = 14;
+    /// The IPv4 source address.
+    pub const IPV4_SRC: u16 = 20;
+    /// The IPv4 destination address.
+    pub const IPV4_DST: u16 = 21;
+    /// The IPv6 source address.
+    pub const IPV6_SRC: u16 = 22;
+    /// The IPv6 destination address.
+    pub const IPV6_DST: u16 = 23;
+    /// The type of the source and destination ports.
+    pub const PORT_TYPE: u16 = 24;
+    /// The source port.
+    pub const SRC_PORT: u16 = 25;
+    /// The destination port.
+    pub const DST_PORT: u16 = 26;
+    /// The number of the frame in the original capture.
+    pub const ORIG_FNO: u16 = 30;
+    /// The value to look up in the dissector table.
+    pub const DISSECTOR_TABLE_NAME_NUM_VAL: u16 = 32;
+    /// The text of the Protocol column.
+    pub const COL_PROT_TEXT: u16 = 33;
+    /// The direction of a point-to-point link.
+    pub const P2P_DIRECTION: u16 = 35;
+    /// The text of the Info column.
+    pub const COL_INFO_TEXT: u16 = 36;
+}
+
+/// The type of the ports in [`Tag::SrcPort`] and [`Tag::DstPort`], which
+/// determines the conversation they belong to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PortType {
+    /// SCTP ports.
+    Sctp = 1,
+    /// TCP ports.
+    Tcp = 2,
+    /// UDP ports.
+    Udp = 3,
+    /// DCCP ports.
+    Dccp = 4,
+    /// USB endpoints.
+    Usb = 8,
+    /// I2C addresses.
+    I2c = 9,
+    /// Bluetooth channels.
+    Bluetooth = 11,
+}
+
+/// The direction of a packet on a point-to-point link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum P2pDirection {
+    /// The packet was sent by this side of the link.
+    Sent = 0,
+    /// The packet was received by this side of the link.
+    Received = 1,
+}
+
+/// A tag of the exported PDU header. See [`ExportedPdu::tag`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Tag<'a> {
+    /// The name of the dissector for the payload, like `json`. This is usually
+    /// set using [`ExportedPdu::new`].
+    DissectorName(&'a str),
+    /// The name of a heuristic dissector for the payload, like `rtp_udp`.
+    HeuristicDissectorName(&'a str),
+    /// The name of a dissector table, like `udp.port`, in which the dissector
+    /// for the payload is looked up with [`Tag::DissectorTableValue`].
+    DissectorTableName(&'a str),
+    /// The value to look up in the [dissector table][Tag::DissectorTableName].
+    DissectorTableValue(u32),
+    /// The IPv4 source address.
+    Ipv4Src(Ipv4Addr),
+    /// The IPv4 destination address.
+    Ipv4Dst(Ipv4Addr),
+    /// The IPv6 source address.
+    Ipv6Src(Ipv6Addr),
+    /// The IPv6 destination address.
+    Ipv6Dst(Ipv6Addr),
+    /// The type of the source and destination ports.
+    PortType(PortType),
+    /// The source port.
+    SrcPort(u32),
+    /// The destination port.
+    DstPort(u32),
+    /// The number of the frame in the original capture.
+    OriginalFrameNumber(u32),
+    /// The text of the Protocol column.
+    ColProtocolText(&'a str),
+    /// The text of the Info column.
+    ColInfoText(&'a str),
+    /// The direction of a point-to-point link.
+    P2pDirection(P2pDirection),
+}
+
+/// The exported PDU header of [`WIRESHARK_UPPER_PDU`][pcap_file::DataLink::WIRESHARK_UPPER_PDU]
+/// packets. See the [module documentation][self] for an example.
+///
+/// Each tag is encoded as a big-endian 16-bit tag type and 16-bit length,
+/// followed by the value. String values are padded with NULs to a multiple of
+/// 4 bytes. The header is terminated by an [`END_OF_OPT`][tag_type::END_OF_OPT]
+/// tag when [encoded][Self::encode].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExportedPdu {
+    tags: Vec<u8>,
+}
+
+impl ExportedPdu {
+    /// Creates a header that hands the payload to the dissector named
+    /// `dissector`.
+    pub fn new(dissector: &str) -> Self {
+        Self::default().tag(Tag::DissectorName(dissector))
+    }
+
+    /// Creates a header that hands the payload to the dissector registered for
+    /// `value` in the dissector table named `table`, like `("udp.port", 53)`.
+    pub fn for_table(table: &str, value: u32) -> Self {
+        Self::default()
+            .tag(Tag::DissectorTableName(table))
+            .tag(Tag::DissectorTableValue(value))
+    }
+
+    /// Appends `tag` to the header.
+    #[must_use]
+    pub fn tag(self, tag: Tag<'_>) -> Self {
+        match tag {
+            Tag::DissectorName(name) => self.string_tag(tag_type::DISSECTOR_NAME, name),
+            Tag::HeuristicDissectorName(name) => {
+                self.string_tag(tag_type::HEUR_DISSECTOR_NAME, name)
+            }
+            Tag::DissectorTableName(name) => self.string_tag(tag_type::DISSECTOR_TABLE_NAME, name),
+            Tag::DissectorTableValue(value) => {
+                self.u32_tag(tag_type::DISSECTOR_TABLE_NAME_NUM_VAL, value)
+            }
+            Tag::Ipv4Src(addr) => self.raw_tag(tag_type::IPV4_SRC, &addr.octets()),
+            Tag::Ipv4Dst(addr) => self.raw_tag(tag_type::IPV4_DST, &addr.octets()),
+            Tag::Ipv6Src(addr) => self.raw_tag(tag_type::IPV6_SRC, &addr.octets()),
+            Tag::Ipv6Dst(addr) => self.raw_tag(tag_type::IPV6_DST, &addr.octets()),
+            Tag::PortType(port_type) => self.u32_tag(tag_type::PORT_TYPE, port_type as u32),
+            Tag::SrcPort(port) => self.u32_tag(tag_type::SRC_PORT, port),
+            Tag::DstPort(port) => self.u32_tag(tag_type::DST_PORT, port),
+            Tag::OriginalFrameNumber(number) => self.u32_tag(tag_type::ORIG_FNO, number),
+            Tag::ColProtocolText(text) => self.string_tag(tag_type::COL_PROT_TEXT, text),
+            Tag::ColInfoText(text) => self.string_tag(tag_type::COL_INFO_TEXT, text),
+            Tag::P2pDirection(direction) => self.u32_tag(tag_type::P2P_DIRECTION, direction as u32),
+        }
+    }
+
+    /// Appends a tag of type `tag_type` with the already encoded `value`, for
+    /// tags not covered by [`Tag`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is longer than `u16::MAX` bytes.
+    #[must_use]
+    pub fn raw_tag(mut self, tag_type: u16, value: &[u8]) -> Self {
+        let len = u16::try_from(value.len()).expect("Exported PDU tag value too long");
+        self.tags.extend_from_slice(&tag_type.to_be_bytes());
+        self.tags.extend_from_slice(&len.to_be_bytes());
+        self.tags.extend_from_slice(value);
+        self
+    }
+
+    fn string_tag(self, tag_type: u16, value: &str) -> Self {
+        let mut padded = value.as_bytes().to_vec();
+        padded.resize((value.len() + 3) & !3, 0);
+        self.raw_tag(tag_type, &padded)
+    }
+
+    fn u32_tag(self, tag_type: u16, value: u32) -> Self {
+        self.raw_tag(tag_type, &value.to_be_bytes())
+    }
+
+    /// Returns the packet data consisting of this header followed by
+    /// `payload`.
+    pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(self.tags.len() + 4 + payload.len());
+        packet.extend_from_slice(&self.tags);
+        packet.extend_from_slice(&tag_type::END_OF_OPT.to_be_bytes());
+        packet.extend_from_slice(&0_u16.to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::{ExportedPdu, P2pDirection, PortType, Tag};
+
+    #[test]
+    fn dissector_name_padding() {
+        assert_eq!(
+            ExportedPdu::new("json").encode(b"{}"),
+            b"\x00\x0c\x00\x04json\x00\x00\x00\x00{}"
+        );
+        assert_eq!(
+            ExportedPdu::new("systemd_journal").encode(b""),
+            b"\x00\x0c\x00\x10systemd_journal\x00\x00\x00\x00\x00"
+        );
+    }
+
+    #[test]
+    fn address_tags() {
+        let packet = ExportedPdu::for_table("udp.port", 53)
+            .tag(Tag::Ipv4Src(Ipv4Addr::new(10, 0, 0, 1)))
+            .tag(Tag::Ipv6Dst(Ipv6Addr::LOCALHOST))
+            .tag(Tag::PortType(PortType::Udp))
+            .tag(Tag::DstPort(53))
+            .tag(Tag::P2pDirection(P2pDirection::Received))
+            .encode(b"x");
+        let expected: &[&[u8]] = &[
+            b"\x00\x0e\x00\x08udp.port",
+            b"\x00\x20\x00\x04\x00\x00\x00\x35",
+            b"\x00\x14\x00\x04\x0a\x00\x00\x01",
+            b"\x00\x17\x00\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01",
+            b"\x00\x18\x00\x04\x00\x00\x00\x03",
+            b"\x00\x1a\x00\x04\x00\x00\x00\x35",
+            b"\x00\x23\x00\x04\x00\x00\x00\x01",
+            b"\x00\x00\x00\x00x",
+        ];
+        assert_eq!(packet, expected.concat());
+    }
+}
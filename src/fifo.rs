@@ -0,0 +1,407 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::shutdown::{ShutdownReason, ShutdownSignal};
+
+/// The fifo that captured packets are written to, given by Wireshark in the
+/// `--fifo` argument.
+///
+/// The fifo is opened when it is first written to, or explicitly using
+/// [`open`][Self::open]. On Unix, opening a fifo for writing blocks until
+/// Wireshark opens the other end for reading.
+///
+/// By default [`ExtcapArgs::run`][crate::ExtcapArgs::run] opens the fifo
+/// before returning the [`CaptureStep`][crate::CaptureStep]. To perform slow
+/// setup (e.g. connecting to a device) before connecting to Wireshark, use
+/// [`ExtcapArgs::run_with_lazy_fifo`][crate::ExtcapArgs::run_with_lazy_fifo]
+/// instead.
+///
+/// If a write fails because Wireshark closed the fifo, the
+/// [`ShutdownSignal`] of the capture is triggered with
+/// [`ShutdownReason::FifoClosed`].
+///
+/// The fifo is `Send`, so it can be moved to the thread that writes the
+/// captured packets. Use
+/// [`CaptureStep::take_fifo`][crate::CaptureStep::take_fifo] to take
+/// ownership of it.
+#[derive(Debug)]
+pub struct Fifo {
+    path: PathBuf,
+    file: Option<File>,
+    shutdown: ShutdownSignal,
+    /// Whether this is the placeholder left behind by
+    /// [`CaptureStep::take_fifo`][crate::CaptureStep::take_fifo].
+    taken: bool,
+}
+
+impl Fifo {
+    pub(crate) fn new(path: PathBuf, shutdown: ShutdownSignal) -> Self {
+        Self {
+            path,
+            file: None,
+            shutdown,
+            taken: false,
+        }
+    }
+
+    /// Replaces this fifo with a placeholder that fails all writes, and
+    /// returns the original.
+    pub(crate) fn take(&mut self) -> Self {
+        let placeholder = Self {
+            path: self.path.clone(),
+            file: None,
+            shutdown: self.shutdown.clone(),
+            taken: true,
+        };
+        std::mem::replace(self, placeholder)
+    }
+
+    /// The path of the fifo.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the fifo has been opened.
+    pub fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Opens the fifo if it is not already open, and returns the opened file.
+    ///
+    /// Fails if the fifo has been moved out using
+    /// [`CaptureStep::take_fifo`][crate::CaptureStep::take_fifo].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(path = %self.path.display()))
+    )]
+    pub fn open(&mut self) -> io::Result<&mut File> {
+        if self.taken {
+            return Err(io::Error::other(
+                "The fifo has been taken using `CaptureStep::take_fifo`",
+            ));
+        }
+        if self.file.is_none() {
+            let file = open_fifo(&self.path, false)?;
+            #[cfg(target_os = "windows")]
+            self.shutdown.watch_pipe(&file);
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    /// Consumes this fifo and returns the underlying file, opening it first if
+    /// it is not already open.
+    ///
+    /// Write errors on the returned file do not trigger the
+    /// [`ShutdownSignal`].
+    pub fn into_file(mut self) -> io::Result<File> {
+        self.open()?;
+        Ok(self.file.take().unwrap())
+    }
+
+    fn check_closed<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if let Err(e) = &result {
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                self.shutdown.trigger(ShutdownReason::FifoClosed);
+            }
+        }
+        result
+    }
+}
+
+impl Write for Fifo {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip_all, fields(len = buf.len()), err)
+    )]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.open()?.write(buf);
+        self.check_closed(result)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(self), err)
+    )]
+    fn flush(&mut self) -> io::Result<()> {
+        let result = match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        };
+        self.check_closed(result)
+    }
+}
+
+impl Drop for Fifo {
+    fn drop(&mut self) {
+        if self.file.is_none() && !self.taken {
+            // Wireshark waits for the fifo to be opened, so open and close it
+            // even if the capture never wrote to it.
+            let _ = open_and_close(&self.path);
+        }
+    }
+}
+
+/// When a [`BufferedFifo`] flushes the buffered packets to Wireshark.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flushes after every write, so that each packet shows up in Wireshark
+    /// as soon as it is written. This has the lowest latency, but makes at
+    /// least one system call per packet.
+    EveryWrite,
+    /// Flushes whenever the given number of bytes is buffered. This has the
+    /// highest throughput, but packets are not shown in Wireshark until enough
+    /// packets have been captured after them.
+    Size(usize),
+    /// Flushes on a write if the given duration has passed since the last
+    /// flush.
+    Interval(Duration),
+}
+
+impl Default for FlushPolicy {
+    /// Flushes at most every 100 ms.
+    fn default() -> Self {
+        Self::Interval(Duration::from_millis(100))
+    }
+}
+
+/// The size of the buffer when the [`FlushPolicy`] does not specify one.
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A buffered writer for the capture fifo, which reduces the number of system
+/// calls for extcaps that capture packets at a high rate. Created using
+/// [`CaptureStep::take_buffered_fifo`][crate::CaptureStep::take_buffered_fifo].
+///
+/// The buffer is flushed according to its [`FlushPolicy`], when
+/// [`flush`][Write::flush] is called, and when the writer is dropped.
+/// Policies are only checked when writing, so an extcap that stops writing for
+/// a while, for example while waiting for a slow device, should call
+/// [`flush_if_due`][Self::flush_if_due] or `flush` when it becomes idle.
+/// Otherwise the last packets may not show up in Wireshark until the next
+/// write.
+///
+/// ```
+/// use r_extcap::{BufferedFifo, FlushPolicy};
+/// use std::io::Write;
+///
+/// # let fifo = Vec::new();
+/// let mut writer = BufferedFifo::new(fifo, FlushPolicy::Size(1024));
+/// writer.write_all(b"packet").unwrap();
+/// assert!(writer.get_ref().is_empty());
+/// writer.flush().unwrap();
+/// assert_eq!(writer.get_ref(), b"packet");
+/// ```
+#[derive(Debug)]
+pub struct BufferedFifo<W: Write = Fifo> {
+    writer: BufWriter<W>,
+    policy: FlushPolicy,
+    last_flush: Instant,
+}
+
+impl<W: Write> BufferedFifo<W> {
+    /// Wraps `writer`, flushing according to `policy`.
+    pub fn new(writer: W, policy: FlushPolicy) -> Self {
+        let capacity = match policy {
+            FlushPolicy::Size(size) => size.max(1),
+            FlushPolicy::EveryWrite | FlushPolicy::Interval(_) => DEFAULT_BUFFER_SIZE,
+        };
+        Self {
+            writer: BufWriter::with_capacity(capacity, writer),
+            policy,
+            last_flush: Instant::now(),
+        }
+    }
+
+    /// The flush policy of this writer.
+    pub fn policy(&self) -> FlushPolicy {
+        self.policy
+    }
+
+    /// Flushes the buffer if the [`Interval`][FlushPolicy::Interval] has
+    /// passed since the last flush. For other policies, this does nothing.
+    pub fn flush_if_due(&mut self) -> io::Result<()> {
+        match self.policy {
+            FlushPolicy::Interval(interval) if self.last_flush.elapsed() >= interval => {
+                self.flush()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.writer.get_ref()
+    }
+
+    /// Flushes the buffer and returns the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<W: Write> Write for BufferedFifo<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        match self.policy {
+            FlushPolicy::EveryWrite => self.flush()?,
+            // `BufWriter` writes out the buffer once it is full.
+            FlushPolicy::Size(_) => {}
+            FlushPolicy::Interval(_) => self.flush_if_due()?,
+        }
+        Ok(written)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "trace", skip(self), err)
+    )]
+    fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+}
+
+/// How long [`open_and_close`] waits for Wireshark to open the fifo for
+/// reading.
+const OPEN_AND_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Opens and closes the fifo, so that Wireshark sees the end of the capture
+/// even if nothing was written to it.
+///
+/// Opening the fifo fails with `ENXIO` if Wireshark has not opened it for
+/// reading yet, and blocking until it does would hang forever if Wireshark is
+/// no longer running. So the open is retried until Wireshark opens the fifo or
+/// [`OPEN_AND_CLOSE_TIMEOUT`] passes.
+pub(crate) fn open_and_close(path: &Path) -> io::Result<()> {
+    let deadline = Instant::now() + OPEN_AND_CLOSE_TIMEOUT;
+    loop {
+        match open_fifo(path, true) {
+            Err(e) if is_not_opened_for_reading(&e) && Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            result => return result.map(drop),
+        }
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_not_opened_for_reading(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::ENXIO)
+}
+
+#[cfg(target_os = "windows")]
+fn is_not_opened_for_reading(_error: &io::Error) -> bool {
+    false
+}
+
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn open_fifo(path: &Path, nonblocking: bool) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .custom_flags(if nonblocking { libc::O_NONBLOCK } else { 0 })
+        .open(path)
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn open_fifo(path: &Path, _nonblocking: bool) -> io::Result<File> {
+    use std::os::windows::prelude::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        // Sets the flag value to `SecurityIdentification`.
+        .security_qos_flags(0x10000)
+        .open(path)
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{
+        fs::OpenOptions,
+        io::{ErrorKind, Read, Write},
+        os::unix::fs::OpenOptionsExt,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::Fifo;
+    use crate::{
+        shutdown::{ShutdownReason, ShutdownSignal},
+        testing::TempFifo,
+    };
+
+    #[test]
+    fn closed_fifo_triggers_shutdown() {
+        let temp_fifo = TempFifo::new("closed").unwrap();
+        let path = temp_fifo.path().to_owned();
+
+        let reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .unwrap();
+        let signal = ShutdownSignal::default();
+        let ended = Arc::new(AtomicBool::new(false));
+        let ended_clone = ended.clone();
+        signal.on_shutdown(move || ended_clone.store(true, Ordering::SeqCst));
+        let mut fifo = Fifo::new(path.clone(), signal.clone());
+        fifo.write_all(b"packet").unwrap();
+        assert!(!ended.load(Ordering::SeqCst));
+
+        drop(reader);
+        let error = fifo.write_all(b"packet").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+        assert!(ended.load(Ordering::SeqCst));
+        assert_eq!(signal.reason(), Some(ShutdownReason::FifoClosed));
+    }
+
+    #[test]
+    fn drop_unopened_fifo() {
+        let temp_fifo = TempFifo::new("unopened").unwrap();
+        let fifo = Fifo::new(temp_fifo.path().to_owned(), ShutdownSignal::default());
+        // Drop the fifo before Wireshark opens the fifo for reading.
+        let dropped = std::thread::spawn(move || drop(fifo));
+        std::thread::sleep(Duration::from_millis(100));
+        let mut data = Vec::new();
+        temp_fifo
+            .open_reader()
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        dropped.join().unwrap();
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn buffered_fifo_policies() {
+        use super::{BufferedFifo, FlushPolicy};
+
+        let mut every_write = BufferedFifo::new(Vec::new(), FlushPolicy::EveryWrite);
+        every_write.write_all(b"packet").unwrap();
+        assert_eq!(every_write.get_ref(), b"packet");
+
+        let mut size = BufferedFifo::new(Vec::new(), FlushPolicy::Size(8));
+        size.write_all(b"packet").unwrap();
+        assert!(size.get_ref().is_empty());
+        size.write_all(b"packet").unwrap();
+        assert_eq!(size.get_ref(), b"packet");
+        assert_eq!(size.into_inner().unwrap(), b"packetpacket");
+
+        let mut interval =
+            BufferedFifo::new(Vec::new(), FlushPolicy::Interval(Duration::from_millis(20)));
+        interval.write_all(b"packet").unwrap();
+        assert!(interval.get_ref().is_empty());
+        std::thread::sleep(Duration::from_millis(20));
+        interval.flush_if_due().unwrap();
+        assert_eq!(interval.get_ref(), b"packet");
+    }
+}
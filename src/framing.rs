@@ -0,0 +1,638 @@
+//! Decoders for the framed byte streams emitted by serial and TCP sniffer
+//! devices.
+//!
+//! Each decoder implements [`FrameDecoder`], which takes the bytes in
+//! whatever chunks they are read and returns the complete frames, like
+//! [`ControlPacketDecoder`][crate::controls::ControlPacketDecoder] does for
+//! control packets. Use [`frame_parser`] to turn a decoder into a
+//! [`FrameParser`] for a [`ChildProcessSource`][crate::sources::ChildProcessSource].
+//!
+//! ```
+//! use r_extcap::framing::{FrameDecoder, Slip};
+//!
+//! let mut decoder = Slip::new();
+//! assert!(decoder.push_bytes(b"\xc0ab\xdb\xdc").unwrap().is_empty());
+//! assert_eq!(decoder.push_bytes(b"c\xc0").unwrap(), [b"ab\xc0c"]);
+//! ```
+
+use std::{collections::VecDeque, io::BufRead};
+
+use thiserror::Error;
+
+use crate::sources::FrameParser;
+
+/// The default [`max_frame_len`][Slip::max_frame_len] of the decoders.
+const DEFAULT_MAX_FRAME_LEN: usize = 1 << 16;
+
+/// Error returned by [`FrameDecoder::push_bytes`] when the stream cannot be
+/// resynchronized.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum FramingError {
+    /// The length prefix of a frame is larger than the maximum frame length.
+    #[error("Frame length {len} exceeds the maximum of {max}")]
+    FrameTooLong {
+        /// The length in the prefix.
+        len: usize,
+        /// The maximum frame length of the decoder.
+        max: usize,
+    },
+    /// The length prefix of a frame is shorter than the prefix itself.
+    #[error("Frame length {0} is shorter than the length prefix")]
+    InvalidLength(usize),
+}
+
+/// Splits a byte stream into frames.
+pub trait FrameDecoder {
+    /// Appends `bytes` to the buffer of the decoder, and returns all of the
+    /// frames that are now complete, in the order they were received. The
+    /// bytes of an incomplete frame at the end are kept until the next call.
+    ///
+    /// Decoders for delimited formats skip malformed frames and continue with
+    /// the next one, counting them in
+    /// [`malformed_frames`][Self::malformed_frames]. If the stream cannot be
+    /// resynchronized, a [`FramingError`] is returned and the buffer is
+    /// cleared, and frames decoded from the same call are discarded.
+    fn push_bytes(&mut self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, FramingError>;
+
+    /// The number of malformed frames skipped so far.
+    fn malformed_frames(&self) -> u64 {
+        0
+    }
+}
+
+/// Returns a [`FrameParser`] that reads the frames from the stream using
+/// `decoder`. Errors from the decoder are returned as
+/// [`InvalidData`][std::io::ErrorKind::InvalidData] IO errors.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use pcap_file::DataLink;
+/// use r_extcap::{framing::{frame_parser, Slip}, sources::ChildProcessSource};
+/// # let fifo = Vec::new();
+///
+/// let mut command = Command::new("socat");
+/// command.args(["-u", "/dev/ttyUSB0,raw,b115200", "-"]);
+/// ChildProcessSource::new(command)
+///     .frame_parser(DataLink::USER0, frame_parser(Slip::new()))
+///     .run(fifo)
+///     .unwrap();
+/// ```
+pub fn frame_parser(decoder: impl FrameDecoder + Send) -> impl FrameParser {
+    let mut pending = VecDeque::new();
+    let mut decoder = decoder;
+    move |reader: &mut dyn BufRead| loop {
+        if let Some(frame) = pending.pop_front() {
+            return Ok(Some(frame));
+        }
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let len = buf.len();
+        let frames = decoder
+            .push_bytes(buf)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        reader.consume(len);
+        pending.extend(frames);
+    }
+}
+
+/// A decoder for SLIP (RFC 1055) framing, where frames are terminated by an
+/// `END` byte (`0xC0`), and `END` and `ESC` (`0xDB`) bytes in the frame are
+/// escaped.
+///
+/// Empty frames, like the ones produced by sending an `END` byte before each
+/// frame to flush line noise, are skipped.
+#[derive(Clone, Debug)]
+pub struct Slip {
+    buffer: Vec<u8>,
+    escaped: bool,
+    discarding: bool,
+    max_frame_len: usize,
+    malformed_frames: u64,
+}
+
+impl Default for Slip {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            escaped: false,
+            discarding: false,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            malformed_frames: 0,
+        }
+    }
+}
+
+impl Slip {
+    const END: u8 = 0xc0;
+    const ESC: u8 = 0xdb;
+    const ESC_END: u8 = 0xdc;
+    const ESC_ESC: u8 = 0xdd;
+
+    /// Creates a decoder for frames of up to 64 KiB.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum length of a decoded frame. Longer frames are skipped
+    /// as malformed.
+    #[must_use]
+    pub fn max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// Encodes `frame`, including the terminating `END` byte.
+    pub fn encode(&self, frame: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(frame.len() + 2);
+        for &byte in frame {
+            match byte {
+                Self::END => encoded.extend_from_slice(&[Self::ESC, Self::ESC_END]),
+                Self::ESC => encoded.extend_from_slice(&[Self::ESC, Self::ESC_ESC]),
+                byte => encoded.push(byte),
+            }
+        }
+        encoded.push(Self::END);
+        encoded
+    }
+
+    fn discard(&mut self, reason: &str) {
+        log::warn!("Skipping malformed SLIP frame: {reason}");
+        self.malformed_frames += 1;
+        self.discarding = true;
+        self.escaped = false;
+        self.buffer.clear();
+    }
+}
+
+impl FrameDecoder for Slip {
+    fn push_bytes(&mut self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, FramingError> {
+        let mut frames = Vec::new();
+        for &byte in bytes {
+            if byte == Self::END {
+                if self.escaped {
+                    self.discard("Frame ends with an escape");
+                }
+                let frame = std::mem::take(&mut self.buffer);
+                if !self.discarding && !frame.is_empty() {
+                    frames.push(frame);
+                }
+                self.discarding = false;
+                continue;
+            }
+            if self.discarding {
+                continue;
+            }
+            let byte = if self.escaped {
+                self.escaped = false;
+                match byte {
+                    Self::ESC_END => Self::END,
+                    Self::ESC_ESC => Self::ESC,
+                    _ => {
+                        self.discard(&format!("Invalid escape {byte:#04x}"));
+                        continue;
+                    }
+                }
+            } else if byte == Self::ESC {
+                self.escaped = true;
+                continue;
+            } else {
+                byte
+            };
+            if self.buffer.len() >= self.max_frame_len {
+                self.discard("Frame too long");
+                continue;
+            }
+            self.buffer.push(byte);
+        }
+        Ok(frames)
+    }
+
+    fn malformed_frames(&self) -> u64 {
+        self.malformed_frames
+    }
+}
+
+/// A decoder for COBS (Consistent Overhead Byte Stuffing) framing, where
+/// frames are encoded to contain no zero bytes and terminated by a zero byte.
+#[derive(Clone, Debug)]
+pub struct Cobs {
+    buffer: Vec<u8>,
+    discarding: bool,
+    max_frame_len: usize,
+    malformed_frames: u64,
+}
+
+impl Default for Cobs {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            discarding: false,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            malformed_frames: 0,
+        }
+    }
+}
+
+impl Cobs {
+    /// Creates a decoder for frames of up to 64 KiB.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum length of a decoded frame. Longer frames are skipped
+    /// as malformed.
+    #[must_use]
+    pub fn max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// Encodes `frame`, including the terminating zero byte.
+    pub fn encode(&self, frame: &[u8]) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(frame.len() + frame.len() / 254 + 2);
+        let mut code_index = 0;
+        encoded.push(0);
+        for &byte in frame {
+            if byte != 0 {
+                encoded.push(byte);
+            }
+            let code = encoded.len() - code_index;
+            if byte == 0 || code == 0xff {
+                encoded[code_index] = code as u8;
+                code_index = encoded.len();
+                encoded.push(0);
+            }
+        }
+        encoded[code_index] = (encoded.len() - code_index) as u8;
+        encoded.push(0);
+        encoded
+    }
+
+    /// Decodes a frame without its terminating zero byte, or returns `None` if
+    /// it is malformed.
+    fn decode(encoded: &[u8]) -> Option<Vec<u8>> {
+        let mut frame = Vec::with_capacity(encoded.len());
+        let mut index = 0;
+        while index < encoded.len() {
+            let code = usize::from(encoded[index]);
+            let end = index + code;
+            if end > encoded.len() {
+                return None;
+            }
+            frame.extend_from_slice(&encoded[index + 1..end]);
+            index = end;
+            if code < 0xff && index < encoded.len() {
+                frame.push(0);
+            }
+        }
+        Some(frame)
+    }
+}
+
+impl FrameDecoder for Cobs {
+    fn push_bytes(&mut self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, FramingError> {
+        let mut frames = Vec::new();
+        // The encoding adds one byte, plus one for every 254 bytes.
+        let max_encoded_len = self.max_frame_len + self.max_frame_len / 254 + 1;
+        for &byte in bytes {
+            if byte == 0 {
+                if !self.discarding && !self.buffer.is_empty() {
+                    match Self::decode(&self.buffer) {
+                        Some(frame) => frames.push(frame),
+                        None => {
+                            log::warn!("Skipping malformed COBS frame: Invalid code");
+                            self.malformed_frames += 1;
+                        }
+                    }
+                }
+                self.buffer.clear();
+                self.discarding = false;
+            } else if !self.discarding {
+                if self.buffer.len() >= max_encoded_len {
+                    log::warn!("Skipping malformed COBS frame: Frame too long");
+                    self.malformed_frames += 1;
+                    self.discarding = true;
+                    self.buffer.clear();
+                    continue;
+                }
+                self.buffer.push(byte);
+            }
+        }
+        Ok(frames)
+    }
+
+    fn malformed_frames(&self) -> u64 {
+        self.malformed_frames
+    }
+}
+
+/// The format of the length prefix of a [`LengthPrefixed`] frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LengthPrefix {
+    /// A single byte.
+    U8,
+    /// A big-endian 16-bit integer.
+    U16Be,
+    /// A little-endian 16-bit integer.
+    U16Le,
+    /// A big-endian 32-bit integer.
+    U32Be,
+    /// A little-endian 32-bit integer.
+    U32Le,
+}
+
+impl LengthPrefix {
+    /// The length of the prefix in bytes.
+    fn len(self) -> usize {
+        match self {
+            LengthPrefix::U8 => 1,
+            LengthPrefix::U16Be | LengthPrefix::U16Le => 2,
+            LengthPrefix::U32Be | LengthPrefix::U32Le => 4,
+        }
+    }
+
+    fn read(self, bytes: &[u8]) -> usize {
+        match self {
+            LengthPrefix::U8 => usize::from(bytes[0]),
+            LengthPrefix::U16Be => usize::from(u16::from_be_bytes([bytes[0], bytes[1]])),
+            LengthPrefix::U16Le => usize::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+            LengthPrefix::U32Be => u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize,
+            LengthPrefix::U32Le => u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize,
+        }
+    }
+
+    fn write(self, len: usize, out: &mut Vec<u8>) {
+        match self {
+            LengthPrefix::U8 => out.push(len as u8),
+            LengthPrefix::U16Be => out.extend_from_slice(&(len as u16).to_be_bytes()),
+            LengthPrefix::U16Le => out.extend_from_slice(&(len as u16).to_le_bytes()),
+            LengthPrefix::U32Be => out.extend_from_slice(&(len as u32).to_be_bytes()),
+            LengthPrefix::U32Le => out.extend_from_slice(&(len as u32).to_le_bytes()),
+        }
+    }
+}
+
+/// A decoder for frames preceded by their length. The decoded frames do not
+/// include the prefix.
+///
+/// Since a corrupted length cannot be told apart from a valid one, the stream
+/// cannot be resynchronized after an invalid length, and
+/// [`push_bytes`][FrameDecoder::push_bytes] returns an error.
+///
+/// ```
+/// use r_extcap::framing::{FrameDecoder, LengthPrefix, LengthPrefixed};
+///
+/// let mut decoder = LengthPrefixed::new(LengthPrefix::U16Be);
+/// assert_eq!(decoder.push_bytes(b"\x00\x02hi\x00\x01").unwrap(), [b"hi"]);
+/// assert_eq!(decoder.push_bytes(b"!").unwrap(), [b"!"]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct LengthPrefixed {
+    prefix: LengthPrefix,
+    includes_prefix: bool,
+    max_frame_len: usize,
+    buffer: Vec<u8>,
+}
+
+impl LengthPrefixed {
+    /// Creates a decoder for frames of up to 64 KiB preceded by a `prefix`
+    /// with the length of the rest of the frame.
+    pub fn new(prefix: LengthPrefix) -> Self {
+        Self {
+            prefix,
+            includes_prefix: false,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Whether the length in the prefix includes the prefix itself.
+    #[must_use]
+    pub fn includes_prefix(mut self, includes_prefix: bool) -> Self {
+        self.includes_prefix = includes_prefix;
+        self
+    }
+
+    /// Sets the maximum length of a frame, not including the prefix.
+    #[must_use]
+    pub fn max_frame_len(mut self, max_frame_len: usize) -> Self {
+        self.max_frame_len = max_frame_len;
+        self
+    }
+
+    /// Encodes `frame` with its length prefix.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `frame` does not fit in the prefix.
+    pub fn encode(&self, frame: &[u8]) -> Vec<u8> {
+        let prefix_len = self.prefix.len();
+        let len = frame.len() + if self.includes_prefix { prefix_len } else { 0 };
+        assert!(
+            (len as u64) >> (8 * prefix_len) == 0,
+            "Frame too long for length prefix"
+        );
+        let mut encoded = Vec::with_capacity(prefix_len + frame.len());
+        self.prefix.write(len, &mut encoded);
+        encoded.extend_from_slice(frame);
+        encoded
+    }
+}
+
+impl FrameDecoder for LengthPrefixed {
+    fn push_bytes(&mut self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, FramingError> {
+        self.buffer.extend_from_slice(bytes);
+        let prefix_len = self.prefix.len();
+        let mut frames = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let rest = &self.buffer[consumed..];
+            if rest.len() < prefix_len {
+                break;
+            }
+            let mut len = self.prefix.read(rest);
+            if self.includes_prefix {
+                let Some(frame_len) = len.checked_sub(prefix_len) else {
+                    self.buffer.clear();
+                    return Err(FramingError::InvalidLength(len));
+                };
+                len = frame_len;
+            }
+            if len > self.max_frame_len {
+                self.buffer.clear();
+                return Err(FramingError::FrameTooLong {
+                    len,
+                    max: self.max_frame_len,
+                });
+            }
+            if rest.len() < prefix_len + len {
+                break;
+            }
+            frames.push(rest[prefix_len..prefix_len + len].to_vec());
+            consumed += prefix_len + len;
+        }
+        self.buffer.drain(..consumed);
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use super::{
+        frame_parser, Cobs, FrameDecoder, FramingError, LengthPrefix, LengthPrefixed, Slip,
+    };
+    use crate::sources::FrameParser;
+
+    /// A xorshift generator, so that the fuzz tests are reproducible.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        /// Random bytes, biased towards the special bytes of the encodings.
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len)
+                .map(|_| match self.below(4) {
+                    0 => [0x00, 0xc0, 0xdb, 0xdc, 0xdd, 0xff][self.below(6)],
+                    _ => self.next() as u8,
+                })
+                .collect()
+        }
+    }
+
+    /// Encodes random frames, feeds them to `decoder` in random chunks, and
+    /// checks that the same frames come out.
+    fn fuzz_round_trip(
+        mut decoder: impl FrameDecoder,
+        encode: impl Fn(&[u8]) -> Vec<u8>,
+        min_len: usize,
+        max_len: usize,
+    ) {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let frames: Vec<Vec<u8>> = (0..rng.below(8))
+                .map(|_| {
+                    let len = min_len + rng.below(max_len - min_len + 1);
+                    rng.bytes(len)
+                })
+                .collect();
+            let stream: Vec<u8> = frames.iter().flat_map(|frame| encode(frame)).collect();
+            let mut decoded = Vec::new();
+            let mut rest = &stream[..];
+            while !rest.is_empty() {
+                let (chunk, remaining) = rest.split_at(rng.below(rest.len()) + 1);
+                decoded.extend(decoder.push_bytes(chunk).unwrap());
+                rest = remaining;
+            }
+            assert_eq!(decoded, frames);
+        }
+        assert_eq!(decoder.malformed_frames(), 0);
+    }
+
+    /// Feeds random garbage to `decoder`, which must not panic, and checks
+    /// that it recovers for the next valid frame.
+    fn fuzz_garbage(mut decoder: impl FrameDecoder, encode: impl Fn(&[u8]) -> Vec<u8>) {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..200 {
+            let len = rng.below(300);
+            let _ = decoder.push_bytes(&rng.bytes(len));
+            // Terminate whatever frame the garbage ended in.
+            let _ = decoder.push_bytes(&encode(b""));
+            assert_eq!(decoder.push_bytes(&encode(b"ok")).unwrap(), [b"ok"]);
+        }
+    }
+
+    #[test]
+    fn slip() {
+        let slip = Slip::new();
+        assert_eq!(slip.encode(b"a\xc0b\xdb"), b"a\xdb\xdcb\xdb\xdd\xc0");
+        let mut decoder = Slip::new().max_frame_len(3);
+        assert_eq!(
+            decoder
+                .push_bytes(b"\xc0\xc0abc\xc0abcd\xc0a\xdbx\xc0d\xdb\xc0e\xc0")
+                .unwrap(),
+            [&b"abc"[..], b"e"]
+        );
+        assert_eq!(decoder.malformed_frames(), 3);
+        fuzz_round_trip(Slip::new(), |frame| slip.encode(frame), 1, 600);
+        fuzz_garbage(Slip::new(), |frame| slip.encode(frame));
+    }
+
+    #[test]
+    fn cobs() {
+        let cobs = Cobs::new();
+        assert_eq!(cobs.encode(b""), b"\x01\x00");
+        assert_eq!(cobs.encode(b"\x00"), b"\x01\x01\x00");
+        assert_eq!(
+            cobs.encode(b"\x11\x22\x00\x33"),
+            b"\x03\x11\x22\x02\x33\x00"
+        );
+        let long: Vec<u8> = (1..=254).collect();
+        let encoded = cobs.encode(&long);
+        assert_eq!((encoded[0], encoded.len()), (0xff, 257));
+        let mut decoder = Cobs::new();
+        assert_eq!(decoder.push_bytes(&encoded).unwrap(), [long]);
+        assert!(decoder.push_bytes(b"\x05ab\x00").unwrap().is_empty());
+        assert_eq!(decoder.malformed_frames(), 1);
+        fuzz_round_trip(Cobs::new(), |frame| cobs.encode(frame), 0, 600);
+        fuzz_garbage(Cobs::new(), |frame| cobs.encode(frame));
+    }
+
+    #[test]
+    fn length_prefixed() {
+        let mut decoder = LengthPrefixed::new(LengthPrefix::U32Le).includes_prefix(true);
+        assert_eq!(
+            decoder.push_bytes(b"\x06\x00\x00\x00hi\x04\x00").unwrap(),
+            [b"hi"]
+        );
+        assert_eq!(decoder.push_bytes(b"\x00\x00").unwrap(), [b""]);
+        assert_eq!(
+            decoder.push_bytes(b"\x03\x00\x00\x00"),
+            Err(FramingError::InvalidLength(3))
+        );
+        let mut decoder = LengthPrefixed::new(LengthPrefix::U16Be).max_frame_len(10);
+        assert_eq!(
+            decoder.push_bytes(b"\x00\x0b"),
+            Err(FramingError::FrameTooLong { len: 11, max: 10 })
+        );
+        for prefix in [
+            LengthPrefix::U8,
+            LengthPrefix::U16Be,
+            LengthPrefix::U16Le,
+            LengthPrefix::U32Be,
+        ] {
+            let codec = LengthPrefixed::new(prefix);
+            let max_len = if prefix == LengthPrefix::U8 { 255 } else { 600 };
+            fuzz_round_trip(
+                LengthPrefixed::new(prefix),
+                |frame| codec.encode(frame),
+                0,
+                max_len,
+            );
+        }
+    }
+
+    #[test]
+    fn parser() {
+        let slip = Slip::new();
+        let stream = [slip.encode(b"one"), slip.encode(b"two")].concat();
+        let mut reader = BufReader::with_capacity(2, &stream[..]);
+        let mut parser = frame_parser(Slip::new());
+        assert_eq!(parser.next_frame(&mut reader).unwrap().unwrap(), b"one");
+        assert_eq!(parser.next_frame(&mut reader).unwrap().unwrap(), b"two");
+        assert_eq!(parser.next_frame(&mut reader).unwrap(), None);
+    }
+}
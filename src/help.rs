@@ -0,0 +1,207 @@
+//! Human-readable help for an extcap program, for users who run it directly
+//! from the command line.
+//!
+//! When the program is not invoked by Wireshark, [`ExtcapArgs::run`] returns
+//! [`ExtcapError::NotExtcapInput`]. Instead of showing just that error, the
+//! program can print an [`ExtcapHelp`], which describes the declared
+//! interfaces, configs and toolbar controls, followed by the
+//! [`installation_instructions`].
+//!
+//! ```no_run
+//! use clap::Parser;
+//! use r_extcap::{help::ExtcapHelp, ExtcapArgs, ExtcapError};
+//! # use r_extcap::{config::ConfigTrait, controls::ToolbarControl, interface::{Interface, Metadata}};
+//! # let metadata: Metadata = r_extcap::cargo_metadata!();
+//! # let interfaces: Vec<&Interface> = vec![];
+//! # let configs: Vec<&dyn ConfigTrait> = vec![];
+//!
+//! #[derive(Debug, Parser)]
+//! struct AppArgs {
+//!     #[command(flatten)]
+//!     extcap: ExtcapArgs,
+//! }
+//!
+//! let args = AppArgs::parse();
+//! match args.extcap.run() {
+//!     Err(ExtcapError::NotExtcapInput) => {
+//!         let help = ExtcapHelp::builder()
+//!             .metadata(&metadata)
+//!             .interfaces(&interfaces)
+//!             .configs(&configs)
+//!             .build();
+//!         eprintln!("{help}");
+//!     }
+//!     step => {
+//!         // Handle the extcap step
+//!     }
+//! }
+//! ```
+//!
+//! [`ExtcapArgs::run`]: crate::ExtcapArgs::run
+//! [`ExtcapError::NotExtcapInput`]: crate::ExtcapError::NotExtcapInput
+//! [`installation_instructions`]: crate::installation_instructions
+
+use std::fmt::{self, Display};
+
+use typed_builder::TypedBuilder;
+
+use crate::{
+    config::ConfigTrait,
+    controls::ToolbarControl,
+    installation_instructions,
+    interface::{Interface, Metadata},
+    parse_attributes, AttributeKey, ExtcapFormatter,
+};
+
+/// Describes an extcap program in human-readable form. See the [module
+/// documentation][self] for details.
+///
+/// The configs and toolbar controls are described using the attributes in
+/// their extcap sentences, so custom implementations of
+/// [`ConfigTrait`] and [`ToolbarControl`] are described as well.
+#[derive(TypedBuilder)]
+pub struct ExtcapHelp<'a> {
+    /// The metadata of the extcap program.
+    pub metadata: &'a Metadata,
+    /// The interfaces of the extcap program.
+    #[builder(default)]
+    pub interfaces: &'a [&'a Interface],
+    /// The configs of the interfaces.
+    #[builder(default)]
+    pub configs: &'a [&'a dyn ConfigTrait],
+    /// The toolbar controls of the extcap program.
+    #[builder(default)]
+    pub controls: &'a [&'a dyn ToolbarControl],
+}
+
+impl Display for ExtcapHelp<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} {}",
+            self.metadata.display_description, self.metadata.version
+        )?;
+        if !self.metadata.help_url.is_empty() {
+            writeln!(f, "{}", self.metadata.help_url)?;
+        }
+        if !self.interfaces.is_empty() {
+            writeln!(f, "\nInterfaces:")?;
+            for interface in self.interfaces {
+                writeln!(f, "  {}  {}", interface.value, interface.display)?;
+            }
+        }
+        if !self.configs.is_empty() {
+            writeln!(f, "\nConfigs:")?;
+            for config in self.configs {
+                // The first line is the `arg` sentence, followed by a `value`
+                // sentence for each option of selectors and the like.
+                let sentences = ExtcapFormatter(*config).to_string();
+                let mut lines = sentences.lines().map(parse_attributes);
+                let attributes = lines.next().unwrap_or_default();
+                let default_options: Vec<String> = lines
+                    .filter(|option| attribute(option, AttributeKey::Default) == Some("true"))
+                    .filter_map(|option| attribute(&option, AttributeKey::Value).map(str::to_owned))
+                    .collect();
+                let mut details = Vec::new();
+                details.extend(attribute(&attributes, AttributeKey::Type).map(str::to_owned));
+                if attribute(&attributes, AttributeKey::Required) == Some("true") {
+                    details.push(String::from("required"));
+                }
+                if let Some(default) = attribute(&attributes, AttributeKey::Default) {
+                    details.push(format!("default: {default}"));
+                } else if !default_options.is_empty() {
+                    details.push(format!("default: {}", default_options.join(",")));
+                }
+                write!(f, "  --{}  {}", config.call(), config.display())?;
+                if !details.is_empty() {
+                    write!(f, " ({})", details.join(", "))?;
+                }
+                writeln!(f)?;
+            }
+        }
+        if !self.controls.is_empty() {
+            writeln!(f, "\nToolbar controls:")?;
+            for control in self.controls {
+                let sentences = ExtcapFormatter(*control).to_string();
+                let attributes = parse_attributes(sentences.lines().next().unwrap_or_default());
+                let display = attribute(&attributes, AttributeKey::Display).unwrap_or_default();
+                match attribute(&attributes, AttributeKey::Type) {
+                    Some(control_type) => writeln!(f, "  {display} ({control_type})")?,
+                    None => writeln!(f, "  {display}")?,
+                }
+            }
+        }
+        write!(f, "\n{}", installation_instructions())
+    }
+}
+
+fn attribute(attributes: &[(String, String)], key: AttributeKey) -> Option<&str> {
+    attributes
+        .iter()
+        .find(|(k, _)| k == key.as_str())
+        .map(|(_, v)| v.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use super::ExtcapHelp;
+    use crate::{
+        config::{ConfigTrait, IntegerConfig, StringConfig},
+        controls::{ButtonControl, ToolbarControl},
+        interface::{Dlt, Interface, Metadata},
+    };
+
+    #[test]
+    fn help_text() {
+        let metadata = Metadata {
+            version: "1.0".into(),
+            help_url: "https://example.com".into(),
+            display_description: "Example extcap".into(),
+            extra_attributes: vec![],
+        };
+        let interface = Interface::builder()
+            .value("example1".into())
+            .display("Example interface".into())
+            .dlt(Dlt::user(0, "Example"))
+            .build();
+        let delay = IntegerConfig::builder()
+            .config_number(0)
+            .call("delay")
+            .display("Time delay")
+            .default_value(5)
+            .build();
+        let message = StringConfig::builder()
+            .config_number(1)
+            .call("message")
+            .display("Message")
+            .required(true)
+            .build();
+        let button = ButtonControl::builder()
+            .control_number(0)
+            .display("Turn on")
+            .build();
+        let help = ExtcapHelp::builder()
+            .metadata(&metadata)
+            .interfaces(&[&interface])
+            .configs(&[&delay as &dyn ConfigTrait, &message])
+            .controls(&[&button as &dyn ToolbarControl])
+            .build()
+            .to_string();
+        let (description, _) = help.split_once("\nThis is an extcap plugin").unwrap();
+        assert_eq!(
+            description,
+            "Example extcap 1.0\n\
+             https://example.com\n\
+             \n\
+             Interfaces:\n  \
+             example1  Example interface\n\
+             \n\
+             Configs:\n  \
+             --delay  Time delay (integer, default: 5)\n  \
+             --message  Message (string, required)\n\
+             \n\
+             Toolbar controls:\n  \
+             Turn on (button)\n"
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! Install the extcap program into Wireshark's extcap directory.
+//!
+//! Wireshark only discovers extcap programs placed in one of its extcap
+//! directories. This module locates those directories for the current platform,
+//! and allows extcap programs to offer a self-installation option to their
+//! users, for example `my-extcap --install`.
+//!
+//! The location of the extcap directories changed in Wireshark 4.1: the
+//! personal extcap directory on Unix-like systems moved from
+//! `~/.config/wireshark/extcap` to `~/.local/lib/wireshark/extcap`, and the
+//! global directories on Windows and macOS gained a `wireshark` subdirectory
+//! (`extcap/wireshark`). [`install_current_exe`] installs into the current
+//! location, and also into the legacy location if it already exists, so that
+//! older versions of Wireshark can find the program too.
+//!
+//! ```no_run
+//! use r_extcap::install::{install_current_exe, Scope};
+//!
+//! let path = install_current_exe(Scope::User).unwrap();
+//! println!("Installed to {}", path.display());
+//! ```
+//!
+//! For a ready-made set of command line flags, see [`InstallArgs`].
+
+use clap::{Args, ValueEnum};
+use std::{
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+use thiserror::Error;
+
+/// Which of Wireshark's extcap directories to install into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Scope {
+    /// The personal extcap directory of the current user. Installing into this
+    /// directory does not require administrator privileges.
+    User,
+    /// The global extcap directory of the Wireshark installation, which is
+    /// shared by all users on the system. Installing into this directory
+    /// typically requires administrator privileges.
+    System,
+}
+
+/// Error installing or uninstalling the extcap program.
+#[derive(Debug, Error)]
+pub enum InstallError {
+    /// The path of the current executable cannot be determined.
+    #[error("Cannot determine the path of the current executable")]
+    CurrentExe(#[source] std::io::Error),
+    /// The extcap directory cannot be determined, typically because the
+    /// environment variable it is derived from is not set.
+    #[error("Cannot determine the extcap directory: ${0} is not set")]
+    MissingEnvVar(&'static str),
+    /// One or more of the checks in [`doctor`] failed. The details are printed
+    /// in the doctor's report.
+    #[error("Some checks failed")]
+    DoctorFailed,
+    /// IO error while running the checks in [`doctor`].
+    #[error("IO error running checks")]
+    DoctorIo(#[source] std::io::Error),
+    /// IO error while modifying the extcap directory.
+    #[error("IO error installing to {path}")]
+    Io {
+        /// The path being modified.
+        path: PathBuf,
+        /// The underlying IO error.
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Returns the extcap directory for the given scope, as used by Wireshark 4.1
+/// and later.
+pub fn extcap_dir(scope: Scope) -> Result<PathBuf, InstallError> {
+    match scope {
+        Scope::User => user_extcap_dirs().map(|(current, _)| current),
+        Scope::System => system_extcap_dirs().map(|(current, _)| current),
+    }
+}
+
+/// Returns the extcap directory for the given scope, as used by Wireshark 4.0
+/// and earlier. This may be the same as [`extcap_dir`] on some platforms.
+pub fn legacy_extcap_dir(scope: Scope) -> Result<PathBuf, InstallError> {
+    match scope {
+        Scope::User => user_extcap_dirs().map(|(_, legacy)| legacy),
+        Scope::System => system_extcap_dirs().map(|(_, legacy)| legacy),
+    }
+}
+
+/// Installs the currently running executable into the extcap directory for
+/// `scope`, creating the directory if needed. Returns the installed path.
+///
+/// On Unix-like systems, the executable is symlinked, so that rebuilding the
+/// program updates the installed extcap as well. On Windows, the executable is
+/// copied, since creating symlinks requires additional privileges.
+///
+/// If the [legacy directory][legacy_extcap_dir] exists and is different from
+/// [`extcap_dir`], the executable is installed there as well.
+pub fn install_current_exe(scope: Scope) -> Result<PathBuf, InstallError> {
+    let exe = std::env::current_exe().map_err(InstallError::CurrentExe)?;
+    let dir = extcap_dir(scope)?;
+    let legacy_dir = legacy_extcap_dir(scope)?;
+    if legacy_dir != dir && legacy_dir.is_dir() {
+        install_to(&exe, &legacy_dir)?;
+    }
+    install_to(&exe, &dir)
+}
+
+/// Removes the currently running executable from the extcap directories for
+/// `scope`, including the legacy directory. Returns the list of paths removed,
+/// which is empty if the program was not installed.
+pub fn uninstall_current_exe(scope: Scope) -> Result<Vec<PathBuf>, InstallError> {
+    let exe = std::env::current_exe().map_err(InstallError::CurrentExe)?;
+    let mut dirs = vec![extcap_dir(scope)?];
+    let legacy_dir = legacy_extcap_dir(scope)?;
+    if legacy_dir != dirs[0] {
+        dirs.push(legacy_dir);
+    }
+    let mut removed = Vec::new();
+    for dir in dirs {
+        if let Some(path) = uninstall_from(&exe, &dir)? {
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+fn install_to(exe: &Path, dir: &Path) -> Result<PathBuf, InstallError> {
+    let io_error = |path: &Path| {
+        let path = path.to_owned();
+        move |source| InstallError::Io { path, source }
+    };
+    std::fs::create_dir_all(dir).map_err(io_error(dir))?;
+    let target = dir.join(exe.file_name().expect("Executable should have a file name"));
+    if target.symlink_metadata().is_ok() {
+        std::fs::remove_file(&target).map_err(io_error(&target))?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(exe, &target).map_err(io_error(&target))?;
+    #[cfg(not(unix))]
+    std::fs::copy(exe, &target).map_err(io_error(&target))?;
+    Ok(target)
+}
+
+fn uninstall_from(exe: &Path, dir: &Path) -> Result<Option<PathBuf>, InstallError> {
+    let target = dir.join(exe.file_name().expect("Executable should have a file name"));
+    match std::fs::remove_file(&target) {
+        Ok(()) => Ok(Some(target)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(InstallError::Io {
+            path: target,
+            source,
+        }),
+    }
+}
+
+fn env_path(var: &'static str) -> Result<PathBuf, InstallError> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .ok_or(InstallError::MissingEnvVar(var))
+}
+
+/// Returns the pair of (current, legacy) personal extcap directories.
+fn user_extcap_dirs() -> Result<(PathBuf, PathBuf), InstallError> {
+    if cfg!(windows) {
+        let dir = env_path("APPDATA")?.join("Wireshark").join("extcap");
+        Ok((dir.clone(), dir))
+    } else {
+        let home = env_path("HOME")?;
+        let config_home = env_path("XDG_CONFIG_HOME").unwrap_or_else(|_| home.join(".config"));
+        Ok((
+            home.join(".local/lib/wireshark/extcap"),
+            config_home.join("wireshark/extcap"),
+        ))
+    }
+}
+
+/// Returns the pair of (current, legacy) global extcap directories.
+fn system_extcap_dirs() -> Result<(PathBuf, PathBuf), InstallError> {
+    if cfg!(windows) {
+        let legacy = env_path("ProgramFiles")?.join("Wireshark").join("extcap");
+        Ok((legacy.join("wireshark"), legacy))
+    } else if cfg!(target_os = "macos") {
+        let legacy = PathBuf::from("/Applications/Wireshark.app/Contents/MacOS/extcap");
+        Ok((legacy.join("wireshark"), legacy))
+    } else {
+        // The library directory varies between distributions. Use the first
+        // one that exists.
+        let candidates = [
+            format!(
+                "/usr/lib/{}-linux-gnu/wireshark/extcap",
+                std::env::consts::ARCH
+            ),
+            String::from("/usr/lib64/wireshark/extcap"),
+            String::from("/usr/lib/wireshark/extcap"),
+        ];
+        let dir = candidates
+            .iter()
+            .map(PathBuf::from)
+            .find(|dir| dir.is_dir())
+            .unwrap_or_else(|| PathBuf::from("/usr/lib/wireshark/extcap"));
+        Ok((dir.clone(), dir))
+    }
+}
+
+/// Command line flags for installing, uninstalling, and diagnosing the extcap
+/// program. These can be flattened into the application's `clap` parser next
+/// to [`ExtcapArgs`][crate::ExtcapArgs]:
+///
+/// ```no_run
+/// use clap::Parser;
+///
+/// #[derive(Debug, Parser)]
+/// struct AppArgs {
+///     #[command(flatten)]
+///     extcap: r_extcap::ExtcapArgs,
+///
+///     #[command(flatten)]
+///     install: r_extcap::install::InstallArgs,
+/// }
+///
+/// fn main() -> anyhow::Result<()> {
+///     let args = AppArgs::parse();
+///     if let Some(result) = args.install.run() {
+///         return Ok(result?);
+///     }
+///     // args.extcap.run()...
+///     # Ok(())
+/// }
+/// ```
+#[derive(Debug, Args)]
+pub struct InstallArgs {
+    /// Install this program into Wireshark's extcap directory.
+    #[arg(long, conflicts_with_all = ["uninstall", "doctor"])]
+    pub install: bool,
+
+    /// Remove this program from Wireshark's extcap directory.
+    #[arg(long, conflicts_with = "doctor")]
+    pub uninstall: bool,
+
+    /// Check that this program is installed correctly and responds to the
+    /// extcap queries from Wireshark.
+    #[arg(long)]
+    pub doctor: bool,
+
+    /// The extcap directory to use for `--install` and `--uninstall`.
+    #[arg(long, value_enum, default_value_t = Scope::User)]
+    pub install_scope: Scope,
+}
+
+impl InstallArgs {
+    /// Performs the operation requested by the flags, printing the result to
+    /// stdout. Returns `None` if none of the flags were given, in which case
+    /// the program should continue with [`ExtcapArgs::run`][crate::ExtcapArgs::run].
+    ///
+    /// If `--doctor` finds a problem, [`InstallError::DoctorFailed`] is
+    /// returned.
+    pub fn run(&self) -> Option<Result<(), InstallError>> {
+        if self.install {
+            Some(install_current_exe(self.install_scope).map(|path| {
+                println!("Installed to {}", path.display());
+                println!("Restart Wireshark to use the new extcap.");
+            }))
+        } else if self.uninstall {
+            Some(uninstall_current_exe(self.install_scope).map(|paths| {
+                if paths.is_empty() {
+                    println!("Not installed, nothing to uninstall.");
+                }
+                for path in paths {
+                    println!("Removed {}", path.display());
+                }
+            }))
+        } else if self.doctor {
+            Some(match doctor(&mut std::io::stdout()) {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(InstallError::DoctorFailed),
+                Err(e) => Err(InstallError::DoctorIo(e)),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Runs a series of checks on the current executable, writing a human-readable
+/// report to `out`. Returns whether all checks passed.
+///
+/// The checks are:
+/// 1. The executable is installed in one of the extcap directories.
+/// 2. The versions of Wireshark and tshark found on `PATH`, if any.
+/// 3. A dry run of the `--extcap-interfaces`, `--extcap-dlts`, and
+///    `--extcap-config` steps, by running the executable as Wireshark would.
+pub fn doctor(out: &mut dyn Write) -> std::io::Result<bool> {
+    let exe = std::env::current_exe()?;
+    let mut ok = true;
+
+    let dirs = [Scope::User, Scope::System]
+        .into_iter()
+        .flat_map(|scope| [extcap_dir(scope), legacy_extcap_dir(scope)])
+        .filter_map(Result::ok);
+    let installed: Vec<PathBuf> = dirs
+        .filter_map(|dir| Some(dir.join(exe.file_name()?)))
+        .filter(|path| path.exists())
+        .collect();
+    if installed.is_empty() {
+        ok = false;
+        writeln!(out, "[FAIL] Not installed in any extcap directory.")?;
+        writeln!(out, "       Run with --install to install it.")?;
+    }
+    for path in installed {
+        writeln!(out, "[ OK ] Installed at {}", path.display())?;
+    }
+
+    for program in ["wireshark", "tshark"] {
+        match Command::new(program).arg("--version").output() {
+            Ok(output) if output.status.success() => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                let version = stdout.lines().next().unwrap_or_default();
+                writeln!(out, "[ OK ] Found {program}: {version}")?;
+            }
+            _ => writeln!(out, "[INFO] {program} not found on PATH")?,
+        }
+    }
+
+    let run = |args: &[&str]| Command::new(&exe).args(args).output();
+    let output = run(&["--extcap-interfaces"])?;
+    let interfaces = if output.status.success() {
+        parse_interface_values(&String::from_utf8_lossy(&output.stdout))
+    } else {
+        writeln!(
+            out,
+            "[FAIL] --extcap-interfaces exited with {}",
+            output.status
+        )?;
+        return Ok(false);
+    };
+    if interfaces.is_empty() {
+        ok = false;
+        writeln!(
+            out,
+            "[FAIL] --extcap-interfaces did not list any interfaces"
+        )?;
+    } else {
+        writeln!(
+            out,
+            "[ OK ] --extcap-interfaces listed {} interface(s)",
+            interfaces.len()
+        )?;
+    }
+    for interface in &interfaces {
+        for (step, expected) in [
+            ("--extcap-dlts", &["dlt "][..]),
+            ("--extcap-config", &["arg ", "value "][..]),
+        ] {
+            let output = run(&["--extcap-interface", interface, step])?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let unexpected_line = stdout
+                .lines()
+                .find(|line| !expected.iter().any(|prefix| line.starts_with(prefix)));
+            if !output.status.success() {
+                ok = false;
+                writeln!(
+                    out,
+                    "[FAIL] {interface}: {step} exited with {}",
+                    output.status
+                )?;
+            } else if let Some(line) = unexpected_line {
+                ok = false;
+                writeln!(
+                    out,
+                    "[FAIL] {interface}: {step} printed unexpected line: {line}"
+                )?;
+            } else {
+                writeln!(out, "[ OK ] {interface}: {step}")?;
+            }
+        }
+    }
+    Ok(ok)
+}
+
+/// Extracts the interface values from the output of `--extcap-interfaces`.
+fn parse_interface_values(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.strip_prefix("interface "))
+        .filter_map(|line| {
+            let value = &line[line.find("{value=")? + "{value=".len()..];
+            Some(value[..value.find('}')?].to_owned())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{install_to, parse_interface_values, uninstall_from};
+
+    #[test]
+    fn parse_interfaces() {
+        let output = concat!(
+            "extcap {version=1.0}{help=http://www.wireshark.org}\n",
+            "interface {value=example1}{display=Example interface 1}\n",
+            "interface {value=example2}{display=Example interface 2}\n",
+            "control {number=0}{type=button}{display=Button}\n",
+        );
+        assert_eq!(parse_interface_values(output), ["example1", "example2"]);
+    }
+
+    #[test]
+    fn install_and_uninstall() {
+        let dir = std::env::temp_dir().join(format!("r-extcap-install-{}", std::process::id()));
+        let exe = std::env::current_exe().unwrap();
+        let extcap_dir = dir.join("extcap");
+
+        let installed = install_to(&exe, &extcap_dir).unwrap();
+        assert_eq!(installed.file_name(), exe.file_name());
+        assert!(installed.exists());
+        // Installing again replaces the existing installation
+        assert_eq!(install_to(&exe, &extcap_dir).unwrap(), installed);
+
+        assert_eq!(
+            uninstall_from(&exe, &extcap_dir).unwrap(),
+            Some(installed.clone())
+        );
+        assert!(!installed.exists());
+        assert_eq!(uninstall_from(&exe, &extcap_dir).unwrap(), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
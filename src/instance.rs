@@ -0,0 +1,217 @@
+//! Prevents two captures from using the same device at the same time.
+//!
+//! Wireshark starts a new extcap process for every capture, and nothing stops
+//! the user from starting two captures on the same interface, for example from
+//! two Wireshark windows. Many devices cannot be shared, and the second
+//! capture then fails with a confusing error, or worse, disturbs the first
+//! one. Taking an [`InstanceLock`] at the start of the capture turns this into
+//! a clear [`DeviceBusy`][CaptureError::DeviceBusy] error, which Wireshark
+//! shows in an error dialog.
+//!
+//! ```no_run
+//! # fn capture(capture_step: r_extcap::CaptureStep) -> Result<(), r_extcap::CaptureError> {
+//! // Held until the end of the capture.
+//! let _lock = capture_step.lock_instance()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use crate::CaptureError;
+
+/// An exclusive lock on a key, like an interface value or a device path,
+/// which is held until the lock is dropped or the process exits.
+///
+/// The lock is an advisory lock on a file in the runtime directory of the
+/// user (`$XDG_RUNTIME_DIR`), or in the temporary directory if there is none,
+/// so it only excludes other processes that take an `InstanceLock` on the same
+/// key. The operating system releases it when the process exits, even if the
+/// process crashes or is killed, so a stale lock never blocks a later capture.
+/// The lock file itself is left in place.
+#[derive(Debug)]
+pub struct InstanceLock {
+    // Closing the file releases the lock.
+    _file: File,
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Takes the lock for `key`, or returns [`CaptureError::DeviceBusy`] if
+    /// another process holds it.
+    ///
+    /// Characters in `key` that are not allowed in file names are replaced,
+    /// so keys that only differ in those characters share the same lock.
+    pub fn acquire(key: &str) -> Result<Self, CaptureError> {
+        let file_name: String = key
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = crate::runtime_dir().join(format!("r-extcap-{file_name}.lock"));
+        Self::acquire_at(path, key)
+    }
+
+    /// Takes the lock using the lock file at `path`, reporting contention as
+    /// [`CaptureError::DeviceBusy`] for `device`. Use this to share the lock
+    /// with other programs, or to keep the lock file in a directory other
+    /// than the default one.
+    ///
+    /// The lock file is not followed if it is a symbolic link, and is never
+    /// truncated, so that a link planted in a shared directory cannot be used
+    /// to overwrite another file.
+    pub fn acquire_at(path: impl Into<PathBuf>, device: &str) -> Result<Self, CaptureError> {
+        let path = path.into();
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NOFOLLOW);
+        }
+        let mut file = options.open(&path)?;
+        if !try_lock(&file)? {
+            let mut owner = String::new();
+            let _ = file.read_to_string(&mut owner);
+            debug!("{} is locked by process {}", path.display(), owner.trim());
+            return Err(CaptureError::DeviceBusy {
+                device: device.to_owned(),
+            });
+        }
+        // Record the owner, to help find the other capture when debugging.
+        // The process ID is padded to the longest possible ID, so that it
+        // overwrites the ID of the previous owner without truncating.
+        file.rewind()?;
+        writeln!(file, "{:<10}", std::process::id())?;
+        Ok(Self { _file: file, path })
+    }
+
+    /// The path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Takes an exclusive lock on `file` without blocking, and returns whether
+/// the lock was taken. The lock is released when the file is closed.
+#[cfg(unix)]
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    // Safety: the file descriptor is open for as long as `file` is borrowed.
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    if error.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(error)
+    }
+}
+
+/// Takes an exclusive lock on `file` without blocking, and returns whether
+/// the lock was taken. The lock is released when the file is closed.
+#[cfg(windows)]
+fn try_lock(file: &File) -> io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::{
+        Foundation::ERROR_LOCK_VIOLATION,
+        Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY},
+        System::IO::OVERLAPPED,
+    };
+
+    // Safety: an all-zero `OVERLAPPED` locks from offset 0.
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    // Safety: the handle is open for as long as `file` is borrowed, and
+    // `overlapped` outlives the call, which does not complete asynchronously
+    // since the file is not opened for overlapped I/O.
+    let locked = unsafe {
+        LockFileEx(
+            file.as_raw_handle(),
+            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+            0,
+            u32::MAX,
+            u32::MAX,
+            &mut overlapped,
+        )
+    };
+    if locked != 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    if error.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+        Ok(false)
+    } else {
+        Err(error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::InstanceLock;
+    use crate::CaptureError;
+
+    #[test]
+    fn exclusive() {
+        let key = format!("instance-test-{}", std::process::id());
+        let lock = InstanceLock::acquire(&key).unwrap();
+        assert!(matches!(
+            InstanceLock::acquire(&key),
+            Err(CaptureError::DeviceBusy { device }) if device == key
+        ));
+        let path = lock.path().to_owned();
+        drop(lock);
+        let lock = InstanceLock::acquire(&key).unwrap();
+        assert_eq!(lock.path(), path);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap().trim(),
+            std::process::id().to_string()
+        );
+        drop(lock);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_not_followed() {
+        let dir = std::env::temp_dir();
+        let target = dir.join(format!("instance-target-{}", std::process::id()));
+        let link = dir.join(format!("instance-link-{}.lock", std::process::id()));
+        std::fs::write(&target, "keep").unwrap();
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(matches!(
+            InstanceLock::acquire_at(&link, "device"),
+            Err(CaptureError::Io(_))
+        ));
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "keep");
+        let _ = std::fs::remove_file(link);
+        let _ = std::fs::remove_file(target);
+    }
+
+    #[test]
+    fn sanitized_key() {
+        let key = format!("/dev/ttyUSB{}", std::process::id());
+        let lock = InstanceLock::acquire(&key).unwrap();
+        let file_name = lock.path().file_name().unwrap().to_string_lossy();
+        assert_eq!(
+            file_name,
+            format!("r-extcap-_dev_ttyUSB{}.lock", std::process::id())
+        );
+        let path = lock.path().to_owned();
+        drop(lock);
+        let _ = std::fs::remove_file(path);
+    }
+}
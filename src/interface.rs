@@ -1,8 +1,9 @@
 //! Module containg code to define the extcap interfaces. These are data used to
 //! popuplate the `Capture` or interface list in the main page of Wireshark.
 
-use crate::PrintSentence;
+use crate::{AttributeKey, EscapedValue, PrintSentence};
 use std::borrow::Cow;
+use thiserror::Error;
 use typed_builder::TypedBuilder;
 
 /// Enum defining the data link types.
@@ -15,6 +16,7 @@ pub use pcap_file::DataLink;
 /// A default implementation of `Metadata` is provided as `Metadata::default()`,
 /// which extracts these information from the `version`, `homepage`, and
 /// `description` attributes in the cargo manifest.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Metadata {
     /// The version of this extcap program, displayed in the about dialog of
     /// Wireshark.
@@ -25,6 +27,61 @@ pub struct Metadata {
     pub help_url: Cow<'static, str>,
     /// A user-friendly description of the extcap program.
     pub display_description: Cow<'static, str>,
+    /// Additional `{key=value}` attributes appended to the metadata sentence,
+    /// for keys added in newer versions of Wireshark that are not supported
+    /// by the fields above. The values are escaped using [`EscapedValue`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extra_attributes: Vec<(String, String)>,
+}
+
+impl Metadata {
+    /// Adds an attribute to [`extra_attributes`][Self::extra_attributes].
+    ///
+    /// ```
+    /// # use r_extcap::ExtcapFormatter;
+    /// use r_extcap::cargo_metadata;
+    ///
+    /// let metadata = cargo_metadata!().with_attribute("future_key", "a {value}");
+    /// assert!(format!("{}", ExtcapFormatter(&metadata))
+    ///     .ends_with("{future_key=a \\{value\\}}\n"));
+    /// ```
+    #[must_use]
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_attributes.push((key.into(), value.into()));
+        self
+    }
+
+    /// Creates the metadata from the `version` and `about` of the clap
+    /// command, so that the version shown in Wireshark cannot drift from the
+    /// one printed by `--version`. Clap does not have a field for the help URL,
+    /// so it is given separately.
+    ///
+    /// Fields that are not set in the command are left empty.
+    ///
+    /// ```
+    /// use clap::{CommandFactory, Parser};
+    /// use r_extcap::interface::Metadata;
+    ///
+    /// #[derive(Parser)]
+    /// #[command(version = "1.2.3", about = "My extcap")]
+    /// struct AppArgs {}
+    ///
+    /// let metadata = Metadata::from_command(&AppArgs::command(), "https://example.com");
+    /// assert_eq!(metadata.version, "1.2.3");
+    /// assert_eq!(metadata.display_description, "My extcap");
+    /// ```
+    pub fn from_command(command: &clap::Command, help_url: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            version: command.get_version().unwrap_or_default().to_owned().into(),
+            help_url: help_url.into(),
+            display_description: command
+                .get_about()
+                .map(|about| about.to_string())
+                .unwrap_or_default()
+                .into(),
+            extra_attributes: vec![],
+        }
+    }
 }
 
 /// ## Example
@@ -37,6 +94,7 @@ pub struct Metadata {
 ///     version: "3.2.1-test".into(),
 ///     help_url: "http://www.wireshark.org".into(),
 ///     display_description: "Just for testing".into(),
+///     extra_attributes: vec![],
 /// };
 /// assert_eq!(
 ///     format!("{}", ExtcapFormatter(&metadata)),
@@ -45,11 +103,14 @@ pub struct Metadata {
 /// ```
 impl PrintSentence for Metadata {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
-            f,
-            "extcap {{version={}}}{{help={}}}{{display={}}}",
-            self.version, self.help_url, self.display_description
-        )
+        f.write_str("extcap ")?;
+        AttributeKey::Version.write(f, &self.version)?;
+        AttributeKey::Help.write(f, &self.help_url)?;
+        AttributeKey::Display.write(f, &self.display_description)?;
+        for (key, value) in &self.extra_attributes {
+            write!(f, "{{{key}={}}}", EscapedValue(value))?;
+        }
+        writeln!(f)
     }
 }
 
@@ -57,7 +118,8 @@ impl PrintSentence for Metadata {
 /// in the Wireshark homepage, similar to `Wi-Fi: en0`. Instances of this should
 /// be passed to
 /// [`InterfacesStep::list_interfaces`][crate::InterfacesStep::list_interfaces].
-#[derive(Debug, TypedBuilder)]
+#[derive(Clone, Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Interface {
     /// A unique identifier for this interface. This value will be passed back
     /// from Wireshark in the `--extcap-interface` argument in subsequent calls
@@ -70,10 +132,195 @@ pub struct Interface {
     /// determine how to dissect the packet data given by this extcap program.
     ///
     /// Note: While the extcap-example and documentation chapter 8.2 says this
-    /// is a list of DLTs, in reality only one DLT per interface is supported,
-    /// per [this
+    /// is a list of DLTs, older versions of Wireshark only support one DLT per
+    /// interface, per [this
     /// thread](https://www.wireshark.org/lists/wireshark-dev/201511/msg00143.html).
+    /// Additional DLTs can be given in [`extra_dlts`][Self::extra_dlts].
     pub dlt: Dlt,
+    /// Additional DLTs the user can choose from, in the order they should be
+    /// displayed. [`dlt`][Self::dlt] is always listed first and is the
+    /// preferred DLT. See
+    /// [`DltsStep::print_from_interfaces`][crate::DltsStep::print_from_interfaces].
+    #[builder(default)]
+    pub extra_dlts: Vec<Dlt>,
+    /// Additional `{key=value}` attributes appended to the interface
+    /// sentence, for keys added in newer versions of Wireshark that are not
+    /// supported by the fields above. The values are escaped using
+    /// [`EscapedValue`].
+    ///
+    /// Versions of Wireshark that do not know a key ignore the attribute, so
+    /// they can be declared without checking the Wireshark version.
+    #[builder(default)]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub extra_attributes: Vec<(String, String)>,
+}
+
+impl Interface {
+    /// Adds an attribute to [`extra_attributes`][Self::extra_attributes],
+    /// replacing the value of an attribute with the same key.
+    ///
+    /// ```
+    /// use r_extcap::{ExtcapFormatter, interface::{DataLink, Dlt, Interface}};
+    ///
+    /// let interface = Interface::builder()
+    ///     .value("MyInterface".into())
+    ///     .display("My interface".into())
+    ///     .dlt(Dlt::builder()
+    ///         .data_link_type(DataLink::ETHERNET)
+    ///         .name("ETHERNET".into())
+    ///         .display("Ethernet".into())
+    ///         .build())
+    ///     .build()
+    ///     .with_attribute("future_key", "a {value}");
+    /// assert_eq!(
+    ///     ExtcapFormatter(&interface).to_string(),
+    ///     "interface {value=MyInterface}{display=My interface}{future_key=a \\{value\\}}\n",
+    /// );
+    /// ```
+    #[must_use]
+    pub fn with_attribute(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        let value = value.into();
+        match self.extra_attributes.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.extra_attributes.push((key, value)),
+        }
+        self
+    }
+
+    /// Declares whether the interface can capture in monitor mode, as the
+    /// `{monitor_mode=...}` attribute.
+    #[must_use]
+    pub fn with_monitor_mode(self, supported: bool) -> Self {
+        self.with_attribute("monitor_mode", supported.to_string())
+    }
+
+    /// Declares the maximum number of bytes captured per packet, as the
+    /// `{snaplen=...}` attribute.
+    ///
+    /// ```
+    /// # use r_extcap::{ExtcapFormatter, interface::{DataLink, Dlt, Interface}};
+    /// # let interface = Interface::builder()
+    /// #     .value("MyInterface".into())
+    /// #     .display("My interface".into())
+    /// #     .dlt(Dlt::builder()
+    /// #         .data_link_type(DataLink::ETHERNET)
+    /// #         .name("ETHERNET".into())
+    /// #         .display("Ethernet".into())
+    /// #         .build())
+    /// #     .build();
+    /// let interface = interface.with_snaplen(65535).with_monitor_mode(true);
+    /// assert!(ExtcapFormatter(&interface)
+    ///     .to_string()
+    ///     .ends_with("{snaplen=65535}{monitor_mode=true}\n"));
+    /// ```
+    #[must_use]
+    pub fn with_snaplen(self, snaplen: u32) -> Self {
+        self.with_attribute("snaplen", snaplen.to_string())
+    }
+
+    /// Returns all DLTs of this interface in display order, starting with the
+    /// preferred [`dlt`][Self::dlt].
+    pub fn dlts(&self) -> impl Iterator<Item = &Dlt> {
+        std::iter::once(&self.dlt).chain(&self.extra_dlts)
+    }
+
+    /// Checks that [`value`][Self::value] is unlikely to collide with the
+    /// interfaces of other extcap programs. Wireshark keeps the interfaces of
+    /// all extcaps in one list, and starts the capture with the first program
+    /// that listed the value, so generic values like `usb` or `capture1` can
+    /// invoke the wrong program.
+    ///
+    /// See [`namespaced_value`] for a way to avoid this.
+    ///
+    /// ```
+    /// use r_extcap::interface::{DataLink, Dlt, Interface};
+    ///
+    /// let interface = Interface::builder()
+    ///     .value("usb0".into())
+    ///     .display("USB".into())
+    ///     .dlt(Dlt::new(DataLink::ETHERNET, "Ethernet"))
+    ///     .build();
+    /// assert!(interface.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), InvalidInterfaceError> {
+        let base = self
+            .value
+            .to_ascii_lowercase()
+            .trim_end_matches(|c: char| c.is_ascii_digit() || c == '_' || c == '-')
+            .to_owned();
+        if GENERIC_VALUES.contains(&base.as_str()) {
+            return Err(InvalidInterfaceError::GenericValue(self.value.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Interface values, without a trailing number, that are likely to be used by
+/// more than one extcap program.
+const GENERIC_VALUES: &[&str] = &[
+    "",
+    "any",
+    "ble",
+    "bluetooth",
+    "can",
+    "capture",
+    "default",
+    "device",
+    "eth",
+    "ethernet",
+    "extcap",
+    "if",
+    "interface",
+    "lo",
+    "serial",
+    "test",
+    "uart",
+    "usb",
+    "wifi",
+    "wlan",
+];
+
+/// Returns `value` prefixed with the name of the executable, like
+/// `myextcap_if1`, to use as an [`Interface::value`] that does not collide with
+/// the interfaces of other extcap programs. See [`Interface::validate`].
+///
+/// The prefix is not added if `value` already starts with it, or if the name
+/// of the executable cannot be determined. The builder does not add the prefix
+/// by default, since changing the value of an existing interface loses the
+/// settings that Wireshark saved for it.
+///
+/// ```
+/// use r_extcap::interface::{namespaced_value, DataLink, Dlt, Interface};
+///
+/// let interface = Interface::builder()
+///     .value(namespaced_value("if1"))
+///     .display("My interface".into())
+///     .dlt(Dlt::new(DataLink::ETHERNET, "Ethernet"))
+///     .build();
+/// assert!(interface.value.ends_with("_if1"));
+/// ```
+pub fn namespaced_value(value: &str) -> Cow<'static, str> {
+    namespaced(crate::executable_name().as_deref(), value).into()
+}
+
+fn namespaced(prefix: Option<&str>, value: &str) -> String {
+    match prefix {
+        Some(prefix) if !value.starts_with(&format!("{prefix}_")) => format!("{prefix}_{value}"),
+        _ => value.to_owned(),
+    }
+}
+
+/// Error returned by [`Interface::validate`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvalidInterfaceError {
+    /// The value of the interface is generic enough to be used by other
+    /// extcap programs.
+    #[error(
+        "Interface value {0:?} may collide with the interfaces of other extcap programs. \
+         Consider prefixing it with the name of the program."
+    )]
+    GenericValue(String),
 }
 
 /// ```
@@ -85,17 +332,19 @@ pub struct Interface {
 /// #     display: "IEEE 802.3 Ethernet".into(),
 /// # };
 /// assert_eq!(
-///     ExtcapFormatter(&Interface{ value: "MyInterface".into(), display: "My interface".into(), dlt }).to_string(),
+///     ExtcapFormatter(&Interface{ value: "MyInterface".into(), display: "My interface".into(), dlt, extra_dlts: vec![], extra_attributes: vec![] }).to_string(),
 ///     "interface {value=MyInterface}{display=My interface}\n",
 /// );
 /// ```
 impl PrintSentence for Interface {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
-            f,
-            "interface {{value={}}}{{display={}}}",
-            self.value, self.display,
-        )
+        f.write_str("interface ")?;
+        AttributeKey::Value.write(f, &self.value)?;
+        AttributeKey::Display.write(f, &self.display)?;
+        for (key, value) in &self.extra_attributes {
+            write!(f, "{{{key}={}}}", EscapedValue(value))?;
+        }
+        writeln!(f)
     }
 }
 
@@ -107,10 +356,12 @@ impl PrintSentence for Interface {
 /// config using the [`print_sentence`][crate::PrintSentence::print_sentence]
 /// method.
 #[derive(Clone, Debug, TypedBuilder)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dlt {
     /// The data link type this packet should be analyzed as.
     ///
     /// See: <http://www.tcpdump.org/linktypes.html> for the list of DLTs.
+    #[cfg_attr(feature = "serde", serde(with = "data_link_number"))]
     pub data_link_type: DataLink,
 
     /// The name of this DLT. Typically this is the same as the name in
@@ -121,6 +372,106 @@ pub struct Dlt {
     pub display: Cow<'static, str>,
 }
 
+impl Dlt {
+    /// Creates a DLT for `data_link_type`, with the name Wireshark expects for
+    /// it, like `ETHERNET` for [`DataLink::ETHERNET`].
+    ///
+    /// ```
+    /// use r_extcap::interface::{DataLink, Dlt};
+    ///
+    /// let dlt = Dlt::new(DataLink::ETHERNET, "IEEE 802.3 Ethernet");
+    /// assert_eq!(dlt.name, "ETHERNET");
+    /// ```
+    pub fn new(data_link_type: DataLink, display: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            data_link_type,
+            name: expected_name(data_link_type)
+                .unwrap_or_else(|| u32::from(data_link_type).to_string())
+                .into(),
+            display: display.into(),
+        }
+    }
+
+    /// Creates a DLT for one of the link types reserved for private use,
+    /// `USER0` to `USER15`. These are typically used for custom protocols,
+    /// which are then dissected using the "DLT User" preferences in Wireshark.
+    ///
+    /// Panics if `number` is greater than 15.
+    ///
+    /// ```
+    /// use r_extcap::interface::{DataLink, Dlt};
+    ///
+    /// let dlt = Dlt::user(0, "Demo Implementation for Extcap");
+    /// assert_eq!(dlt.data_link_type, DataLink::USER0);
+    /// assert_eq!(dlt.name, "USER0");
+    /// ```
+    pub fn user(number: u8, display: impl Into<Cow<'static, str>>) -> Self {
+        assert!(
+            number <= 15,
+            "USER DLT number {number} is out of range 0-15"
+        );
+        Self::new(DataLink::from(USER0 + u32::from(number)), display)
+    }
+
+    /// Checks that [`name`][Self::name] matches the name of
+    /// [`data_link_type`][Self::data_link_type]. Wireshark does not report a
+    /// mismatch, but dissects the packets as the data link type, which can be
+    /// confusing when the name suggests otherwise.
+    ///
+    /// The names of data link types not known to this library are not checked.
+    ///
+    /// ```
+    /// use r_extcap::interface::{DataLink, Dlt};
+    ///
+    /// let dlt = Dlt {
+    ///     data_link_type: DataLink::USER0,
+    ///     name: "ETHERNET".into(),
+    ///     display: "Ethernet".into(),
+    /// };
+    /// assert!(dlt.validate().is_err());
+    /// assert!(Dlt::new(DataLink::USER0, "Ethernet").validate().is_ok());
+    /// ```
+    pub fn validate(&self) -> Result<(), InvalidDltError> {
+        match expected_name(self.data_link_type) {
+            Some(expected) if expected != self.name => Err(InvalidDltError::NameMismatch {
+                number: self.data_link_type.into(),
+                expected,
+                actual: self.name.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The number of the `USER0` data link type. `USER1` to `USER15` follow it.
+const USER0: u32 = 147;
+
+/// Returns the name of the data link type without the `LINKTYPE_` prefix, as
+/// in <http://www.tcpdump.org/linktypes.html>, or `None` if the data link type
+/// is unknown.
+fn expected_name(data_link_type: DataLink) -> Option<String> {
+    match data_link_type {
+        DataLink::Unknown(_) => None,
+        // The variants of `DataLink` are named after the link types.
+        known => Some(format!("{known:?}")),
+    }
+}
+
+/// Error returned by [`Dlt::validate`].
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvalidDltError {
+    /// The name of the DLT does not match its data link type.
+    #[error("DLT name {actual:?} does not match data link type {number}, expected {expected:?}")]
+    NameMismatch {
+        /// The number of the data link type.
+        number: u32,
+        /// The name of the data link type.
+        expected: String,
+        /// The name given in the DLT.
+        actual: String,
+    },
+}
+
 /// Print the configuration line suitable for use with `--extcap-dlts`.
 ///
 /// ## Example
@@ -140,12 +491,137 @@ pub struct Dlt {
 /// ```
 impl PrintSentence for Dlt {
     fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(
-            f,
-            "dlt {{number={}}}{{name={}}}{{display={}}}",
-            <u32>::from(self.data_link_type),
-            self.name,
-            self.display
+        f.write_str("dlt ")?;
+        AttributeKey::Number.write(f, <u32>::from(self.data_link_type))?;
+        AttributeKey::Name.write(f, &self.name)?;
+        AttributeKey::Display.write(f, &self.display)?;
+        writeln!(f)
+    }
+}
+
+/// Returns the output of the `--extcap-interfaces` step for the given
+/// `wireshark_version`, like
+/// [`InterfacesStep::list_interfaces_for_version`][crate::InterfacesStep::list_interfaces_for_version]
+/// prints it. The output only depends on the arguments, so it can be compared
+/// with a golden file in tests, to catch accidental changes to the interfaces
+/// or to the toolbar.
+///
+/// The controls that require a later version of Wireshark than
+/// `wireshark_version` are left out, so a test can check the output for each
+/// supported version.
+///
+/// ```
+/// use r_extcap::controls::ButtonControl;
+/// use r_extcap::interface::{self, Dlt, Interface, Metadata};
+///
+/// let metadata = Metadata {
+///     version: "1.0".into(),
+///     help_url: "https://example.com".into(),
+///     display_description: "Example".into(),
+///     extra_attributes: vec![],
+/// };
+/// let interface = Interface::builder()
+///     .value("if1".into())
+///     .display("Interface 1".into())
+///     .dlt(Dlt::user(0, "Example"))
+///     .build();
+/// let reset = ButtonControl::builder()
+///     .control_number(0)
+///     .display("Reset")
+///     .build();
+/// assert_eq!(
+///     interface::to_sentences(&metadata, &[&interface], &[&reset], None),
+///     "extcap {version=1.0}{help=https://example.com}{display=Example}\n\
+///      interface {value=if1}{display=Interface 1}\n\
+///      control {number=0}{type=button}{display=Reset}\n",
+/// );
+/// ```
+pub fn to_sentences(
+    metadata: &Metadata,
+    interfaces: &[&Interface],
+    controls: &[&dyn crate::controls::ToolbarControl],
+    wireshark_version: Option<crate::WiresharkVersion>,
+) -> String {
+    let mut output = Vec::new();
+    crate::InterfacesStep
+        .write_interfaces_for_version(
+            &mut output,
+            wireshark_version,
+            metadata,
+            interfaces,
+            controls,
         )
+        .expect("Writing to a Vec cannot fail");
+    String::from_utf8(output).expect("Extcap sentences are valid UTF-8")
+}
+
+/// Serializes the data link type as its number, as in the `dlt` sentence.
+#[cfg(feature = "serde")]
+mod data_link_number {
+    use super::DataLink;
+
+    pub(super) fn serialize<S: serde::Serializer>(
+        data_link: &DataLink,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32((*data_link).into())
+    }
+
+    pub(super) fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DataLink, D::Error> {
+        <u32 as serde::Deserialize>::deserialize(deserializer).map(DataLink::from)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{namespaced, to_sentences, Dlt, Interface, Metadata};
+    use crate::{controls::ButtonControl, WiresharkVersion};
+
+    #[test]
+    fn namespaced_value() {
+        assert_eq!(namespaced(Some("myextcap"), "if1"), "myextcap_if1");
+        assert_eq!(namespaced(Some("myextcap"), "myextcap_if1"), "myextcap_if1");
+        assert_eq!(namespaced(None, "if1"), "if1");
+    }
+
+    #[test]
+    fn sentences_for_version() {
+        let metadata = Metadata {
+            version: "1.0".into(),
+            help_url: "https://example.com".into(),
+            display_description: "Example".into(),
+            extra_attributes: vec![],
+        };
+        let interface = Interface::builder()
+            .value("if1".into())
+            .display("Interface 1".into())
+            .dlt(Dlt::user(0, "Example"))
+            .build();
+        let reset = ButtonControl::builder()
+            .control_number(0)
+            .display("Reset")
+            .build();
+        let header = "extcap {version=1.0}{help=https://example.com}{display=Example}\n\
+                      interface {value=if1}{display=Interface 1}\n";
+        assert_eq!(
+            to_sentences(
+                &metadata,
+                &[&interface],
+                &[&reset],
+                Some(WiresharkVersion::INTERFACE_TOOLBAR)
+            ),
+            format!("{header}control {{number=0}}{{type=button}}{{display=Reset}}\n")
+        );
+        assert_eq!(
+            to_sentences(
+                &metadata,
+                &[&interface],
+                &[&reset],
+                Some(WiresharkVersion::new(2, 4))
+            ),
+            header
+        );
     }
 }
@@ -0,0 +1,360 @@
+//! Records how the extcap was invoked, to debug problems that only happen
+//! when the extcap is run by Wireshark.
+//!
+//! Wireshark runs extcaps with its own environment and working directory,
+//! which can differ a lot from a terminal: on macOS, for example, Wireshark
+//! started from the Dock has a minimal `PATH`, and tools that the extcap runs
+//! may not be found. An [`Invocation`], created with
+//! [`ExtcapArgs::capture_invocation`][crate::ExtcapArgs::capture_invocation],
+//! collects the arguments, the relevant environment variables and the working
+//! directory, so that the user can include them in a bug report, or rerun the
+//! same command from a terminal.
+//!
+//! Wireshark passes the values of [`PasswordConfig`]s on the command line, so
+//! the values of options that look like secrets, like `--password`, are
+//! recorded as [`REDACTED`]. See [`SECRET_NAME_WORDS`]. Use
+//! [`redact_configs`][Invocation::redact_configs] to also redact the password
+//! configs with other names.
+//!
+//! ```
+//! use clap::Parser;
+//! use r_extcap::ExtcapArgs;
+//!
+//! #[derive(Debug, Parser)]
+//! struct AppArgs {
+//!     #[command(flatten)]
+//!     extcap: ExtcapArgs,
+//! }
+//!
+//! let args = AppArgs::parse_from(["app", "--extcap-interfaces"]);
+//! // Logs the invocation at debug level.
+//! args.extcap.capture_invocation().log();
+//! ```
+//!
+//! [`PasswordConfig`]: crate::config::PasswordConfig
+
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::debug;
+
+use crate::config::{ConfigTrait, PasswordConfig};
+
+/// The environment variables recorded in an [`Invocation`]. Other variables
+/// are not recorded, since they may contain secrets.
+pub const RECORDED_ENV_VARS: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "USERNAME",
+    "SHELL",
+    "LANG",
+    "TMPDIR",
+    "TEMP",
+    "TMP",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "SSH_AUTH_SOCK",
+];
+
+/// The prefixes of the environment variables recorded in an [`Invocation`],
+/// in addition to [`RECORDED_ENV_VARS`]. This includes the variables that
+/// configure Wireshark, like `WIRESHARK_CONFIG_DIR`, and the ones that
+/// configure this crate, like
+/// [`EXTCAP_WIRESHARK_VERSION`][crate::WIRESHARK_VERSION_ENV].
+pub const RECORDED_ENV_PREFIXES: &[&str] = &["WIRESHARK_", "EXTCAP_", "RUST_", "LC_", "XDG_"];
+
+/// The words that mark a command line option or an environment variable as a
+/// secret. The values of options and variables whose names contain one of
+/// these words, ignoring case, are recorded as [`REDACTED`] in an
+/// [`Invocation`].
+pub const SECRET_NAME_WORDS: &[&str] = &["password", "passwd", "passphrase", "secret", "token"];
+
+/// The text recorded in an [`Invocation`] in place of a secret value.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// How the extcap was invoked. See the [module documentation][self].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Invocation {
+    /// The command line arguments, including the program.
+    pub args: Vec<String>,
+    /// The environment variables matching [`RECORDED_ENV_VARS`] or
+    /// [`RECORDED_ENV_PREFIXES`], sorted by name.
+    pub env: Vec<(String, String)>,
+    /// The working directory, or `None` if it cannot be read.
+    pub cwd: Option<PathBuf>,
+    /// The ID of the extcap process.
+    pub pid: u32,
+    /// The step requested by Wireshark, like `--capture`, or `None` if the
+    /// extcap was run without any step.
+    pub step: Option<&'static str>,
+    /// The time the invocation was recorded.
+    pub timestamp: SystemTime,
+}
+
+impl Invocation {
+    /// Records the invocation of the current process.
+    pub(crate) fn current(step: Option<&'static str>) -> Self {
+        let mut env: Vec<(String, String)> = std::env::vars_os()
+            .map(|(name, value)| {
+                (
+                    name.to_string_lossy().into_owned(),
+                    value.to_string_lossy().into_owned(),
+                )
+            })
+            .filter(|(name, _)| is_recorded(name))
+            .map(|(name, value)| {
+                if is_secret_name(&name) {
+                    (name, REDACTED.to_owned())
+                } else {
+                    (name, value)
+                }
+            })
+            .collect();
+        env.sort();
+        let mut invocation = Self {
+            args: std::env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            env,
+            cwd: std::env::current_dir().ok(),
+            pid: std::process::id(),
+            step,
+            timestamp: SystemTime::now(),
+        };
+        invocation.redact_options(is_secret_name);
+        invocation
+    }
+
+    /// Records the values of the [`PasswordConfig`]s in `configs` as
+    /// [`REDACTED`], for password configs whose names do not contain any of
+    /// the [`SECRET_NAME_WORDS`].
+    ///
+    /// ```
+    /// # use r_extcap::{config::{ConfigTrait, PasswordConfig}, ExtcapArgs};
+    /// # fn example(args: &ExtcapArgs) {
+    /// let pin = PasswordConfig::builder()
+    ///     .config_number(0)
+    ///     .call("pin")
+    ///     .display("PIN")
+    ///     .build();
+    /// args.capture_invocation().redact_configs(&[&pin]).log();
+    /// # }
+    /// ```
+    pub fn redact_configs(mut self, configs: &[&dyn ConfigTrait]) -> Self {
+        let calls: Vec<&str> = configs
+            .iter()
+            .filter(|config| config.as_any().is::<PasswordConfig>())
+            .map(|config| config.call())
+            .collect();
+        self.redact_options(|name| calls.contains(&name));
+        self
+    }
+
+    /// Replaces the values of the options for which `is_secret` returns true,
+    /// given either as `--name=value` or as `--name value`.
+    fn redact_options(&mut self, is_secret: impl Fn(&str) -> bool) {
+        let mut redact_next = false;
+        for arg in &mut self.args {
+            if std::mem::take(&mut redact_next) && !arg.starts_with("--") {
+                *arg = REDACTED.to_owned();
+                continue;
+            }
+            let Some(option) = arg.strip_prefix("--") else {
+                continue;
+            };
+            match option.split_once('=') {
+                Some((name, _)) if is_secret(name) => *arg = format!("--{name}={REDACTED}"),
+                Some(_) => {}
+                None => redact_next = is_secret(option),
+            }
+        }
+    }
+
+    /// Writes this invocation to the debug log, one line per item.
+    pub fn log(&self) {
+        for line in self.to_string().lines() {
+            debug!("{line}");
+        }
+    }
+}
+
+fn is_recorded(name: &str) -> bool {
+    RECORDED_ENV_VARS.contains(&name)
+        || RECORDED_ENV_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_NAME_WORDS.iter().any(|word| name.contains(word))
+}
+
+/// Quotes `arg` for a POSIX shell if needed, so that the command can be
+/// copied to a terminal.
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=./:,+@%".contains(c))
+    {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+impl Display for Invocation {
+    /// Formats the invocation as a report to include in bug reports.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args: Vec<String> = self.args.iter().map(|arg| shell_quote(arg)).collect();
+        writeln!(f, "Command: {}", args.join(" "))?;
+        writeln!(f, "Step: {}", self.step.unwrap_or("none"))?;
+        match &self.cwd {
+            Some(cwd) => writeln!(f, "Working directory: {}", cwd.display())?,
+            None => writeln!(f, "Working directory: unknown")?,
+        }
+        writeln!(f, "Process ID: {}", self.pid)?;
+        match self.timestamp.duration_since(UNIX_EPOCH) {
+            Ok(time) => writeln!(
+                f,
+                "Time: {}.{:06} seconds since the Unix epoch",
+                time.as_secs(),
+                time.subsec_micros()
+            )?,
+            Err(_) => writeln!(f, "Time: unknown")?,
+        }
+        writeln!(f, "Environment:")?;
+        for (name, value) in &self.env {
+            writeln!(f, "  {name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use indoc::indoc;
+
+    use super::{is_recorded, is_secret_name, shell_quote, Invocation};
+    use crate::config::{BooleanConfig, PasswordConfig};
+
+    #[test]
+    fn recorded_env() {
+        assert!(is_recorded("PATH"));
+        assert!(is_recorded("WIRESHARK_CONFIG_DIR"));
+        assert!(is_recorded("EXTCAP_WIRESHARK_VERSION"));
+        assert!(is_recorded("RUST_LOG"));
+        assert!(!is_recorded("AWS_SECRET_ACCESS_KEY"));
+        assert!(!is_recorded("PATHEXT_SECRET"));
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(shell_quote("--fifo"), "--fifo");
+        assert_eq!(shell_quote("/tmp/fifo"), "/tmp/fifo");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("my interface"), "'my interface'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn current() {
+        let invocation = Invocation::current(Some("--capture"));
+        assert_eq!(invocation.pid, std::process::id());
+        assert_eq!(invocation.step, Some("--capture"));
+        assert!(!invocation.args.is_empty());
+        assert!(invocation.env.iter().all(|(name, _)| is_recorded(name)));
+        assert!(invocation.env.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn display() {
+        let invocation = Invocation {
+            args: vec![
+                "/opt/extcap".into(),
+                "--capture".into(),
+                "--fifo".into(),
+                "/tmp/wireshark fifo".into(),
+            ],
+            env: vec![("PATH".into(), "/usr/bin:/bin".into())],
+            cwd: Some("/".into()),
+            pid: 42,
+            step: Some("--capture"),
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_500),
+        };
+        assert_eq!(
+            invocation.to_string(),
+            indoc! {"
+                Command: /opt/extcap --capture --fifo '/tmp/wireshark fifo'
+                Step: --capture
+                Working directory: /
+                Process ID: 42
+                Time: 1.500000 seconds since the Unix epoch
+                Environment:
+                  PATH=/usr/bin:/bin
+            "}
+        );
+    }
+
+    #[test]
+    fn redact_passwords() {
+        let mut invocation = Invocation {
+            args: vec![
+                "/opt/extcap".into(),
+                "--capture".into(),
+                "--password".into(),
+                "hunter2".into(),
+                "--api-token=abc123".into(),
+                "--pin".into(),
+                "1234".into(),
+                "--verbose".into(),
+                "--fifo".into(),
+                "/tmp/fifo".into(),
+            ],
+            env: vec![],
+            cwd: None,
+            pid: 42,
+            step: Some("--capture"),
+            timestamp: UNIX_EPOCH,
+        };
+        invocation.redact_options(is_secret_name);
+        let pin = PasswordConfig::builder()
+            .config_number(0)
+            .call("pin")
+            .display("PIN")
+            .build();
+        let verbose = BooleanConfig::builder()
+            .config_number(1)
+            .call("verbose")
+            .display("Verbose")
+            .build();
+        let invocation = invocation.redact_configs(&[&pin, &verbose]);
+        let report = invocation.to_string();
+        assert!(!report.contains("hunter2"), "{report}");
+        assert!(!report.contains("abc123"), "{report}");
+        assert!(!report.contains("1234"), "{report}");
+        assert!(
+            report.starts_with(
+                "Command: /opt/extcap --capture --password '[REDACTED]' \
+                 '--api-token=[REDACTED]' --pin '[REDACTED]' --verbose --fifo /tmp/fifo\n"
+            ),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn secret_names() {
+        assert!(is_secret_name("password"));
+        assert!(is_secret_name("EXTCAP_SSH_PASSWORD"));
+        assert!(is_secret_name("GITHUB_TOKEN"));
+        assert!(!is_secret_name("fifo"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Stores passwords in the credential store of the operating system.
+//!
+//! Wireshark passes the value of a [`PasswordConfig`] on the command line of
+//! the extcap and never saves it, so the user has to enter the password again
+//! each time a capture is started. With a [`KeyringEntry`], the extcap can
+//! save the password the first time it is entered, and look it up when the
+//! password field is left empty. See [`PasswordConfig::resolve`].
+//!
+//! The credential store is accessed using the `secret-tool` command (from
+//! libsecret) on Linux, and the `security` command on macOS. Other platforms
+//! are not supported, and return [`KeyringError::Unsupported`].
+
+use std::process::{Command, Output, Stdio};
+
+use thiserror::Error;
+
+#[cfg(doc)]
+use crate::config::PasswordConfig;
+use crate::Secret;
+
+/// Error accessing the OS credential store.
+#[derive(Debug, Error)]
+pub enum KeyringError {
+    /// No password is stored for the entry.
+    #[error("No password stored for {service}/{account}")]
+    NotFound {
+        /// The service of the entry.
+        service: String,
+        /// The account of the entry.
+        account: String,
+    },
+    /// The credential store of this platform is not supported.
+    #[error("The OS credential store is not supported on this platform")]
+    Unsupported,
+    /// The command used to access the credential store could not be run.
+    #[error("Unable to run credential store command")]
+    Io(#[from] std::io::Error),
+    /// The credential store command failed.
+    #[error("Credential store command failed: {0}")]
+    CommandFailed(String),
+}
+
+/// A password in the OS credential store, identified by a service and an
+/// account name.
+///
+/// ```no_run
+/// use r_extcap::{keyring::KeyringEntry, Secret};
+///
+/// let entry = KeyringEntry::new("my-extcap", "remote-password");
+/// entry.set(&Secret::new(String::from("hunter2"))).unwrap();
+/// assert_eq!(entry.get().unwrap().expose_secret(), "hunter2");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyringEntry {
+    service: String,
+    account: String,
+}
+
+impl KeyringEntry {
+    /// Creates an entry for the given `service` and `account`.
+    pub fn new(service: impl Into<String>, account: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+            account: account.into(),
+        }
+    }
+
+    /// Creates an entry for `account`, with the service named after the
+    /// current executable, like `wireshark-extcap-sshdump`.
+    pub fn for_current_exe(account: impl Into<String>) -> Self {
+        let name = crate::executable_name().unwrap_or_else(|| String::from("unknown"));
+        Self::new(format!("wireshark-extcap-{name}"), account)
+    }
+
+    /// The service name of this entry.
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// The account name of this entry.
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    /// Looks up the stored password. Returns [`KeyringError::NotFound`] if no
+    /// password is stored.
+    pub fn get(&self) -> Result<Secret<String>, KeyringError> {
+        let output = self.lookup_command()?.stderr(Stdio::piped()).output()?;
+        if !output.status.success() {
+            // `secret-tool` fails without an error message when the password is
+            // not found.
+            return Err(match command_error(&output) {
+                KeyringError::CommandFailed(message) if message.is_empty() => self.not_found(),
+                #[cfg(target_os = "macos")]
+                _ if output.status.code() == Some(SECURITY_ITEM_NOT_FOUND) => self.not_found(),
+                e => e,
+            });
+        }
+        let mut password = String::from_utf8(output.stdout)
+            .map_err(|_| KeyringError::CommandFailed(String::from("Password is not UTF-8")))?;
+        if password.ends_with('\n') {
+            password.pop();
+        }
+        Ok(Secret::new(password))
+    }
+
+    /// Stores `password`, replacing the existing password if there is one.
+    pub fn set(&self, password: &Secret<String>) -> Result<(), KeyringError> {
+        let output = self.store(password.expose_secret())?;
+        if !output.status.success() {
+            return Err(command_error(&output));
+        }
+        Ok(())
+    }
+
+    /// Deletes the stored password.
+    pub fn delete(&self) -> Result<(), KeyringError> {
+        let output = self.delete_command()?.output()?;
+        if !output.status.success() {
+            return Err(self.not_found());
+        }
+        Ok(())
+    }
+
+    fn not_found(&self) -> KeyringError {
+        KeyringError::NotFound {
+            service: self.service.clone(),
+            account: self.account.clone(),
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn lookup_command(&self) -> Result<Command, KeyringError> {
+        let mut command = Command::new("security");
+        command.args(["find-generic-password", "-w", "-s", &self.service]);
+        command.args(["-a", &self.account]);
+        Ok(command)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn store(&self, password: &str) -> Result<Output, KeyringError> {
+        use std::io::Write;
+
+        // `add-generic-password` only accepts the password as an argument,
+        // which any local user can see in the process list. Instead, the
+        // command is read from stdin by the interactive mode of `security`.
+        let line = security_command_line(&[
+            "add-generic-password",
+            "-U",
+            "-s",
+            &self.service,
+            "-a",
+            &self.account,
+            "-w",
+            password,
+        ])?;
+        let mut child = Command::new("security")
+            .arg("-i")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(line.as_bytes())?;
+        let output = child.wait_with_output()?;
+        // The interactive mode exits successfully even if the command fails,
+        // so the error message is the only indication of failure.
+        if !output.stderr.is_empty() {
+            return Err(command_error(&output));
+        }
+        Ok(output)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn delete_command(&self) -> Result<Command, KeyringError> {
+        let mut command = Command::new("security");
+        command.args(["delete-generic-password", "-s", &self.service]);
+        command.args(["-a", &self.account]);
+        Ok(command)
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn lookup_command(&self) -> Result<Command, KeyringError> {
+        let mut command = Command::new("secret-tool");
+        command.args(["lookup", "service", &self.service, "account", &self.account]);
+        Ok(command)
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn store(&self, password: &str) -> Result<Output, KeyringError> {
+        use std::io::Write;
+
+        let mut child = Command::new("secret-tool")
+            .args([
+                "store",
+                "--label",
+                &format!("{} {}", self.service, self.account),
+            ])
+            .args(["service", &self.service, "account", &self.account])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // The password is read from stdin, so it doesn't appear in the
+        // process arguments.
+        child.stdin.take().unwrap().write_all(password.as_bytes())?;
+        Ok(child.wait_with_output()?)
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn delete_command(&self) -> Result<Command, KeyringError> {
+        let mut command = Command::new("secret-tool");
+        command.args(["clear", "service", &self.service, "account", &self.account]);
+        Ok(command)
+    }
+
+    #[cfg(not(unix))]
+    fn lookup_command(&self) -> Result<Command, KeyringError> {
+        Err(KeyringError::Unsupported)
+    }
+
+    #[cfg(not(unix))]
+    fn store(&self, _password: &str) -> Result<Output, KeyringError> {
+        Err(KeyringError::Unsupported)
+    }
+
+    #[cfg(not(unix))]
+    fn delete_command(&self) -> Result<Command, KeyringError> {
+        Err(KeyringError::Unsupported)
+    }
+}
+
+/// The exit code of `security` when the item is not found.
+#[cfg(target_os = "macos")]
+const SECURITY_ITEM_NOT_FOUND: i32 = 44;
+
+/// Formats `args` as a command line for the interactive mode of `security`,
+/// quoting each argument. Line breaks cannot be quoted, since each line is a
+/// separate command.
+#[cfg(any(target_os = "macos", test))]
+fn security_command_line(args: &[&str]) -> Result<String, KeyringError> {
+    let mut line = String::new();
+    for arg in args {
+        if arg.contains(['\n', '\r']) {
+            return Err(KeyringError::CommandFailed(String::from(
+                "Credential store values cannot contain line breaks",
+            )));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push('"');
+        for c in arg.chars() {
+            if c == '"' || c == '\\' {
+                line.push('\\');
+            }
+            line.push(c);
+        }
+        line.push('"');
+    }
+    line.push('\n');
+    Ok(line)
+}
+
+fn command_error(output: &Output) -> KeyringError {
+    KeyringError::CommandFailed(String::from_utf8_lossy(&output.stderr).trim().to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::security_command_line;
+
+    #[test]
+    fn quoted_command_line() {
+        assert_eq!(
+            security_command_line(&["add-generic-password", "-w", r#"pa"ss w\ord"#]).unwrap(),
+            "\"add-generic-password\" \"-w\" \"pa\\\"ss w\\\\ord\"\n"
+        );
+        assert!(security_command_line(&["-w", "line\nbreak"]).is_err());
+    }
+}
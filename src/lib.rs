@@ -50,7 +50,7 @@
 //! 3. In the `main` function, parse the arguments and call
 //!    [`ExtcapArgs::run`](crate::ExtcapArgs::run). Use the returned
 //!    [`ExtcapStep`](crate::ExtcapStep) to perform the requested operation.
-//!    There are 5 steps:
+//!    There are 6 steps:
 //!
 //!     1. [`InterfacesStep`](crate::InterfacesStep): List the interfaces that
 //!            can be captured by this program, as well as the metadata and
@@ -62,11 +62,14 @@
 //!            [`SelectorConfig::reload`](crate::config::SelectorConfig)
 //!            is configured in one of the configs, invoked to reload the list
 //!            of options the user can choose from.
-//!     5. [`CaptureStep`](crate::CaptureStep): described below.
+//!     5. [`FilterValidationStep`](crate::FilterValidationStep): Optional,
+//!            check whether the capture filter entered by the user is valid for
+//!            the interface.
+//!     6. [`CaptureStep`](crate::CaptureStep): described below.
 //!
 //!  4. In the [`CaptureStep`](crate::CaptureStep), start capturing packets from
 //!     the external interface, and write the packets to
-//!     [`CaptureStep::fifo`](crate::CaptureStep) using the
+//!     [`CaptureStep::take_fifo`](crate::CaptureStep::take_fifo) using the
 //!     [`pcap_file`](https://docs.rs/pcap-file/latest/pcap_file/index.html)
 //!     crate.
 //!
@@ -121,37 +124,133 @@
 //!                 // &*CONFIG_BAR,
 //!             ])?;
 //!         }
+//!         ExtcapStep::FilterValidation(filter_validation_step) => {
+//!             // Validate filter_validation_step.filter
+//!         }
 //!         ExtcapStep::Capture(capture_step) => {
 //!             // Run capture
 //!         }
+//!         _ => unimplemented!(),
 //!     }
 //!     Ok(())
 //! }
 //! ```
 //!
+//! # Tracing
+//!
+//! With the `tracing` feature, [`ExtcapArgs::run`], the methods of each step,
+//! control packet reads and writes, and fifo writes are instrumented with
+//! [`tracing`](https://docs.rs/tracing/latest/tracing/) spans. `ExtcapArgs::run`
+//! is at the `INFO` level, fifo writes at `TRACE`, and the rest at `DEBUG`.
+//!
 //! References:
 //! * <https://www.wireshark.org/docs/wsdg_html_chunked/ChCaptureExtcap.html>
 //! * <https://www.wireshark.org/docs/man-pages/extcap.html>
 //! * <https://gitlab.com/wireshark/wireshark/-/blob/master/doc/extcap_example.py>
 
 #![warn(missing_docs)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
 use clap::Args;
+#[cfg(feature = "std")]
 use config::{ConfigTrait, SelectorConfig};
+#[cfg(feature = "std")]
 use controls::ToolbarControl;
-use interface::{Interface, Metadata};
+#[cfg(feature = "std")]
+use interface::{Dlt, Interface, Metadata};
+#[cfg(feature = "std")]
 use std::{
     fmt::Display,
+    io::Write,
     path::{Path, PathBuf},
 };
+#[cfg(feature = "std")]
 use thiserror::Error;
 
-#[cfg(not(target_os = "windows"))]
-use std::fs::File;
-
+#[cfg(feature = "std")]
+pub mod app;
+#[cfg(feature = "std")]
+pub mod bluetooth;
+#[cfg(feature = "bpf")]
+pub mod bpf;
+#[cfg(feature = "can")]
+pub mod can;
+#[cfg(feature = "std")]
 pub mod config;
+#[cfg(feature = "std")]
 pub mod controls;
+#[cfg(feature = "std")]
+pub mod encap;
+#[cfg(feature = "std")]
+pub mod error_report;
+#[cfg(feature = "std")]
+pub mod exported_pdu;
+#[cfg(feature = "std")]
+mod fifo;
+#[cfg(feature = "std")]
+pub mod framing;
+#[cfg(feature = "std")]
+pub mod help;
+#[cfg(feature = "std")]
+pub mod install;
+#[cfg(feature = "std")]
+pub mod instance;
+#[cfg(feature = "std")]
 pub mod interface;
+#[cfg(feature = "std")]
+pub mod invocation;
+#[cfg(feature = "keyring")]
+pub mod keyring;
+#[cfg(feature = "serde")]
+pub mod model;
+#[cfg(feature = "std")]
+pub mod panic;
+#[cfg(feature = "std")]
+pub mod pcap_stream;
+#[cfg(feature = "std")]
+pub mod remote;
+#[cfg(feature = "std")]
+mod secret;
+mod sentence;
+#[cfg(feature = "std")]
+pub mod shutdown;
+#[cfg(feature = "std")]
+pub mod sink;
+#[cfg(feature = "std")]
+pub mod sources;
+#[cfg(feature = "std")]
+pub mod template;
+#[cfg(feature = "std")]
+pub mod testing;
+#[cfg(feature = "std")]
+mod version;
+
+/// Without the `std` feature, only the control packet format is available.
+/// See the documentation with the `std` feature for the full module.
+#[cfg(not(feature = "std"))]
+pub mod controls {
+    mod packet;
+    pub use packet::{
+        ControlCommand, ControlCommandMask, ControlNumber, ControlPacket, InvalidControlNumber,
+        PayloadTooLarge,
+    };
+}
+
+/// The version of [`clap`] used by [`ExtcapArgs`] and the
+/// [`configs!`] macro, so that the macro does not require a direct dependency
+/// on `clap`.
+#[cfg(feature = "std")]
+pub use clap;
+#[cfg(feature = "std")]
+pub use fifo::{BufferedFifo, Fifo, FlushPolicy};
+#[cfg(feature = "std")]
+pub use secret::{Secret, Zeroize};
+pub use sentence::{parse_attributes, AttributeKey, EscapedValue, ExtcapFormatter, PrintSentence};
+#[cfg(feature = "std")]
+pub use version::{ParseVersionError, WiresharkVersion};
 
 /// The arguments defined by extcap. These arguments are usable as a clap
 /// parser.
@@ -184,6 +283,7 @@ pub mod interface;
 ///
 /// When the capturing stops (i.e. the user presses the red Stop button),
 /// `SIGTERM` is sent by Wireshark.
+#[cfg(feature = "std")]
 #[derive(Debug, Args)]
 pub struct ExtcapArgs {
     /// First step in the extcap exchange: this program is queried for its
@@ -206,7 +306,9 @@ pub struct ExtcapArgs {
     /// Wireshark version in question.
     ///
     /// This argument is passed during the
-    /// [`--extcap-interfaces`][ExtcapArgs::extcap_interfaces] call.
+    /// [`--extcap-interfaces`][ExtcapArgs::extcap_interfaces] call. During
+    /// the capture phase, it is made available in
+    /// [`CaptureContext::wireshark_version`].
     #[arg(long)]
     pub extcap_version: Option<String>,
 
@@ -282,7 +384,10 @@ pub struct ExtcapArgs {
 
     /// The capture filter provided by wireshark. This extcap should avoid capturing packets that do
     /// not match this filter. Used during the `--capture` phase.
-    #[arg(long, requires = "capture")]
+    ///
+    /// When given without `--capture`, Wireshark is asking the extcap to
+    /// validate the filter. See [`FilterValidationStep`] for details.
+    #[arg(long, requires = "extcap_interface")]
     pub extcap_capture_filter: Option<String>,
 
     /// Used to get control messages from toolbar. Control messages are in the
@@ -349,6 +454,7 @@ pub struct ExtcapArgs {
 }
 
 /// Error during the `--capture` phase of extcap.
+#[cfg(feature = "std")]
 #[derive(Debug, Error)]
 pub enum CaptureError {
     /// The `--extcap-interface` argument is required during the `--capture`
@@ -370,16 +476,306 @@ when invoked by Wireshark during the capture stage."
     /// show that to the user in an error dialog.
     #[error("IO error opening output FIFO for capture")]
     Io(#[from] std::io::Error),
+    /// The capture device does not exist, for example because it is not
+    /// plugged in.
+    #[error("Device {device} not found. Check that it is connected and try again.")]
+    DeviceNotFound {
+        /// The path or name of the device.
+        device: String,
+    },
+    /// The user running Wireshark does not have permission to open the capture
+    /// device. The `hint` tells the user how to get access.
+    #[error("Permission denied opening device {device}. {hint}")]
+    PermissionDenied {
+        /// The path or name of the device.
+        device: String,
+        /// Instructions on how to get access to the device, like adding the
+        /// user to a group.
+        hint: String,
+    },
+    /// The capture device is in use by another program.
+    #[error("Device {device} is busy. Close other programs using it and try again.")]
+    DeviceBusy {
+        /// The path or name of the device.
+        device: String,
+    },
+}
+
+#[cfg(feature = "std")]
+impl CaptureError {
+    /// Converts an error opening the capture device `device` into a
+    /// [`DeviceNotFound`][Self::DeviceNotFound],
+    /// [`PermissionDenied`][Self::PermissionDenied] or
+    /// [`DeviceBusy`][Self::DeviceBusy] error where possible, so that the
+    /// message printed to stderr, and shown by Wireshark in an error dialog,
+    /// tells the user how to fix the problem. Other errors are returned as
+    /// [`Io`][Self::Io].
+    ///
+    /// For serial devices on Linux, the permission hint suggests adding the
+    /// user to the `dialout` group.
+    ///
+    /// ```
+    /// use r_extcap::CaptureError;
+    ///
+    /// let error = std::io::Error::from(std::io::ErrorKind::NotFound);
+    /// assert_eq!(
+    ///     CaptureError::from_device_error("/dev/ttyUSB0", error).to_string(),
+    ///     "Device /dev/ttyUSB0 not found. Check that it is connected and try again.",
+    /// );
+    /// ```
+    pub fn from_device_error(device: impl Into<String>, error: std::io::Error) -> Self {
+        let device = device.into();
+        match error.kind() {
+            std::io::ErrorKind::NotFound => Self::DeviceNotFound { device },
+            std::io::ErrorKind::PermissionDenied => Self::PermissionDenied {
+                hint: permission_hint(&device),
+                device,
+            },
+            std::io::ErrorKind::ResourceBusy => Self::DeviceBusy { device },
+            _ => Self::Io(error),
+        }
+    }
+}
+
+/// The directory for the files that are shared between the phases and
+/// captures of the extcap: the runtime directory of the user
+/// (`$XDG_RUNTIME_DIR`), which is private to the user unlike `/tmp`, or the
+/// temporary directory if there is none.
+#[cfg(feature = "std")]
+pub(crate) fn runtime_dir() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(std::env::temp_dir)
 }
 
+/// The name of the running executable, without the extension, like `sshdump`.
+#[cfg(feature = "std")]
+pub(crate) fn executable_name() -> Option<String> {
+    let exe = std::env::current_exe().ok()?;
+    Some(exe.file_stem()?.to_string_lossy().into_owned())
+}
+
+#[cfg(feature = "std")]
+fn permission_hint(device: &str) -> String {
+    if cfg!(target_os = "linux") && device.starts_with("/dev/tty") {
+        String::from(
+            "Add your user to the dialout group with `sudo usermod -aG dialout $USER`, \
+then log out and back in.",
+        )
+    } else {
+        String::from("Make sure the user running Wireshark has access to the device.")
+    }
+}
+
+#[cfg(feature = "std")]
 impl ExtcapArgs {
     /// Runs the extcap program with the parsed arguments. This is the main
     /// entry point for the extcap program. Implementations should call this
     /// from their `main` functions.
     ///
+    /// The arguments are first checked with [`validate`][Self::validate], so
+    /// malformed combinations fail with a specific error instead of being
+    /// ignored.
+    ///
     /// For detailed usage, see the [crate documentation][crate]
     pub fn run(&self) -> Result<ExtcapStep, ExtcapError> {
+        self.run_impl(true)
+    }
+
+    /// Same as [`run`][Self::run], except that the [`CaptureStep::fifo()`] is
+    /// not opened until it is first written to, or until
+    /// [`CaptureStep::open_fifo`] is called.
+    ///
+    /// On Unix, opening the fifo blocks until Wireshark opens it for reading,
+    /// and Wireshark waits for the fifo to be opened before showing the
+    /// capture. Deferring the open allows slow setup, like connecting to a
+    /// device, to complete before the capture is shown. If the fifo is never
+    /// opened, it is opened and closed when the `CaptureStep` is dropped so
+    /// that Wireshark does not hang.
+    pub fn run_with_lazy_fifo(&self) -> Result<ExtcapStep<'_>, ExtcapError> {
+        self.run_impl(false)
+    }
+
+    /// Runs `app`, handling the step requested by Wireshark with the
+    /// corresponding method of [`ExtcapApp`][app::ExtcapApp], instead of
+    /// matching on the [`ExtcapStep`] returned by [`run`][Self::run].
+    ///
+    /// The interface given by Wireshark is checked with
+    /// [`validate_interface`][Self::validate_interface] against the
+    /// interfaces of `app`. If the program is run without any extcap
+    /// arguments, this returns [`ExtcapError::NotExtcapInput`], so that the
+    /// program can print its help instead.
+    pub fn run_app(
+        &self,
+        app: &dyn app::ExtcapApp,
+        metadata: &Metadata,
+    ) -> Result<(), app::AppError> {
+        let step = self.run()?;
+        let interfaces = app.interfaces();
+        self.validate_interface(&interfaces)?;
+        match step {
+            ExtcapStep::Interfaces(step) => step.list_interfaces_for_version(
+                self.wireshark_version(),
+                metadata,
+                &interfaces,
+                &app.controls(),
+            ),
+            ExtcapStep::Dlts(step) => step.print_from_interfaces(&interfaces)?,
+            ExtcapStep::Config(step) => step.list_configs(&app.configs(step.interface)),
+            ExtcapStep::ReloadConfig(step) => {
+                step.reload_from_configs(&app.configs(step.interface))?
+            }
+            ExtcapStep::FilterValidation(step) => app.validate_capture_filter(&step),
+            ExtcapStep::Capture(step) => app.capture(step).map_err(app::AppError::Capture)?,
+        }
+        Ok(())
+    }
+
+    /// Returns the version of Wireshark given in
+    /// [`--extcap-version`][Self::extcap_version], or in the environment
+    /// variable [`WIRESHARK_VERSION_ENV`] if the argument is not given.
+    ///
+    /// Wireshark only passes `--extcap-version` with `--extcap-interfaces`, so
+    /// [`run`][Self::run] saves it in that phase, and the later phases, like
+    /// `--extcap-dlts` and `--capture`, fall back to the saved version.
+    /// Returns `None` if the version is not known or cannot be parsed.
+    pub fn wireshark_version(&self) -> Option<WiresharkVersion> {
+        self.extcap_version
+            .clone()
+            .or_else(|| std::env::var(WIRESHARK_VERSION_ENV).ok())
+            .or_else(version::saved_version)?
+            .parse()
+            .ok()
+    }
+
+    /// Records the arguments, environment and working directory of this
+    /// process, to help debug extcaps that work from a terminal but fail when
+    /// run by Wireshark. Call [`Invocation::log`][invocation::Invocation::log]
+    /// on the result to write it to the debug log. See the
+    /// [`invocation`] module.
+    pub fn capture_invocation(&self) -> invocation::Invocation {
+        let step = if self.extcap_interfaces {
+            Some("--extcap-interfaces")
+        } else if self.extcap_dlts {
+            Some("--extcap-dlts")
+        } else if self.extcap_reload_option.is_some() {
+            Some("--extcap-reload-option")
+        } else if self.extcap_config {
+            Some("--extcap-config")
+        } else if self.capture {
+            Some("--capture")
+        } else if self.extcap_capture_filter.is_some() {
+            Some("--extcap-capture-filter")
+        } else {
+            None
+        };
+        invocation::Invocation::current(step)
+    }
+
+    /// Checks that the combination of arguments is one that Wireshark sends.
+    /// This is called by [`run`][Self::run], and catches arguments that clap's
+    /// `requires` checks do not cover, or arguments that did not come from
+    /// clap at all.
+    ///
+    /// Running without any extcap arguments is valid here, and is reported
+    /// by `run` as [`ExtcapError::NotExtcapInput`].
+    ///
+    /// ```
+    /// use clap::Parser;
+    /// use r_extcap::{ExtcapArgs, ExtcapError};
+    ///
+    /// #[derive(Debug, Parser)]
+    /// struct AppArgs {
+    ///     #[command(flatten)]
+    ///     extcap: ExtcapArgs,
+    /// }
+    ///
+    /// let args = AppArgs::parse_from(["app", "--extcap-interfaces", "--extcap-config"]);
+    /// assert!(matches!(
+    ///     args.extcap.validate(),
+    ///     Err(ExtcapError::ConflictingSteps("--extcap-interfaces", "--extcap-config"))
+    /// ));
+    /// ```
+    pub fn validate(&self) -> Result<(), ExtcapError> {
+        let steps = [
+            (self.extcap_interfaces, "--extcap-interfaces"),
+            (self.extcap_config, "--extcap-config"),
+            (self.extcap_dlts, "--extcap-dlts"),
+            (self.capture, "--capture"),
+        ];
+        let mut given_steps = steps
+            .into_iter()
+            .filter_map(|(given, name)| given.then_some(name));
+        if let (Some(first), Some(second)) = (given_steps.next(), given_steps.next()) {
+            return Err(ExtcapError::ConflictingSteps(first, second));
+        }
+        if self.capture {
+            if self.fifo.is_none() {
+                return Err(CaptureError::MissingFifo.into());
+            }
+        } else {
+            let capture_args = [
+                (self.fifo.is_some(), "--fifo"),
+                (self.extcap_control_in.is_some(), "--extcap-control-in"),
+                (self.extcap_control_out.is_some(), "--extcap-control-out"),
+            ];
+            if let Some((_, name)) = capture_args.into_iter().find(|(given, _)| *given) {
+                return Err(ExtcapError::RequiresCapture(name));
+            }
+        }
+        if self.extcap_reload_option.is_some() && !self.extcap_config {
+            return Err(ExtcapError::ReloadOptionWithoutConfig);
+        }
+        if self.extcap_interface.is_none() {
+            if self.extcap_config {
+                return Err(ExtcapError::MissingInterfaceForConfig);
+            } else if self.extcap_dlts {
+                return Err(ExtcapError::MissingInterfaceForDlts);
+            } else if self.capture {
+                return Err(CaptureError::MissingInterface.into());
+            } else if self.extcap_capture_filter.is_some() {
+                return Err(ExtcapError::MissingInterfaceForFilterValidation);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that [`--extcap-interface`][Self::extcap_interface], if given,
+    /// is one of `interfaces`, and returns
+    /// [`ExtcapError::UnknownInterface`] otherwise.
+    ///
+    /// [`run`][Self::run] does not know the interfaces of the extcap, so
+    /// calling this before it makes a capture on an interface that no longer
+    /// exists fail right away, instead of when the interface is looked up
+    /// later in the capture.
+    pub fn validate_interface(&self, interfaces: &[&Interface]) -> Result<(), ExtcapError> {
+        match &self.extcap_interface {
+            Some(name) if !interfaces.iter().any(|interface| interface.value == *name) => {
+                Err(ExtcapError::UnknownInterface(name.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "extcap_run",
+            level = "info",
+            skip(self),
+            fields(interface = self.extcap_interface.as_deref()),
+            err
+        )
+    )]
+    fn run_impl(&self, open_fifo: bool) -> Result<ExtcapStep<'_>, ExtcapError> {
+        self.validate()?;
         if self.extcap_interfaces {
+            if let Some(version) = &self.extcap_version {
+                if let Err(e) = version::save_version(version) {
+                    log::debug!("Cannot save the Wireshark version for the capture: {e}");
+                }
+            }
             Ok(ExtcapStep::Interfaces(InterfacesStep))
         } else if let Some(interface) = &self.extcap_interface {
             if self.extcap_config {
@@ -392,38 +788,47 @@ impl ExtcapArgs {
                     Ok(ExtcapStep::Config(ConfigStep { interface }))
                 }
             } else if self.extcap_dlts {
-                Ok(ExtcapStep::Dlts(DltsStep { interface }))
+                Ok(ExtcapStep::Dlts(DltsStep {
+                    interface,
+                    wireshark_version: self.wireshark_version(),
+                }))
             } else if self.capture {
                 let fifo_path = self.fifo.as_ref().ok_or(CaptureError::MissingFifo)?;
-
-                #[cfg(target_os = "windows")]
-                let fifo = {
-                    use std::os::windows::prelude::OpenOptionsExt;
-                    std::fs::OpenOptions::new()
-                        .write(true)
-                        .create(true)
-                        // Sets the flag value to `SecurityIdentification`.
-                        .security_qos_flags(0x10000)
-                        .open(fifo_path)
-                        .map_err(CaptureError::Io)?
-                };
-
-                #[cfg(not(target_os = "windows"))]
-                let fifo = File::create(fifo_path).map_err(CaptureError::Io)?;
+                let shutdown = shutdown::ShutdownSignal::global().clone();
+                let mut fifo = Fifo::new(fifo_path.clone(), shutdown.clone());
+                if open_fifo {
+                    fifo.open().map_err(CaptureError::Io)?;
+                }
 
                 let interface = self
                     .extcap_interface
                     .as_ref()
                     .ok_or(CaptureError::MissingInterface)?;
+                #[allow(deprecated)]
                 Ok(ExtcapStep::Capture(CaptureStep {
                     interface,
                     // Note: It is important to open this file, so the file gets
                     // closed even if the implementation doesn't use it.
                     // Otherwise Wireshark will hang there waiting for the FIFO.
+                    // If the open is deferred, `Fifo` opens it on drop.
                     fifo,
                     fifo_path,
                     extcap_control_in: &self.extcap_control_in,
                     extcap_control_out: &self.extcap_control_out,
+                    shutdown,
+                    context: Box::new(CaptureContext {
+                        interface: interface.clone(),
+                        capture_filter: self.extcap_capture_filter.clone(),
+                        fifo_path: fifo_path.clone(),
+                        extcap_control_in: self.extcap_control_in.clone(),
+                        extcap_control_out: self.extcap_control_out.clone(),
+                        wireshark_version: self.wireshark_version(),
+                    }),
+                }))
+            } else if let Some(filter) = &self.extcap_capture_filter {
+                Ok(ExtcapStep::FilterValidation(FilterValidationStep {
+                    interface,
+                    filter,
                 }))
             } else {
                 Err(ExtcapError::NotExtcapInput)
@@ -435,16 +840,50 @@ impl ExtcapArgs {
 }
 
 /// Error reported when running [`ExtcapArgs::run`].
+#[cfg(feature = "std")]
 #[derive(Debug, Error)]
 pub enum ExtcapError {
     /// The inputs given are not expected input from Wireshark. This can happen
     /// for example, when the user tries to run the application directly from
-    /// command line. When this happens, you can print out the
+    /// command line. When this happens, you can print out an
+    /// [`ExtcapHelp`][help::ExtcapHelp] describing the extcap, or just the
     /// [`installation_instructions`], to help the user install this in the
     /// right location.
     #[error("Missing input extcap command. {}", installation_instructions())]
     NotExtcapInput,
 
+    /// More than one step was requested, like both `--capture` and
+    /// `--extcap-dlts`. Wireshark only requests one step per invocation.
+    #[error("Cannot combine `{0}` and `{1}`")]
+    ConflictingSteps(&'static str, &'static str),
+
+    /// `--extcap-config` was given without `--extcap-interface`.
+    #[error("Missing `--extcap-interface` argument for `--extcap-config`")]
+    MissingInterfaceForConfig,
+
+    /// `--extcap-dlts` was given without `--extcap-interface`.
+    #[error("Missing `--extcap-interface` argument for `--extcap-dlts`")]
+    MissingInterfaceForDlts,
+
+    /// `--extcap-capture-filter` was given for validation without
+    /// `--extcap-interface`.
+    #[error("Missing `--extcap-interface` argument for `--extcap-capture-filter`")]
+    MissingInterfaceForFilterValidation,
+
+    /// An argument that is only used in the `--capture` step, like `--fifo`,
+    /// was given without `--capture`.
+    #[error("`{0}` can only be used with `--capture`")]
+    RequiresCapture(&'static str),
+
+    /// `--extcap-reload-option` was given without `--extcap-config`.
+    #[error("`--extcap-reload-option` can only be used with `--extcap-config`")]
+    ReloadOptionWithoutConfig,
+
+    /// The interface given in `--extcap-interface` is not one of the
+    /// interfaces of this extcap. See [`ExtcapArgs::validate_interface`].
+    #[error("Unknown interface \"{0}\"")]
+    UnknownInterface(String),
+
     /// Error when capturing packets. See [`CaptureError`].
     #[error(transparent)]
     CaptureError(#[from] CaptureError),
@@ -454,6 +893,9 @@ pub enum ExtcapError {
 /// used in unexpected ways (e.g. not as an extcap program), so users can easily
 /// install with a copy-pastable command.
 ///
+/// To let users install the program without copying commands, see the
+/// [`install`] module.
+///
 /// ```
 /// # use indoc::formatdoc;
 /// # let exe = std::env::current_exe().unwrap();
@@ -473,6 +915,7 @@ pub enum ExtcapError {
 ///     "}
 /// )
 /// ```
+#[cfg(feature = "std")]
 pub fn installation_instructions() -> String {
     let install_cmd = std::env::current_exe()
         .ok()
@@ -497,7 +940,29 @@ pub fn installation_instructions() -> String {
     )
 }
 
+/// The interface given by Wireshark is not one of the interfaces of this
+/// extcap. Returned by [`CaptureStep::resolve_interface`] and
+/// [`ConfigStep::resolve_interface`].
+#[cfg(feature = "std")]
+#[derive(Debug, Error)]
+#[error("Unknown interface \"{0}\".")]
+pub struct UnknownInterface(pub String);
+
+/// Finds the interface in `interfaces` with the given `value`.
+#[cfg(feature = "std")]
+fn find_interface<'i>(
+    value: &str,
+    interfaces: &[&'i Interface],
+) -> Result<&'i Interface, UnknownInterface> {
+    interfaces
+        .iter()
+        .find(|interface| interface.value == value)
+        .copied()
+        .ok_or_else(|| UnknownInterface(value.to_owned()))
+}
+
 /// Error printing DLTs to Wireshark.
+#[cfg(feature = "std")]
 #[derive(Debug, Error)]
 pub enum PrintDltError {
     /// The interface string value given from Wireshark is not found. Wireshark
@@ -512,6 +977,7 @@ pub enum PrintDltError {
 /// Error when reloading configs. Config reload happens when a config, like
 /// [`crate::config::SelectorConfig`] specifics the `reload` field and the user
 /// clicks on the created reload button.
+#[cfg(feature = "std")]
 #[derive(Debug, Error)]
 pub enum ReloadConfigError {
     /// The config `call` value given from Wireshark is not found in the configs
@@ -531,6 +997,7 @@ pub enum ReloadConfigError {
 }
 
 /// Error listing configs.
+#[cfg(feature = "std")]
 #[derive(Debug, Error)]
 pub enum ListConfigError {
     /// The interface string value given from Wireshark is not found. Wireshark
@@ -543,10 +1010,50 @@ pub enum ListConfigError {
     UnknownInterface(String),
 }
 
+/// Prints the sentences written by `write` to stdout for Wireshark. See
+/// [`write_step_output`].
+///
+/// Panics: If stdout cannot be written to for a reason other than Wireshark
+/// closing it, like [`print!`] does.
+#[cfg(feature = "std")]
+fn print_step_output(write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>) {
+    if let Err(e) = write_step_output(&mut std::io::stdout().lock(), write) {
+        panic!("failed printing to stdout: {e}");
+    }
+}
+
+/// Writes the sentences written by `write` to `out` with a single write and
+/// flush. The sentences are buffered first, so that a panic while producing
+/// them leaves Wireshark with no output rather than partial output.
+///
+/// Wireshark may close stdout before reading all of the output, for example
+/// when it is quitting. This surfaces as a `BrokenPipe` error, since Rust
+/// ignores `SIGPIPE`, and the output is discarded without an error.
+#[cfg(feature = "std")]
+fn write_step_output(
+    out: &mut dyn Write,
+    write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut buffer = Vec::new();
+    write(&mut buffer)?;
+    match out.write_all(&buffer).and_then(|()| out.flush()) {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {
+            log::debug!("Wireshark closed stdout, discarding the output: {e}");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
 /// The step of extcap to execute, which is returned from [`ExtcapArgs::run`].
 /// Each step has its own type which contains the relevant methods for each
 /// step. See the docs for each individual step to for details on what
 /// operations should be performed.
+///
+/// New steps may be added in minor releases as Wireshark adds them, so
+/// matches on this enum need a wildcard arm.
+#[cfg(feature = "std")]
+#[non_exhaustive]
 pub enum ExtcapStep<'a> {
     /// List the interfaces and toolbar controls supported by this extcap
     /// implementation in stdout for Wireshark's consumption. Corresponds to the
@@ -571,9 +1078,15 @@ pub enum ExtcapStep<'a> {
     ///
     /// See the documentation on [`ReloadConfigStep`] for details.
     ReloadConfig(ReloadConfigStep<'a>),
+    /// Validates the capture filter entered by the user. Corresponds to the
+    /// `--extcap-capture-filter` argument in extcap, when given without
+    /// `--capture`.
+    ///
+    /// See the documentation on [`FilterValidationStep`] for details.
+    FilterValidation(FilterValidationStep<'a>),
     /// Corresponds to the `--capture` step in Wireshark. In this step, the
     /// implementation should start capturing from the external interface and
-    /// write the output to the fifo given in [`CaptureStep::fifo`].
+    /// write the output to the fifo given in [`CaptureStep::take_fifo`].
     ///
     /// See the documentation on [`CaptureStep`] for details.
     Capture(CaptureStep<'a>),
@@ -583,8 +1096,10 @@ pub enum ExtcapStep<'a> {
 /// implementation in stdout for Wireshark's consumption. Corresponds to the
 /// `--extcap-interfaces` argument in extcap. Implementations should call
 /// [`list_interfaces`][Self::list_interfaces] during this step.
+#[cfg(feature = "std")]
 pub struct InterfacesStep;
 
+#[cfg(feature = "std")]
 impl InterfacesStep {
     /// List the interfaces and toolbar controls supported by this extcap
     /// implementation in stdout for Wireshark's consumption. Wireshark calls
@@ -603,22 +1118,183 @@ impl InterfacesStep {
     ///   interfaces can change, the extcap program must be prepared to handle
     ///   stale values in [`ConfigStep::interface`] and
     ///   [`CaptureStep::interface`].
+    ///
+    ///   A warning is logged for interfaces that fail [`Interface::validate`].
     /// * controls: List the toolbar controls for this interface. In Wireshark,
     ///   this is presented to the user in View > Interface Toolbars. See the
     ///   documentation in [`controls`] for details.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(interfaces = interfaces.len(), controls = controls.len())
+        )
+    )]
     pub fn list_interfaces(
         &self,
         metadata: &Metadata,
         interfaces: &[&Interface],
         controls: &[&dyn ToolbarControl],
     ) {
-        metadata.print_sentence();
+        print_step_output(|writer| self.write_interfaces(writer, metadata, interfaces, controls));
+    }
+
+    /// Same as [`list_interfaces`][Self::list_interfaces], but returns an
+    /// error instead of panicking if stdout cannot be written to. Like the
+    /// other steps, the output is still discarded without an error if
+    /// Wireshark has already closed stdout.
+    pub fn try_list_interfaces(
+        &self,
+        metadata: &Metadata,
+        interfaces: &[&Interface],
+        controls: &[&dyn ToolbarControl],
+    ) -> std::io::Result<()> {
+        write_step_output(&mut std::io::stdout().lock(), |writer| {
+            self.write_interfaces(writer, metadata, interfaces, controls)
+        })
+    }
+
+    /// Same as [`list_interfaces`][Self::list_interfaces], but leaves out the
+    /// toolbar controls that the calling Wireshark does not understand, as
+    /// given by [`ToolbarControl::min_wireshark_version`]. In particular, no
+    /// controls are listed for versions older than
+    /// [`WiresharkVersion::INTERFACE_TOOLBAR`], which do not have the
+    /// interface toolbar.
+    ///
+    /// `wireshark_version` is typically
+    /// [`ExtcapArgs::wireshark_version`]. If it is `None`, all controls are
+    /// listed. Extcaps that know the toolbar is not shown, for example when
+    /// they are only run from `tshark`, can pass no controls at all.
+    ///
+    /// ```no_run
+    /// use r_extcap::{controls::ToolbarControl, interface::{Interface, Metadata}, ExtcapArgs, ExtcapStep};
+    /// # fn example(args: ExtcapArgs, metadata: Metadata, interface: Interface, controls: Vec<&dyn ToolbarControl>) {
+    /// if let Ok(ExtcapStep::Interfaces(interfaces_step)) = args.run() {
+    ///     interfaces_step.list_interfaces_for_version(
+    ///         args.wireshark_version(),
+    ///         &metadata,
+    ///         &[&interface],
+    ///         &controls,
+    ///     );
+    /// }
+    /// # }
+    /// ```
+    pub fn list_interfaces_for_version(
+        &self,
+        wireshark_version: Option<WiresharkVersion>,
+        metadata: &Metadata,
+        interfaces: &[&Interface],
+        controls: &[&dyn ToolbarControl],
+    ) {
+        print_step_output(|writer| {
+            self.write_interfaces_for_version(
+                writer,
+                wireshark_version,
+                metadata,
+                interfaces,
+                controls,
+            )
+        });
+    }
+
+    /// Writes the sentences like [`write_interfaces`][Self::write_interfaces],
+    /// leaving out the controls not supported by `wireshark_version`. See
+    /// [`list_interfaces_for_version`][Self::list_interfaces_for_version].
+    pub fn write_interfaces_for_version(
+        &self,
+        writer: &mut dyn Write,
+        wireshark_version: Option<WiresharkVersion>,
+        metadata: &Metadata,
+        interfaces: &[&Interface],
+        controls: &[&dyn ToolbarControl],
+    ) -> std::io::Result<()> {
+        let supported: Vec<&dyn ToolbarControl> = controls
+            .iter()
+            .copied()
+            .filter(|control| {
+                let min_version = control.min_wireshark_version();
+                let supported = wireshark_version.map_or(true, |version| version >= min_version);
+                if !supported {
+                    log::debug!(
+                        "Not listing control {} which requires Wireshark {min_version}",
+                        control.control_number()
+                    );
+                }
+                supported
+            })
+            .collect();
+        self.write_interfaces(writer, metadata, interfaces, &supported)
+    }
+
+    /// Reports a problem found while discovering the interfaces, like a
+    /// missing driver or no devices being connected, so that an empty or
+    /// partial interface list is not left unexplained. Call this in addition
+    /// to listing the interfaces that were found.
+    ///
+    /// The message is written to stderr, on a line of its own prefixed with
+    /// the name of the executable so that it can be told apart from other
+    /// extcaps. Wireshark runs every extcap in this phase at startup, so it
+    /// does not show a dialog for their stderr. Instead the message ends up
+    /// in Wireshark's log, which is visible when running Wireshark or
+    /// `tshark -D` from a terminal with `--log-level debug` in Wireshark 3.6
+    /// and later. Older versions may discard it.
+    ///
+    /// ```no_run
+    /// # let interfaces_step = r_extcap::InterfacesStep;
+    /// # let devices: Vec<String> = Vec::new();
+    /// if devices.is_empty() {
+    ///     interfaces_step.warn("Found 0 devices. Is the USB driver installed?");
+    /// }
+    /// ```
+    pub fn warn(&self, message: impl Display) {
+        let program = executable_name().unwrap_or_else(|| String::from("extcap"));
+        // Failing to report a warning is not worth failing the step for.
+        let _ = self.write_warning(&mut std::io::stderr().lock(), &program, message);
+    }
+
+    fn write_warning(
+        &self,
+        writer: &mut dyn Write,
+        program: &str,
+        message: impl Display,
+    ) -> std::io::Result<()> {
+        let message = error_report::ErrorReport::default().format_message(&message.to_string());
+        for line in message.lines() {
+            writeln!(writer, "{program}: warning: {line}")?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the sentences for the metadata, interfaces and toolbar controls
+    /// to `writer`. See [`list_interfaces`][Self::list_interfaces] for details
+    /// on the parameters.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(interfaces = interfaces.len(), controls = controls.len())
+        )
+    )]
+    pub fn write_interfaces(
+        &self,
+        writer: &mut dyn Write,
+        metadata: &Metadata,
+        interfaces: &[&Interface],
+        controls: &[&dyn ToolbarControl],
+    ) -> std::io::Result<()> {
+        metadata.write_sentence(writer)?;
         for interface in interfaces {
-            interface.print_sentence();
+            if let Err(e) = interface.validate() {
+                log::warn!("{e}");
+            }
+            interface.write_sentence(writer)?;
         }
         for control in controls {
-            control.print_sentence();
+            control.write_sentence(writer)?;
         }
+        Ok(())
     }
 }
 
@@ -626,52 +1302,107 @@ impl InterfacesStep {
 /// interface. DLT stands for data link type, and is used to determine how
 /// Wireshark analyzes (dissects) the given packets.
 ///
-/// Despite this step being named with plurals (DLTs) by extcap, only one DLT is
-/// expected for each interface. Corresponds to the `--extcap-dlts` argument in
-/// extcap.
+/// Each interface has a preferred DLT, and optionally additional DLTs the user
+/// can choose from. See [`Interface::extra_dlts`]. Corresponds to the
+/// `--extcap-dlts` argument in extcap.
+#[cfg(feature = "std")]
 pub struct DltsStep<'a> {
     /// The interface to print the DLT for.
     pub interface: &'a str,
+    /// The version of Wireshark, if known. See
+    /// [`ExtcapArgs::wireshark_version`].
+    pub wireshark_version: Option<WiresharkVersion>,
 }
 
+#[cfg(feature = "std")]
 impl<'a> DltsStep<'a> {
     /// Print the DLT for the given interface. If you have the list of
     /// interfaces from [`InterfacesStep`], consider using
     /// [`print_from_interfaces`][Self::print_from_interfaces] instead.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(interface = self.interface))
+    )]
     pub fn print_dlt(&self, interface: &Interface) {
-        interface.dlt.print_sentence();
+        print_step_output(|writer| interface.dlt.write_sentence(writer));
     }
 
     /// Finds the interface within `interfaces` that matches the given request
-    /// and prints out its DLT. Typically `interfaces` will be the same list
+    /// and prints out its DLTs. Typically `interfaces` will be the same list
     /// given to [`InterfacesStep::list_interfaces`].
+    ///
+    /// The preferred [`Interface::dlt`] is printed first, followed by the
+    /// [`Interface::extra_dlts`]. The extra DLTs are omitted if the Wireshark
+    /// version is known to be older than
+    /// [`WiresharkVersion::MULTIPLE_DLTS`].
+    ///
+    /// A warning is logged for DLTs that fail [`Dlt::validate`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(interface = self.interface), err)
+    )]
     pub fn print_from_interfaces(&self, interfaces: &[&Interface]) -> Result<(), PrintDltError> {
-        interfaces
-            .iter()
-            .find(|i| i.value == self.interface)
-            .ok_or_else(|| PrintDltError::UnknownInterface(self.interface.to_owned()))?
-            .dlt
-            .print_sentence();
+        let interface = find_interface(self.interface, interfaces)
+            .map_err(|UnknownInterface(value)| PrintDltError::UnknownInterface(value))?;
+        print_step_output(|writer| {
+            for dlt in self.dlts_to_print(interface) {
+                if let Err(e) = dlt.validate() {
+                    log::warn!("{e}");
+                }
+                dlt.write_sentence(writer)?;
+            }
+            Ok(())
+        });
         Ok(())
     }
+
+    fn dlts_to_print<'i>(&self, interface: &'i Interface) -> impl Iterator<Item = &'i Dlt> {
+        let multiple = self
+            .wireshark_version
+            .map_or(true, |version| version >= WiresharkVersion::MULTIPLE_DLTS);
+        interface.dlts().take(if multiple { usize::MAX } else { 1 })
+    }
 }
 
 /// List the configurable UI elements for this interface. This is presented to
 /// the user when they click on the gear icon next to the capture interface
 /// name, or if they try to start a capture that is lacking a required config
 /// value.
+#[cfg(feature = "std")]
 pub struct ConfigStep<'a> {
     /// The interface that the configurations should be associated with.
     pub interface: &'a str,
 }
 
+#[cfg(feature = "std")]
 impl<'a> ConfigStep<'a> {
+    /// Returns the interface in `interfaces` that the configs are requested
+    /// for. Typically `interfaces` will be the same list given to
+    /// [`InterfacesStep::list_interfaces`].
+    pub fn resolve_interface<'i>(
+        &self,
+        interfaces: &[&'i Interface],
+    ) -> Result<&'i Interface, UnknownInterface> {
+        find_interface(self.interface, interfaces)
+    }
+
     /// List the `configs` given, printing them out to stdout for consumption by
     /// Wireshark. This list can vary by [`interface`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(interface = self.interface, configs = configs.len())
+        )
+    )]
     pub fn list_configs(&self, configs: &[&dyn ConfigTrait]) {
-        for config in configs {
-            config.print_sentence();
-        }
+        print_step_output(|writer| {
+            for config in configs {
+                config.write_sentence(writer)?;
+            }
+            Ok(())
+        });
     }
 }
 
@@ -679,6 +1410,7 @@ impl<'a> ConfigStep<'a> {
 /// user clicks on the reload button created by a [`SelectorConfig`] with the
 /// [`reload`][SelectorConfig::reload] field set. Corresponds to the
 /// `--extcap-reload-option` argument in extcap.
+#[cfg(feature = "std")]
 pub struct ReloadConfigStep<'a> {
     /// The [`Interface::value`] from the interface the reloaded config is
     /// associated with.
@@ -687,6 +1419,7 @@ pub struct ReloadConfigStep<'a> {
     pub config: &'a str,
 }
 
+#[cfg(feature = "std")]
 impl<'a> ReloadConfigStep<'a> {
     /// Calls the [`reload`][SelectorConfig::reload] function in the given
     /// `config`. Returns the error [`ReloadConfigError::UnsupportedConfig`] if
@@ -694,14 +1427,27 @@ impl<'a> ReloadConfigStep<'a> {
     ///
     /// If you have the list of configs for the given interface, consider using
     /// [`reload_from_configs`][Self::reload_from_configs] instead.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(interface = self.interface, config = %config.call),
+            err
+        )
+    )]
     pub fn reload_options(&self, config: &SelectorConfig) -> Result<(), ReloadConfigError> {
         let reload = config
             .reload
             .as_ref()
             .ok_or_else(|| ReloadConfigError::UnsupportedConfig(config.call.clone()))?;
-        for value in (reload.reload_fn)() {
-            value.print_sentence(config.config_number);
-        }
+        let values = (reload.reload_fn)();
+        print_step_output(|writer| {
+            for value in &values {
+                (value, config.config_number).write_sentence(writer)?;
+            }
+            Ok(())
+        });
         Ok(())
     }
 
@@ -723,10 +1469,71 @@ impl<'a> ReloadConfigStep<'a> {
     }
 }
 
+/// Validates the capture filter the user entered for an interface. Wireshark
+/// invokes this step as the user types a capture filter, to color the filter
+/// text box green or red. Corresponds to the `--extcap-capture-filter`
+/// argument in extcap, when given without `--capture`.
+///
+/// If the filter is valid, the implementation should not print anything to
+/// stdout. Otherwise, use [`reject`][Self::reject] to report the error, which
+/// is shown to the user.
+#[cfg(feature = "std")]
+pub struct FilterValidationStep<'a> {
+    /// The interface the filter will be applied to.
+    pub interface: &'a str,
+    /// The capture filter entered by the user.
+    pub filter: &'a str,
+}
+
+#[cfg(feature = "std")]
+impl<'a> FilterValidationStep<'a> {
+    /// Reports to Wireshark that the filter is invalid. Wireshark only shows
+    /// the first line of the message, so any line breaks in `message` are
+    /// replaced with spaces.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(interface = self.interface, filter = self.filter)
+        )
+    )]
+    pub fn reject(&self, message: impl Display) {
+        let message = message.to_string().replace(['\r', '\n'], " ");
+        print_step_output(|writer| writeln!(writer, "{message}"));
+    }
+
+    /// Validates the filter by compiling it into a BPF program for the given
+    /// data link type, and [rejects][Self::reject] it if compilation fails.
+    /// Returns whether the filter is valid.
+    ///
+    /// See [`FilterMatcher`][bpf::FilterMatcher] for details.
+    #[cfg(feature = "bpf")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip(self),
+            fields(interface = self.interface, filter = self.filter),
+            ret
+        )
+    )]
+    pub fn validate_bpf(&self, datalink: pcap_file::DataLink) -> bool {
+        match bpf::FilterMatcher::new(self.filter, datalink) {
+            Ok(_) => true,
+            Err(e) => {
+                self.reject(e);
+                false
+            }
+        }
+    }
+}
+
 /// When this value is returned in [`ExtcapArgs::run`], the implementation
 /// should use these returned values to start capturing packets from the
-/// external interface and write them to the [`fifo`][Self::fifo] in PCAP
+/// external interface and write them to the [`fifo`][Self::take_fifo] in PCAP
 /// format.
+#[cfg(feature = "std")]
 pub struct CaptureStep<'a> {
     /// The interface to run this capture on. This is the string previously
     /// defined in [`Interface::value`].
@@ -735,7 +1542,16 @@ pub struct CaptureStep<'a> {
     /// written in PCAP format. Implementations can use the
     /// [`pcap-file`](https://docs.rs/pcap-file/latest/pcap_file/) crate to help
     /// format the packets.
-    pub fifo: std::fs::File,
+    ///
+    /// The fifo is already open if this step is returned from
+    /// [`ExtcapArgs::run`]. If it is returned from
+    /// [`ExtcapArgs::run_with_lazy_fifo`], it is opened on the first write.
+    ///
+    /// This field used to be a [`std::fs::File`]. Code that needs the file can
+    /// use `capture_step.take_fifo().into_file()`, which opens the fifo if it
+    /// is not already open.
+    #[deprecated(note = "Use `fifo` or `take_fifo` instead")]
+    pub fifo: Fifo,
     fifo_path: &'a Path,
     /// The extcap control reader if the `--extcap-control-in` argument is
     /// provided on the command line. This is used to receive arguments from the
@@ -745,15 +1561,297 @@ pub struct CaptureStep<'a> {
     /// provided on the command line. This is used to send control messages to
     /// Wireshark to modify the toolbar controls and show status messages.
     pub extcap_control_out: &'a Option<std::path::PathBuf>,
+    shutdown: shutdown::ShutdownSignal,
+    context: Box<CaptureContext>,
 }
 
+/// The environment variable read for
+/// [`ExtcapArgs::wireshark_version`] when `--extcap-version` is not passed,
+/// which takes precedence over the version saved in the interfaces phase.
+#[cfg(feature = "std")]
+pub const WIRESHARK_VERSION_ENV: &str = "EXTCAP_WIRESHARK_VERSION";
+
+/// The parameters of a capture requested by Wireshark, collected from the
+/// command line arguments of the `--capture` phase.
+///
+/// Unlike [`CaptureStep`], this does not borrow from [`ExtcapArgs`], so it can
+/// be cloned and moved into the threads or tasks that perform the capture.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureContext {
+    /// The interface to run this capture on. This is the string previously
+    /// defined in [`Interface::value`].
+    pub interface: String,
+    /// The capture filter entered by the user, given in
+    /// [`--extcap-capture-filter`][ExtcapArgs::extcap_capture_filter]. The
+    /// extcap should avoid capturing packets that do not match this filter.
+    pub capture_filter: Option<String>,
+    /// The path of the fifo to write the captured packets to.
+    pub fifo_path: PathBuf,
+    /// The path of the control pipe to read control packets from, given in
+    /// [`--extcap-control-in`][ExtcapArgs::extcap_control_in].
+    pub extcap_control_in: Option<PathBuf>,
+    /// The path of the control pipe to send control packets to, given in
+    /// [`--extcap-control-out`][ExtcapArgs::extcap_control_out].
+    pub extcap_control_out: Option<PathBuf>,
+    /// The version of Wireshark running the capture.
+    ///
+    /// Wireshark only passes
+    /// [`--extcap-version`][ExtcapArgs::extcap_version] when querying the
+    /// interfaces, so this is the version saved in that phase, unless the
+    /// environment variable [`WIRESHARK_VERSION_ENV`] is set, which allows
+    /// wrapper scripts to pass the version through. This is `None` if the
+    /// version is not known or cannot be parsed. See
+    /// [`ExtcapArgs::wireshark_version`].
+    pub wireshark_version: Option<WiresharkVersion>,
+}
+
+#[cfg(feature = "bpf")]
+impl CaptureContext {
+    /// Compiles the [capture filter][Self::capture_filter] as a BPF filter for
+    /// packets of the given `datalink` type, to be added to the sink with
+    /// [`PacketSink::with_middleware`][sink::PacketSink::with_middleware].
+    /// Returns `None` if no filter was given.
+    ///
+    /// ```no_run
+    /// use r_extcap::interface::DataLink;
+    /// use r_extcap::sink::{MiddlewareSink, PcapSink};
+    /// # fn example(capture_step: r_extcap::CaptureStep, fifo: std::fs::File) -> anyhow::Result<()> {
+    /// let mut sink = MiddlewareSink::new(PcapSink::new(fifo, DataLink::ETHERNET)?);
+    /// if let Some(filter) = capture_step.context().compile_capture_filter(DataLink::ETHERNET)? {
+    ///     sink = sink.with_middleware(filter);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn compile_capture_filter(
+        &self,
+        datalink: pcap_file::DataLink,
+    ) -> Result<Option<sink::CaptureFilter>, bpf::FilterError> {
+        match self.capture_filter.as_deref() {
+            Some(filter) if !filter.trim().is_empty() => {
+                sink::CaptureFilter::bpf(filter, datalink).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl<'a> CaptureStep<'a> {
+    /// Returns the interface in `interfaces` to capture on, to look up its
+    /// [`dlt`][Interface::dlt] or other details. Typically `interfaces` will be
+    /// the same list given to [`InterfacesStep::list_interfaces`].
+    ///
+    /// ```
+    /// use r_extcap::interface::{Dlt, Interface};
+    /// use r_extcap::sink::PcapSink;
+    ///
+    /// # fn capture(mut capture_step: r_extcap::CaptureStep) -> anyhow::Result<()> {
+    /// # let interface1 = Interface::builder()
+    /// #     .value("if1".into())
+    /// #     .display("Interface 1".into())
+    /// #     .dlt(Dlt::user(0, "Interface 1"))
+    /// #     .build();
+    /// # let interface2 = Interface::builder()
+    /// #     .value("if2".into())
+    /// #     .display("Interface 2".into())
+    /// #     .dlt(Dlt::user(1, "Interface 2"))
+    /// #     .build();
+    /// let interface = capture_step.resolve_interface(&[&interface1, &interface2])?;
+    /// let sink = PcapSink::new(capture_step.take_fifo(), interface.dlt.data_link_type)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn resolve_interface<'i>(
+        &self,
+        interfaces: &[&'i Interface],
+    ) -> Result<&'i Interface, UnknownInterface> {
+        find_interface(self.interface, interfaces)
+    }
+
+    /// Takes an [`InstanceLock`][instance::InstanceLock] keyed by the
+    /// interface value, so that a second capture on the same interface fails
+    /// with [`CaptureError::DeviceBusy`] instead of competing for the device.
+    /// Keep the returned lock alive until the capture ends.
+    ///
+    /// If several interfaces share the same device, take the lock with
+    /// [`InstanceLock::acquire`][instance::InstanceLock::acquire] keyed by the
+    /// device instead.
+    pub fn lock_instance(&self) -> Result<instance::InstanceLock, CaptureError> {
+        instance::InstanceLock::acquire(self.interface)
+    }
+
+    /// Returns the parameters of this capture. See [`CaptureContext`].
+    pub fn context(&self) -> &CaptureContext {
+        &self.context
+    }
+
+    /// The path of the fifo given in `--fifo`, which the output packets are
+    /// written to.
+    pub fn fifo_path(&self) -> &Path {
+        self.fifo_path
+    }
+
+    /// Ends the capture with an error, for failures that happen before the
+    /// capture can produce packets, like a device that cannot be opened.
+    ///
+    /// Wireshark waits for the fifo to be opened before it notices that the
+    /// extcap has exited, so returning an error before opening the fifo can
+    /// leave Wireshark hanging. This writes `error` to stderr, which Wireshark
+    /// shows in an error dialog, opens and closes the fifo, and exits the
+    /// process with status 1. If Wireshark has not opened the fifo yet, this
+    /// waits up to a few seconds for it. The message is cleaned up for the
+    /// dialog with [`ErrorReport`][error_report::ErrorReport].
+    ///
+    /// ```no_run
+    /// # fn example(capture_step: r_extcap::CaptureStep) {
+    /// let device = match std::fs::File::open("/dev/ttyUSB0") {
+    ///     Ok(device) => device,
+    ///     Err(e) => capture_step.abort_capture(format!("Cannot open the device: {e}")),
+    /// };
+    /// # }
+    /// ```
+    pub fn abort_capture(self, error: impl Display) -> ! {
+        eprintln!(
+            "{}",
+            error_report::ErrorReport::default().format_message(&error.to_string())
+        );
+        let _ = fifo::open_and_close(self.fifo_path);
+        std::process::exit(1)
+    }
+
+    /// Opens the [`fifo`][Self::fifo()] if it is not already open. This blocks
+    /// until Wireshark opens the fifo for reading.
+    ///
+    /// This is only needed after
+    /// [`ExtcapArgs::run_with_lazy_fifo`], to connect to Wireshark at a
+    /// specific point instead of on the first write.
+    #[allow(deprecated)]
+    pub fn open_fifo(&mut self) -> std::io::Result<&mut std::fs::File> {
+        self.fifo.open()
+    }
+
+    /// The fifo to write the output packets to, in PCAP format. To move the
+    /// fifo to another thread, use [`take_fifo`][Self::take_fifo] instead.
+    #[allow(deprecated)]
+    pub fn fifo(&mut self) -> &mut Fifo {
+        &mut self.fifo
+    }
+
+    /// Takes ownership of the fifo to write the output packets to, which can
+    /// then be moved to another thread or wrapped in a
+    /// [`PcapWriter`](https://docs.rs/pcap-file/latest/pcap_file/pcap/struct.PcapWriter.html).
+    /// The output packets should be written in PCAP format.
+    ///
+    /// The returned [`Fifo`] triggers the capture's shutdown signal when
+    /// Wireshark closes the fifo. See [`on_capture_end`][Self::on_capture_end].
+    ///
+    /// The fifo can only be taken once. Later calls return a fifo that fails
+    /// all writes, so that there are never two handles writing interleaved
+    /// packets to the same fifo.
+    #[allow(deprecated)]
+    pub fn take_fifo(&mut self) -> Fifo {
+        self.fifo.take()
+    }
+
+    /// Takes the [`fifo`][Self::take_fifo] and wraps it in a
+    /// [`PcapWriter`](https://docs.rs/pcap-file/latest/pcap_file/pcap/struct.PcapWriter.html)
+    /// whose header declares the data link type of
+    /// [`interface.dlt`][Interface::dlt], so that the packets are dissected as
+    /// the DLT listed to Wireshark.
+    ///
+    /// The header is written immediately, which opens the fifo if it is not
+    /// already open.
+    ///
+    /// ```no_run
+    /// # use r_extcap::interface::Interface;
+    /// # fn example(mut capture_step: r_extcap::CaptureStep, interface: &Interface) {
+    /// use pcap_file::pcap::PcapPacket;
+    ///
+    /// let mut pcap_writer = capture_step.pcap_writer_for(interface).unwrap();
+    /// pcap_writer
+    ///     .write_packet(&PcapPacket::new(std::time::Duration::ZERO, 4, b"data"))
+    ///     .unwrap();
+    /// # }
+    /// ```
+    pub fn pcap_writer_for(
+        &mut self,
+        interface: &Interface,
+    ) -> Result<pcap_file::pcap::PcapWriter<Fifo>, pcap_file::PcapError> {
+        let header = pcap_file::pcap::PcapHeader {
+            datalink: interface.dlt.data_link_type,
+            ..Default::default()
+        };
+        pcap_file::pcap::PcapWriter::with_header(self.take_fifo(), header)
+    }
+
+    /// Same as [`take_fifo`][Self::take_fifo], except that the writes are
+    /// buffered and flushed according to `policy`. See [`BufferedFifo`].
+    ///
+    /// ```no_run
+    /// # fn example(mut capture_step: r_extcap::CaptureStep) {
+    /// use pcap_file::pcap::{PcapHeader, PcapWriter};
+    /// use r_extcap::FlushPolicy;
+    ///
+    /// let fifo = capture_step.take_buffered_fifo(FlushPolicy::default());
+    /// let mut pcap_writer = PcapWriter::with_header(fifo, PcapHeader::default()).unwrap();
+    /// # }
+    /// ```
+    pub fn take_buffered_fifo(&mut self, policy: FlushPolicy) -> BufferedFifo {
+        BufferedFifo::new(self.take_fifo(), policy)
+    }
+
+    /// Registers `callback` to be run when the capture ends, which is when
+    /// Wireshark sends `SIGTERM`, or when writing to the [`fifo`][Self::take_fifo]
+    /// fails because Wireshark closed it. The callback is run exactly once,
+    /// and can be used to power down hardware or close remote sessions.
+    ///
+    /// This installs a `SIGTERM` handler using
+    /// [`ShutdownSignal::handle_sigterm`][shutdown::ShutdownSignal::handle_sigterm].
+    /// After the callbacks have run, the process is terminated by `SIGTERM`
+    /// as before. When the fifo is closed, the write error is still returned
+    /// after the callbacks have run, so the capture can stop.
+    ///
+    /// On Windows, Wireshark does not send `SIGTERM`. Instead, the fifo is
+    /// monitored for closure, and console control events are handled. See
+    /// the [`shutdown`] module for details.
+    ///
+    /// ```no_run
+    /// # fn example(capture_step: r_extcap::CaptureStep) {
+    /// capture_step.on_capture_end(|| {
+    ///     // Power down the device
+    /// });
+    /// # }
+    /// ```
+    pub fn on_capture_end(&self, callback: impl FnOnce() + Send + 'static) {
+        shutdown::ShutdownSignal::handle_sigterm();
+        self.shutdown.on_shutdown(callback);
+    }
+
+    /// The signal that is triggered when this capture ends. See
+    /// [`on_capture_end`][Self::on_capture_end].
+    pub fn shutdown_signal(&self) -> &shutdown::ShutdownSignal {
+        &self.shutdown
+    }
+
+    /// Installs a panic hook that reports panics to the user, including in an
+    /// error dialog through the control pipe if `--extcap-control-out` is
+    /// given. See [`panic::install_hook_with_control_out`].
+    pub fn install_panic_hook(&self) {
+        panic::install_hook_with_control_out(self.extcap_control_out.clone());
+    }
+
     /// Create a new control sender for this capture, if `--extcap-control-out`
     /// is specified in the command line. The control sender is used to send
     /// control messages to Wireshark to modify
     /// [`ToolbarControls`][controls::ToolbarControl] and communicate other
     /// states.
     #[cfg(feature = "sync")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(interface = self.interface))
+    )]
     pub fn new_control_sender(&self) -> Option<controls::synchronous::ExtcapControlSender> {
         self.extcap_control_out
             .as_ref()
@@ -766,6 +1864,10 @@ impl<'a> CaptureStep<'a> {
     /// [`ToolbarControls`][controls::ToolbarControl] and communicate other
     /// states.
     #[cfg(feature = "async")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(interface = self.interface))
+    )]
     pub async fn new_control_sender_async(
         &self,
     ) -> Option<controls::asynchronous::ExtcapControlSender> {
@@ -776,6 +1878,66 @@ impl<'a> CaptureStep<'a> {
         }
     }
 
+    /// Creates a [`ControlSession`][controls::synchronous::ControlSession]
+    /// with a channel control reader and a control sender for this capture.
+    ///
+    /// The session is unavailable, making all control calls no-ops, if either
+    /// control pipe is not given on the command line, or if the Wireshark
+    /// version is known to be older than
+    /// [`WiresharkVersion::INTERFACE_TOOLBAR`]. Otherwise, this blocks until
+    /// Wireshark opens its end of the control pipes.
+    #[cfg(feature = "sync")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(interface = self.interface))
+    )]
+    pub fn control_session(&self) -> controls::synchronous::ControlSession {
+        let version = self.context.wireshark_version;
+        if version.is_some_and(|v| v < WiresharkVersion::INTERFACE_TOOLBAR)
+            || self.extcap_control_in.is_none()
+            || self.extcap_control_out.is_none()
+        {
+            return controls::synchronous::ControlSession::unavailable()
+                .with_wireshark_version(version);
+        }
+        controls::synchronous::ControlSession::new(
+            self.spawn_channel_control_reader(),
+            self.new_control_sender(),
+        )
+        .with_wireshark_version(version)
+    }
+
+    /// Spawns a [`ControlHub`][controls::synchronous::ControlHub] that owns
+    /// both control pipes of this capture, or returns `None` if either
+    /// `--extcap-control-in` or `--extcap-control-out` is not given.
+    ///
+    /// `controls` are the toolbar controls of the extcap, used to resolve the
+    /// inbound packets into [`ControlEvent`][controls::ControlEvent]s.
+    #[cfg(feature = "sync")]
+    pub fn spawn_control_hub(
+        &self,
+        controls: &[&dyn ToolbarControl],
+    ) -> Option<controls::synchronous::ControlHub> {
+        Some(controls::synchronous::ControlHub::spawn(
+            self.extcap_control_in.clone()?,
+            self.extcap_control_out.clone()?,
+            controls,
+        ))
+    }
+
+    /// Spawns a [`ControlHub`][controls::asynchronous::ControlHub] that owns
+    /// both control pipes of this capture, or returns `None` if either
+    /// `--extcap-control-in` or `--extcap-control-out` is not given.
+    ///
+    /// This must be called within a Tokio runtime.
+    #[cfg(feature = "async")]
+    pub fn spawn_control_hub_async(&self) -> Option<controls::asynchronous::ControlHub> {
+        Some(controls::asynchronous::ControlHub::spawn(
+            self.extcap_control_in.clone()?,
+            self.extcap_control_out.clone()?,
+        ))
+    }
+
     /// Spawn a new channel control reader, which also spawns a thread to
     /// continuously forward control packets from the input fifo to the reader's
     /// channel.
@@ -817,6 +1979,10 @@ impl<'a> CaptureStep<'a> {
     /// For a higher level, easier to use API, see
     /// [`spawn_channel_control_reader`][Self::spawn_channel_control_reader].
     #[cfg(feature = "sync")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(interface = self.interface))
+    )]
     pub fn new_control_reader(&self) -> Option<controls::synchronous::ExtcapControlReader> {
         self.extcap_control_in
             .as_ref()
@@ -832,6 +1998,10 @@ impl<'a> CaptureStep<'a> {
     /// For a higher level, easier to use API, see
     /// [`spawn_channel_control_reader`][Self::spawn_channel_control_reader].
     #[cfg(feature = "async")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(interface = self.interface))
+    )]
     pub async fn new_control_reader_async(
         &self,
     ) -> Option<controls::asynchronous::ExtcapControlReader> {
@@ -850,80 +2020,265 @@ impl<'a> CaptureStep<'a> {
     }
 }
 
-/// The extcap interface expects certain output "sentences" to stdout to
-/// communicate with Wireshark, like
+/// Creates a [`Metadata`] from information in `Cargo.toml`, using the mapping
+/// as follows:
 ///
-/// ```text
-/// extcap {version=1.0}{help=Some help url}
-/// ```
+/// | Metadata field       | Cargo.toml    | Fallback if not set |
+/// |----------------------|---------------|---------------------|
+/// |`version`             | `version`     |                     |
+/// |`help_url`            | `homepage`    | `repository`        |
+/// |`display_description` | `description` | `name`              |
 ///
-/// This formatter serves as a wrapper to implement that format via the
-/// `Display` trait, and the Extcap output can be printed out like this:
+/// Individual fields can be overridden by passing them to the macro:
 ///
 /// ```
-/// use r_extcap::interface::Metadata;
-/// # use r_extcap::ExtcapFormatter;
+/// use r_extcap::cargo_metadata;
 ///
-/// print!("{}", ExtcapFormatter(&Metadata {
-///     version: "1.0".into(),
-///     help_url: "Some help url".into(),
-///     display_description: "Example extcap".into(),
-/// }));
-/// // Output: extcap {version=1.0}{help=Some help url}{display=Example extcap}
+/// let metadata = cargo_metadata!(
+///     help_url = "https://example.com/help",
+///     display_description = "Example extcap",
+/// );
+/// assert_eq!(metadata.help_url, "https://example.com/help");
+/// assert_eq!(metadata.version, env!("CARGO_PKG_VERSION"));
 /// ```
-pub struct ExtcapFormatter<'a, T: ?Sized>(pub &'a T)
-where
-    Self: Display;
-
-/// Elements that has a printable extcap sentence. See the documentation for
-/// [`ExtcapFormatter`] for details.
-pub trait PrintSentence {
-    /// The extcap interface expects certain output "sentences" to stdout to
-    /// communicate with Wireshark, like
-    ///
-    /// ```text
-    /// extcap {version=1.0}{help=Some help url}
-    /// ```
-    ///
-    /// This function writes to the formatter `f` in that format.
-    fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
-
-    /// Prints the extcap sentence to stdout.
-    fn print_sentence(&self) {
-        print!("{}", ExtcapFormatter(self));
-    }
-}
-
-impl<'a, T: PrintSentence + ?Sized> Display for ExtcapFormatter<'a, T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.format_sentence(f)
-    }
-}
-
-/// Creates a [`Metadata`] from information in `Cargo.toml`, using the mapping
-/// as follows:
-///
-/// | Metadata field       | Cargo.toml    |
-/// |----------------------|---------------|
-/// |`version`             | `version`     |
-/// |`help_url`            | `homepage`    |
-/// |`display_description` | `description` |
+#[cfg(feature = "std")]
 #[macro_export]
 macro_rules! cargo_metadata {
     () => {
         $crate::interface::Metadata {
             version: env!("CARGO_PKG_VERSION").into(),
-            help_url: env!("CARGO_PKG_HOMEPAGE").into(),
-            display_description: env!("CARGO_PKG_DESCRIPTION").into(),
+            help_url: $crate::cargo_metadata!(
+                @or env!("CARGO_PKG_HOMEPAGE"),
+                env!("CARGO_PKG_REPOSITORY")
+            )
+            .into(),
+            display_description: $crate::cargo_metadata!(
+                @or env!("CARGO_PKG_DESCRIPTION"),
+                env!("CARGO_PKG_NAME")
+            )
+            .into(),
+            extra_attributes: ::std::vec::Vec::new(),
+        }
+    };
+    ($($field:ident = $value:expr),+ $(,)?) => {
+        $crate::interface::Metadata {
+            $($field: ($value).into(),)+
+            ..$crate::cargo_metadata!()
         }
     };
+    // Cargo sets the variables of unset manifest keys to empty strings.
+    (@or $value:expr, $fallback:expr) => {{
+        const VALUE: &str = $value;
+        if VALUE.is_empty() {
+            $fallback
+        } else {
+            VALUE
+        }
+    }};
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
-    use clap::Args;
+    use clap::{Args, FromArgMatches};
+    use indoc::indoc;
+
+    use super::{
+        CaptureContext, CaptureError, ConfigStep, DltsStep, ExtcapArgs, ExtcapError, ExtcapStep,
+        InterfacesStep, UnknownInterface, WiresharkVersion,
+    };
+    use crate::{
+        controls::BooleanControl,
+        interface::{DataLink, Dlt, Interface, Metadata},
+        testing::TempFifo,
+    };
+
+    #[test]
+    fn capture_error_from_device_error() {
+        use std::io::{Error, ErrorKind};
+
+        let error = CaptureError::from_device_error("/dev/ttyUSB0", ErrorKind::ResourceBusy.into());
+        assert!(matches!(error, CaptureError::DeviceBusy { device } if device == "/dev/ttyUSB0"));
+        let error =
+            CaptureError::from_device_error("/dev/ttyUSB0", ErrorKind::PermissionDenied.into());
+        let CaptureError::PermissionDenied { hint, .. } = error else {
+            panic!("Unexpected error {error:?}");
+        };
+        if cfg!(target_os = "linux") {
+            assert!(hint.contains("dialout"), "{hint}");
+        }
+        let error = CaptureError::from_device_error("usb0", Error::other("other"));
+        assert!(matches!(error, CaptureError::Io(_)));
+    }
+
+    /// Returns arguments with nothing set, bypassing the `requires` checks of
+    /// clap.
+    fn empty_args() -> ExtcapArgs {
+        ExtcapArgs {
+            extcap_interfaces: false,
+            extcap_version: None,
+            extcap_config: false,
+            extcap_dlts: false,
+            capture: false,
+            extcap_interface: None,
+            fifo: None,
+            extcap_capture_filter: None,
+            extcap_control_in: None,
+            extcap_control_out: None,
+            extcap_reload_option: None,
+        }
+    }
 
-    use super::ExtcapArgs;
+    #[test]
+    fn validate_args() {
+        let with = |update: fn(&mut ExtcapArgs)| {
+            let mut args = empty_args();
+            update(&mut args);
+            args.validate()
+        };
+        assert!(with(|_| {}).is_ok());
+        assert!(with(|a| a.extcap_interfaces = true).is_ok());
+        assert!(with(|a| {
+            a.extcap_config = true;
+            a.extcap_interface = Some("if1".into());
+            a.extcap_reload_option = Some("remote".into());
+        })
+        .is_ok());
+        assert!(matches!(
+            with(|a| {
+                a.extcap_dlts = true;
+                a.capture = true;
+            }),
+            Err(ExtcapError::ConflictingSteps("--extcap-dlts", "--capture"))
+        ));
+        assert!(matches!(
+            with(|a| a.extcap_config = true),
+            Err(ExtcapError::MissingInterfaceForConfig)
+        ));
+        assert!(matches!(
+            with(|a| a.extcap_dlts = true),
+            Err(ExtcapError::MissingInterfaceForDlts)
+        ));
+        assert!(matches!(
+            with(|a| a.extcap_capture_filter = Some("tcp".into())),
+            Err(ExtcapError::MissingInterfaceForFilterValidation)
+        ));
+        assert!(matches!(
+            with(|a| {
+                a.capture = true;
+                a.fifo = Some("/tmp/fifo".into());
+            }),
+            Err(ExtcapError::CaptureError(CaptureError::MissingInterface))
+        ));
+        assert!(matches!(
+            with(|a| {
+                a.capture = true;
+                a.extcap_interface = Some("if1".into());
+            }),
+            Err(ExtcapError::CaptureError(CaptureError::MissingFifo))
+        ));
+        assert!(matches!(
+            with(|a| {
+                a.extcap_interface = Some("if1".into());
+                a.extcap_control_out = Some("/tmp/out".into());
+            }),
+            Err(ExtcapError::RequiresCapture("--extcap-control-out"))
+        ));
+        assert!(matches!(
+            with(|a| {
+                a.extcap_interface = Some("if1".into());
+                a.extcap_reload_option = Some("remote".into());
+            }),
+            Err(ExtcapError::ReloadOptionWithoutConfig)
+        ));
+    }
+
+    #[test]
+    fn run_invalid_args() {
+        let mut args = empty_args();
+        assert!(matches!(args.run(), Err(ExtcapError::NotExtcapInput)));
+        args.extcap_dlts = true;
+        assert!(matches!(
+            args.run(),
+            Err(ExtcapError::MissingInterfaceForDlts)
+        ));
+        args.extcap_interfaces = true;
+        let Err(error) = args.run() else {
+            panic!("Expected conflicting steps");
+        };
+        assert_eq!(
+            error.to_string(),
+            "Cannot combine `--extcap-interfaces` and `--extcap-dlts`"
+        );
+    }
+
+    #[test]
+    fn capture_invocation_step() {
+        let mut args = empty_args();
+        assert_eq!(args.capture_invocation().step, None);
+        args.extcap_interface = Some("if1".into());
+        args.extcap_capture_filter = Some("tcp".into());
+        assert_eq!(
+            args.capture_invocation().step,
+            Some("--extcap-capture-filter")
+        );
+        args.capture = true;
+        assert_eq!(args.capture_invocation().step, Some("--capture"));
+        args.capture = false;
+        args.extcap_config = true;
+        assert_eq!(args.capture_invocation().step, Some("--extcap-config"));
+        args.extcap_reload_option = Some("remote".into());
+        assert_eq!(
+            args.capture_invocation().step,
+            Some("--extcap-reload-option")
+        );
+    }
+
+    #[test]
+    fn validate_interface() {
+        let interface = Interface::builder()
+            .value("if1".into())
+            .display("Interface 1".into())
+            .dlt(
+                Dlt::builder()
+                    .data_link_type(DataLink::USER0)
+                    .name("USER0".into())
+                    .display("User 0".into())
+                    .build(),
+            )
+            .build();
+        let mut args = empty_args();
+        assert!(args.validate_interface(&[&interface]).is_ok());
+        args.extcap_interface = Some("if1".into());
+        assert!(args.validate_interface(&[&interface]).is_ok());
+        args.extcap_interface = Some("if2".into());
+        assert!(matches!(
+            args.validate_interface(&[&interface]),
+            Err(ExtcapError::UnknownInterface(name)) if name == "if2"
+        ));
+    }
+
+    #[test]
+    fn resolve_interface() {
+        let interface1 = Interface::builder()
+            .value("if1".into())
+            .display("Interface 1".into())
+            .dlt(Dlt::user(0, "User 0"))
+            .build();
+        let interface2 = Interface::builder()
+            .value("if2".into())
+            .display("Interface 2".into())
+            .dlt(Dlt::user(1, "User 1"))
+            .build();
+        let interfaces = [&interface1, &interface2];
+        let step = ConfigStep { interface: "if2" };
+        let resolved = step.resolve_interface(&interfaces).unwrap();
+        assert_eq!(resolved.dlt.data_link_type, DataLink::USER1);
+        let step = ConfigStep { interface: "if3" };
+        assert!(matches!(
+            step.resolve_interface(&interfaces),
+            Err(UnknownInterface(name)) if name == "if3"
+        ));
+    }
 
     #[test]
     fn assert_args() {
@@ -931,4 +2286,286 @@ mod test {
         let augmented_cmd = ExtcapArgs::augment_args(cmd);
         augmented_cmd.debug_assert();
     }
+
+    #[test]
+    fn capture_context() {
+        let fifo = TempFifo::new("capture-context").unwrap();
+        let args = ExtcapArgs::from_arg_matches(
+            &ExtcapArgs::augment_args(clap::Command::new("test")).get_matches_from([
+                "test",
+                "--capture",
+                "--extcap-interface",
+                "if1",
+                "--fifo",
+                fifo.path().to_str().unwrap(),
+                "--extcap-capture-filter",
+                "tcp port 80",
+                "--extcap-version",
+                "4.2",
+            ]),
+        )
+        .unwrap();
+        let Ok(ExtcapStep::Capture(capture_step)) = args.run_with_lazy_fifo() else {
+            panic!("Expected capture step");
+        };
+        assert_eq!(
+            capture_step.context(),
+            &CaptureContext {
+                interface: "if1".into(),
+                capture_filter: Some("tcp port 80".into()),
+                fifo_path: fifo.path().to_owned(),
+                extcap_control_in: None,
+                extcap_control_out: None,
+                wireshark_version: Some(WiresharkVersion::new(4, 2)),
+            }
+        );
+        std::thread::scope(|s| {
+            let reader = s.spawn(|| fifo.open_reader().unwrap());
+            drop(capture_step);
+            reader.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn lazy_fifo() {
+        let fifo = TempFifo::new("lazy-fifo").unwrap();
+        let args = ExtcapArgs::from_arg_matches(
+            &ExtcapArgs::augment_args(clap::Command::new("test")).get_matches_from([
+                "test",
+                "--capture",
+                "--extcap-interface",
+                "if1",
+                "--fifo",
+                fifo.path().to_str().unwrap(),
+            ]),
+        )
+        .unwrap();
+        let Ok(ExtcapStep::Capture(mut capture_step)) = args.run_with_lazy_fifo() else {
+            panic!("Expected capture step");
+        };
+        assert!(!capture_step.fifo().is_open());
+        std::thread::scope(|s| {
+            let reader = s.spawn(|| fifo.open_reader().unwrap());
+            capture_step.open_fifo().unwrap();
+            reader.join().unwrap();
+        });
+        let taken = capture_step.take_fifo();
+        assert!(taken.is_open());
+        // The fifo can only be taken once.
+        let mut placeholder = capture_step.take_fifo();
+        assert!(std::io::Write::write_all(&mut placeholder, b"packet").is_err());
+    }
+
+    #[test]
+    fn pcap_writer_for() {
+        let fifo = TempFifo::new("pcap-writer-for").unwrap();
+        let args = ExtcapArgs::from_arg_matches(
+            &ExtcapArgs::augment_args(clap::Command::new("test")).get_matches_from([
+                "test",
+                "--capture",
+                "--extcap-interface",
+                "if1",
+                "--fifo",
+                fifo.path().to_str().unwrap(),
+            ]),
+        )
+        .unwrap();
+        let Ok(ExtcapStep::Capture(mut capture_step)) = args.run_with_lazy_fifo() else {
+            panic!("Expected capture step");
+        };
+        let interface = Interface::builder()
+            .value("if1".into())
+            .display("Interface 1".into())
+            .dlt(Dlt::user(0, "Example"))
+            .build();
+        let capture = std::thread::scope(|s| {
+            let reader = s.spawn(|| fifo.read_capture().unwrap());
+            drop(capture_step.pcap_writer_for(&interface).unwrap());
+            drop(capture_step);
+            reader.join().unwrap()
+        });
+        assert_eq!(capture.datalinks, [DataLink::USER0]);
+    }
+
+    #[test]
+    fn multiple_dlts() {
+        let dlt = |data_link_type, name: &'static str| Dlt {
+            data_link_type,
+            name: name.into(),
+            display: name.into(),
+        };
+        let interface = Interface::builder()
+            .value("if1".into())
+            .display("Interface 1".into())
+            .dlt(dlt(DataLink::ETHERNET, "EN10MB"))
+            .extra_dlts(vec![dlt(DataLink::USER0, "USER0")])
+            .build();
+        let names = |wireshark_version| {
+            DltsStep {
+                interface: "if1",
+                wireshark_version,
+            }
+            .dlts_to_print(&interface)
+            .map(|dlt| dlt.name.as_ref())
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(names(None), ["EN10MB", "USER0"]);
+        assert_eq!(
+            names(Some(WiresharkVersion::new(4, 2))),
+            ["EN10MB", "USER0"]
+        );
+        assert_eq!(names(Some(WiresharkVersion::new(2, 6))), ["EN10MB"]);
+    }
+
+    #[test]
+    fn cargo_metadata_fallback() {
+        // This crate sets `repository` but not `homepage`.
+        let metadata = crate::cargo_metadata!();
+        assert_eq!(metadata.help_url, env!("CARGO_PKG_REPOSITORY"));
+        assert_eq!(metadata.display_description, env!("CARGO_PKG_DESCRIPTION"));
+    }
+
+    #[test]
+    fn write_interfaces() {
+        let metadata = Metadata {
+            version: "1.0".into(),
+            help_url: "http://www.wireshark.org".into(),
+            display_description: "Example".into(),
+            extra_attributes: vec![],
+        };
+        let interfaces: Vec<Interface> = (0..3)
+            .map(|i| {
+                Interface::builder()
+                    .value(format!("if{i}").into())
+                    .display(format!("Interface {i}").into())
+                    .dlt(
+                        Dlt::builder()
+                            .data_link_type(DataLink::USER0)
+                            .name("USER0".into())
+                            .display("User 0".into())
+                            .build(),
+                    )
+                    .build()
+            })
+            .collect();
+        let control = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .build();
+        let mut output = Vec::new();
+        InterfacesStep
+            .write_interfaces(
+                &mut output,
+                &metadata,
+                &interfaces.iter().collect::<Vec<_>>(),
+                &[&control],
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            indoc! {"
+                extcap {version=1.0}{help=http://www.wireshark.org}{display=Example}
+                interface {value=if0}{display=Interface 0}
+                interface {value=if1}{display=Interface 1}
+                interface {value=if2}{display=Interface 2}
+                control {number=0}{type=boolean}{display=Verify}{default=false}
+            "}
+        );
+    }
+
+    #[test]
+    fn write_interfaces_for_version() {
+        struct NewControl;
+
+        impl crate::PrintSentence for NewControl {
+            fn format_sentence(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                writeln!(f, "control {{number=1}}{{type=button}}{{display=New}}")
+            }
+        }
+
+        impl crate::controls::ToolbarControl for NewControl {
+            fn control_number(&self) -> u8 {
+                1
+            }
+
+            fn min_wireshark_version(&self) -> WiresharkVersion {
+                WiresharkVersion::new(4, 4)
+            }
+        }
+
+        let metadata = crate::cargo_metadata!();
+        let control = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .build();
+        let write = |version: Option<WiresharkVersion>| {
+            let mut output = Vec::new();
+            InterfacesStep
+                .write_interfaces_for_version(
+                    &mut output,
+                    version,
+                    &metadata,
+                    &[],
+                    &[&control, &NewControl],
+                )
+                .unwrap();
+            String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .filter(|line| line.starts_with("control "))
+                .count()
+        };
+        assert_eq!(write(None), 2);
+        assert_eq!(write(Some(WiresharkVersion::new(4, 4))), 2);
+        assert_eq!(write(Some(WiresharkVersion::new(4, 2))), 1);
+        assert_eq!(write(Some(WiresharkVersion::new(2, 4))), 0);
+    }
+
+    #[test]
+    fn interfaces_warning() {
+        let mut output = Vec::new();
+        InterfacesStep
+            .write_warning(
+                &mut output,
+                "sniffer",
+                "\x1b[1mFound 0 devices\x1b[0m\nIs the driver installed?",
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            indoc! {"
+                sniffer: warning: Found 0 devices
+                sniffer: warning: Is the driver installed?
+            "}
+        );
+    }
+
+    #[test]
+    fn write_step_output() {
+        use std::io::{ErrorKind, Write};
+
+        struct ClosedPipe;
+
+        impl Write for ClosedPipe {
+            fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+                Err(ErrorKind::BrokenPipe.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Err(ErrorKind::BrokenPipe.into())
+            }
+        }
+
+        super::write_step_output(&mut ClosedPipe, |writer| writeln!(writer, "value")).unwrap();
+
+        let mut output = Vec::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            super::write_step_output(&mut output, |writer| {
+                writeln!(writer, "partial")?;
+                panic!("Failed in the middle of the step");
+            })
+        }));
+        assert!(result.is_err());
+        assert!(output.is_empty());
+    }
 }
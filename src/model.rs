@@ -0,0 +1,183 @@
+//! Dumps the declared interfaces, configs and toolbar controls of an extcap
+//! program as JSON.
+//!
+//! Wireshark only sees the model of an extcap program through the sentences
+//! printed in each step. External tools, like linters, documentation
+//! generators, or previews of the config dialog, can use
+//! [`write_model_json`] to inspect the same model in a structured format.
+//!
+//! A typical extcap program exposes this through an additional command line
+//! flag:
+//!
+//! ```no_run
+//! use clap::Parser;
+//! use r_extcap::{cargo_metadata, model::write_model_json, ExtcapArgs};
+//! # use r_extcap::{config::ConfigTrait, controls::ToolbarControl, interface::Interface};
+//! # let interfaces: Vec<&Interface> = vec![];
+//! # let configs: Vec<&dyn ConfigTrait> = vec![];
+//! # let controls: Vec<&dyn ToolbarControl> = vec![];
+//!
+//! #[derive(Debug, Parser)]
+//! struct AppArgs {
+//!     #[command(flatten)]
+//!     extcap: ExtcapArgs,
+//!
+//!     /// Print the declared model as JSON.
+//!     #[arg(long)]
+//!     dump_model: bool,
+//! }
+//!
+//! let args = AppArgs::parse();
+//! if args.dump_model {
+//!     write_model_json(
+//!         std::io::stdout(),
+//!         &cargo_metadata!(),
+//!         &interfaces,
+//!         &configs,
+//!         &controls,
+//!     )
+//!     .unwrap();
+//!     return;
+//! }
+//! ```
+
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::{
+    config::ConfigTrait, controls::ToolbarControl, interface::Interface, interface::Metadata,
+};
+
+#[derive(Serialize)]
+struct Model<'a> {
+    metadata: &'a Metadata,
+    interfaces: &'a [&'a Interface],
+    configs: Vec<serde_json::Value>,
+    controls: Vec<serde_json::Value>,
+}
+
+/// Writes the metadata, interfaces, configs and toolbar controls of this
+/// extcap program to `writer` as a pretty-printed JSON object, with the keys
+/// `metadata`, `interfaces`, `configs` and `controls`.
+///
+/// The parameters are the same as the ones passed to
+/// [`InterfacesStep::list_interfaces`][crate::InterfacesStep::list_interfaces]
+/// and [`ConfigStep::list_configs`][crate::ConfigStep::list_configs]. Configs
+/// and controls are serialized using [`ConfigTrait::to_json`] and
+/// [`ToolbarControl::to_json`], which include a `type` key with the name of the
+/// config or control type.
+pub fn write_model_json(
+    writer: impl Write,
+    metadata: &Metadata,
+    interfaces: &[&Interface],
+    configs: &[&dyn ConfigTrait],
+    controls: &[&dyn ToolbarControl],
+) -> serde_json::Result<()> {
+    let model = Model {
+        metadata,
+        interfaces,
+        configs: configs.iter().map(|config| config.to_json()).collect(),
+        controls: controls.iter().map(|control| control.to_json()).collect(),
+    };
+    serde_json::to_writer_pretty(writer, &model)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::write_model_json;
+    use crate::{
+        config::{ConfigOptionValue, IntegerConfig, SelectorConfig},
+        controls::ButtonControl,
+        interface::{DataLink, Dlt, Interface, Metadata},
+    };
+
+    #[test]
+    fn model_json() {
+        let metadata = Metadata {
+            version: "1.0".into(),
+            help_url: "http://www.wireshark.org".into(),
+            display_description: "Example extcap".into(),
+            extra_attributes: vec![],
+        };
+        let interface = Interface::builder()
+            .value("rs-example1".into())
+            .display("Example interface".into())
+            .dlt(Dlt {
+                data_link_type: DataLink::ETHERNET,
+                name: "ETHERNET".into(),
+                display: "Ethernet".into(),
+            })
+            .build();
+        let delay = IntegerConfig::builder()
+            .config_number(0)
+            .call("delay")
+            .display("Delay")
+            .range(1..=15)
+            .default_value(5)
+            .build();
+        let remote = SelectorConfig::builder()
+            .config_number(1)
+            .call("remote")
+            .display("Remote")
+            .default_options([ConfigOptionValue::builder()
+                .value("if1")
+                .display("Remote1")
+                .default(true)
+                .build()])
+            .build();
+        let button = ButtonControl::builder()
+            .control_number(0)
+            .display("Turn on")
+            .build();
+
+        let mut output = Vec::new();
+        write_model_json(
+            &mut output,
+            &metadata,
+            &[&interface],
+            &[&delay, &remote],
+            &[&button],
+        )
+        .unwrap();
+        let model: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            model["metadata"],
+            json!({
+                "version": "1.0",
+                "help_url": "http://www.wireshark.org",
+                "display_description": "Example extcap",
+                "extra_attributes": [],
+            })
+        );
+        assert_eq!(
+            model["interfaces"][0],
+            json!({
+                "value": "rs-example1",
+                "display": "Example interface",
+                "dlt": { "data_link_type": 1, "name": "ETHERNET", "display": "Ethernet" },
+                "extra_dlts": [],
+                "extra_attributes": [],
+            })
+        );
+        assert_eq!(model["configs"][0]["type"], "IntegerConfig");
+        assert_eq!(
+            model["configs"][0]["range"],
+            json!({ "start": 1, "end": 15 })
+        );
+        assert_eq!(model["configs"][0]["default_value"], 5);
+        assert_eq!(model["configs"][1]["type"], "SelectorConfig");
+        assert_eq!(model["configs"][1]["default_options"][0]["value"], "if1");
+        assert_eq!(
+            model["controls"][0],
+            json!({
+                "type": "ButtonControl",
+                "control_number": 0,
+                "display": "Turn on",
+                "tooltip": null,
+            })
+        );
+    }
+}
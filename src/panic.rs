@@ -0,0 +1,126 @@
+//! Reports panics to the user in Wireshark, instead of ending the capture
+//! silently.
+//!
+//! When an extcap exits with a non-zero status, Wireshark shows what it wrote
+//! to stderr in an error dialog. The default panic message is written to
+//! stderr too, but with a thread name and source location that mean little to
+//! end users. [`install_hook`] replaces it with a concise
+//! `extcap crashed: <reason>` message and exits the process with
+//! [`PANIC_EXIT_CODE`], even if the panic happens in a background thread.
+//!
+//! During a capture, [`CaptureStep::install_panic_hook`] additionally shows
+//! the message in an error dialog through the control pipe, if Wireshark
+//! passed one.
+//!
+//! [`CaptureStep::install_panic_hook`]: crate::CaptureStep::install_panic_hook
+
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    io::Write,
+    panic::PanicHookInfo,
+    path::PathBuf,
+};
+
+/// The exit code of the process after a panic. This is `EX_SOFTWARE` from
+/// `sysexits.h`, to distinguish crashes from the errors returned by `main`.
+pub const PANIC_EXIT_CODE: i32 = 70;
+
+/// Installs a panic hook that writes a concise message to stderr and exits
+/// the process with [`PANIC_EXIT_CODE`].
+///
+/// The backtrace is included if it is enabled with `RUST_BACKTRACE`. Otherwise
+/// the message points to that variable, so that the crash can be reproduced
+/// with more details.
+///
+/// Because the process exits, panics cannot be caught with
+/// [`std::panic::catch_unwind`] after this is installed.
+pub fn install_hook() {
+    install_hook_with_control_out(None);
+}
+
+/// Same as [`install_hook`], but also sends the message as an
+/// [`ErrorMessage`][crate::controls::ControlCommand::ErrorMessage] control
+/// packet to `control_out`, the path given in `--extcap-control-out`, so that
+/// it is shown in an error dialog while Wireshark is still running the
+/// capture.
+pub fn install_hook_with_control_out(control_out: Option<PathBuf>) {
+    std::panic::set_hook(Box::new(move |info| {
+        let message = crash_message(info);
+        let backtrace = Backtrace::capture();
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(stderr, "{message}");
+        if backtrace.status() == BacktraceStatus::Captured {
+            let _ = writeln!(stderr, "{backtrace}");
+        } else {
+            let _ = writeln!(
+                stderr,
+                "Run with the environment variable RUST_BACKTRACE=1 to show a backtrace."
+            );
+        }
+        #[cfg(feature = "sync")]
+        if let Some(control_out) = &control_out {
+            let _ = send_error_message(control_out, &message);
+        }
+        #[cfg(not(feature = "sync"))]
+        let _ = &control_out;
+        std::process::exit(PANIC_EXIT_CODE);
+    }));
+}
+
+fn crash_message(info: &PanicHookInfo<'_>) -> String {
+    let payload = info.payload();
+    let reason = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    match info.location() {
+        Some(location) => format!(
+            "extcap crashed: {reason} (at {}:{})",
+            location.file(),
+            location.line()
+        ),
+        None => format!("extcap crashed: {reason}"),
+    }
+}
+
+#[cfg(feature = "sync")]
+fn send_error_message(control_out: &std::path::Path, message: &str) -> std::io::Result<()> {
+    use crate::controls::synchronous::{ExtcapControlSender, ExtcapControlSenderTrait as _};
+
+    ExtcapControlSender::reopen(control_out)?.error_message(message)
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::{install_hook, PANIC_EXIT_CODE};
+
+    /// Runs the panicking part of the test in a child process, since the hook
+    /// exits the process.
+    #[test]
+    fn reports_panic_and_exits() {
+        if std::env::var_os("R_EXTCAP_PANIC_TEST").is_some() {
+            install_hook();
+            panic!("device unplugged");
+        }
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "panic::test::reports_panic_and_exits",
+                "--nocapture",
+            ])
+            .env("R_EXTCAP_PANIC_TEST", "1")
+            .env_remove("RUST_BACKTRACE")
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(PANIC_EXIT_CODE));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("extcap crashed: device unplugged (at src/panic.rs:"),
+            "{stderr}"
+        );
+        assert!(stderr.contains("RUST_BACKTRACE=1"), "{stderr}");
+    }
+}
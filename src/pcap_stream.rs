@@ -0,0 +1,318 @@
+//! Utilities for forwarding an existing stream of pcap or pcapng data into the
+//! capture fifo.
+//!
+//! This is useful when the packets are already captured and serialized by
+//! another program, for example `tcpdump -w -` running on a remote host or a
+//! vendor sniffer tool that writes pcap to its stdout. Instead of copying the
+//! bytes blindly, [`forward_pcap_stream`] checks the framing of each record, so
+//! that a corrupted or truncated stream results in an error instead of
+//! confusing Wireshark.
+
+use std::io::{Read, Write};
+use thiserror::Error;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+/// The maximum length of a single pcap record or pcapng block accepted by
+/// [`forward_pcap_stream`]. Anything larger is almost certainly the result of a
+/// corrupted stream.
+pub const MAX_RECORD_LENGTH: u32 = 16 * 1024 * 1024;
+
+/// Error forwarding a pcap or pcapng stream.
+#[derive(Debug, Error)]
+pub enum PcapStreamError {
+    /// Error reading from the input stream or writing to the output.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// The stream does not start with a pcap magic number or a pcapng section
+    /// header block.
+    #[error("Unrecognized capture format magic number {0:#010x}")]
+    UnknownMagic(u32),
+
+    /// A record or block in the stream has a length that is not valid for the
+    /// format. This typically means the stream is corrupted.
+    #[error("Invalid record length {length} at byte offset {offset}")]
+    InvalidRecordLength {
+        /// The length value read from the stream.
+        length: u32,
+        /// The byte offset of the record in the input stream.
+        offset: u64,
+    },
+}
+
+/// The format of a capture stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// The classic libpcap format, with either microsecond or nanosecond
+    /// timestamps.
+    Pcap,
+    /// The pcapng format.
+    PcapNg,
+}
+
+impl CaptureFormat {
+    /// Detects the capture format from the first 4 bytes of the stream.
+    /// Returns `None` if the bytes do not match a known format.
+    ///
+    /// ```
+    /// use r_extcap::pcap_stream::CaptureFormat;
+    ///
+    /// assert_eq!(CaptureFormat::detect([0xa1, 0xb2, 0xc3, 0xd4]), Some(CaptureFormat::Pcap));
+    /// assert_eq!(CaptureFormat::detect([0x0a, 0x0d, 0x0d, 0x0a]), Some(CaptureFormat::PcapNg));
+    /// assert_eq!(CaptureFormat::detect(*b"GET "), None);
+    /// ```
+    pub fn detect(magic: [u8; 4]) -> Option<Self> {
+        if magic == PCAPNG_SECTION_HEADER {
+            return Some(CaptureFormat::PcapNg);
+        }
+        match (u32::from_be_bytes(magic), u32::from_le_bytes(magic)) {
+            (PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS, _)
+            | (_, PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS) => Some(CaptureFormat::Pcap),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a pcap or pcapng stream from `reader` and writes it to `writer`,
+/// validating the framing of every record along the way. The writer is flushed
+/// after each record so that packets show up in Wireshark as soon as they are
+/// read.
+///
+/// Returns the number of records forwarded (packet records for pcap, blocks for
+/// pcapng) when `reader` reaches the end of the stream at a record boundary.
+///
+/// ```
+/// use r_extcap::pcap_stream::forward_pcap_stream;
+///
+/// let mut input = Vec::new();
+/// // Global header: magic, version 2.4, tz, sigfigs, snaplen, linktype
+/// input.extend_from_slice(&0xa1b2c3d4_u32.to_be_bytes());
+/// input.extend_from_slice(&[0, 2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 0, 1]);
+/// // One packet record with 3 bytes of data
+/// input.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 3, 0xaa, 0xbb, 0xcc]);
+///
+/// let mut output = Vec::new();
+/// assert_eq!(forward_pcap_stream(&input[..], &mut output).unwrap(), 1);
+/// assert_eq!(input, output);
+/// ```
+pub fn forward_pcap_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+) -> Result<u64, PcapStreamError> {
+    let mut magic = [0_u8; 4];
+    reader.read_exact(&mut magic)?;
+    match CaptureFormat::detect(magic) {
+        Some(CaptureFormat::Pcap) => forward_pcap(&mut reader, &mut writer, magic),
+        Some(CaptureFormat::PcapNg) => forward_pcapng(&mut reader, &mut writer),
+        None => Err(PcapStreamError::UnknownMagic(u32::from_be_bytes(magic))),
+    }
+}
+
+fn forward_pcap<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    magic: [u8; 4],
+) -> Result<u64, PcapStreamError> {
+    let big_endian = matches!(
+        u32::from_be_bytes(magic),
+        PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS
+    );
+    let mut header = [0_u8; 24];
+    header[..4].copy_from_slice(&magic);
+    reader.read_exact(&mut header[4..])?;
+    writer.write_all(&header)?;
+    writer.flush()?;
+
+    let mut offset = header.len() as u64;
+    let mut count = 0;
+    let mut record_header = [0_u8; 16];
+    let mut data = Vec::new();
+    while try_read_exact(reader, &mut record_header)? {
+        let length = read_u32(&record_header[8..12], big_endian);
+        if length > MAX_RECORD_LENGTH {
+            return Err(PcapStreamError::InvalidRecordLength { length, offset });
+        }
+        data.resize(length as usize, 0);
+        reader.read_exact(&mut data)?;
+        writer.write_all(&record_header)?;
+        writer.write_all(&data)?;
+        writer.flush()?;
+        offset += (record_header.len() + data.len()) as u64;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn forward_pcapng<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64, PcapStreamError> {
+    let mut block_type = PCAPNG_SECTION_HEADER;
+    let mut big_endian = false;
+    let mut offset = 0_u64;
+    let mut count = 0;
+    let mut body = Vec::new();
+    loop {
+        let mut length_bytes = [0_u8; 4];
+        reader.read_exact(&mut length_bytes)?;
+        body.clear();
+        let is_section_header = block_type == PCAPNG_SECTION_HEADER;
+        if is_section_header {
+            // Each section header block can change the endianness of the
+            // blocks that follow it.
+            let mut byte_order_magic = [0_u8; 4];
+            reader.read_exact(&mut byte_order_magic)?;
+            big_endian = match (
+                u32::from_be_bytes(byte_order_magic),
+                u32::from_le_bytes(byte_order_magic),
+            ) {
+                (PCAPNG_BYTE_ORDER_MAGIC, _) => true,
+                (_, PCAPNG_BYTE_ORDER_MAGIC) => false,
+                (magic, _) => return Err(PcapStreamError::UnknownMagic(magic)),
+            };
+            body.extend_from_slice(&byte_order_magic);
+        }
+        let length = read_u32(&length_bytes, big_endian);
+        let min_length = if is_section_header { 28 } else { 12 };
+        if length < min_length || length & 0b11 != 0 || length > MAX_RECORD_LENGTH {
+            return Err(PcapStreamError::InvalidRecordLength { length, offset });
+        }
+        let already_read = body.len();
+        body.resize(length as usize - 8, 0);
+        reader.read_exact(&mut body[already_read..])?;
+        let trailing_length = read_u32(&body[body.len() - 4..], big_endian);
+        if trailing_length != length {
+            return Err(PcapStreamError::InvalidRecordLength {
+                length: trailing_length,
+                offset: offset + u64::from(length) - 4,
+            });
+        }
+        writer.write_all(&block_type)?;
+        writer.write_all(&length_bytes)?;
+        writer.write_all(&body)?;
+        writer.flush()?;
+        offset += u64::from(length);
+        count += 1;
+        if !try_read_exact(reader, &mut block_type)? {
+            return Ok(count);
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], big_endian: bool) -> u32 {
+    let bytes: [u8; 4] = bytes[..4].try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Fills `buf` completely, or returns `Ok(false)` if the reader is at EOF
+/// before any bytes are read. EOF in the middle of `buf` is an
+/// `UnexpectedEof` error.
+fn try_read_exact<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<bool> {
+    let mut count = 0;
+    while count < buf.len() {
+        match reader.read(&mut buf[count..]) {
+            Ok(0) if count == 0 => return Ok(false),
+            Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => count += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{forward_pcap_stream, PcapStreamError};
+
+    fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (body.len() + 12) as u32;
+        let mut block = Vec::new();
+        block.extend_from_slice(&block_type.to_le_bytes());
+        block.extend_from_slice(&length.to_le_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&length.to_le_bytes());
+        block
+    }
+
+    fn pcapng_section_header() -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0x1a2b3c4d_u32.to_le_bytes());
+        body.extend_from_slice(&1_u16.to_le_bytes());
+        body.extend_from_slice(&0_u16.to_le_bytes());
+        body.extend_from_slice(&(-1_i64).to_le_bytes());
+        pcapng_block(0x0a0d0d0a, &body)
+    }
+
+    #[test]
+    fn forward_pcapng() {
+        let mut input = pcapng_section_header();
+        input.extend(pcapng_block(1, &[1, 0, 0, 0, 0, 0, 0, 0]));
+        input.extend(pcapng_block(6, &[0; 24]));
+        let mut output = Vec::new();
+        assert_eq!(forward_pcap_stream(&input[..], &mut output).unwrap(), 3);
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn forward_pcapng_mismatched_trailer() {
+        let mut input = pcapng_section_header();
+        let mut block = pcapng_block(1, &[1, 0, 0, 0, 0, 0, 0, 0]);
+        let len = block.len();
+        block[len - 4] = 0xff;
+        input.extend(block);
+        let mut output = Vec::new();
+        assert!(matches!(
+            forward_pcap_stream(&input[..], &mut output),
+            Err(PcapStreamError::InvalidRecordLength { .. })
+        ));
+    }
+
+    #[test]
+    fn forward_pcap_little_endian_nanos() {
+        let mut input = Vec::new();
+        input.extend_from_slice(&0xa1b23c4d_u32.to_le_bytes());
+        input.extend_from_slice(&[
+            2, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0, 0, 1, 0, 0, 0,
+        ]);
+        for _ in 0..2 {
+            input.extend_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 0xaa, 0xbb]);
+        }
+        let mut output = Vec::new();
+        assert_eq!(forward_pcap_stream(&input[..], &mut output).unwrap(), 2);
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn forward_pcap_truncated() {
+        let mut input = Vec::new();
+        input.extend_from_slice(&0xa1b2c3d4_u32.to_be_bytes());
+        input.extend_from_slice(&[0; 20]);
+        input.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 8, 0, 0, 0, 8, 0xaa]);
+        let mut output = Vec::new();
+        match forward_pcap_stream(&input[..], &mut output) {
+            Err(PcapStreamError::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof)
+            }
+            other => panic!("Unexpected result {other:?}"),
+        }
+    }
+
+    #[test]
+    fn forward_unknown_magic() {
+        let mut output = Vec::new();
+        assert!(matches!(
+            forward_pcap_stream(&b"HTTP/1.1 200 OK"[..], &mut output),
+            Err(PcapStreamError::UnknownMagic(0x48545450))
+        ));
+        assert!(output.is_empty());
+    }
+}
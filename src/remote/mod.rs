@@ -0,0 +1,8 @@
+//! Building blocks for extcaps that capture packets on a remote machine, like
+//! Wireshark's own `sshdump`.
+//!
+//! Each submodule is gated behind its own cargo feature, so that the
+//! dependencies of the transport are only pulled in when needed.
+
+#[cfg(feature = "ssh")]
+pub mod ssh;
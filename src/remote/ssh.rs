@@ -0,0 +1,610 @@
+//! Capture packets on a remote host over SSH, similar to Wireshark's `sshdump`.
+//!
+//! This module provides the pieces needed to implement an `sshdump`-like
+//! extcap:
+//!
+//! 1. [`SshArgs`], a set of command line arguments that can be flattened into
+//!    the application's `clap` parser.
+//! 2. [`SshConfigs`], the matching set of [configs][crate::config] that lets
+//!    the user fill in the arguments from the Wireshark UI.
+//! 3. [`SshCapture`], which connects to the remote host, runs a capture command
+//!    (`tcpdump` by default) and forwards its output to the capture fifo.
+//!
+//! This module is only available with the `ssh` feature.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! use clap::Parser;
+//! use r_extcap::{ExtcapArgs, ExtcapStep};
+//! use r_extcap::remote::ssh::{SshArgs, SshCapture, SshConfigs};
+//!
+//! #[derive(Debug, Parser)]
+//! struct AppArgs {
+//!     #[command(flatten)]
+//!     extcap: ExtcapArgs,
+//!
+//!     #[command(flatten)]
+//!     ssh: SshArgs,
+//! }
+//!
+//! let args = AppArgs::parse();
+//! match args.extcap.run().unwrap() {
+//!     ExtcapStep::Config(config_step) => {
+//!         config_step.list_configs(&SshConfigs::new(0).unwrap().configs());
+//!     }
+//!     ExtcapStep::Capture(mut capture_step) => {
+//!         let command = args
+//!             .ssh
+//!             .remote_capture_command(args.extcap.extcap_capture_filter.as_deref());
+//!         let capture = SshCapture::connect(&args.ssh).unwrap();
+//!         capture.run(&command, capture_step.take_fifo()).unwrap();
+//!     }
+//!     // ...
+//!     # _ => {}
+//! }
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+};
+
+use clap::Args;
+use ssh2::{Session, Stream};
+use thiserror::Error;
+
+use crate::config::{
+    BooleanConfig, ConfigTrait, FileSelectConfig, PasswordConfig, StringConfig, UnsignedConfig,
+};
+use crate::pcap_stream::{forward_pcap_stream, PcapStreamError};
+use crate::Secret;
+
+/// Command line arguments for connecting to a remote host over SSH. The names
+/// of the arguments follow the ones used by Wireshark's `sshdump`, and match
+/// the calls in [`SshConfigs`].
+#[derive(Debug, Args)]
+pub struct SshArgs {
+    /// The address of the remote host to capture on.
+    #[arg(long)]
+    pub remote_host: Option<String>,
+
+    /// The SSH port of the remote host.
+    #[arg(long, default_value_t = 22)]
+    pub remote_port: u16,
+
+    /// The username to log in to the remote host with.
+    #[arg(long)]
+    pub remote_username: Option<String>,
+
+    /// The password to log in to the remote host with. If neither this nor
+    /// [`sshkey`][Self::sshkey] is provided, authentication is attempted using
+    /// the SSH agent.
+    #[arg(long)]
+    pub remote_password: Option<Secret<String>>,
+
+    /// The path to the private key used to log in to the remote host.
+    #[arg(long)]
+    pub sshkey: Option<PathBuf>,
+
+    /// The passphrase for [`sshkey`][Self::sshkey], if the key is encrypted.
+    #[arg(long)]
+    pub sshkey_passphrase: Option<Secret<String>>,
+
+    /// The network interface on the remote host to capture from. If not
+    /// provided, the default interface chosen by `tcpdump` is used.
+    #[arg(long)]
+    pub remote_interface: Option<String>,
+
+    /// A custom command to run on the remote host instead of `tcpdump`. The
+    /// command must write a pcap or pcapng stream to its stdout.
+    #[arg(long)]
+    pub remote_capture_command: Option<String>,
+
+    /// Whether to run the capture command with `sudo`.
+    #[arg(long)]
+    pub remote_sudo: bool,
+
+    /// Whether to disable promiscuous mode on the remote interface.
+    #[arg(long)]
+    pub remote_noprom: bool,
+}
+
+impl SshArgs {
+    /// Returns the command to run on the remote host.
+    ///
+    /// If [`remote_capture_command`][Self::remote_capture_command] is set, it
+    /// is returned as-is. Otherwise a `tcpdump` command is built from the other
+    /// arguments and the given `capture_filter`, which is typically
+    /// [`ExtcapArgs::extcap_capture_filter`][crate::ExtcapArgs::extcap_capture_filter].
+    ///
+    /// ```
+    /// use clap::Parser;
+    /// use r_extcap::remote::ssh::SshArgs;
+    ///
+    /// #[derive(Parser)]
+    /// struct AppArgs {
+    ///     #[command(flatten)]
+    ///     ssh: SshArgs,
+    /// }
+    ///
+    /// let args = AppArgs::parse_from([
+    ///     "sshdump", "--remote-interface", "eth0", "--remote-sudo",
+    /// ]);
+    /// assert_eq!(
+    ///     args.ssh.remote_capture_command(Some("port 80")),
+    ///     "sudo tcpdump -U -i 'eth0' -w - 'port 80'"
+    /// );
+    /// ```
+    pub fn remote_capture_command(&self, capture_filter: Option<&str>) -> String {
+        if let Some(command) = &self.remote_capture_command {
+            return command.clone();
+        }
+        let mut command = String::new();
+        if self.remote_sudo {
+            command.push_str("sudo ");
+        }
+        command.push_str("tcpdump -U");
+        if let Some(interface) = &self.remote_interface {
+            command.push_str(" -i ");
+            command.push_str(&shell_quote(interface));
+        }
+        command.push_str(" -w -");
+        if self.remote_noprom {
+            command.push_str(" -p");
+        }
+        if let Some(filter) = capture_filter.filter(|f| !f.is_empty()) {
+            command.push(' ');
+            command.push_str(&shell_quote(filter));
+        }
+        command
+    }
+}
+
+/// Quotes `s` so that it is passed as a single argument by a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// The configs corresponding to the arguments in [`SshArgs`], which allows the
+/// user to configure the SSH connection in the Wireshark UI. The configs are
+/// placed in the "Server", "Authentication" and "Capture" groups.
+#[derive(Debug)]
+pub struct SshConfigs {
+    /// Config for [`SshArgs::remote_host`].
+    pub remote_host: StringConfig,
+    /// Config for [`SshArgs::remote_port`].
+    pub remote_port: UnsignedConfig,
+    /// Config for [`SshArgs::remote_username`].
+    pub remote_username: StringConfig,
+    /// Config for [`SshArgs::remote_password`].
+    pub remote_password: PasswordConfig,
+    /// Config for [`SshArgs::sshkey`].
+    pub sshkey: FileSelectConfig,
+    /// Config for [`SshArgs::sshkey_passphrase`].
+    pub sshkey_passphrase: PasswordConfig,
+    /// Config for [`SshArgs::remote_interface`].
+    pub remote_interface: StringConfig,
+    /// Config for [`SshArgs::remote_capture_command`].
+    pub remote_capture_command: StringConfig,
+    /// Config for [`SshArgs::remote_sudo`].
+    pub remote_sudo: BooleanConfig,
+    /// Config for [`SshArgs::remote_noprom`].
+    pub remote_noprom: BooleanConfig,
+}
+
+impl SshConfigs {
+    /// The number of configs in this bundle. The configs use the config
+    /// numbers `first_config_number..first_config_number + COUNT`.
+    pub const COUNT: u8 = 10;
+
+    /// Creates the SSH configs, numbered consecutively starting from
+    /// `first_config_number`, or returns an error if the last config number
+    /// would be larger than 255.
+    pub fn new(first_config_number: u8) -> Result<Self, ConfigNumberOverflow> {
+        let n = first_config_number;
+        n.checked_add(Self::COUNT - 1)
+            .ok_or(ConfigNumberOverflow(first_config_number))?;
+        Ok(Self {
+            remote_host: StringConfig::builder()
+                .config_number(n)
+                .call("remote-host")
+                .display("Remote SSH server address")
+                .tooltip("The remote SSH host. It can be both an IP address or a hostname")
+                .required(true)
+                .group("Server")
+                .build(),
+            remote_port: UnsignedConfig::builder()
+                .config_number(n + 1)
+                .call("remote-port")
+                .display("Remote SSH server port")
+                .tooltip("The remote SSH host port (1-65535)")
+                .range(1..=65535)
+                .default_value(22)
+                .group("Server")
+                .build(),
+            remote_username: StringConfig::builder()
+                .config_number(n + 2)
+                .call("remote-username")
+                .display("Remote SSH server username")
+                .tooltip("The remote SSH username")
+                .group("Authentication")
+                .build(),
+            remote_password: PasswordConfig::builder()
+                .config_number(n + 3)
+                .call("remote-password")
+                .display("Remote SSH server password")
+                .tooltip("The SSH password, used when other methods (SSH agent or key files) are unavailable")
+                .group("Authentication")
+                .build(),
+            sshkey: FileSelectConfig::builder()
+                .config_number(n + 4)
+                .call("sshkey")
+                .display("Path to SSH private key")
+                .tooltip("The path on the local filesystem of the private SSH key")
+                .group("Authentication")
+                .build(),
+            sshkey_passphrase: PasswordConfig::builder()
+                .config_number(n + 5)
+                .call("sshkey-passphrase")
+                .display("SSH key passphrase")
+                .tooltip("Passphrase to unlock the SSH private key")
+                .group("Authentication")
+                .build(),
+            remote_interface: StringConfig::builder()
+                .config_number(n + 6)
+                .call("remote-interface")
+                .display("Remote interface")
+                .tooltip("The remote network interface used for capture")
+                .group("Capture")
+                .build(),
+            remote_capture_command: StringConfig::builder()
+                .config_number(n + 7)
+                .call("remote-capture-command")
+                .display("Remote capture command")
+                .tooltip("The remote command used to capture. It must write pcap or pcapng to stdout")
+                .group("Capture")
+                .build(),
+            remote_sudo: BooleanConfig::builder()
+                .config_number(n + 8)
+                .call("remote-sudo")
+                .display("Use sudo on the remote machine")
+                .tooltip("Prepend the capture command with sudo on the remote machine")
+                .group("Capture")
+                .build(),
+            remote_noprom: BooleanConfig::builder()
+                .config_number(n + 9)
+                .call("remote-noprom")
+                .display("No promiscuous mode")
+                .tooltip("Don't use promiscuous mode on the remote machine")
+                .group("Capture")
+                .build(),
+        })
+    }
+
+    /// Returns all of the configs in this bundle, which can be passed to
+    /// [`ConfigStep::list_configs`][crate::ConfigStep::list_configs] or
+    /// appended to the application's own list of configs.
+    pub fn configs(&self) -> Vec<&dyn ConfigTrait> {
+        vec![
+            &self.remote_host,
+            &self.remote_port,
+            &self.remote_username,
+            &self.remote_password,
+            &self.sshkey,
+            &self.sshkey_passphrase,
+            &self.remote_interface,
+            &self.remote_capture_command,
+            &self.remote_sudo,
+            &self.remote_noprom,
+        ]
+    }
+}
+
+/// Error returned by [`SshConfigs::new`] when the config numbers starting
+/// from the contained number do not fit in a `u8`.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("SSH configs starting from config number {0} exceed the maximum config number 255")]
+pub struct ConfigNumberOverflow(pub u8);
+
+/// Error when capturing over SSH.
+#[derive(Debug, Error)]
+pub enum SshCaptureError {
+    /// The `--remote-host` argument is required to connect, but is not
+    /// provided.
+    #[error("Missing `--remote-host` argument")]
+    MissingHost,
+    /// IO error connecting to the remote host.
+    #[error("IO error connecting to remote host")]
+    Io(#[from] std::io::Error),
+    /// Error reported by the SSH library.
+    #[error(transparent)]
+    Ssh(#[from] ssh2::Error),
+    /// None of the available authentication methods were accepted by the
+    /// remote host.
+    #[error("Authentication to the remote host failed")]
+    AuthenticationFailed,
+    /// The output of the remote command is not a valid pcap or pcapng stream,
+    /// or writing it to the fifo failed.
+    #[error(transparent)]
+    Stream(#[from] PcapStreamError),
+    /// The remote command exited with a non-zero status.
+    #[error("Remote capture command exited with status {status}: {stderr}")]
+    RemoteCommandFailed {
+        /// The exit status of the remote command.
+        status: i32,
+        /// The stderr output of the remote command.
+        stderr: String,
+        /// The error forwarding the output of the remote command, if any.
+        /// Forwarding can fail because the command failed, like on an
+        /// unexpected end of the stream, or make the command fail, like when
+        /// Wireshark closes the fifo.
+        #[source]
+        forward_error: Option<PcapStreamError>,
+    },
+}
+
+/// An authenticated SSH session to the remote host, which can be used to run
+/// a capture command. See the [module documentation][self] for an example.
+pub struct SshCapture {
+    session: Session,
+}
+
+impl SshCapture {
+    /// Connects and authenticates to the remote host given in `args`.
+    ///
+    /// Authentication is attempted using the private key in
+    /// [`SshArgs::sshkey`] and the password in [`SshArgs::remote_password`], if
+    /// they are provided. If neither is provided, the SSH agent is used.
+    pub fn connect(args: &SshArgs) -> Result<Self, SshCaptureError> {
+        let host = args
+            .remote_host
+            .as_deref()
+            .ok_or(SshCaptureError::MissingHost)?;
+        let tcp = TcpStream::connect((host, args.remote_port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+
+        let username = match &args.remote_username {
+            Some(username) => username.clone(),
+            None => std::env::var("USER").unwrap_or_default(),
+        };
+        if let Some(sshkey) = &args.sshkey {
+            if let Err(e) = session.userauth_pubkey_file(
+                &username,
+                None,
+                sshkey,
+                args.sshkey_passphrase
+                    .as_ref()
+                    .map(|passphrase| passphrase.expose_secret().as_str()),
+            ) {
+                log::warn!("Public key authentication failed: {e}");
+            }
+        }
+        if !session.authenticated() {
+            if let Some(password) = &args.remote_password {
+                if let Err(e) = session.userauth_password(&username, password.expose_secret()) {
+                    log::warn!("Password authentication failed: {e}");
+                }
+            }
+        }
+        if args.sshkey.is_none() && args.remote_password.is_none() {
+            if let Err(e) = session.userauth_agent(&username) {
+                log::warn!("SSH agent authentication failed: {e}");
+            }
+        }
+        if !session.authenticated() {
+            return Err(SshCaptureError::AuthenticationFailed);
+        }
+        Ok(Self { session })
+    }
+
+    /// Runs `command` on the remote host, and forwards its stdout to `fifo`
+    /// using [`forward_pcap_stream`]. This blocks until the remote command
+    /// exits, or until an error occurs.
+    ///
+    /// The command is typically created using
+    /// [`SshArgs::remote_capture_command`].
+    ///
+    /// The stderr of the remote command is read while its stdout is forwarded,
+    /// since the remote command blocks if it writes more to stderr than the
+    /// SSH channel can buffer. Up to [`MAX_STDERR_LEN`] bytes of it are
+    /// included in [`SshCaptureError::RemoteCommandFailed`], along with the
+    /// error forwarding the stdout, if any.
+    pub fn run<W: Write>(&self, command: &str, fifo: W) -> Result<u64, SshCaptureError> {
+        let mut channel = self.session.channel_session()?;
+        log::debug!("Running remote command: {command}");
+        channel.exec(command)?;
+        self.session.set_blocking(false);
+        let mut reader = DrainStderr {
+            session: &self.session,
+            stdout: channel.stream(0),
+            stderr: channel.stderr(),
+            stderr_buf: Vec::new(),
+        };
+        let result = forward_pcap_stream(&mut reader, fifo);
+        let mut stderr_buf = reader.stderr_buf;
+        self.session.set_blocking(true);
+
+        if result.is_ok() {
+            // The command has closed stdout, so it is about to exit. Best
+            // effort only, the exit status is more important.
+            let mut rest = Vec::new();
+            let _ = channel.stderr().read_to_end(&mut rest);
+            append_stderr(&mut stderr_buf, &rest);
+        }
+        let stderr = String::from_utf8_lossy(&stderr_buf).into_owned();
+        channel.close()?;
+        channel.wait_close()?;
+        let status = channel.exit_status()?;
+        if status != 0 {
+            return Err(SshCaptureError::RemoteCommandFailed {
+                status,
+                stderr,
+                forward_error: result.err(),
+            });
+        }
+        Ok(result?)
+    }
+}
+
+/// The maximum number of bytes of the stderr of the remote command that are
+/// kept by [`SshCapture::run`]. The rest is read and discarded.
+pub const MAX_STDERR_LEN: usize = 64 * 1024;
+
+fn append_stderr(buf: &mut Vec<u8>, data: &[u8]) {
+    let len = data.len().min(MAX_STDERR_LEN - buf.len());
+    buf.extend_from_slice(&data[..len]);
+}
+
+/// Reads the stdout of a channel in a non-blocking session, reading any
+/// pending stderr before each read so that the remote command never blocks
+/// on a full stderr.
+struct DrainStderr<'a> {
+    session: &'a Session,
+    stdout: Stream,
+    stderr: Stream,
+    stderr_buf: Vec<u8>,
+}
+
+impl DrainStderr<'_> {
+    fn drain_stderr(&mut self) -> io::Result<()> {
+        let mut chunk = [0_u8; 4096];
+        loop {
+            match self.stderr.read(&mut chunk) {
+                Ok(0) => return Ok(()),
+                Ok(n) => append_stderr(&mut self.stderr_buf, &chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits until the session socket is ready in the directions that
+    /// blocked the last read.
+    #[cfg(unix)]
+    fn wait(&self) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        use ssh2::BlockDirections;
+
+        let events = match self.session.block_directions() {
+            BlockDirections::Outbound => libc::POLLOUT,
+            BlockDirections::Both => libc::POLLIN | libc::POLLOUT,
+            BlockDirections::Inbound | BlockDirections::None => libc::POLLIN,
+        };
+        let mut fd = libc::pollfd {
+            fd: self.session.as_raw_fd(),
+            events,
+            revents: 0,
+        };
+        // Safety: `fd` is a valid `pollfd`, and the count is 1. The timeout
+        // makes sure that the read is retried even if the directions are
+        // out of date.
+        if unsafe { libc::poll(&mut fd, 1, 1000) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits a short time before the read is retried.
+    #[cfg(not(unix))]
+    fn wait(&self) -> io::Result<()> {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        Ok(())
+    }
+}
+
+impl Read for DrainStderr<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            self.drain_stderr()?;
+            match self.stdout.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.wait()?,
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::{Args, Parser};
+
+    use super::{append_stderr, ConfigNumberOverflow, SshArgs, SshConfigs, MAX_STDERR_LEN};
+
+    #[derive(Parser)]
+    struct TestArgs {
+        #[command(flatten)]
+        ssh: SshArgs,
+    }
+
+    #[test]
+    fn default_command() {
+        let args = TestArgs::parse_from(["test"]);
+        assert_eq!(args.ssh.remote_capture_command(None), "tcpdump -U -w -");
+        assert_eq!(args.ssh.remote_capture_command(Some("")), "tcpdump -U -w -");
+    }
+
+    #[test]
+    fn command_quotes_filter() {
+        let args = TestArgs::parse_from(["test", "--remote-noprom"]);
+        assert_eq!(
+            args.ssh.remote_capture_command(Some("host 'a'; rm -rf /")),
+            r"tcpdump -U -w - -p 'host '\''a'\''; rm -rf /'"
+        );
+    }
+
+    #[test]
+    fn custom_command() {
+        let args = TestArgs::parse_from([
+            "test",
+            "--remote-capture-command",
+            "dumpcap -w -",
+            "--remote-sudo",
+        ]);
+        assert_eq!(
+            args.ssh.remote_capture_command(Some("port 80")),
+            "dumpcap -w -"
+        );
+    }
+
+    #[test]
+    fn configs_match_args() {
+        let cmd = SshArgs::augment_args(clap::Command::new("test"));
+        let configs = SshConfigs::new(5).unwrap();
+        for config in configs.configs() {
+            assert!(
+                cmd.get_arguments()
+                    .any(|arg| arg.get_long() == Some(config.call())),
+                "No argument for config {}",
+                config.call()
+            );
+        }
+        assert_eq!(configs.configs().len(), SshConfigs::COUNT as usize);
+        assert_eq!(configs.remote_noprom.config_number, 14);
+    }
+
+    #[test]
+    fn config_number_overflow() {
+        assert_eq!(
+            SshConfigs::new(246).unwrap().remote_noprom.config_number,
+            255
+        );
+        assert_eq!(SshConfigs::new(247).unwrap_err(), ConfigNumberOverflow(247));
+    }
+
+    #[test]
+    fn stderr_is_capped() {
+        let mut buf = Vec::new();
+        append_stderr(&mut buf, &[b'a'; 100]);
+        append_stderr(&mut buf, &vec![b'b'; MAX_STDERR_LEN]);
+        assert_eq!(buf.len(), MAX_STDERR_LEN);
+        assert_eq!(&buf[..101], [&[b'a'; 100][..], b"b"].concat());
+    }
+}
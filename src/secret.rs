@@ -0,0 +1,112 @@
+use std::{
+    convert::Infallible,
+    fmt::Debug,
+    str::FromStr,
+    sync::atomic::{compiler_fence, Ordering},
+};
+
+/// A value that should not be revealed, like a password. The value is
+/// redacted in the `Debug` output, so that it doesn't leak into logs, and its
+/// memory is overwritten with zeros when it is dropped.
+///
+/// `Secret<String>` can be used as the type of a clap argument, for example
+/// for the value of a [`PasswordConfig`][crate::config::PasswordConfig].
+///
+/// ```
+/// use r_extcap::Secret;
+///
+/// let password = Secret::new(String::from("hunter2"));
+/// assert_eq!(format!("{password:?}"), "Secret([REDACTED])");
+/// assert_eq!(password.expose_secret(), "hunter2");
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    /// Wraps `value` in a secret.
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Returns the secret value. Take care not to log or print the returned
+    /// value.
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl FromStr for Secret<String> {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl<T: Zeroize> Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// Values that can be overwritten with zeros, so that a [`Secret`] does not
+/// remain in memory after it is dropped.
+pub trait Zeroize {
+    /// Overwrites the value with zeros.
+    fn zeroize(&mut self);
+}
+
+impl Zeroize for Vec<u8> {
+    fn zeroize(&mut self) {
+        for byte in self.iter_mut() {
+            // Volatile writes are not optimized away even though the value is
+            // about to be dropped.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+        self.clear();
+    }
+}
+
+impl Zeroize for String {
+    fn zeroize(&mut self) {
+        // Zero bytes are valid UTF-8, so the string stays valid.
+        unsafe { self.as_mut_vec() }.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Secret, Zeroize};
+
+    #[test]
+    fn zeroize_string() {
+        let mut password = String::from("hunter2");
+        let capacity = password.capacity();
+        password.zeroize();
+        assert!(password.is_empty());
+        assert_eq!(password.capacity(), capacity);
+        assert_eq!(
+            unsafe { std::slice::from_raw_parts(password.as_ptr(), 7) },
+            &[0; 7]
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let password: Secret<String> = "hunter2".parse().unwrap();
+        assert_eq!(password.expose_secret(), "hunter2");
+    }
+}
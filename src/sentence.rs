@@ -0,0 +1,569 @@
+//! Formatting of the extcap sentences printed to stdout for Wireshark. This
+//! module only needs `core` and `alloc`, so it is available without the `std`
+//! feature.
+
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+use core::fmt::{self, Display, Write as _};
+
+/// The extcap interface expects certain output "sentences" to stdout to
+/// communicate with Wireshark, like
+///
+/// ```text
+/// extcap {version=1.0}{help=Some help url}
+/// ```
+///
+/// This formatter serves as a wrapper to implement that format via the
+/// `Display` trait, and the Extcap output can be printed out like this:
+///
+/// ```
+/// use r_extcap::interface::Metadata;
+/// # use r_extcap::ExtcapFormatter;
+///
+/// print!("{}", ExtcapFormatter(&Metadata {
+///     version: "1.0".into(),
+///     help_url: "Some help url".into(),
+///     display_description: "Example extcap".into(),
+///     extra_attributes: vec![],
+/// }));
+/// // Output: extcap {version=1.0}{help=Some help url}{display=Example extcap}
+/// ```
+pub struct ExtcapFormatter<'a, T: ?Sized>(pub &'a T)
+where
+    Self: Display;
+
+/// Elements that has a printable extcap sentence. See the documentation for
+/// [`ExtcapFormatter`] for details.
+pub trait PrintSentence {
+    /// The extcap interface expects certain output "sentences" to stdout to
+    /// communicate with Wireshark, like
+    ///
+    /// ```text
+    /// extcap {version=1.0}{help=Some help url}
+    /// ```
+    ///
+    /// This function writes to the formatter `f` in that format.
+    fn format_sentence(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+
+    /// Prints the extcap sentence to stdout.
+    #[cfg(feature = "std")]
+    fn print_sentence(&self) {
+        print!("{}", ExtcapFormatter(self));
+    }
+
+    /// Writes the extcap sentence to `writer`. The sentence is formatted
+    /// directly into the writer without allocating an intermediate string.
+    #[cfg(feature = "std")]
+    fn write_sentence(&self, writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+        write!(writer, "{}", ExtcapFormatter(self))
+    }
+}
+
+impl<'a, T: PrintSentence + ?Sized> Display for ExtcapFormatter<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.format_sentence(f)
+    }
+}
+
+/// Escapes a value written inside the braces of an extcap sentence, like the
+/// `value` in `{display=value}`.
+///
+/// Wireshark ends a value at the first unescaped `}`, so braces and
+/// backslashes in the value are escaped with a backslash. Other characters are
+/// written unchanged.
+///
+/// ```
+/// use r_extcap::EscapedValue;
+///
+/// assert_eq!(EscapedValue(r"{a}\b").to_string(), r"\{a\}\\b");
+/// ```
+pub struct EscapedValue<'a>(pub &'a str);
+
+impl Display for EscapedValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut rest = self.0;
+        while let Some(index) = rest.find(['\\', '{', '}']) {
+            f.write_str(&rest[..index])?;
+            f.write_char('\\')?;
+            f.write_str(&rest[index..index + 1])?;
+            rest = &rest[index + 1..];
+        }
+        f.write_str(rest)
+    }
+}
+
+/// Declares [`AttributeKey`] with the key written for each variant.
+macro_rules! attribute_keys {
+    ($($(#[$doc:meta])* $variant:ident => $key:literal,)*) => {
+        /// The key of a `{key=value}` attribute in an extcap sentence, like
+        /// `display` in `{display=Delay}`.
+        ///
+        /// All sentences in this crate are written with these keys, so that
+        /// programs parsing them, like a test harness for an extcap, can share
+        /// the same vocabulary. Keys that are not known to this crate can be
+        /// added with the `with_attribute` methods of the interface and the
+        /// metadata.
+        ///
+        /// ```
+        /// use r_extcap::AttributeKey;
+        ///
+        /// assert_eq!(AttributeKey::Display.as_str(), "display");
+        /// assert_eq!(AttributeKey::parse("mustexist"), Some(AttributeKey::MustExist));
+        /// assert_eq!(AttributeKey::parse("unknown"), None);
+        /// ```
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        #[non_exhaustive]
+        pub enum AttributeKey {
+            $($(#[$doc])* $variant,)*
+        }
+
+        impl AttributeKey {
+            /// All the keys, in alphabetical order.
+            pub const ALL: &'static [AttributeKey] = &[$(AttributeKey::$variant,)*];
+
+            /// The key as written in the sentence.
+            pub const fn as_str(self) -> &'static str {
+                match self {
+                    $(AttributeKey::$variant => $key,)*
+                }
+            }
+
+            /// Returns the attribute key written as `key`, or `None` if the key
+            /// is not known to this crate.
+            pub fn parse(key: &str) -> Option<Self> {
+                match key {
+                    $($key => Some(AttributeKey::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+attribute_keys! {
+    /// The number of the config a `value` sentence belongs to.
+    Arg => "arg",
+    /// The command line flag of a config, like `--delay`.
+    Call => "call",
+    /// The number of the toolbar control a `value` sentence belongs to.
+    Control => "control",
+    /// The default value of a config, control or option.
+    Default => "default",
+    /// The text shown to the user.
+    Display => "display",
+    /// Whether an option of a multicheck config can be selected.
+    Enabled => "enabled",
+    /// The file extension filter of a file select config.
+    FileExt => "fileext",
+    /// The tab of the config dialog a config is shown in.
+    Group => "group",
+    /// The help URL of the extcap program.
+    Help => "help",
+    /// Whether the file of a file select config must exist.
+    MustExist => "mustexist",
+    /// The name of a DLT.
+    Name => "name",
+    /// The number of a config, toolbar control or DLT.
+    Number => "number",
+    /// The parent of an option of a multicheck config.
+    Parent => "parent",
+    /// The placeholder shown in an empty text field, or the label of the
+    /// reload button of a selector config.
+    Placeholder => "placeholder",
+    /// The range of valid values of a numeric config.
+    Range => "range",
+    /// Whether the options of a selector config can be reloaded.
+    Reload => "reload",
+    /// Whether a config must be given a value.
+    Required => "required",
+    /// The role of a toolbar button, like `logger`.
+    Role => "role",
+    /// Whether Wireshark saves the value of a config.
+    Save => "save",
+    /// The tooltip shown when hovering over a config or control.
+    Tooltip => "tooltip",
+    /// The type of a config or toolbar control.
+    Type => "type",
+    /// The regular expression a string value is validated with.
+    Validation => "validation",
+    /// The value of an interface or option.
+    Value => "value",
+    /// The version of the extcap program.
+    Version => "version",
+}
+
+impl AttributeKey {
+    /// Writes the attribute `{key=value}` to `f`. The value is written
+    /// unchanged, so it must be wrapped in [`EscapedValue`] where needed.
+    pub fn write(self, f: &mut fmt::Formatter<'_>, value: impl Display) -> fmt::Result {
+        write!(f, "{{{}={value}}}", self.as_str())
+    }
+}
+
+impl Display for AttributeKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Parses the `{key=value}` attributes of an extcap sentence, unescaping
+/// values written with [`EscapedValue`]. The keys can be matched with
+/// [`AttributeKey::parse`].
+///
+/// Only some values, like the values of options, are escaped when written, so
+/// backslashes in other values, like a `validation` regex, are not preserved.
+///
+/// ```
+/// use r_extcap::parse_attributes;
+///
+/// assert_eq!(
+///     parse_attributes(r"arg {number=0}{display=a \{b\}}"),
+///     [
+///         (String::from("number"), String::from("0")),
+///         (String::from("display"), String::from("a {b}")),
+///     ]
+/// );
+/// ```
+pub fn parse_attributes(sentence: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut chars = sentence.chars();
+    while chars.by_ref().any(|c| c == '{') {
+        let mut current = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => current.extend(chars.next()),
+                '}' => break,
+                c => current.push(c),
+            }
+        }
+        if let Some((key, value)) = current.split_once('=') {
+            attributes.push((key.to_owned(), value.to_owned()));
+        }
+    }
+    attributes
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::{parse_attributes, AttributeKey, ExtcapFormatter, PrintSentence};
+    use crate::{
+        config::{
+            BooleanConfig, ConfigOptionValue, FileSelectConfig, LongConfig, MultiCheckConfig,
+            MultiCheckValue, SelectorConfig, StringConfig,
+        },
+        controls::{
+            BooleanControl, HelpButtonControl, SelectorControl, SelectorControlOption,
+            StringControl,
+        },
+        interface::{Dlt, Interface, Metadata},
+    };
+
+    /// Formats `sentence` and parses the attributes of each line back, checking
+    /// that every key is an [`AttributeKey`].
+    fn round_trip(sentence: &dyn PrintSentence) -> Vec<Vec<(AttributeKey, String)>> {
+        ExtcapFormatter(sentence)
+            .to_string()
+            .lines()
+            .map(|line| {
+                parse_attributes(line)
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let parsed = AttributeKey::parse(&key)
+                            .unwrap_or_else(|| panic!("Unknown key {key:?} in {line:?}"));
+                        (parsed, value)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn attributes<const N: usize>(
+        attributes: [(AttributeKey, &str); N],
+    ) -> Vec<(AttributeKey, String)> {
+        attributes
+            .into_iter()
+            .map(|(key, value)| (key, value.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn attribute_keys() {
+        for key in AttributeKey::ALL {
+            assert_eq!(AttributeKey::parse(key.as_str()), Some(*key));
+            assert_eq!(key.to_string(), key.as_str());
+        }
+        assert!(AttributeKey::ALL
+            .windows(2)
+            .all(|keys| keys[0].as_str() < keys[1].as_str()));
+    }
+
+    #[test]
+    fn parse_sentence_attributes() {
+        assert_eq!(
+            parse_attributes(r"arg {number=0}{display=a \{b\} \\c}{empty=}"),
+            [
+                (String::from("number"), String::from("0")),
+                (String::from("display"), String::from(r"a {b} \c")),
+                (String::from("empty"), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip_interfaces() {
+        use AttributeKey::*;
+
+        let metadata = Metadata {
+            version: "1.0".into(),
+            help_url: "https://example.com".into(),
+            display_description: "Example".into(),
+            extra_attributes: vec![],
+        };
+        assert_eq!(
+            round_trip(&metadata),
+            [attributes([
+                (Version, "1.0"),
+                (Help, "https://example.com"),
+                (Display, "Example")
+            ])]
+        );
+        let dlt = Dlt::user(1, "Example DLT");
+        let interface = Interface::builder()
+            .value("example1".into())
+            .display("Example interface".into())
+            .dlt(dlt.clone())
+            .build();
+        assert_eq!(
+            round_trip(&interface),
+            [attributes([
+                (Value, "example1"),
+                (Display, "Example interface")
+            ])]
+        );
+        assert_eq!(
+            round_trip(&dlt),
+            [attributes([
+                (Number, "148"),
+                (Name, "USER1"),
+                (Display, "Example DLT")
+            ])]
+        );
+    }
+
+    #[test]
+    fn round_trip_configs() {
+        use AttributeKey::*;
+
+        let selector = SelectorConfig::builder()
+            .config_number(0)
+            .call("device")
+            .display("Device")
+            .tooltip("The device")
+            .reload(crate::config::Reload {
+                label: String::from("Scan"),
+                reload_fn: Vec::new,
+            })
+            .group("Connection")
+            .save(false)
+            .default_options([ConfigOptionValue::builder()
+                .value("a {b}")
+                .display("A")
+                .default(true)
+                .build()])
+            .build();
+        assert_eq!(
+            round_trip(&selector),
+            [
+                attributes([
+                    (Number, "0"),
+                    (Call, "--device"),
+                    (Display, "Device"),
+                    (Tooltip, "The device"),
+                    (Type, "selector"),
+                    (Reload, "true"),
+                    (Placeholder, "Scan"),
+                    (Group, "Connection"),
+                    (Save, "false"),
+                ]),
+                attributes([
+                    (Arg, "0"),
+                    (Value, "a {b}"),
+                    (Display, "A"),
+                    (Default, "true")
+                ]),
+            ]
+        );
+        let multicheck = MultiCheckConfig::builder()
+            .config_number(1)
+            .call("channels")
+            .display("Channels")
+            .options([MultiCheckValue::builder()
+                .value("all")
+                .display("All")
+                .children([MultiCheckValue::builder()
+                    .value("one")
+                    .display("One")
+                    .enabled(false)
+                    .build()])
+                .build()])
+            .build();
+        assert_eq!(
+            round_trip(&multicheck)[2],
+            attributes([
+                (Arg, "1"),
+                (Value, "one"),
+                (Display, "One"),
+                (Default, "false"),
+                (Enabled, "false"),
+                (Parent, "all"),
+            ])
+        );
+        let long = LongConfig::builder()
+            .config_number(2)
+            .call("offset")
+            .display("Offset")
+            .placeholder("0")
+            .required(true)
+            .range(-10..=10)
+            .default_value(0)
+            .build();
+        assert_eq!(
+            round_trip(&long),
+            [attributes([
+                (Number, "2"),
+                (Call, "--offset"),
+                (Display, "Offset"),
+                (Placeholder, "0"),
+                (Required, "true"),
+                (Range, "-10,10"),
+                (Default, "0"),
+                (Type, "long"),
+            ])]
+        );
+        let string = StringConfig::builder()
+            .config_number(3)
+            .call("serial")
+            .display("Serial")
+            .validation("[0-9]+")
+            .build();
+        assert_eq!(
+            round_trip(&string),
+            [attributes([
+                (Number, "3"),
+                (Call, "--serial"),
+                (Display, "Serial"),
+                (Validation, "[0-9]+"),
+                (Type, "string"),
+            ])]
+        );
+        let file = FileSelectConfig::builder()
+            .config_number(4)
+            .call("firmware")
+            .display("Firmware")
+            .must_exist(false)
+            .file_extension_filter("Firmware (*.bin)")
+            .build();
+        assert_eq!(
+            round_trip(&file),
+            [attributes([
+                (Number, "4"),
+                (Call, "--firmware"),
+                (Display, "Firmware"),
+                (Type, "fileselect"),
+                (MustExist, "false"),
+                (FileExt, "Firmware (*.bin)"),
+            ])]
+        );
+        let boolean = BooleanConfig::builder()
+            .config_number(5)
+            .call("verify")
+            .display("Verify")
+            .default_value(true)
+            .always_include_option(true)
+            .build();
+        assert_eq!(
+            round_trip(&boolean),
+            [attributes([
+                (Number, "5"),
+                (Call, "--verify"),
+                (Display, "Verify"),
+                (Default, "true"),
+                (Type, "boolean"),
+            ])]
+        );
+    }
+
+    #[test]
+    fn round_trip_controls() {
+        use AttributeKey::*;
+
+        let boolean = BooleanControl::builder()
+            .control_number(0)
+            .display("Verify")
+            .tooltip("Verify the packets")
+            .build();
+        assert_eq!(
+            round_trip(&boolean),
+            [attributes([
+                (Number, "0"),
+                (Type, "boolean"),
+                (Display, "Verify"),
+                (Default, "false"),
+                (Tooltip, "Verify the packets"),
+            ])]
+        );
+        let help = HelpButtonControl::builder()
+            .control_number(1)
+            .display("Help")
+            .build();
+        assert_eq!(
+            round_trip(&help),
+            [attributes([
+                (Number, "1"),
+                (Type, "button"),
+                (Role, "help"),
+                (Display, "Help"),
+            ])]
+        );
+        let selector = SelectorControl::builder()
+            .control_number(2)
+            .display("Delay")
+            .options(vec![SelectorControlOption::builder()
+                .value("1")
+                .display("1s")
+                .default(true)
+                .build()])
+            .build();
+        assert_eq!(
+            round_trip(&selector),
+            [
+                attributes([(Number, "2"), (Type, "selector"), (Display, "Delay")]),
+                attributes([
+                    (Control, "2"),
+                    (Value, "1"),
+                    (Display, "1s"),
+                    (Default, "true")
+                ]),
+            ]
+        );
+        let string = StringControl::builder()
+            .control_number(3)
+            .display("Message")
+            .tooltip("The message")
+            .placeholder("Hello")
+            .validation(".+")
+            .default_value("Hi")
+            .build();
+        assert_eq!(
+            round_trip(&string),
+            [attributes([
+                (Number, "3"),
+                (Type, "string"),
+                (Display, "Message"),
+                (Tooltip, "The message"),
+                (Placeholder, "Hello"),
+                (Validation, ".+"),
+                (Default, "Hi"),
+            ])]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Detects the end of a capture, so that the extcap can clean up before the
+//! process exits.
+//!
+//! When the user stops a capture, Wireshark sends `SIGTERM` to the extcap and
+//! closes the reading end of the fifo. Register a callback using
+//! [`CaptureStep::on_capture_end`][crate::CaptureStep::on_capture_end] to
+//! release resources like hardware devices or remote sessions when this
+//! happens.
+//!
+//! Windows has no `SIGTERM`. There, Wireshark closes the fifo before
+//! terminating the extcap, so the fifo is monitored in the background and the
+//! signal is triggered as soon as it is closed, even if no packets are being
+//! written. Console control events (like Ctrl+C or closing the console window)
+//! also trigger the signal.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, LazyLock, Mutex, OnceLock,
+};
+
+use log::debug;
+#[cfg(target_os = "windows")]
+use std::{fs::File, io::Write, time::Duration};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// The reason a [`ShutdownSignal`] was triggered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// Wireshark requested the extcap to terminate, by sending `SIGTERM`.
+    Terminated,
+    /// Writing to the fifo failed because Wireshark closed it.
+    FifoClosed,
+}
+
+/// A signal that is triggered once when the capture ends. Cloned signals
+/// share the same state.
+///
+/// Callbacks registered with [`on_shutdown`][Self::on_shutdown] are run
+/// exactly once, on the thread that triggers the signal.
+#[derive(Clone, Default)]
+pub struct ShutdownSignal {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    triggered: AtomicBool,
+    reason: OnceLock<ShutdownReason>,
+    callbacks: Mutex<Vec<Callback>>,
+}
+
+static GLOBAL: LazyLock<ShutdownSignal> = LazyLock::new(ShutdownSignal::default);
+
+impl ShutdownSignal {
+    /// The signal for this process, which is triggered when `SIGTERM` is
+    /// received (once [`handle_sigterm`][Self::handle_sigterm] is called) or
+    /// when writing to the [`Fifo`][crate::Fifo] fails with a broken pipe.
+    pub fn global() -> &'static ShutdownSignal {
+        &GLOBAL
+    }
+
+    /// Registers `callback` to be run when this signal is triggered. If the
+    /// signal has already been triggered, `callback` is run immediately.
+    pub fn on_shutdown(&self, callback: impl FnOnce() + Send + 'static) {
+        let mut callbacks = self.inner.callbacks.lock().unwrap();
+        if self.is_triggered() {
+            drop(callbacks);
+            callback();
+        } else {
+            callbacks.push(Box::new(callback));
+        }
+    }
+
+    /// Triggers this signal, running the registered callbacks. Does nothing if
+    /// the signal has already been triggered.
+    pub fn trigger(&self, reason: ShutdownReason) {
+        if self.inner.triggered.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        debug!("Capture ended: {reason:?}");
+        let _ = self.inner.reason.set(reason);
+        let callbacks = std::mem::take(&mut *self.inner.callbacks.lock().unwrap());
+        for callback in callbacks {
+            callback();
+        }
+    }
+
+    /// Whether this signal has been triggered.
+    pub fn is_triggered(&self) -> bool {
+        self.inner.triggered.load(Ordering::SeqCst)
+    }
+
+    /// The reason this signal was triggered, or `None` if it has not been
+    /// triggered.
+    pub fn reason(&self) -> Option<ShutdownReason> {
+        self.inner.reason.get().copied()
+    }
+
+    /// Installs a handler for `SIGTERM` that triggers the
+    /// [global][Self::global] signal, and then terminates the process the
+    /// same way as the default `SIGTERM` handler would.
+    ///
+    /// The callbacks run on a background thread rather than in the signal
+    /// handler, so they are not restricted to async-signal-safe operations.
+    /// Calling this more than once has no additional effect.
+    ///
+    /// On Windows, this installs a console control handler instead, which
+    /// triggers the signal on Ctrl+C, Ctrl+Break and console close events
+    /// before the process is terminated.
+    pub fn handle_sigterm() {
+        #[cfg(not(target_os = "windows"))]
+        sigterm::install();
+        #[cfg(target_os = "windows")]
+        console::install();
+    }
+
+    /// Triggers this signal with [`ShutdownReason::FifoClosed`] when the
+    /// reading end of `pipe` is closed, checking periodically on a background
+    /// thread. Writing to a closed pipe already triggers the signal through
+    /// [`Fifo`][crate::Fifo], but this also detects the closure while the
+    /// capture is idle.
+    #[cfg(target_os = "windows")]
+    pub(crate) fn watch_pipe(&self, pipe: &File) {
+        let Ok(mut pipe) = pipe.try_clone() else {
+            return;
+        };
+        let signal = self.clone();
+        std::thread::spawn(move || {
+            while !signal.is_triggered() {
+                // A zero-length write fails once the reader closes the pipe.
+                if let Err(e) = pipe.write(&[]) {
+                    if e.kind() == std::io::ErrorKind::BrokenPipe {
+                        signal.trigger(ShutdownReason::FifoClosed);
+                    }
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(200));
+            }
+        });
+    }
+}
+
+impl std::fmt::Debug for ShutdownSignal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShutdownSignal")
+            .field("reason", &self.reason())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+mod sigterm {
+    use std::{
+        fs::File,
+        io::Read,
+        os::fd::FromRawFd,
+        sync::{
+            atomic::{AtomicI32, Ordering},
+            Once,
+        },
+    };
+
+    use log::warn;
+
+    use super::{ShutdownReason, ShutdownSignal};
+
+    /// The write end of the pipe used to notify the watcher thread.
+    static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn on_sigterm(_: libc::c_int) {
+        let fd = PIPE_WRITE.load(Ordering::SeqCst);
+        if fd >= 0 {
+            // Only async-signal-safe operations are allowed here.
+            unsafe { libc::write(fd, [1_u8].as_ptr().cast(), 1) };
+        }
+    }
+
+    pub(super) fn install() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            let mut fds = [0; 2];
+            if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+                warn!(
+                    "Unable to handle SIGTERM: {}",
+                    std::io::Error::last_os_error()
+                );
+                return;
+            }
+            PIPE_WRITE.store(fds[1], Ordering::SeqCst);
+            let mut read_end = unsafe { File::from_raw_fd(fds[0]) };
+            std::thread::spawn(move || {
+                if read_end.read_exact(&mut [0]).is_ok() {
+                    ShutdownSignal::global().trigger(ShutdownReason::Terminated);
+                    unsafe {
+                        libc::signal(libc::SIGTERM, libc::SIG_DFL);
+                        libc::raise(libc::SIGTERM);
+                    }
+                }
+            });
+            unsafe {
+                libc::signal(
+                    libc::SIGTERM,
+                    on_sigterm as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                )
+            };
+        });
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod console {
+    use std::sync::Once;
+
+    use log::warn;
+    use windows_sys::{
+        core::BOOL,
+        Win32::{
+            Foundation::{FALSE, TRUE},
+            System::Console::{
+                SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT,
+            },
+        },
+    };
+
+    use super::{ShutdownReason, ShutdownSignal};
+
+    unsafe extern "system" fn on_ctrl(ctrl_type: u32) -> BOOL {
+        if matches!(
+            ctrl_type,
+            CTRL_C_EVENT | CTRL_BREAK_EVENT | CTRL_CLOSE_EVENT
+        ) {
+            // Control handlers run on their own thread, so the callbacks can
+            // run here directly.
+            ShutdownSignal::global().trigger(ShutdownReason::Terminated);
+        }
+        // Let the default handler terminate the process.
+        FALSE
+    }
+
+    pub(super) fn install() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            if unsafe { SetConsoleCtrlHandler(Some(on_ctrl), TRUE) } == 0 {
+                warn!(
+                    "Unable to handle console control events: {}",
+                    std::io::Error::last_os_error()
+                );
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::{ShutdownReason, ShutdownSignal};
+
+    #[test]
+    fn callbacks_run_once() {
+        let signal = ShutdownSignal::default();
+        let count = Arc::new(AtomicUsize::new(0));
+        let count_clone = count.clone();
+        signal.on_shutdown(move || {
+            count_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(signal.reason(), None);
+        signal.clone().trigger(ShutdownReason::FifoClosed);
+        signal.trigger(ShutdownReason::Terminated);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!(signal.reason(), Some(ShutdownReason::FifoClosed));
+
+        let count_clone = count.clone();
+        signal.on_shutdown(move || {
+            count_clone.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,746 @@
+//! Packet sinks, which write captured packets to the capture fifo in the format
+//! expected by Wireshark.
+//!
+//! [`PcapSink`] writes the classic pcap format, which supports a single data
+//! link type for the whole capture. [`PcapNgSink`] writes the pcapng format,
+//! which allows multiple interfaces (each with its own data link type) in the
+//! same capture.
+//!
+//! ## Timestamp resolution
+//!
+//! Packet timestamps are stored with a fixed [`TimestampResolution`].
+//! [`PcapSink::new`] uses microseconds, which is what most pcap readers expect,
+//! and [`PcapNgSink::new`] uses nanoseconds. If the capture source provides
+//! more precise timestamps than the sink stores, they are truncated, so pick
+//! the resolution explicitly with `with_resolution` when it matters:
+//!
+//! ```
+//! use r_extcap::interface::DataLink;
+//! use r_extcap::sink::{PacketSink, PcapSink, TimestampResolution};
+//! use std::time::Duration;
+//!
+//! # let fifo = Vec::new();
+//! let mut sink =
+//!     PcapSink::with_resolution(fifo, DataLink::ETHERNET, TimestampResolution::Nanosecond)
+//!         .unwrap();
+//! sink.write_packet(Duration::new(1, 123_456_789), &[0; 60]).unwrap();
+//! ```
+//!
+//! ## Capturing multiple channels in one process
+//!
+//! Wireshark starts one extcap process for each interface the user selects.
+//! Some devices however expose several logical channels which are cheaper to
+//! capture together, for example a radio that sniffs multiple frequencies at
+//! once. To capture them in a single process, list an extra
+//! [`Interface`] that represents all of the channels, and in the capture step
+//! add one pcapng interface per channel using
+//! [`PcapNgSink::add_extcap_interface`]. Packets are then written to a specific
+//! channel using [`PcapNgSink::write_packet_for`], and Wireshark shows the
+//! channel of each packet in the "Interface" column.
+//!
+//! ```
+//! use r_extcap::interface::{DataLink, Dlt, Interface};
+//! use r_extcap::sink::PcapNgSink;
+//! use std::time::Duration;
+//!
+//! let dlt = Dlt::builder()
+//!     .data_link_type(DataLink::USER0)
+//!     .name("USER0".into())
+//!     .display("Channel data".into())
+//!     .build();
+//! let channel_a = Interface::builder()
+//!     .value("channel-a".into())
+//!     .display("Channel A".into())
+//!     .dlt(dlt.clone())
+//!     .build();
+//! let channel_b = Interface::builder()
+//!     .value("channel-b".into())
+//!     .display("Channel B".into())
+//!     .dlt(dlt)
+//!     .build();
+//!
+//! # let fifo = Vec::new();
+//! let mut sink = PcapNgSink::new(fifo).unwrap();
+//! let a = sink.add_extcap_interface(&channel_a).unwrap();
+//! let b = sink.add_extcap_interface(&channel_b).unwrap();
+//! assert_eq!(sink.interface_id("channel-b"), Some(b));
+//!
+//! sink.write_packet_for(a, Duration::from_secs(1), &[1, 2, 3]).unwrap();
+//! sink.write_packet_for(b, Duration::from_secs(2), &[4, 5, 6]).unwrap();
+//! ```
+//!
+//! ## Validating packets before writing them
+//!
+//! Checks like validating a device CRC or dropping malformed frames can be
+//! added to any sink as [`SinkMiddleware`] with
+//! [`PacketSink::with_middleware`], which also keeps [`SinkStats`] of the
+//! written and dropped packets. See [`MiddlewareSink`] for an example.
+
+use std::{borrow::Cow, io::Write, time::Duration};
+
+use pcap_file::{
+    pcap::{PcapHeader, PcapPacket, PcapWriter},
+    pcapng::{
+        blocks::{
+            enhanced_packet::{EnhancedPacketBlock, EnhancedPacketOption},
+            interface_description::{InterfaceDescriptionBlock, InterfaceDescriptionOption},
+        },
+        PcapNgWriter, RawBlock,
+    },
+    DataLink, Endianness, PcapError, TsResolution,
+};
+
+use typed_builder::TypedBuilder;
+
+use crate::interface::Interface;
+
+mod middleware;
+
+pub use middleware::{
+    CaptureFilter, ChecksumValidator, MiddlewareSink, SinkMiddleware, SinkPacket, SinkStats,
+    Verdict,
+};
+
+/// The resolution of the timestamps written by a sink.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TimestampResolution {
+    /// Timestamps in microseconds. This is the resolution of the classic pcap
+    /// format, and the default when `if_tsresol` is absent in pcapng.
+    #[default]
+    Microsecond,
+    /// Timestamps in nanoseconds.
+    Nanosecond,
+}
+
+impl TimestampResolution {
+    /// The number of timestamp units in a second.
+    pub fn units_per_second(self) -> u64 {
+        match self {
+            Self::Microsecond => 1_000_000,
+            Self::Nanosecond => 1_000_000_000,
+        }
+    }
+
+    /// Converts `timestamp` into the number of units of this resolution,
+    /// truncating any more precise part. Saturates at `u64::MAX`.
+    pub fn to_units(self, timestamp: Duration) -> u64 {
+        let units = match self {
+            Self::Microsecond => timestamp.as_micros(),
+            Self::Nanosecond => timestamp.as_nanos(),
+        };
+        u64::try_from(units).unwrap_or(u64::MAX)
+    }
+
+    /// Converts a number of units of this resolution into a duration.
+    pub fn from_units(self, units: u64) -> Duration {
+        match self {
+            Self::Microsecond => Duration::from_micros(units),
+            Self::Nanosecond => Duration::from_nanos(units),
+        }
+    }
+
+    /// Truncates `timestamp` to this resolution, which is the timestamp
+    /// Wireshark shows for a packet written with it.
+    ///
+    /// ```
+    /// use r_extcap::sink::TimestampResolution;
+    /// use std::time::Duration;
+    ///
+    /// assert_eq!(
+    ///     TimestampResolution::Microsecond.truncate(Duration::from_nanos(1_234_567)),
+    ///     Duration::from_micros(1_234),
+    /// );
+    /// ```
+    pub fn truncate(self, timestamp: Duration) -> Duration {
+        self.from_units(self.to_units(timestamp))
+    }
+
+    /// The value of the pcapng `if_tsresol` option for this resolution, which
+    /// is the negative power of 10 of the unit.
+    pub fn if_tsresol(self) -> u8 {
+        match self {
+            Self::Microsecond => 6,
+            Self::Nanosecond => 9,
+        }
+    }
+}
+
+impl From<TimestampResolution> for TsResolution {
+    fn from(resolution: TimestampResolution) -> Self {
+        match resolution {
+            TimestampResolution::Microsecond => TsResolution::MicroSecond,
+            TimestampResolution::Nanosecond => TsResolution::NanoSecond,
+        }
+    }
+}
+
+impl From<TsResolution> for TimestampResolution {
+    fn from(resolution: TsResolution) -> Self {
+        match resolution {
+            TsResolution::MicroSecond => TimestampResolution::Microsecond,
+            TsResolution::NanoSecond => TimestampResolution::Nanosecond,
+        }
+    }
+}
+
+/// The direction of a packet, relative to the captured interface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The packet was received by the interface.
+    Inbound,
+    /// The packet was sent by the interface.
+    Outbound,
+}
+
+impl Direction {
+    /// The direction bits of the pcapng `epb_flags` option.
+    fn epb_flags(self) -> u32 {
+        match self {
+            Self::Inbound => 0b01,
+            Self::Outbound => 0b10,
+        }
+    }
+}
+
+/// Metadata of a captured packet, written alongside the packet data by
+/// [`PacketSink::write_packet_with_info`].
+///
+/// ```
+/// use r_extcap::sink::{Direction, PacketInfo};
+///
+/// let info = PacketInfo::builder()
+///     .direction(Direction::Outbound)
+///     .dropped(3)
+///     .comment("CRC error")
+///     .build();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, TypedBuilder)]
+pub struct PacketInfo {
+    /// The ID of the interface the packet was captured on, as returned by
+    /// [`PcapNgSink::add_interface`].
+    #[builder(default)]
+    pub interface_id: u32,
+    /// The direction of the packet, if known. Wireshark shows this in the
+    /// frame details and in the `frame.packet_flags_direction` field.
+    #[builder(default, setter(strip_option))]
+    pub direction: Option<Direction>,
+    /// The number of packets lost between the previous packet on the same
+    /// interface and this one, if known.
+    #[builder(default, setter(strip_option))]
+    pub dropped: Option<u64>,
+    /// A comment on the packet, for example an error flag, the RSSI or the
+    /// channel reported by the device. Wireshark shows it in the packet
+    /// details, and it can be filtered with `frame.comment`.
+    #[builder(default, setter(strip_option, into))]
+    pub comment: Option<String>,
+}
+
+/// The block type of a copyable custom block.
+const CUSTOM_BLOCK_COPYABLE: u32 = 0x0000_0bad;
+
+/// A destination for captured packets.
+pub trait PacketSink {
+    /// Writes a packet captured at `timestamp` (the duration since the Unix
+    /// epoch) with the given `data`.
+    fn write_packet(&mut self, timestamp: Duration, data: &[u8]) -> Result<(), PcapError>;
+
+    /// Writes a packet like [`write_packet`][Self::write_packet], together
+    /// with the metadata in `info`.
+    ///
+    /// The default implementation ignores `info`, for formats that cannot
+    /// store it. In particular, [`PcapSink`] writes the packet without the
+    /// metadata, since classic pcap has a single interface and no per-packet
+    /// options.
+    fn write_packet_with_info(
+        &mut self,
+        timestamp: Duration,
+        data: &[u8],
+        info: &PacketInfo,
+    ) -> Result<(), PcapError> {
+        let _ = info;
+        self.write_packet(timestamp, data)
+    }
+
+    /// Wraps this sink in a [`MiddlewareSink`] that passes each packet through
+    /// `middleware` before writing it. Calling `with_middleware` on the result
+    /// appends to the same chain.
+    fn with_middleware(self, middleware: impl SinkMiddleware + 'static) -> MiddlewareSink<Self>
+    where
+        Self: Sized,
+    {
+        MiddlewareSink::new(self).with_middleware(middleware)
+    }
+}
+
+/// Writes packets in the pcap format. All packets share the same data link
+/// type, given when creating the sink.
+///
+/// ```
+/// use r_extcap::sink::{PacketSink, PcapSink};
+/// use r_extcap::interface::DataLink;
+/// use std::time::Duration;
+///
+/// # let fifo = Vec::new();
+/// let mut sink = PcapSink::new(fifo, DataLink::ETHERNET).unwrap();
+/// sink.write_packet(Duration::from_secs(1), &[0; 60]).unwrap();
+/// ```
+pub struct PcapSink<W: Write> {
+    writer: PcapWriter<W>,
+}
+
+impl<W: Write> PcapSink<W> {
+    /// Creates a new sink and writes the pcap header to `writer`. Timestamps
+    /// are written in microseconds.
+    pub fn new(writer: W, datalink: DataLink) -> Result<Self, PcapError> {
+        Self::with_resolution(writer, datalink, TimestampResolution::Microsecond)
+    }
+
+    /// Creates a new sink that writes timestamps with the given `resolution`,
+    /// and writes the pcap header to `writer`. The resolution is declared by
+    /// the magic number in the header.
+    pub fn with_resolution(
+        writer: W,
+        datalink: DataLink,
+        resolution: TimestampResolution,
+    ) -> Result<Self, PcapError> {
+        let header = PcapHeader {
+            datalink,
+            ts_resolution: resolution.into(),
+            ..Default::default()
+        };
+        Ok(Self {
+            writer: PcapWriter::with_header(writer, header)?,
+        })
+    }
+
+    /// The resolution of the timestamps written by this sink.
+    pub fn resolution(&self) -> TimestampResolution {
+        self.writer.ts_resolution().into()
+    }
+
+    /// Consumes the sink and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_writer()
+    }
+}
+
+impl<W: Write> PacketSink for PcapSink<W> {
+    fn write_packet(&mut self, timestamp: Duration, data: &[u8]) -> Result<(), PcapError> {
+        self.writer
+            .write_packet(&PcapPacket::new(timestamp, data.len() as u32, data))?;
+        Ok(())
+    }
+}
+
+/// Writes packets in the pcapng format, which supports packets from multiple
+/// interfaces in a single capture. See the [module documentation][self] for
+/// an example.
+///
+/// At least one interface must be added using
+/// [`add_interface`][Self::add_interface] or
+/// [`add_extcap_interface`][Self::add_extcap_interface] before writing
+/// packets.
+pub struct PcapNgSink<W: Write> {
+    writer: PcapNgWriter<W>,
+    resolution: TimestampResolution,
+}
+
+impl<W: Write> PcapNgSink<W> {
+    /// Creates a new sink and writes the section header block to `writer`.
+    /// Timestamps are written in nanoseconds.
+    pub fn new(writer: W) -> Result<Self, PcapError> {
+        Self::with_resolution(writer, TimestampResolution::Nanosecond)
+    }
+
+    /// Creates a new sink that writes timestamps with the given `resolution`,
+    /// and writes the section header block to `writer`. The resolution is
+    /// declared in the `if_tsresol` option of every interface added to the
+    /// sink.
+    pub fn with_resolution(writer: W, resolution: TimestampResolution) -> Result<Self, PcapError> {
+        Ok(Self {
+            writer: PcapNgWriter::new(writer)?,
+            resolution,
+        })
+    }
+
+    /// The resolution of the timestamps written by this sink.
+    pub fn resolution(&self) -> TimestampResolution {
+        self.resolution
+    }
+
+    /// Adds an interface with the given data link type and name. Returns the
+    /// interface ID to pass to [`write_packet_for`][Self::write_packet_for].
+    /// Interface IDs are assigned sequentially starting from 0.
+    pub fn add_interface(
+        &mut self,
+        datalink: DataLink,
+        name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<u32, PcapError> {
+        let mut options = vec![InterfaceDescriptionOption::IfTsResol(
+            self.resolution.if_tsresol(),
+        )];
+        if let Some(name) = name {
+            options.push(InterfaceDescriptionOption::IfName(Cow::Owned(name.into())));
+        }
+        if let Some(description) = description {
+            options.push(InterfaceDescriptionOption::IfDescription(Cow::Owned(
+                description.into(),
+            )));
+        }
+        self.writer.write_pcapng_block(InterfaceDescriptionBlock {
+            linktype: datalink,
+            snaplen: 0,
+            options,
+        })?;
+        Ok(self.writer.interfaces().len() as u32 - 1)
+    }
+
+    /// Adds an interface for the given extcap [`Interface`], using its DLT as
+    /// the data link type, [`Interface::value`] as the name and
+    /// [`Interface::display`] as the description. Returns the interface ID to
+    /// pass to [`write_packet_for`][Self::write_packet_for].
+    pub fn add_extcap_interface(&mut self, interface: &Interface) -> Result<u32, PcapError> {
+        self.add_interface(
+            interface.dlt.data_link_type,
+            Some(&interface.value),
+            Some(&interface.display),
+        )
+    }
+
+    /// Returns the ID of the interface previously added with the given name
+    /// (or [`Interface::value`]).
+    pub fn interface_id(&self, name: &str) -> Option<u32> {
+        self.writer
+            .interfaces()
+            .iter()
+            .position(|idb| {
+                idb.options.iter().any(
+                    |option| matches!(option, InterfaceDescriptionOption::IfName(n) if n == name),
+                )
+            })
+            .map(|id| id as u32)
+    }
+
+    /// Writes a packet captured on the interface `interface_id` at
+    /// `timestamp` (the duration since the Unix epoch).
+    ///
+    /// Returns [`PcapError::InvalidInterfaceId`] if no interface with the ID
+    /// has been added.
+    pub fn write_packet_for(
+        &mut self,
+        interface_id: u32,
+        timestamp: Duration,
+        data: &[u8],
+    ) -> Result<(), PcapError> {
+        self.write_packet_with_info(
+            timestamp,
+            data,
+            &PacketInfo {
+                interface_id,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Writes a custom block with vendor specific `data`.
+    ///
+    /// The data is identified by the [Private Enterprise
+    /// Number](https://www.iana.org/assignments/enterprise-numbers/) `pen` of
+    /// the organization that defines its format. The block is marked as safe
+    /// to copy when the capture is edited.
+    ///
+    /// Custom blocks are not associated with a packet, so they are useful for
+    /// metadata about the whole capture, like the firmware version of the
+    /// device. Wireshark ignores custom blocks unless a plugin handles the
+    /// `pen`.
+    pub fn write_custom_block(&mut self, pen: u32, data: &[u8]) -> Result<(), PcapError> {
+        let pen = match self.writer.section().endianness {
+            Endianness::Big => pen.to_be_bytes(),
+            Endianness::Little => pen.to_le_bytes(),
+        };
+        let padding = (4 - data.len() % 4) % 4;
+        let body = [&pen[..], data, &[0; 3][..padding]].concat();
+        // The type and the two length fields take 12 bytes.
+        let length = body.len() as u32 + 12;
+        self.writer.write_raw_block(&RawBlock {
+            type_: CUSTOM_BLOCK_COPYABLE,
+            initial_len: length,
+            body: Cow::Owned(body),
+            trailer_len: length,
+        })?;
+        Ok(())
+    }
+
+    /// Consumes the sink and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+}
+
+/// Writes the packet to the first interface, with ID 0, unless another
+/// interface is given in the [`PacketInfo`]. The rest of the metadata is
+/// written as enhanced packet block options.
+impl<W: Write> PacketSink for PcapNgSink<W> {
+    fn write_packet(&mut self, timestamp: Duration, data: &[u8]) -> Result<(), PcapError> {
+        self.write_packet_for(0, timestamp, data)
+    }
+
+    /// Returns [`PcapError::InvalidInterfaceId`] if no interface with the ID
+    /// in `info` has been added.
+    fn write_packet_with_info(
+        &mut self,
+        timestamp: Duration,
+        data: &[u8],
+        info: &PacketInfo,
+    ) -> Result<(), PcapError> {
+        let mut options = vec![];
+        if let Some(direction) = info.direction {
+            options.push(EnhancedPacketOption::Flags(direction.epb_flags()));
+        }
+        if let Some(dropped) = info.dropped {
+            options.push(EnhancedPacketOption::DropCount(dropped));
+        }
+        if let Some(comment) = &info.comment {
+            options.push(EnhancedPacketOption::Comment(Cow::Borrowed(comment)));
+        }
+        // `pcap-file` writes the timestamp as a number of nanoseconds, but
+        // readers interpret the number using `if_tsresol`, so pass the number
+        // of units as nanoseconds.
+        let timestamp = Duration::from_nanos(self.resolution.to_units(timestamp));
+        self.writer.write_pcapng_block(EnhancedPacketBlock {
+            interface_id: info.interface_id,
+            timestamp,
+            original_len: data.len() as u32,
+            data: Cow::Borrowed(data),
+            options,
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use pcap_file::{
+        pcapng::{blocks::interface_description::InterfaceDescriptionOption, Block, PcapNgReader},
+        DataLink, PcapError,
+    };
+
+    use super::{PacketSink, PcapNgSink};
+
+    #[test]
+    fn multiple_interfaces() {
+        let mut sink = PcapNgSink::new(Vec::new()).unwrap();
+        assert_eq!(
+            sink.add_interface(DataLink::ETHERNET, Some("eth"), None)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            sink.add_interface(DataLink::USER0, Some("user"), Some("User data"))
+                .unwrap(),
+            1
+        );
+        sink.write_packet_for(1, Duration::from_micros(1_500_000), &[1, 2])
+            .unwrap();
+        sink.write_packet(Duration::from_secs(2), &[3]).unwrap();
+        let output = sink.into_inner();
+
+        let mut reader = PcapNgReader::new(&output[..]).unwrap();
+        let mut blocks = vec![];
+        while let Some(block) = reader.next_block() {
+            blocks.push(block.unwrap().into_owned());
+        }
+        match &blocks[..] {
+            [Block::InterfaceDescription(eth), Block::InterfaceDescription(user), Block::EnhancedPacket(p1), Block::EnhancedPacket(p2)] =>
+            {
+                assert_eq!(eth.linktype, DataLink::ETHERNET);
+                assert_eq!(user.linktype, DataLink::USER0);
+                assert!(user
+                    .options
+                    .contains(&InterfaceDescriptionOption::IfTsResol(9)));
+                assert_eq!(
+                    (p1.interface_id, p1.timestamp, &p1.data[..]),
+                    (1, Duration::from_micros(1_500_000), &[1, 2][..])
+                );
+                assert_eq!(
+                    (p2.interface_id, p2.timestamp, &p2.data[..]),
+                    (0, Duration::from_secs(2), &[3][..])
+                );
+            }
+            _ => panic!("Unexpected blocks {blocks:?}"),
+        }
+    }
+
+    #[test]
+    fn pcap_resolution() {
+        use pcap_file::pcap::PcapReader;
+
+        use super::{PcapSink, TimestampResolution};
+
+        let timestamp = Duration::new(1, 123_456_789);
+        for (resolution, magic, expected) in [
+            (
+                TimestampResolution::Microsecond,
+                0xa1b2_c3d4_u32,
+                Duration::new(1, 123_456_000),
+            ),
+            (TimestampResolution::Nanosecond, 0xa1b2_3c4d, timestamp),
+        ] {
+            let mut sink =
+                PcapSink::with_resolution(Vec::new(), DataLink::ETHERNET, resolution).unwrap();
+            assert_eq!(sink.resolution(), resolution);
+            sink.write_packet(timestamp, &[1]).unwrap();
+            let output = sink.into_inner();
+            assert_eq!(output[..4], magic.to_be_bytes());
+            let mut reader = PcapReader::new(&output[..]).unwrap();
+            let packet = reader.next_packet().unwrap().unwrap();
+            assert_eq!(packet.timestamp, expected);
+            assert_eq!(resolution.truncate(timestamp), expected);
+        }
+    }
+
+    #[test]
+    fn pcapng_resolution() {
+        use super::TimestampResolution;
+
+        let mut sink =
+            PcapNgSink::with_resolution(Vec::new(), TimestampResolution::Microsecond).unwrap();
+        sink.add_interface(DataLink::ETHERNET, None, None).unwrap();
+        sink.write_packet(Duration::new(1, 123_456_789), &[1])
+            .unwrap();
+        let output = sink.into_inner();
+
+        let mut reader = PcapNgReader::new(&output[..]).unwrap();
+        let mut blocks = vec![];
+        while let Some(block) = reader.next_block() {
+            blocks.push(block.unwrap().into_owned());
+        }
+        match &blocks[..] {
+            [Block::InterfaceDescription(idb), Block::EnhancedPacket(packet)] => {
+                assert!(idb
+                    .options
+                    .contains(&InterfaceDescriptionOption::IfTsResol(6)));
+                // `pcap-file` reads the raw value as nanoseconds, regardless
+                // of `if_tsresol`.
+                assert_eq!(
+                    TimestampResolution::Microsecond.from_units(packet.timestamp.as_nanos() as u64),
+                    Duration::new(1, 123_456_000)
+                );
+            }
+            _ => panic!("Unexpected blocks {blocks:?}"),
+        }
+    }
+
+    #[test]
+    fn packet_info() {
+        use pcap_file::pcapng::blocks::enhanced_packet::EnhancedPacketOption;
+
+        use super::{Direction, PacketInfo};
+
+        let mut sink = PcapNgSink::new(Vec::new()).unwrap();
+        sink.add_interface(DataLink::ETHERNET, Some("a"), None)
+            .unwrap();
+        sink.add_interface(DataLink::ETHERNET, Some("b"), None)
+            .unwrap();
+        sink.write_packet_with_info(
+            Duration::ZERO,
+            &[1],
+            &PacketInfo::builder()
+                .interface_id(1)
+                .direction(Direction::Outbound)
+                .dropped(3)
+                .build(),
+        )
+        .unwrap();
+        sink.write_packet_with_info(
+            Duration::ZERO,
+            &[2],
+            &PacketInfo::builder().direction(Direction::Inbound).build(),
+        )
+        .unwrap();
+        let output = sink.into_inner();
+
+        let mut reader = PcapNgReader::new(&output[..]).unwrap();
+        let mut packets = vec![];
+        while let Some(block) = reader.next_block() {
+            if let Block::EnhancedPacket(packet) = block.unwrap().into_owned() {
+                packets.push((packet.interface_id, packet.options));
+            }
+        }
+        assert_eq!(
+            packets,
+            [
+                (
+                    1,
+                    vec![
+                        EnhancedPacketOption::Flags(2),
+                        EnhancedPacketOption::DropCount(3)
+                    ]
+                ),
+                (0, vec![EnhancedPacketOption::Flags(1)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_custom_blocks() {
+        use pcap_file::pcapng::blocks::enhanced_packet::EnhancedPacketOption;
+
+        use super::PacketInfo;
+
+        let mut sink = PcapNgSink::new(Vec::new()).unwrap();
+        sink.add_interface(DataLink::ETHERNET, None, None).unwrap();
+        sink.write_custom_block(32473, b"firmware 1.2").unwrap();
+        sink.write_packet_with_info(
+            Duration::ZERO,
+            &[1],
+            &PacketInfo::builder().comment("CRC error").build(),
+        )
+        .unwrap();
+        let output = sink.into_inner();
+
+        let mut reader = PcapNgReader::new(&output[..]).unwrap();
+        let mut blocks = vec![];
+        while let Some(block) = reader.next_block() {
+            blocks.push(block.unwrap().into_owned());
+        }
+        match &blocks[..] {
+            [Block::InterfaceDescription(_), Block::Unknown(custom), Block::EnhancedPacket(packet)] =>
+            {
+                assert_eq!(custom.type_, 0x0bad);
+                assert_eq!(
+                    &custom.value[..],
+                    [&32473_u32.to_ne_bytes()[..], b"firmware 1.2"].concat()
+                );
+                assert_eq!(
+                    packet.options,
+                    [EnhancedPacketOption::Comment("CRC error".into())]
+                );
+            }
+            _ => panic!("Unexpected blocks {blocks:?}"),
+        }
+    }
+
+    #[test]
+    fn interface_id_lookup() {
+        let mut sink = PcapNgSink::new(Vec::new()).unwrap();
+        sink.add_interface(DataLink::ETHERNET, Some("a"), None)
+            .unwrap();
+        sink.add_interface(DataLink::ETHERNET, Some("b"), None)
+            .unwrap();
+        assert_eq!(sink.interface_id("b"), Some(1));
+        assert_eq!(sink.interface_id("c"), None);
+    }
+
+    #[test]
+    fn invalid_interface_id() {
+        let mut sink = PcapNgSink::new(Vec::new()).unwrap();
+        assert!(matches!(
+            sink.write_packet(Duration::ZERO, &[]),
+            Err(PcapError::InvalidInterfaceId(0))
+        ));
+    }
+}
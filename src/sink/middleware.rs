@@ -0,0 +1,404 @@
+use std::{borrow::Cow, time::Duration};
+
+use pcap_file::PcapError;
+
+use super::{PacketInfo, PacketSink};
+
+/// A packet on its way through the middleware of a [`MiddlewareSink`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SinkPacket<'a> {
+    /// The time the packet was captured, as the duration since the Unix epoch.
+    pub timestamp: Duration,
+    /// The data of the packet. Middleware can replace it, for example to strip
+    /// a device header.
+    pub data: Cow<'a, [u8]>,
+    /// The metadata of the packet. Middleware can add to it, for example a
+    /// comment about a bad checksum.
+    pub info: PacketInfo,
+}
+
+impl SinkPacket<'_> {
+    /// Shortens the data to `len` bytes, without copying it. Has no effect if
+    /// the data is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        match &mut self.data {
+            Cow::Borrowed(data) => *data = &data[..len.min(data.len())],
+            Cow::Owned(data) => data.truncate(len),
+        }
+    }
+}
+
+/// What to do with a packet after a [`SinkMiddleware`] processed it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Verdict {
+    /// Pass the packet to the next middleware, or write it to the sink.
+    Pass,
+    /// Drop the packet. It is not passed to the rest of the middleware.
+    Drop,
+}
+
+/// A hook that processes each packet before it is written to a sink, to
+/// validate, rewrite or drop it. See [`MiddlewareSink`].
+///
+/// This is implemented for closures with the signature
+/// `FnMut(&mut SinkPacket) -> Verdict`.
+pub trait SinkMiddleware: Send {
+    /// Processes `packet`, and returns whether to keep it.
+    fn process(&mut self, packet: &mut SinkPacket<'_>) -> Verdict;
+}
+
+impl<F> SinkMiddleware for F
+where
+    F: FnMut(&mut SinkPacket<'_>) -> Verdict + Send,
+{
+    fn process(&mut self, packet: &mut SinkPacket<'_>) -> Verdict {
+        self(packet)
+    }
+}
+
+/// Statistics of the packets that went through a [`MiddlewareSink`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SinkStats {
+    /// The number of packets written to the sink.
+    pub written_packets: u64,
+    /// The number of bytes written to the sink, after any rewriting by the
+    /// middleware.
+    pub written_bytes: u64,
+    /// The number of packets dropped by the middleware.
+    pub dropped_packets: u64,
+}
+
+/// A sink that passes each packet through a chain of [`SinkMiddleware`]
+/// before writing it to the inner sink, giving a single place for the
+/// validation and accounting that would otherwise be spread through the
+/// capture loop.
+///
+/// The middleware run in the order they were added, and a packet dropped by
+/// one of them is not passed to the rest.
+///
+/// ```
+/// use r_extcap::interface::DataLink;
+/// use r_extcap::sink::{ChecksumValidator, PacketSink, PcapSink, SinkPacket, Verdict};
+/// use std::time::Duration;
+///
+/// # let fifo = Vec::new();
+/// let mut sink = PcapSink::new(fifo, DataLink::USER0)
+///     .unwrap()
+///     // The device appends the sum of the bytes of each frame.
+///     .with_middleware(
+///         ChecksumValidator::new(1, |data: &[u8], trailer: &[u8]| {
+///             data.iter().fold(0_u8, |sum, byte| sum.wrapping_add(*byte)) == trailer[0]
+///         })
+///         .strip_trailer(true),
+///     )
+///     .with_middleware(|packet: &mut SinkPacket| match packet.data.is_empty() {
+///         true => Verdict::Drop,
+///         false => Verdict::Pass,
+///     });
+/// sink.write_packet(Duration::from_secs(1), &[1, 2, 3]).unwrap();
+/// sink.write_packet(Duration::from_secs(2), &[1, 2, 3, 7]).unwrap();
+/// assert_eq!(sink.stats().written_packets, 1);
+/// assert_eq!(sink.stats().dropped_packets, 1);
+/// ```
+pub struct MiddlewareSink<S: PacketSink> {
+    sink: S,
+    middleware: Vec<Box<dyn SinkMiddleware>>,
+    stats: SinkStats,
+}
+
+impl<S: PacketSink> MiddlewareSink<S> {
+    /// Creates a sink that writes to `sink`, with no middleware yet.
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            middleware: Vec::new(),
+            stats: SinkStats::default(),
+        }
+    }
+
+    /// Appends `middleware` to the end of the chain.
+    #[must_use]
+    pub fn with_middleware(mut self, middleware: impl SinkMiddleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// The statistics of the packets written so far.
+    pub fn stats(&self) -> SinkStats {
+        self.stats
+    }
+
+    /// The inner sink.
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+
+    /// Consumes this sink and returns the inner sink.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<S: PacketSink> PacketSink for MiddlewareSink<S> {
+    fn write_packet(&mut self, timestamp: Duration, data: &[u8]) -> Result<(), PcapError> {
+        self.write_packet_with_info(timestamp, data, &PacketInfo::default())
+    }
+
+    fn write_packet_with_info(
+        &mut self,
+        timestamp: Duration,
+        data: &[u8],
+        info: &PacketInfo,
+    ) -> Result<(), PcapError> {
+        let mut packet = SinkPacket {
+            timestamp,
+            data: Cow::Borrowed(data),
+            info: info.clone(),
+        };
+        for middleware in &mut self.middleware {
+            if middleware.process(&mut packet) == Verdict::Drop {
+                self.stats.dropped_packets += 1;
+                return Ok(());
+            }
+        }
+        self.sink
+            .write_packet_with_info(packet.timestamp, &packet.data, &packet.info)?;
+        self.stats.written_packets += 1;
+        self.stats.written_bytes += packet.data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Middleware that validates a checksum or CRC at the end of each packet, as
+/// appended by many sniffer devices.
+///
+/// Packets shorter than the trailer, or whose trailer does not match, are
+/// dropped, unless [`drop_invalid`][Self::drop_invalid] is turned off, in
+/// which case they are kept with a comment. See [`MiddlewareSink`] for an
+/// example.
+pub struct ChecksumValidator<F> {
+    trailer_len: usize,
+    check: F,
+    strip_trailer: bool,
+    drop_invalid: bool,
+}
+
+impl<F> ChecksumValidator<F>
+where
+    F: FnMut(&[u8], &[u8]) -> bool + Send,
+{
+    /// Creates a validator for packets ending with a checksum of
+    /// `trailer_len` bytes. `check` is called with the data before the
+    /// trailer and the trailer, and returns whether the checksum matches.
+    pub fn new(trailer_len: usize, check: F) -> Self {
+        Self {
+            trailer_len,
+            check,
+            strip_trailer: false,
+            drop_invalid: true,
+        }
+    }
+
+    /// Removes the trailer from valid packets, for devices whose checksum is
+    /// not part of the protocol being dissected.
+    #[must_use]
+    pub fn strip_trailer(mut self, strip_trailer: bool) -> Self {
+        self.strip_trailer = strip_trailer;
+        self
+    }
+
+    /// Whether to drop packets with an invalid checksum. If `false`, they are
+    /// written with the comment "Invalid checksum", which can be filtered
+    /// with `frame.comment`.
+    #[must_use]
+    pub fn drop_invalid(mut self, drop_invalid: bool) -> Self {
+        self.drop_invalid = drop_invalid;
+        self
+    }
+}
+
+impl<F> SinkMiddleware for ChecksumValidator<F>
+where
+    F: FnMut(&[u8], &[u8]) -> bool + Send,
+{
+    fn process(&mut self, packet: &mut SinkPacket<'_>) -> Verdict {
+        let valid = packet
+            .data
+            .len()
+            .checked_sub(self.trailer_len)
+            .filter(|&len| {
+                let (data, trailer) = packet.data.split_at(len);
+                (self.check)(data, trailer)
+            });
+        match valid {
+            Some(len) => {
+                if self.strip_trailer {
+                    packet.truncate(len);
+                }
+                Verdict::Pass
+            }
+            None if self.drop_invalid => Verdict::Drop,
+            None => {
+                packet.info.comment = Some(String::from("Invalid checksum"));
+                Verdict::Pass
+            }
+        }
+    }
+}
+
+/// Middleware that drops packets that don't match the capture filter given in
+/// [`--extcap-capture-filter`][crate::ExtcapArgs::extcap_capture_filter], for
+/// captures where the filter cannot be applied by the device itself.
+///
+/// The filter is either a predicate written by the extcap, for filters in its
+/// own syntax, or with the `bpf` feature, a BPF filter compiled with
+/// [`bpf`][Self::bpf] or
+/// [`CaptureContext::compile_capture_filter`][crate::CaptureContext::compile_capture_filter].
+///
+/// ```
+/// use r_extcap::interface::DataLink;
+/// use r_extcap::sink::{CaptureFilter, PacketSink, PcapSink};
+/// use std::time::Duration;
+///
+/// # let fifo = Vec::new();
+/// // The capture filter is the hex value of the first byte, like "0x01".
+/// # let capture_filter = String::from("0x01");
+/// let first_byte = u8::from_str_radix(capture_filter.trim_start_matches("0x"), 16).unwrap();
+/// let mut sink = PcapSink::new(fifo, DataLink::USER0)
+///     .unwrap()
+///     .with_middleware(CaptureFilter::new(move |packet: &[u8]| {
+///         packet.first() == Some(&first_byte)
+///     }));
+/// sink.write_packet(Duration::from_secs(1), &[0x01, 0xff]).unwrap();
+/// sink.write_packet(Duration::from_secs(2), &[0x02, 0xff]).unwrap();
+/// assert_eq!(sink.stats().written_packets, 1);
+/// ```
+pub struct CaptureFilter {
+    predicate: Box<FilterPredicate>,
+}
+
+type FilterPredicate = dyn FnMut(&[u8]) -> bool + Send;
+
+impl CaptureFilter {
+    /// Creates a filter that keeps the packets for which `predicate` returns
+    /// `true`.
+    pub fn new(predicate: impl FnMut(&[u8]) -> bool + Send + 'static) -> Self {
+        Self {
+            predicate: Box::new(predicate),
+        }
+    }
+
+    /// Compiles the BPF `filter` for packets of the given `datalink` type,
+    /// using [`FilterMatcher`][crate::bpf::FilterMatcher].
+    #[cfg(feature = "bpf")]
+    pub fn bpf(
+        filter: &str,
+        datalink: pcap_file::DataLink,
+    ) -> Result<Self, crate::bpf::FilterError> {
+        let matcher = crate::bpf::FilterMatcher::new(filter, datalink)?;
+        Ok(Self::new(move |packet: &[u8]| matcher.matches(packet)))
+    }
+
+    /// Returns whether `packet` matches the filter.
+    pub fn matches(&mut self, packet: &[u8]) -> bool {
+        (self.predicate)(packet)
+    }
+}
+
+impl std::fmt::Debug for CaptureFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CaptureFilter").finish_non_exhaustive()
+    }
+}
+
+impl SinkMiddleware for CaptureFilter {
+    fn process(&mut self, packet: &mut SinkPacket<'_>) -> Verdict {
+        match self.matches(&packet.data) {
+            true => Verdict::Pass,
+            false => Verdict::Drop,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{borrow::Cow, time::Duration};
+
+    use pcap_file::pcapng::{Block, PcapNgReader};
+
+    use super::{ChecksumValidator, MiddlewareSink, SinkPacket, SinkStats, Verdict};
+    use crate::{
+        interface::DataLink,
+        sink::{PacketInfo, PacketSink, PcapNgSink},
+    };
+
+    fn xor_check(data: &[u8], trailer: &[u8]) -> bool {
+        data.iter().fold(0, |xor, byte| xor ^ byte) == trailer[0]
+    }
+
+    #[test]
+    fn chain() {
+        let mut sink = PcapNgSink::new(Vec::new()).unwrap();
+        sink.add_interface(DataLink::USER0, None, None).unwrap();
+        let mut sink = MiddlewareSink::new(sink)
+            .with_middleware(ChecksumValidator::new(1, xor_check).drop_invalid(false))
+            .with_middleware(|packet: &mut SinkPacket| {
+                packet.data = Cow::Owned(packet.data.to_ascii_uppercase());
+                Verdict::Pass
+            })
+            .with_middleware(|packet: &mut SinkPacket| match &packet.data[..] {
+                [b'X', ..] => Verdict::Drop,
+                _ => Verdict::Pass,
+            });
+        sink.write_packet(Duration::from_secs(1), b"ab\x03")
+            .unwrap();
+        sink.write_packet(Duration::from_secs(2), b"xy\x01")
+            .unwrap();
+        sink.write_packet_with_info(
+            Duration::from_secs(3),
+            b"cd\x00",
+            &PacketInfo::builder().interface_id(0).build(),
+        )
+        .unwrap();
+        assert_eq!(
+            sink.stats(),
+            SinkStats {
+                written_packets: 2,
+                written_bytes: 6,
+                dropped_packets: 1
+            }
+        );
+
+        let output = sink.into_inner().into_inner();
+        let mut reader = PcapNgReader::new(&output[..]).unwrap();
+        let mut packets = Vec::new();
+        while let Some(block) = reader.next_block() {
+            if let Block::EnhancedPacket(packet) = block.unwrap() {
+                packets.push((packet.data.into_owned(), packet.options.len()));
+            }
+        }
+        // The invalid packet is kept with a comment.
+        assert_eq!(packets, [(b"AB\x03".to_vec(), 0), (b"CD\x00".to_vec(), 1)]);
+    }
+
+    #[test]
+    fn checksum_validator() {
+        let mut validator = ChecksumValidator::new(2, |_: &[u8], trailer: &[u8]| trailer == b"ok")
+            .strip_trailer(true);
+        let mut packet = SinkPacket {
+            timestamp: Duration::ZERO,
+            data: Cow::Borrowed(b"dataok"),
+            info: PacketInfo::default(),
+        };
+        assert_eq!(
+            super::SinkMiddleware::process(&mut validator, &mut packet),
+            Verdict::Pass
+        );
+        assert_eq!(packet.data, Cow::Borrowed(b"data"));
+        packet.data = Cow::Borrowed(b"k");
+        assert_eq!(
+            super::SinkMiddleware::process(&mut validator, &mut packet),
+            Verdict::Drop
+        );
+    }
+}
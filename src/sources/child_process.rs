@@ -0,0 +1,310 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdout, Command, ExitStatus, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use pcap_file::{
+    pcap::{PcapHeader, PcapPacket, PcapWriter},
+    DataLink, PcapError,
+};
+use thiserror::Error;
+
+use crate::{
+    pcap_stream::{forward_pcap_stream, PcapStreamError},
+    sink::CaptureFilter,
+};
+
+/// Parses frames out of the stdout of a child process. See
+/// [`ChildProcessSource::frame_parser`].
+///
+/// This is implemented for closures with the signature
+/// `FnMut(&mut dyn BufRead) -> std::io::Result<Option<Vec<u8>>>`.
+pub trait FrameParser: Send {
+    /// Reads the next frame from `reader`. Returns `Ok(None)` when there are
+    /// no more frames, which typically means the child process has closed its
+    /// stdout.
+    fn next_frame(&mut self, reader: &mut dyn BufRead) -> std::io::Result<Option<Vec<u8>>>;
+}
+
+impl<F> FrameParser for F
+where
+    F: FnMut(&mut dyn BufRead) -> std::io::Result<Option<Vec<u8>>> + Send,
+{
+    fn next_frame(&mut self, reader: &mut dyn BufRead) -> std::io::Result<Option<Vec<u8>>> {
+        self(reader)
+    }
+}
+
+/// Error running a [`ChildProcessSource`].
+#[derive(Debug, Error)]
+pub enum ChildProcessError {
+    /// Error spawning the child process.
+    #[error("Failed to spawn child process")]
+    Spawn(#[source] std::io::Error),
+    /// IO error reading from the child process or writing to the fifo.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The pcap or pcapng output of the child process is malformed.
+    #[error(transparent)]
+    Stream(#[from] PcapStreamError),
+    /// Error writing the parsed frames to the fifo.
+    #[error(transparent)]
+    Pcap(#[from] PcapError),
+    /// The child process exited unsuccessfully.
+    #[error("Child process exited with {0}")]
+    ChildFailed(ExitStatus),
+}
+
+/// A packet source that spawns an external command, typically a vendor CLI
+/// sniffer, and forwards the packets it writes to stdout into the capture fifo.
+///
+/// By default the child is expected to write a pcap or pcapng stream, which is
+/// validated and forwarded using
+/// [`forward_pcap_stream`][crate::pcap_stream::forward_pcap_stream]. For tools
+/// with their own output format, use [`frame_parser`][Self::frame_parser] to
+/// split the output into frames, which are then written to the fifo as pcap.
+///
+/// When Wireshark stops the capture, it sends `SIGTERM` to the extcap. On
+/// Linux, the child process is set up to receive `SIGTERM` when the extcap
+/// process dies, so it does not outlive the capture. The child is also killed
+/// when the source is dropped, for example when returning early due to an
+/// error.
+///
+/// ```no_run
+/// use std::process::Command;
+/// use r_extcap::sources::ChildProcessSource;
+/// # let fifo = Vec::new();
+///
+/// let mut command = Command::new("tcpdump");
+/// command.args(["-U", "-w", "-"]);
+/// ChildProcessSource::new(command).run(fifo).unwrap();
+/// ```
+pub struct ChildProcessSource {
+    command: Command,
+    parser: Option<(DataLink, Box<dyn FrameParser>)>,
+    capture_filter: Option<CaptureFilter>,
+    child: Option<Child>,
+}
+
+impl ChildProcessSource {
+    /// Creates a new source that will run `command`. Stdout of the command is
+    /// piped to this source, and stdin is closed. Stderr is inherited, so that
+    /// error messages from the child show up in Wireshark's logs.
+    pub fn new(command: Command) -> Self {
+        Self {
+            command,
+            parser: None,
+            capture_filter: None,
+            child: None,
+        }
+    }
+
+    /// Parse the output of the child using `parser` instead of treating it as
+    /// a pcap stream. Each frame returned by the parser is written to the fifo
+    /// as a pcap packet with the given `datalink`, timestamped with the time
+    /// it was read.
+    ///
+    /// ```no_run
+    /// use std::{io::BufRead, process::Command};
+    /// use r_extcap::sources::ChildProcessSource;
+    /// use pcap_file::DataLink;
+    /// # let fifo = Vec::new();
+    ///
+    /// // Each line of output from `vendor-sniffer` is one packet.
+    /// ChildProcessSource::new(Command::new("vendor-sniffer"))
+    ///     .frame_parser(DataLink::USER0, |reader: &mut dyn BufRead| {
+    ///         let mut line = Vec::new();
+    ///         match reader.read_until(b'\n', &mut line)? {
+    ///             0 => Ok(None),
+    ///             _ => Ok(Some(line)),
+    ///         }
+    ///     })
+    ///     .run(fifo)
+    ///     .unwrap();
+    /// ```
+    pub fn frame_parser(mut self, datalink: DataLink, parser: impl FrameParser + 'static) -> Self {
+        self.parser = Some((datalink, Box::new(parser)));
+        self
+    }
+
+    /// Drops the frames returned by the [`frame_parser`][Self::frame_parser]
+    /// that don't match `filter`, which is typically created from the
+    /// [capture filter][crate::CaptureContext::capture_filter] of the capture.
+    ///
+    /// The filter is not applied to pcap output of the child, which is
+    /// forwarded as is. For those commands, pass the capture filter to the
+    /// command instead, like `tcpdump` does with its trailing arguments.
+    #[must_use]
+    pub fn capture_filter(mut self, filter: CaptureFilter) -> Self {
+        self.capture_filter = Some(filter);
+        self
+    }
+
+    /// Spawns the child process and forwards its output to `fifo` until the
+    /// child closes its stdout. Returns the number of records (for pcap
+    /// output) or frames (when using a [`FrameParser`]) forwarded.
+    ///
+    /// Returns [`ChildProcessError::ChildFailed`] if the child exits with a
+    /// non-zero status.
+    pub fn run<W: Write>(mut self, fifo: W) -> Result<u64, ChildProcessError> {
+        let stdout = self.spawn()?;
+        let result = self.forward(stdout, fifo);
+        let mut child = self.child.take().expect("Child should be spawned");
+        let status = if result.is_ok() {
+            child.wait()?
+        } else if let Some(status) = child.try_wait()? {
+            // If the child has already exited, its exit status is likely more
+            // informative than the (truncated) output.
+            status
+        } else {
+            let _ = child.kill();
+            let _ = child.wait();
+            return result;
+        };
+        if !status.success() {
+            return Err(ChildProcessError::ChildFailed(status));
+        }
+        result
+    }
+
+    fn forward<W: Write>(
+        &mut self,
+        stdout: ChildStdout,
+        fifo: W,
+    ) -> Result<u64, ChildProcessError> {
+        let Some((datalink, parser)) = &mut self.parser else {
+            if self.capture_filter.is_some() {
+                log::warn!("Capture filter is not applied to the pcap output of the child");
+            }
+            return Ok(forward_pcap_stream(stdout, fifo)?);
+        };
+        let mut reader = BufReader::new(stdout);
+        let header = PcapHeader {
+            datalink: *datalink,
+            ..Default::default()
+        };
+        let mut pcap_writer = PcapWriter::with_header(fifo, header)?;
+        let mut count = 0;
+        while let Some(frame) = parser.next_frame(&mut reader)? {
+            if let Some(filter) = &mut self.capture_filter {
+                if !filter.matches(&frame) {
+                    continue;
+                }
+            }
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            pcap_writer.write_packet(&PcapPacket::new(timestamp, frame.len() as u32, &frame))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn spawn(&mut self) -> Result<ChildStdout, ChildProcessError> {
+        self.command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::process::CommandExt;
+            // Safety: `prctl` is async-signal-safe and does not allocate.
+            unsafe {
+                self.command.pre_exec(|| {
+                    if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        log::debug!("Spawning child process {:?}", self.command);
+        let mut child = self.command.spawn().map_err(ChildProcessError::Spawn)?;
+        let stdout = child.stdout.take().expect("Stdout should be piped");
+        self.child = Some(child);
+        Ok(stdout)
+    }
+}
+
+impl Drop for ChildProcessSource {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{io::BufRead, process::Command};
+
+    use pcap_file::{pcap::PcapReader, DataLink};
+
+    use super::{ChildProcessError, ChildProcessSource};
+    use crate::sink::CaptureFilter;
+
+    fn read_line(reader: &mut dyn BufRead) -> std::io::Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line)),
+        }
+    }
+
+    #[test]
+    fn frame_parser() {
+        let mut command = Command::new("printf");
+        command.arg("abc\\ndefg\\n");
+        let mut output = Vec::new();
+        let count = ChildProcessSource::new(command)
+            .frame_parser(DataLink::USER0, read_line)
+            .run(&mut output)
+            .unwrap();
+        assert_eq!(count, 2);
+
+        let mut reader = PcapReader::new(&output[..]).unwrap();
+        assert_eq!(reader.header().datalink, DataLink::USER0);
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, &b"abc\n"[..]);
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, &b"defg\n"[..]);
+        assert!(reader.next_packet().is_none());
+    }
+
+    #[test]
+    fn capture_filter() {
+        let mut command = Command::new("printf");
+        command.arg("abc\\nxyz\\nabd\\n");
+        let mut output = Vec::new();
+        let count = ChildProcessSource::new(command)
+            .frame_parser(DataLink::USER0, read_line)
+            .capture_filter(CaptureFilter::new(|frame: &[u8]| frame.starts_with(b"ab")))
+            .run(&mut output)
+            .unwrap();
+        assert_eq!(count, 2);
+
+        let mut reader = PcapReader::new(&output[..]).unwrap();
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, &b"abc\n"[..]);
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, &b"abd\n"[..]);
+        assert!(reader.next_packet().is_none());
+    }
+
+    #[test]
+    fn child_failed() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "exit 3"]);
+        let result = ChildProcessSource::new(command)
+            .frame_parser(DataLink::USER0, read_line)
+            .run(Vec::new());
+        match result {
+            Err(ChildProcessError::ChildFailed(status)) => assert_eq!(status.code(), Some(3)),
+            other => panic!("Unexpected result {other:?}"),
+        }
+    }
+
+    #[test]
+    fn spawn_failed() {
+        let result = ChildProcessSource::new(Command::new("/nonexistent/command")).run(Vec::new());
+        assert!(matches!(result, Err(ChildProcessError::Spawn(_))));
+    }
+}
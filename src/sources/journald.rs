@@ -0,0 +1,203 @@
+//! A packet source for systemd journal entries, for extcaps that present logs
+//! as packets.
+//!
+//! [`JournaldSource`] follows the journal using `journalctl`, and writes each
+//! entry as a [`DataLink::WIRESHARK_UPPER_PDU`] packet addressed to
+//! Wireshark's `systemd_journal` dissector, which shows the fields of the
+//! entry in the packet details. Other log sources can use
+//! [`ExportedPdu`] to hand their records to a Wireshark dissector the same
+//! way.
+
+use std::{
+    io::{BufRead, Read, Write},
+    process::Command,
+};
+
+use pcap_file::DataLink;
+
+use super::{ChildProcessError, ChildProcessSource};
+use crate::exported_pdu::ExportedPdu;
+
+/// The name of Wireshark's dissector for entries in the journal export format.
+pub const SYSTEMD_JOURNAL_DISSECTOR: &str = "systemd_journal";
+
+/// Reads the next entry in the [journal export format] from `reader`, as
+/// written by `journalctl --output=export`. Returns the fields of the entry,
+/// without the empty line that ends it, or `None` at the end of the stream.
+///
+/// [journal export format]: https://systemd.io/JOURNAL_EXPORT_FORMATS/
+pub fn read_export_entry(reader: &mut dyn BufRead) -> std::io::Result<Option<Vec<u8>>> {
+    let mut entry = Vec::new();
+    loop {
+        let start = entry.len();
+        if reader.read_until(b'\n', &mut entry)? == 0 {
+            if entry.is_empty() {
+                return Ok(None);
+            }
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = &entry[start..];
+        if line == b"\n" {
+            entry.truncate(start);
+            if entry.is_empty() {
+                // Tolerate extra empty lines between entries.
+                continue;
+            }
+            return Ok(Some(entry));
+        }
+        if !line.contains(&b'=') {
+            // A binary field: the name is followed by the little endian
+            // 64-bit length of the value, the value and a newline.
+            let mut len = [0_u8; 8];
+            reader.read_exact(&mut len)?;
+            entry.extend_from_slice(&len);
+            let len = u64::from_le_bytes(len);
+            let read = (&mut *reader).take(len + 1).read_to_end(&mut entry)?;
+            if read as u64 != len + 1 {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+/// A packet source that follows the systemd journal using `journalctl`, and
+/// writes the matching entries into the capture fifo.
+///
+/// Each entry is written as a [`DataLink::WIRESHARK_UPPER_PDU`] packet for the
+/// [`systemd_journal`][SYSTEMD_JOURNAL_DISSECTOR] dissector, timestamped with
+/// the time it was read. The fields of the entry, including
+/// `__REALTIME_TIMESTAMP`, are shown in the packet details.
+///
+/// This requires `journalctl`, so it only works on Linux systems running
+/// systemd. Elsewhere, [`run`][Self::run] returns
+/// [`ChildProcessError::Spawn`].
+///
+/// ```no_run
+/// use r_extcap::sources::journald::JournaldSource;
+/// # let fifo = Vec::new();
+///
+/// JournaldSource::new()
+///     .unit("sshd.service")
+///     .priority(4)
+///     .run(fifo)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct JournaldSource {
+    matches: Vec<String>,
+    units: Vec<String>,
+    priority: Option<u8>,
+    lines: u32,
+}
+
+impl JournaldSource {
+    /// Creates a source for all new entries in the journal.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only includes entries where the journal field `field` is `value`, like
+    /// `_COMM=sshd`. Matches on the same field are combined with OR, and
+    /// matches on different fields with AND.
+    pub fn match_field(mut self, field: &str, value: &str) -> Self {
+        self.matches.push(format!("{field}={value}"));
+        self
+    }
+
+    /// Only includes entries from the systemd unit `unit`. If called multiple
+    /// times, entries from any of the units are included.
+    pub fn unit(mut self, unit: &str) -> Self {
+        self.units.push(unit.to_owned());
+        self
+    }
+
+    /// Only includes entries with a syslog priority of `max_priority` or more
+    /// important, from 0 (emerg) to 7 (debug).
+    pub fn priority(mut self, max_priority: u8) -> Self {
+        self.priority = Some(max_priority.min(7));
+        self
+    }
+
+    /// Also includes the last `lines` entries already in the journal when the
+    /// capture starts. By default, only new entries are included.
+    pub fn lines(mut self, lines: u32) -> Self {
+        self.lines = lines;
+        self
+    }
+
+    /// The `journalctl` command run by this source.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new("journalctl");
+        command.args([
+            "--follow",
+            "--output=export",
+            &format!("--lines={}", self.lines),
+        ]);
+        for unit in &self.units {
+            command.arg(format!("--unit={unit}"));
+        }
+        if let Some(priority) = self.priority {
+            command.arg(format!("--priority={priority}"));
+        }
+        command.args(&self.matches);
+        command
+    }
+
+    /// Runs `journalctl` and writes the entries to `fifo` until `journalctl`
+    /// exits or the capture is stopped. Returns the number of entries written.
+    pub fn run<W: Write>(self, fifo: W) -> Result<u64, ChildProcessError> {
+        ChildProcessSource::new(self.command())
+            .frame_parser(DataLink::WIRESHARK_UPPER_PDU, |reader: &mut dyn BufRead| {
+                Ok(read_export_entry(reader)?
+                    .map(|entry| ExportedPdu::new(SYSTEMD_JOURNAL_DISSECTOR).encode(&entry)))
+            })
+            .run(fifo)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_export_entry, JournaldSource};
+
+    #[test]
+    fn export_entries() {
+        let mut export = &b"__REALTIME_TIMESTAMP=1700000000000000\nMESSAGE=hello\n\n\
+            MESSAGE\n\x03\x00\x00\x00\x00\x00\x00\x00a\nb\n_PID=1\n\n"[..];
+        assert_eq!(
+            read_export_entry(&mut export).unwrap().unwrap(),
+            b"__REALTIME_TIMESTAMP=1700000000000000\nMESSAGE=hello\n"
+        );
+        assert_eq!(
+            read_export_entry(&mut export).unwrap().unwrap(),
+            b"MESSAGE\n\x03\x00\x00\x00\x00\x00\x00\x00a\nb\n_PID=1\n"
+        );
+        assert_eq!(read_export_entry(&mut export).unwrap(), None);
+
+        let mut truncated = &b"MESSAGE\n\x05\x00\x00\x00\x00\x00\x00\x00ab"[..];
+        assert_eq!(
+            read_export_entry(&mut truncated).unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn command() {
+        let command = JournaldSource::new()
+            .unit("sshd.service")
+            .priority(9)
+            .match_field("_UID", "0")
+            .command();
+        assert_eq!(command.get_program(), "journalctl");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "--follow",
+                "--output=export",
+                "--lines=0",
+                "--unit=sshd.service",
+                "--priority=7",
+                "_UID=0"
+            ]
+        );
+    }
+}
@@ -0,0 +1,14 @@
+//! Reusable packet sources that take care of the boilerplate of getting packets
+//! from somewhere else into the capture fifo.
+
+mod child_process;
+#[cfg(feature = "journald")]
+pub mod journald;
+mod pcap_replay;
+#[cfg(all(feature = "can", target_os = "linux"))]
+pub mod socketcan;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use child_process::{ChildProcessError, ChildProcessSource, FrameParser};
+pub use pcap_replay::{PcapReplayError, PcapReplaySource, ReplayTiming};
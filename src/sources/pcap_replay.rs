@@ -0,0 +1,345 @@
+use std::{
+    fs::File,
+    io::{BufReader, Chain, Cursor, Read},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use pcap_file::{
+    pcap::PcapReader,
+    pcapng::{Block, PcapNgReader},
+    DataLink, PcapError,
+};
+use thiserror::Error;
+
+use crate::{
+    pcap_stream::CaptureFormat,
+    sink::{PacketInfo, PacketSink},
+};
+
+/// Error replaying a capture file with a [`PcapReplaySource`].
+#[derive(Debug, Error)]
+pub enum PcapReplayError {
+    /// Error opening the capture file.
+    #[error("Cannot open capture file {path:?}")]
+    Open {
+        /// The path of the capture file.
+        path: PathBuf,
+        /// The underlying error.
+        #[source]
+        source: std::io::Error,
+    },
+    /// Error reading the capture file.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The file does not start with a pcap magic number or a pcapng section
+    /// header block.
+    #[error("Unrecognized capture format magic number {0:#010x}")]
+    UnknownMagic(u32),
+    /// The pcapng file has no interface description block before its first
+    /// packet, so its data link type is unknown.
+    #[error("Capture file has no interface")]
+    NoInterface,
+    /// Error parsing the capture file or writing the packets to the sink.
+    #[error(transparent)]
+    Pcap(#[from] PcapError),
+}
+
+/// The timing of the packets replayed by a [`PcapReplaySource`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ReplayTiming {
+    /// Packets are written with the same spacing as when they were captured.
+    #[default]
+    Original,
+    /// Packets are written the given times faster than they were captured,
+    /// so `Accelerated(2.0)` replays a 10 second capture in 5 seconds.
+    Accelerated(f64),
+    /// Packets are written as fast as the sink accepts them.
+    Unpaced,
+}
+
+/// The reader for the format of the capture file. The magic number read to
+/// detect the format is chained back in front of the reader.
+enum CaptureReader<R: Read> {
+    Pcap(PcapReader<Chain<Cursor<[u8; 4]>, R>>),
+    PcapNg(PcapNgReader<Chain<Cursor<[u8; 4]>, R>>),
+}
+
+/// A packet source that replays an existing pcap or pcapng file, typically
+/// chosen by the user in a
+/// [`FileSelectConfig`][crate::config::FileSelectConfig], to feed recorded
+/// data into a live Wireshark session.
+///
+/// By default, the packets are written with their original spacing, and their
+/// timestamps are shifted so that the first packet is stamped with the time
+/// the replay started. See [`timing`][Self::timing] and
+/// [`keep_timestamps`][Self::keep_timestamps].
+///
+/// ```no_run
+/// use r_extcap::{config::FileSelectConfig, sink::PcapSink, sources::PcapReplaySource};
+/// # let fifo = Vec::new();
+/// # let file = String::from("recording.pcapng");
+///
+/// let source = PcapReplaySource::open(&FileSelectConfig::parse_value(&file)).unwrap();
+/// let mut sink = PcapSink::new(fifo, source.datalink()).unwrap();
+/// source.run(&mut sink).unwrap();
+/// ```
+pub struct PcapReplaySource<R: Read> {
+    reader: CaptureReader<R>,
+    datalink: DataLink,
+    timing: ReplayTiming,
+    keep_timestamps: bool,
+}
+
+impl PcapReplaySource<BufReader<File>> {
+    /// Opens the capture file at `path` for replay.
+    pub fn open(path: &Path) -> Result<Self, PcapReplayError> {
+        let file = File::open(path).map_err(|source| PcapReplayError::Open {
+            path: path.to_owned(),
+            source,
+        })?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> PcapReplaySource<R> {
+    /// Creates a source replaying the pcap or pcapng stream read from
+    /// `reader`. The header of the stream is read to detect its format and
+    /// data link type.
+    pub fn new(mut reader: R) -> Result<Self, PcapReplayError> {
+        let mut magic = [0_u8; 4];
+        reader.read_exact(&mut magic)?;
+        let format = CaptureFormat::detect(magic)
+            .ok_or(PcapReplayError::UnknownMagic(u32::from_be_bytes(magic)))?;
+        let reader = Cursor::new(magic).chain(reader);
+        let (reader, datalink) = match format {
+            CaptureFormat::Pcap => {
+                let reader = PcapReader::new(reader)?;
+                let datalink = reader.header().datalink;
+                (CaptureReader::Pcap(reader), datalink)
+            }
+            CaptureFormat::PcapNg => {
+                let mut reader = PcapNgReader::new(reader)?;
+                // Packets refer to an interface, so the first interface comes
+                // before any packet.
+                while reader.interfaces().is_empty() {
+                    match reader.next_block().transpose()? {
+                        Some(Block::InterfaceDescription(_)) => {}
+                        Some(Block::EnhancedPacket(_) | Block::SimplePacket(_)) | None => {
+                            return Err(PcapReplayError::NoInterface)
+                        }
+                        Some(_) => {}
+                    }
+                }
+                let datalink = reader.interfaces()[0].linktype;
+                (CaptureReader::PcapNg(reader), datalink)
+            }
+        };
+        Ok(Self {
+            reader,
+            datalink,
+            timing: ReplayTiming::default(),
+            keep_timestamps: false,
+        })
+    }
+
+    /// The data link type of the capture, for creating the sink. For pcapng
+    /// files, this is the data link type of the first interface.
+    pub fn datalink(&self) -> DataLink {
+        self.datalink
+    }
+
+    /// Sets the timing of the replayed packets.
+    #[must_use]
+    pub fn timing(mut self, timing: ReplayTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Writes the packets with the timestamps in the file, instead of shifting
+    /// them to the time of the replay.
+    #[must_use]
+    pub fn keep_timestamps(mut self, keep_timestamps: bool) -> Self {
+        self.keep_timestamps = keep_timestamps;
+        self
+    }
+
+    /// Replays the packets into `sink` until the end of the file, and returns
+    /// the number of packets written.
+    ///
+    /// Packets from pcapng files are written with the interface ID from the
+    /// file, so when replaying a file with multiple interfaces into a
+    /// [`PcapNgSink`][crate::sink::PcapNgSink], the same interfaces should be
+    /// added to the sink first. Simple packet blocks, which have no
+    /// timestamp, are written right after the previous packet.
+    pub fn run(mut self, sink: &mut impl PacketSink) -> Result<u64, PcapReplayError> {
+        let start = Instant::now();
+        let start_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut first_timestamp = None;
+        let mut last_timestamp = Duration::ZERO;
+        let mut count = 0;
+        while let Some((timestamp, data, info)) = self.next_packet()? {
+            let timestamp = timestamp.unwrap_or(last_timestamp);
+            last_timestamp = timestamp;
+            let offset = timestamp.saturating_sub(*first_timestamp.get_or_insert(timestamp));
+            let delay = match self.timing {
+                ReplayTiming::Original => Some(offset),
+                ReplayTiming::Accelerated(speed) => Some(offset.div_f64(speed)),
+                ReplayTiming::Unpaced => None,
+            };
+            if let Some(delay) = delay {
+                if let Some(wait) = delay.checked_sub(start.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+            }
+            let timestamp = if self.keep_timestamps {
+                timestamp
+            } else {
+                start_time + delay.unwrap_or(offset)
+            };
+            sink.write_packet_with_info(timestamp, &data, &info)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reads the next packet, with its timestamp if it has one.
+    #[allow(clippy::type_complexity)]
+    fn next_packet(
+        &mut self,
+    ) -> Result<Option<(Option<Duration>, Vec<u8>, PacketInfo)>, PcapReplayError> {
+        match &mut self.reader {
+            CaptureReader::Pcap(reader) => Ok(reader.next_packet().transpose()?.map(|packet| {
+                (
+                    Some(packet.timestamp),
+                    packet.data.into_owned(),
+                    PacketInfo::default(),
+                )
+            })),
+            CaptureReader::PcapNg(reader) => loop {
+                match reader.next_block().transpose()? {
+                    Some(Block::EnhancedPacket(packet)) => {
+                        let info = PacketInfo::builder()
+                            .interface_id(packet.interface_id)
+                            .build();
+                        return Ok(Some((
+                            Some(packet.timestamp),
+                            packet.data.into_owned(),
+                            info,
+                        )));
+                    }
+                    Some(Block::SimplePacket(packet)) => {
+                        return Ok(Some((
+                            None,
+                            packet.data.into_owned(),
+                            PacketInfo::default(),
+                        )))
+                    }
+                    Some(_) => {}
+                    None => return Ok(None),
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{borrow::Cow, time::Duration};
+
+    use pcap_file::{
+        pcap::{PcapPacket, PcapReader, PcapWriter},
+        pcapng::{
+            blocks::{
+                enhanced_packet::EnhancedPacketBlock,
+                interface_description::InterfaceDescriptionBlock,
+            },
+            PcapNgWriter,
+        },
+        DataLink,
+    };
+
+    use super::{PcapReplayError, PcapReplaySource, ReplayTiming};
+    use crate::sink::PcapSink;
+
+    fn read_packets(output: &[u8]) -> Vec<(Duration, Vec<u8>)> {
+        let mut reader = PcapReader::new(output).unwrap();
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.next_packet() {
+            let packet = packet.unwrap();
+            packets.push((packet.timestamp, packet.data.into_owned()));
+        }
+        packets
+    }
+
+    #[test]
+    fn replay_pcap() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for (i, millis) in [1000, 1100, 1300].into_iter().enumerate() {
+            let data = [i as u8; 4];
+            writer
+                .write_packet(&PcapPacket::new(Duration::from_millis(millis), 4, &data))
+                .unwrap();
+        }
+        let file = writer.into_writer();
+
+        let source = PcapReplaySource::new(&file[..])
+            .unwrap()
+            .timing(ReplayTiming::Accelerated(2.0));
+        assert_eq!(source.datalink(), DataLink::ETHERNET);
+        let mut sink = PcapSink::new(Vec::new(), source.datalink()).unwrap();
+        let start = std::time::Instant::now();
+        assert_eq!(source.run(&mut sink).unwrap(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        let packets = read_packets(&sink.into_inner());
+        assert_eq!(packets[2].1, [2; 4]);
+        // The timestamps are shifted to the time of the replay.
+        assert_eq!(packets[2].0 - packets[0].0, Duration::from_millis(150));
+        assert!(packets[0].0 > Duration::from_secs(1_000_000));
+    }
+
+    #[test]
+    fn replay_pcapng() {
+        let mut writer = PcapNgWriter::new(Vec::new()).unwrap();
+        writer
+            .write_pcapng_block(InterfaceDescriptionBlock::new(DataLink::USER0, 0))
+            .unwrap();
+        for seconds in [10, 3600] {
+            writer
+                .write_pcapng_block(EnhancedPacketBlock {
+                    interface_id: 0,
+                    timestamp: Duration::from_secs(seconds),
+                    original_len: 1,
+                    data: Cow::Borrowed(&[0xaa]),
+                    options: vec![],
+                })
+                .unwrap();
+        }
+        let file = writer.into_inner();
+
+        let source = PcapReplaySource::new(&file[..])
+            .unwrap()
+            .timing(ReplayTiming::Unpaced)
+            .keep_timestamps(true);
+        assert_eq!(source.datalink(), DataLink::USER0);
+        let mut sink = PcapSink::new(Vec::new(), source.datalink()).unwrap();
+        assert_eq!(source.run(&mut sink).unwrap(), 2);
+        assert_eq!(
+            read_packets(&sink.into_inner()),
+            [
+                (Duration::from_secs(10), vec![0xaa]),
+                (Duration::from_secs(3600), vec![0xaa])
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_format() {
+        assert!(matches!(
+            PcapReplaySource::new(&b"GET / HTTP/1.1"[..]),
+            Err(PcapReplayError::UnknownMagic(0x4745_5420))
+        ));
+    }
+}
@@ -0,0 +1,257 @@
+//! A packet source for Linux SocketCAN interfaces, for building CAN bus
+//! extcaps.
+//!
+//! ```no_run
+//! use pcap_file::DataLink;
+//! use r_extcap::{shutdown::ShutdownSignal, sink::PcapSink, sources::socketcan::SocketCanSource};
+//! # let fifo = Vec::new();
+//!
+//! let mut sink = PcapSink::new(fifo, DataLink::CAN_SOCKETCAN).unwrap();
+//! SocketCanSource::new("can0")
+//!     // Only capture standard IDs 0x100 to 0x1ff.
+//!     .filter(0x100, 0x700 | SocketCanSource::EFF_FLAG)
+//!     .with_shutdown(&ShutdownSignal::global())
+//!     .run(&mut sink)
+//!     .unwrap();
+//! ```
+
+use std::{
+    ffi::CString,
+    io::ErrorKind,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pcap_file::PcapError;
+use thiserror::Error;
+
+use crate::{can::CanFrame, shutdown::ShutdownSignal, sink::PacketSink};
+
+/// How often a blocked read checks the [`ShutdownSignal`].
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The `ARPHRD_CAN` hardware type of CAN network interfaces.
+const ARPHRD_CAN: &str = "280";
+
+/// Error capturing from a SocketCAN interface.
+#[derive(Debug, Error)]
+pub enum SocketCanError {
+    /// There is no network interface with the given name.
+    #[error("No such CAN interface {0:?}")]
+    NoSuchInterface(String),
+    /// Error opening or reading from the CAN socket.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Error writing the frames to the sink.
+    #[error(transparent)]
+    Pcap(#[from] PcapError),
+}
+
+/// Returns the names of the CAN network interfaces (including virtual `vcan`
+/// interfaces) on this system, for listing them as extcap interfaces.
+pub fn can_interfaces() -> std::io::Result<Vec<String>> {
+    let mut interfaces = Vec::new();
+    for entry in std::fs::read_dir("/sys/class/net")? {
+        let entry = entry?;
+        let Ok(hw_type) = std::fs::read_to_string(entry.path().join("type")) else {
+            continue;
+        };
+        if hw_type.trim() == ARPHRD_CAN {
+            interfaces.extend(entry.file_name().to_str().map(str::to_owned));
+        }
+    }
+    interfaces.sort();
+    Ok(interfaces)
+}
+
+/// A packet source that captures the frames on a SocketCAN interface, like
+/// `can0` or `vcan0`, and writes them to a sink created with
+/// [`DataLink::CAN_SOCKETCAN`][pcap_file::DataLink::CAN_SOCKETCAN].
+///
+/// Frames are timestamped with the time they were read. See the
+/// [module documentation][self] for an example.
+pub struct SocketCanSource {
+    interface: String,
+    filters: Vec<libc::can_filter>,
+    fd_frames: bool,
+    shutdown: Option<ShutdownSignal>,
+}
+
+impl SocketCanSource {
+    /// The flag in filter IDs and masks for extended identifiers. See
+    /// [`filter`][Self::filter].
+    pub const EFF_FLAG: u32 = CanFrame::EFF_FLAG;
+
+    /// Creates a source capturing all frames on `interface`, including CAN FD
+    /// frames.
+    pub fn new(interface: &str) -> Self {
+        Self {
+            interface: interface.to_owned(),
+            filters: Vec::new(),
+            fd_frames: true,
+            shutdown: None,
+        }
+    }
+
+    /// Only captures frames where `frame_id & mask == id & mask`. If called
+    /// multiple times, frames matching any of the filters are captured.
+    ///
+    /// The ID includes the flags of [`CanFrame::can_id`], so include
+    /// [`EFF_FLAG`][Self::EFF_FLAG] in `mask` to tell standard and extended
+    /// IDs apart.
+    #[must_use]
+    pub fn filter(mut self, id: u32, mask: u32) -> Self {
+        self.filters.push(libc::can_filter {
+            can_id: id,
+            can_mask: mask,
+        });
+        self
+    }
+
+    /// Whether to capture CAN FD frames. Enabled by default.
+    #[must_use]
+    pub fn fd_frames(mut self, fd_frames: bool) -> Self {
+        self.fd_frames = fd_frames;
+        self
+    }
+
+    /// Stops the capture once `signal` is triggered, like
+    /// [`ShutdownSignal::global`]. Otherwise the capture runs until the process
+    /// is terminated.
+    #[must_use]
+    pub fn with_shutdown(mut self, signal: &ShutdownSignal) -> Self {
+        self.shutdown = Some(signal.clone());
+        self
+    }
+
+    fn open(&self) -> Result<OwnedFd, SocketCanError> {
+        let name = CString::new(self.interface.as_str())
+            .map_err(|_| SocketCanError::NoSuchInterface(self.interface.clone()))?;
+        // Safety: `name` is a valid C string.
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(SocketCanError::NoSuchInterface(self.interface.clone()));
+        }
+        // Safety: `socket` has no preconditions, and the returned descriptor
+        // is owned by the `OwnedFd` if it is valid.
+        let socket = unsafe {
+            let fd = libc::socket(libc::PF_CAN, libc::SOCK_RAW, libc::CAN_RAW);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+        if self.fd_frames {
+            set_option(&socket, libc::SOL_CAN_RAW, libc::CAN_RAW_FD_FRAMES, &1_i32)?;
+        }
+        if !self.filters.is_empty() {
+            set_option(
+                &socket,
+                libc::SOL_CAN_RAW,
+                libc::CAN_RAW_FILTER,
+                &self.filters[..],
+            )?;
+        }
+        if self.shutdown.is_some() {
+            let timeout = libc::timeval {
+                tv_sec: 0,
+                tv_usec: SHUTDOWN_POLL_INTERVAL.as_micros() as libc::suseconds_t,
+            };
+            set_option(&socket, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout)?;
+        }
+        // Safety: `sockaddr_can` is plain old data, for which all zeros is
+        // valid.
+        let mut addr: libc::sockaddr_can = unsafe { std::mem::zeroed() };
+        addr.can_family = libc::AF_CAN as libc::sa_family_t;
+        addr.can_ifindex = ifindex as libc::c_int;
+        // Safety: `addr` is a valid `sockaddr_can` of the given length.
+        let result = unsafe {
+            libc::bind(
+                socket.as_raw_fd(),
+                (&addr as *const libc::sockaddr_can).cast(),
+                std::mem::size_of::<libc::sockaddr_can>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(socket)
+    }
+
+    /// Captures frames from the interface and writes them to `sink` until the
+    /// [shutdown signal][Self::with_shutdown] is triggered, and returns the
+    /// number of frames written.
+    pub fn run(self, sink: &mut impl PacketSink) -> Result<u64, SocketCanError> {
+        let socket = self.open()?;
+        let mut buf = [0_u8; 72];
+        let mut count = 0;
+        while !self
+            .shutdown
+            .as_ref()
+            .is_some_and(ShutdownSignal::is_triggered)
+        {
+            // Safety: `buf` is valid for writes of its length.
+            let len = unsafe { libc::read(socket.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+            if len < 0 {
+                let error = std::io::Error::last_os_error();
+                match error.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted => {
+                        continue
+                    }
+                    _ => return Err(error.into()),
+                }
+            }
+            let Some(frame) = CanFrame::from_socketcan(&buf[..len as usize]) else {
+                log::warn!("Ignoring CAN frame of unexpected length {len}");
+                continue;
+            };
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            sink.write_packet(timestamp, &frame.encode())?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Sets the socket option `name` at `level` to `value`, which may be a slice
+/// for options that take an array.
+fn set_option<T: ?Sized>(
+    socket: &OwnedFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &T,
+) -> std::io::Result<()> {
+    // Safety: `value` is valid for reads of its size.
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            (value as *const T).cast(),
+            std::mem::size_of_val(value) as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pcap_file::DataLink;
+
+    use super::{SocketCanError, SocketCanSource};
+    use crate::sink::PcapSink;
+
+    #[test]
+    fn no_such_interface() {
+        let mut sink = PcapSink::new(Vec::new(), DataLink::CAN_SOCKETCAN).unwrap();
+        assert!(matches!(
+            SocketCanSource::new("nonexistent-can9").run(&mut sink),
+            Err(SocketCanError::NoSuchInterface(name)) if name == "nonexistent-can9"
+        ));
+    }
+}
@@ -0,0 +1,638 @@
+//! A packet source that subscribes to a WebSocket stream, for debugging IoT
+//! gateways and other services that push their messages over WebSockets.
+//!
+//! Each message received from the server is written to the sink as a packet.
+//! Messages can be handed to a Wireshark dissector, like `json`, using
+//! [`dissector`][WebSocketSource::dissector]. The connection status can be
+//! shown in a [`LoggerControl`] of the toolbar using
+//! [`status_logger`][WebSocketSource::status_logger].
+//!
+//! Only plain `ws://` URLs are supported. To capture from a `wss://` server,
+//! run a TLS-terminating proxy on the local machine.
+//!
+//! ```no_run
+//! use r_extcap::{
+//!     controls::{asynchronous::ControlHub, LoggerControl},
+//!     sink::PcapSink,
+//!     sources::websocket::WebSocketSource,
+//! };
+//! # async fn example(hub: ControlHub, url: String, topic: String) -> anyhow::Result<()> {
+//! # let fifo = Vec::new();
+//! let logger = LoggerControl::builder().control_number(2).display("Log").build();
+//! let source = WebSocketSource::new(&url)
+//!     .subscribe_message(format!(r#"{{"subscribe": "{topic}"}}"#))
+//!     .dissector("json")
+//!     .status_logger(hub.sender(), logger);
+//! let mut sink = PcapSink::new(fifo, source.datalink())?;
+//! source.run(&mut sink).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use pcap_file::{DataLink, PcapError};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::{
+    controls::{
+        asynchronous::{ControlHubSender, ExtcapControlSenderTrait},
+        LoggerControl,
+    },
+    exported_pdu::ExportedPdu,
+    sink::PacketSink,
+};
+
+/// Appended to the handshake key to compute the expected
+/// `Sec-WebSocket-Accept` header, as defined in RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The largest message accepted from the server, to bound memory use when the
+/// server misbehaves.
+const MAX_MESSAGE_LEN: u64 = 16 << 20;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Error capturing from a WebSocket server.
+#[derive(Debug, Error)]
+pub enum WebSocketError {
+    /// The URL is not a valid `ws://` URL.
+    #[error("Invalid WebSocket URL {0:?}")]
+    InvalidUrl(String),
+    /// The URL has a scheme other than `ws://`, like `wss://`.
+    #[error("Unsupported scheme in WebSocket URL {0:?}, only ws:// is supported")]
+    UnsupportedScheme(String),
+    /// The server did not accept the WebSocket handshake.
+    #[error("WebSocket handshake failed: {0}")]
+    Handshake(String),
+    /// The server sent a malformed frame.
+    #[error("WebSocket protocol error: {0}")]
+    Protocol(&'static str),
+    /// The server sent a message larger than 16 MiB.
+    #[error("WebSocket message of {0} bytes is too large")]
+    MessageTooLarge(u64),
+    /// Error connecting to or communicating with the server.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// Error writing the messages to the sink.
+    #[error(transparent)]
+    Pcap(#[from] PcapError),
+}
+
+/// The parts of a `ws://` URL needed to connect.
+#[derive(Debug, PartialEq, Eq)]
+struct WsUrl<'a> {
+    /// The host and optional port, for the `Host` header.
+    authority: &'a str,
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+impl<'a> WsUrl<'a> {
+    fn parse(url: &'a str) -> Result<Self, WebSocketError> {
+        let invalid = || WebSocketError::InvalidUrl(url.to_owned());
+        let Some(rest) = url.strip_prefix("ws://") else {
+            if url.contains("://") {
+                return Err(WebSocketError::UnsupportedScheme(url.to_owned()));
+            }
+            return Err(invalid());
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            // An IPv6 address, like `[::1]:8080`.
+            Some(ipv6) => {
+                let (host, port) = ipv6.split_once(']').ok_or_else(invalid)?;
+                (host, port.strip_prefix(':'))
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            authority,
+            host,
+            port,
+            path,
+        })
+    }
+}
+
+/// A frame received from the server.
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// A packet source that connects to a WebSocket server and writes each
+/// message it receives to a sink. See the [module documentation][self].
+pub struct WebSocketSource {
+    url: String,
+    subscribe_message: Option<String>,
+    dissector: Option<String>,
+    status: Option<(ControlHubSender, LoggerControl)>,
+}
+
+impl WebSocketSource {
+    /// Creates a source for the WebSocket server at `url`, like
+    /// `ws://gateway.local:8080/events`.
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_owned(),
+            subscribe_message: None,
+            dissector: None,
+            status: None,
+        }
+    }
+
+    /// Sends `message` as a text message once connected, for servers that
+    /// expect the client to subscribe to a topic first.
+    #[must_use]
+    pub fn subscribe_message(mut self, message: impl Into<String>) -> Self {
+        self.subscribe_message = Some(message.into());
+        self
+    }
+
+    /// Writes the messages as [`DataLink::WIRESHARK_UPPER_PDU`] packets to be
+    /// dissected by the Wireshark dissector named `dissector`, like `json`.
+    /// Otherwise the messages are written as [`DataLink::USER0`] packets.
+    #[must_use]
+    pub fn dissector(mut self, dissector: &str) -> Self {
+        self.dissector = Some(dissector.to_owned());
+        self
+    }
+
+    /// Reports the connection status, like connecting, subscribing and
+    /// disconnecting, in `logger` through `sender`.
+    #[must_use]
+    pub fn status_logger(mut self, sender: ControlHubSender, logger: LoggerControl) -> Self {
+        self.status = Some((sender, logger));
+        self
+    }
+
+    /// The data link type of the packets, for creating the sink.
+    pub fn datalink(&self) -> DataLink {
+        match self.dissector {
+            Some(_) => DataLink::WIRESHARK_UPPER_PDU,
+            None => DataLink::USER0,
+        }
+    }
+
+    /// Connects to the server and writes the messages to `sink` until the
+    /// server closes the connection. Returns the number of messages written.
+    pub async fn run(self, sink: &mut impl PacketSink) -> Result<u64, WebSocketError> {
+        let result = self.run_connection(sink).await;
+        if let Err(e) = &result {
+            self.log(&format!("Disconnected from {}: {e}", self.url))
+                .await;
+        }
+        result
+    }
+
+    async fn run_connection(&self, sink: &mut impl PacketSink) -> Result<u64, WebSocketError> {
+        let url = WsUrl::parse(&self.url)?;
+        self.log(&format!("Connecting to {}", self.url)).await;
+        let stream = TcpStream::connect((url.host, url.port)).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        handshake(&mut reader, &mut writer, &url).await?;
+        self.log(&format!("Connected to {}", self.url)).await;
+        if let Some(message) = &self.subscribe_message {
+            write_frame(&mut writer, OPCODE_TEXT, message.as_bytes()).await?;
+            self.log(&format!("Subscribed with {message}")).await;
+        }
+
+        let mut count = 0;
+        let mut fragments: Option<Vec<u8>> = None;
+        loop {
+            let Some(frame) = read_frame(&mut reader).await? else {
+                self.log("Connection closed").await;
+                return Ok(count);
+            };
+            let message = match frame.opcode {
+                OPCODE_TEXT | OPCODE_BINARY if fragments.is_some() => {
+                    return Err(WebSocketError::Protocol("Expected a continuation frame"));
+                }
+                OPCODE_TEXT | OPCODE_BINARY if !frame.fin => {
+                    fragments = Some(frame.payload);
+                    continue;
+                }
+                OPCODE_TEXT | OPCODE_BINARY => frame.payload,
+                OPCODE_CONTINUATION => {
+                    let Some(message) = &mut fragments else {
+                        return Err(WebSocketError::Protocol("Unexpected continuation frame"));
+                    };
+                    let len = (message.len() + frame.payload.len()) as u64;
+                    if len > MAX_MESSAGE_LEN {
+                        return Err(WebSocketError::MessageTooLarge(len));
+                    }
+                    message.extend_from_slice(&frame.payload);
+                    if !frame.fin {
+                        continue;
+                    }
+                    fragments.take().unwrap_or_default()
+                }
+                OPCODE_PING => {
+                    write_frame(&mut writer, OPCODE_PONG, &frame.payload).await?;
+                    continue;
+                }
+                OPCODE_PONG => continue,
+                OPCODE_CLOSE => {
+                    // Echo the status code to complete the closing handshake.
+                    let code = frame.payload.get(..2).unwrap_or_default();
+                    let _ = write_frame(&mut writer, OPCODE_CLOSE, code).await;
+                    let reason = match code {
+                        [high, low] => u16::from_be_bytes([*high, *low]).to_string(),
+                        _ => String::from("no status"),
+                    };
+                    self.log(&format!("Connection closed by server ({reason})"))
+                        .await;
+                    return Ok(count);
+                }
+                _ => return Err(WebSocketError::Protocol("Unknown opcode")),
+            };
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            match &self.dissector {
+                Some(dissector) => {
+                    sink.write_packet(timestamp, &ExportedPdu::new(dissector).encode(&message))?
+                }
+                None => sink.write_packet(timestamp, &message)?,
+            }
+            count += 1;
+        }
+    }
+
+    async fn log(&self, message: &str) {
+        log::info!("{message}");
+        if let Some((sender, logger)) = &self.status {
+            // The status is only informational, so failing to show it does not
+            // stop the capture.
+            let _ = sender.send(logger.add_log(message.into())).await;
+        }
+    }
+}
+
+/// Sends the opening handshake and validates the response of the server.
+async fn handshake(
+    reader: &mut (impl AsyncBufReadExt + Unpin),
+    writer: &mut (impl AsyncWrite + Unpin),
+    url: &WsUrl<'_>,
+) -> Result<(), WebSocketError> {
+    let key = base64(&[random_u64().to_ne_bytes(), random_u64().to_ne_bytes()].concat());
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        url.path, url.authority
+    );
+    writer.write_all(request.as_bytes()).await?;
+
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line).await?;
+    if status_line.split_whitespace().nth(1) != Some("101") {
+        return Err(WebSocketError::Handshake(format!(
+            "Unexpected response {:?}",
+            status_line.trim_end()
+        )));
+    }
+    let mut accept = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(WebSocketError::Handshake(String::from(
+                "Connection closed during handshake",
+            )));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-accept") {
+                accept = Some(value.trim().to_owned());
+            }
+        }
+    }
+    if accept.as_deref() != Some(&accept_key(&key)) {
+        return Err(WebSocketError::Handshake(String::from(
+            "Invalid Sec-WebSocket-Accept header",
+        )));
+    }
+    Ok(())
+}
+
+/// The `Sec-WebSocket-Accept` value the server must respond with to `key`.
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{WEBSOCKET_GUID}").as_bytes()))
+}
+
+/// Reads the next frame, or returns `None` if the connection is closed
+/// between frames.
+async fn read_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<Frame>, WebSocketError> {
+    let mut header = [0_u8; 2];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if header[0] & 0x70 != 0 {
+        return Err(WebSocketError::Protocol("Reserved bits are set"));
+    }
+    let len = match header[1] & 0x7f {
+        126 => u64::from(reader.read_u16().await?),
+        127 => reader.read_u64().await?,
+        len => u64::from(len),
+    };
+    if len > MAX_MESSAGE_LEN {
+        return Err(WebSocketError::MessageTooLarge(len));
+    }
+    // Servers must not mask their frames, but unmasking them costs nothing.
+    let mask = match header[1] & 0x80 {
+        0 => None,
+        _ => Some(reader.read_u32().await?.to_be_bytes()),
+    };
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+    Ok(Some(Frame {
+        fin: header[0] & 0x80 != 0,
+        opcode: header[0] & 0x0f,
+        payload,
+    }))
+}
+
+/// Writes a single, masked frame, as required for frames sent by clients.
+async fn write_frame(
+    writer: &mut (impl AsyncWrite + Unpin),
+    opcode: u8,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let mut frame = Vec::with_capacity(14 + payload.len());
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    let mask = (random_u64() as u32).to_be_bytes();
+    frame.extend_from_slice(&mask);
+    let start = frame.len();
+    frame.extend_from_slice(payload);
+    apply_mask(&mut frame[start..], mask);
+    writer.write_all(&frame).await
+}
+
+fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (byte, mask) in payload.iter_mut().zip(mask.iter().cycle()) {
+        *byte ^= mask;
+    }
+}
+
+/// A random number for the handshake key and the frame masks, which only need
+/// to be unpredictable to intermediaries, not cryptographically secure.
+fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+/// Encodes `data` in standard base64 with padding.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0_u32, |bits, (i, byte)| {
+            bits | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Computes the SHA-1 digest of `data`, which the handshake uses to prove
+/// that the server understood the request. It is not used for security.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0_u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in state.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use pcap_file::{pcap::PcapReader, DataLink};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::{
+        accept_key, base64, read_frame, sha1, WebSocketError, WebSocketSource, WsUrl, OPCODE_PONG,
+        OPCODE_TEXT,
+    };
+    use crate::{
+        controls::{asynchronous::ControlHubSender, ControlCommand, LoggerControl},
+        sink::PcapSink,
+    };
+
+    #[test]
+    fn handshake_key() {
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(
+            sha1(b"abc"),
+            *b"\xa9\x99\x3e\x36\x47\x06\x81\x6a\xba\x3e\x25\x71\x78\x50\xc2\x6c\x9c\xd0\xd8\x9d"
+        );
+        // The example from RFC 6455.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            WsUrl::parse("ws://[::1]:8080/events?topic=a").unwrap(),
+            WsUrl {
+                authority: "[::1]:8080",
+                host: "::1",
+                port: 8080,
+                path: "/events?topic=a"
+            }
+        );
+        assert_eq!(WsUrl::parse("ws://gateway").unwrap().port, 80);
+        assert!(matches!(
+            WsUrl::parse("wss://gateway/"),
+            Err(WebSocketError::UnsupportedScheme(_))
+        ));
+        assert!(matches!(
+            WsUrl::parse("ws://gateway:http/"),
+            Err(WebSocketError::InvalidUrl(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn receive_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/stream", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            let mut key = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("Sec-WebSocket-Key: ") {
+                    key = Some(value.trim().to_owned());
+                }
+            }
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key.unwrap())
+            );
+            writer.write_all(response.as_bytes()).await.unwrap();
+
+            let subscribe = read_frame(&mut reader).await.unwrap().unwrap();
+            assert_eq!(subscribe.opcode, OPCODE_TEXT);
+            assert_eq!(subscribe.payload, b"sub");
+            // A text message, a ping, a fragmented binary message and a close.
+            writer.write_all(b"\x81\x02hi\x89\x01p").await.unwrap();
+            writer.write_all(b"\x02\x01a\x80\x02bc").await.unwrap();
+            let pong = read_frame(&mut reader).await.unwrap().unwrap();
+            assert_eq!((pong.opcode, &pong.payload[..]), (OPCODE_PONG, &b"p"[..]));
+            writer.write_all(b"\x88\x02\x03\xe8").await.unwrap();
+            let mut close = Vec::new();
+            reader.read_to_end(&mut close).await.unwrap();
+            assert_eq!(close[0], 0x88);
+        });
+
+        let (tx, mut rx) = mpsc::channel(10);
+        let logger = LoggerControl::builder()
+            .control_number(2)
+            .display("Log")
+            .build();
+        let source = WebSocketSource::new(&url)
+            .subscribe_message("sub")
+            .status_logger(ControlHubSender(tx), logger);
+        assert_eq!(source.datalink(), DataLink::USER0);
+        let mut sink = PcapSink::new(Vec::new(), source.datalink()).unwrap();
+        assert_eq!(source.run(&mut sink).await.unwrap(), 2);
+        server.await.unwrap();
+
+        let output = sink.into_inner();
+        let mut reader = PcapReader::new(&output[..]).unwrap();
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, &b"hi"[..]);
+        assert_eq!(reader.next_packet().unwrap().unwrap().data, &b"abc"[..]);
+        assert!(reader.next_packet().is_none());
+
+        let mut logs = Vec::new();
+        while let Ok(packet) = rx.try_recv() {
+            assert_eq!(packet.command, ControlCommand::Add);
+            logs.push(String::from_utf8(packet.payload.into_owned()).unwrap());
+        }
+        assert_eq!(
+            logs,
+            [
+                format!("Connecting to {url}\n"),
+                format!("Connected to {url}\n"),
+                String::from("Subscribed with sub\n"),
+                String::from("Connection closed by server (1000)\n"),
+            ]
+        );
+    }
+}
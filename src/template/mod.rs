@@ -0,0 +1,165 @@
+//! Generates a minimal extcap project, to get from zero to an interface shown
+//! in Wireshark in a few minutes.
+//!
+//! The generated project lists one interface with a config, writes a packet
+//! every second during the capture, and can install itself into Wireshark's
+//! extcap directory with `--install`.
+//!
+//! ```no_run
+//! use r_extcap::template::{write_project, TemplateOptions};
+//! use std::path::Path;
+//!
+//! let options = TemplateOptions::builder()
+//!     .name("my-extcap")
+//!     .description("My sniffer")
+//!     .build();
+//! write_project(Path::new("my-extcap"), &options).unwrap();
+//! ```
+//!
+//! This creates:
+//!
+//! * `my-extcap/Cargo.toml`
+//! * `my-extcap/src/main.rs`
+//! * `my-extcap/README.md`, with the installation instructions
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+use typed_builder::TypedBuilder;
+
+const CARGO_TOML: &str = include_str!("Cargo.toml.tmpl");
+const MAIN_RS: &str = include_str!("main.rs.tmpl");
+const README_MD: &str = include_str!("README.md.tmpl");
+
+/// Options for the project generated by [`write_project`].
+#[derive(Clone, Debug, TypedBuilder)]
+pub struct TemplateOptions {
+    /// The name of the crate, which is also the name of the executable and
+    /// the value of its interface. Must start with a letter and contain only
+    /// ASCII letters, digits, `-` and `_`.
+    #[builder(setter(into))]
+    pub name: String,
+    /// The description of the crate, which is also the display name of its
+    /// interface in Wireshark.
+    #[builder(default = String::from("Rust extcap interface"), setter(into))]
+    pub description: String,
+    /// The version of `r-extcap` to depend on. Defaults to the version of
+    /// this crate.
+    #[builder(default = String::from(env!("CARGO_PKG_VERSION")), setter(into))]
+    pub r_extcap_version: String,
+}
+
+/// Error generating a project in [`write_project`].
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    /// The name is not a valid crate name. See [`TemplateOptions::name`].
+    #[error("Invalid crate name {0:?}")]
+    InvalidName(String),
+
+    /// The description contains quotes, backslashes or control characters,
+    /// which cannot be used in the generated files without escaping.
+    #[error("The description cannot contain quotes, backslashes or control characters")]
+    InvalidDescription,
+
+    /// The directory already exists and is not empty.
+    #[error("{} already exists and is not empty", .0.display())]
+    DirectoryNotEmpty(PathBuf),
+
+    /// Error writing the project files.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Writes a new extcap project to `dir`, which must not exist or be empty.
+pub fn write_project(dir: &Path, options: &TemplateOptions) -> Result<(), TemplateError> {
+    let name = &options.name;
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(TemplateError::InvalidName(name.clone()));
+    }
+    if options
+        .description
+        .chars()
+        .any(|c| c == '"' || c == '\\' || c.is_control())
+    {
+        return Err(TemplateError::InvalidDescription);
+    }
+    if dir
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(TemplateError::DirectoryNotEmpty(dir.to_owned()));
+    }
+    let render = |template: &str| {
+        template
+            .replace("{{name}}", name)
+            .replace("{{description}}", &options.description)
+            .replace("{{r_extcap_version}}", &options.r_extcap_version)
+    };
+    std::fs::create_dir_all(dir.join("src"))?;
+    std::fs::write(dir.join("Cargo.toml"), render(CARGO_TOML))?;
+    std::fs::write(dir.join("src").join("main.rs"), render(MAIN_RS))?;
+    std::fs::write(dir.join("README.md"), render(README_MD))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_project, TemplateError, TemplateOptions};
+
+    #[test]
+    fn write() {
+        let dir = std::env::temp_dir().join(format!("r-extcap-template-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let options = TemplateOptions::builder()
+            .name("my-extcap")
+            .description("My sniffer")
+            .r_extcap_version("0.2")
+            .build();
+        write_project(&dir, &options).unwrap();
+
+        let cargo_toml = std::fs::read_to_string(dir.join("Cargo.toml")).unwrap();
+        assert!(cargo_toml.contains("name = \"my-extcap\""), "{cargo_toml}");
+        assert!(cargo_toml.contains("r-extcap = \"0.2\""), "{cargo_toml}");
+        let main_rs = std::fs::read_to_string(dir.join("src/main.rs")).unwrap();
+        assert!(
+            main_rs.contains(".value(\"my-extcap\".into())"),
+            "{main_rs}"
+        );
+        assert!(!main_rs.contains("{{"), "{main_rs}");
+        let readme = std::fs::read_to_string(dir.join("README.md")).unwrap();
+        assert!(readme.contains("./target/release/my-extcap --install"));
+
+        assert!(matches!(
+            write_project(&dir, &options),
+            Err(TemplateError::DirectoryNotEmpty(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_options() {
+        let dir =
+            std::env::temp_dir().join(format!("r-extcap-template-invalid-{}", std::process::id()));
+        for name in ["", "1extcap", "my extcap", "my\"extcap"] {
+            assert!(matches!(
+                write_project(&dir, &TemplateOptions::builder().name(name).build()),
+                Err(TemplateError::InvalidName(_))
+            ));
+        }
+        assert!(matches!(
+            write_project(
+                &dir,
+                &TemplateOptions::builder()
+                    .name("extcap")
+                    .description("\"quoted\"")
+                    .build()
+            ),
+            Err(TemplateError::InvalidDescription)
+        ));
+        assert!(!dir.exists());
+    }
+}
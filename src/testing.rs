@@ -0,0 +1,510 @@
+//! Utilities for testing extcaps without Wireshark.
+//!
+//! Wireshark creates the fifo given in `--fifo` and the control pipes given in
+//! `--extcap-control-in` and `--extcap-control-out` before starting the
+//! capture: fifos on Unix, and named pipes on Windows. [`TempFifo`] creates
+//! the same kind of pipe, so that tests and other programs can run a capture
+//! the way Wireshark does.
+//!
+//! ```no_run
+//! use std::process::Command;
+//! use r_extcap::testing::TempFifo;
+//!
+//! # fn main() -> Result<(), r_extcap::testing::ReadCaptureError> {
+//! let fifo = TempFifo::new("capture")?;
+//! let mut extcap = Command::new("my-extcap")
+//!     .args(["--capture", "--extcap-interface", "if1", "--fifo"])
+//!     .arg(fifo.path())
+//!     .spawn()?;
+//! // Waits for the extcap to open the fifo, then reads the packets until the
+//! // extcap closes it.
+//! let capture = fifo.read_capture()?;
+//! assert_eq!(capture.packets.len(), 3);
+//! assert_eq!(capture.packets[0].data, b"first packet");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`read_capture`] reads back the packets written by a
+//! [`PacketSink`][crate::sink::PacketSink], so that a test can check what a
+//! capture loop produced, either through a fifo or from an in-memory buffer.
+
+use std::{
+    fs::File,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use pcap_file::{
+    pcap::PcapReader,
+    pcapng::{
+        blocks::{
+            enhanced_packet::EnhancedPacketOption,
+            interface_description::InterfaceDescriptionOption,
+        },
+        Block, PcapNgReader,
+    },
+    DataLink, PcapError,
+};
+use thiserror::Error;
+
+use crate::{
+    pcap_stream::CaptureFormat,
+    sink::{Direction, PacketInfo},
+};
+
+/// Makes the names of the fifos created by this process unique.
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A fifo, or a named pipe on Windows, which is removed when dropped.
+///
+/// The fifo plays the part of Wireshark: the extcap opens [`path`][Self::path]
+/// as given on its command line, and the test opens the other end with
+/// [`open_reader`][Self::open_reader] or [`open_writer`][Self::open_writer].
+#[derive(Debug)]
+pub struct TempFifo {
+    path: PathBuf,
+    #[cfg(windows)]
+    pipe: std::os::windows::io::OwnedHandle,
+}
+
+impl TempFifo {
+    /// Creates a fifo with a unique path containing `name`. On Unix, the fifo
+    /// is created in the temporary directory, and is only accessible by the
+    /// current user.
+    pub fn new(name: &str) -> io::Result<Self> {
+        let unique_name = format!(
+            "r-extcap-{}-{}-{name}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        Self::create(unique_name)
+    }
+
+    #[cfg(unix)]
+    fn create(unique_name: String) -> io::Result<Self> {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        let path = std::env::temp_dir().join(unique_name);
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // Safety: `c_path` is a valid nul-terminated string.
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { path })
+    }
+
+    #[cfg(windows)]
+    fn create(unique_name: String) -> io::Result<Self> {
+        use std::os::windows::{
+            ffi::OsStrExt,
+            io::{FromRawHandle, OwnedHandle},
+        };
+        use windows_sys::Win32::{
+            Foundation::INVALID_HANDLE_VALUE,
+            Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX},
+            System::Pipes::{CreateNamedPipeW, PIPE_TYPE_BYTE, PIPE_WAIT},
+        };
+
+        let path = PathBuf::from(format!(r"\\.\pipe\{unique_name}"));
+        let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+        // Safety: `wide_path` is a valid nul-terminated wide string.
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide_path.as_ptr(),
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                PIPE_TYPE_BYTE | PIPE_WAIT,
+                1,
+                65536,
+                65536,
+                0,
+                std::ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        // Safety: `handle` is a valid handle owned by nothing else.
+        let pipe = unsafe { OwnedHandle::from_raw_handle(handle) };
+        Ok(Self { path, pipe })
+    }
+
+    /// The path of the fifo, to be passed to the extcap.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens the fifo for reading, like Wireshark does with `--fifo` and
+    /// `--extcap-control-out`. Blocks until the extcap opens the fifo for
+    /// writing.
+    pub fn open_reader(&self) -> io::Result<File> {
+        #[cfg(unix)]
+        {
+            File::open(&self.path)
+        }
+        #[cfg(windows)]
+        {
+            self.connect()
+        }
+    }
+
+    /// Reads the packets written to the fifo with [`read_capture`], until the
+    /// extcap closes the fifo. Blocks until the extcap opens the fifo for
+    /// writing.
+    pub fn read_capture(&self) -> Result<Capture, ReadCaptureError> {
+        read_capture(self.open_reader()?)
+    }
+
+    /// Opens the fifo for writing, like Wireshark does with
+    /// `--extcap-control-in`. Blocks until the extcap opens the fifo for
+    /// reading.
+    pub fn open_writer(&self) -> io::Result<File> {
+        #[cfg(unix)]
+        {
+            std::fs::OpenOptions::new().write(true).open(&self.path)
+        }
+        #[cfg(windows)]
+        {
+            self.connect()
+        }
+    }
+
+    /// Waits for the extcap to connect to the server end of the named pipe,
+    /// and returns the server end.
+    #[cfg(windows)]
+    fn connect(&self) -> io::Result<File> {
+        use std::os::windows::io::AsRawHandle;
+        use windows_sys::Win32::{
+            Foundation::ERROR_PIPE_CONNECTED, System::Pipes::ConnectNamedPipe,
+        };
+
+        // Safety: `self.pipe` is a valid named pipe handle.
+        if unsafe { ConnectNamedPipe(self.pipe.as_raw_handle(), std::ptr::null_mut()) } == 0 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                return Err(error);
+            }
+        }
+        Ok(File::from(self.pipe.try_clone()?))
+    }
+}
+
+/// Error reading a capture with [`read_capture`].
+#[derive(Debug, Error)]
+pub enum ReadCaptureError {
+    /// Error reading the capture.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The capture does not start with a pcap magic number or a pcapng section
+    /// header block.
+    #[error("Unrecognized capture format magic number {0:#010x}")]
+    UnknownMagic(u32),
+    /// Error parsing the capture, for example because it ends in the middle
+    /// of a packet.
+    #[error(transparent)]
+    Pcap(#[from] PcapError),
+}
+
+/// A packet read by [`read_capture`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedPacket {
+    /// The timestamp of the packet, as the duration since the Unix epoch.
+    /// Zero for the pcapng simple packet blocks, which have no timestamp.
+    pub timestamp: Duration,
+    /// The packet data.
+    pub data: Vec<u8>,
+    /// The interface ID and the options of the packet. Always the default for
+    /// the pcap format, which has a single interface and no options.
+    pub info: PacketInfo,
+}
+
+/// The contents of a capture read by [`read_capture`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capture {
+    /// The format of the capture.
+    pub format: CaptureFormat,
+    /// The data link type of each interface, indexed by
+    /// [`PacketInfo::interface_id`]. The pcap format has a single interface.
+    pub datalinks: Vec<DataLink>,
+    /// The packets in the capture, in order.
+    pub packets: Vec<CapturedPacket>,
+}
+
+impl Capture {
+    /// The data of each packet, to compare the packets without their
+    /// timestamps.
+    pub fn data(&self) -> Vec<&[u8]> {
+        self.packets.iter().map(|packet| &packet.data[..]).collect()
+    }
+}
+
+/// Reads a pcap or pcapng capture from `reader` until the end, like Wireshark
+/// reads the fifo.
+///
+/// Unlike the readers of `pcap_file`, the pcapng timestamps are converted
+/// using the `if_tsresol` option of their interface, so that the timestamps
+/// match the ones given to the [`PacketSink`][crate::sink::PacketSink] for
+/// any [`TimestampResolution`][crate::sink::TimestampResolution].
+///
+/// ```
+/// use r_extcap::interface::DataLink;
+/// use r_extcap::sink::{PacketSink, PcapSink};
+/// use r_extcap::testing::read_capture;
+/// use std::time::Duration;
+///
+/// let mut sink = PcapSink::new(Vec::new(), DataLink::ETHERNET).unwrap();
+/// sink.write_packet(Duration::from_secs(1), b"packet").unwrap();
+/// let capture = read_capture(&sink.into_inner()[..]).unwrap();
+/// assert_eq!(capture.datalinks, [DataLink::ETHERNET]);
+/// assert_eq!(capture.packets[0].timestamp, Duration::from_secs(1));
+/// assert_eq!(capture.data(), [b"packet"]);
+/// ```
+pub fn read_capture(mut reader: impl Read) -> Result<Capture, ReadCaptureError> {
+    let mut magic = [0_u8; 4];
+    reader.read_exact(&mut magic)?;
+    let format = CaptureFormat::detect(magic)
+        .ok_or(ReadCaptureError::UnknownMagic(u32::from_be_bytes(magic)))?;
+    let reader = Cursor::new(magic).chain(reader);
+    let mut capture = Capture {
+        format,
+        datalinks: Vec::new(),
+        packets: Vec::new(),
+    };
+    match format {
+        CaptureFormat::Pcap => {
+            let mut reader = PcapReader::new(reader)?;
+            capture.datalinks.push(reader.header().datalink);
+            while let Some(packet) = reader.next_packet().transpose()? {
+                capture.packets.push(CapturedPacket {
+                    timestamp: packet.timestamp,
+                    data: packet.data.into_owned(),
+                    info: PacketInfo::default(),
+                });
+            }
+        }
+        CaptureFormat::PcapNg => {
+            let mut reader = PcapNgReader::new(reader)?;
+            // The `if_tsresol` of each interface.
+            let mut resolutions = Vec::new();
+            while let Some(block) = reader.next_block().transpose()? {
+                match block {
+                    Block::InterfaceDescription(interface) => {
+                        capture.datalinks.push(interface.linktype);
+                        resolutions.push(
+                            interface
+                                .options
+                                .iter()
+                                .find_map(|option| match option {
+                                    InterfaceDescriptionOption::IfTsResol(resolution) => {
+                                        Some(*resolution)
+                                    }
+                                    _ => None,
+                                })
+                                .unwrap_or(6),
+                        );
+                    }
+                    Block::EnhancedPacket(packet) => {
+                        let resolution = resolutions
+                            .get(packet.interface_id as usize)
+                            .copied()
+                            .ok_or(PcapError::InvalidInterfaceId(packet.interface_id))?;
+                        let mut info = PacketInfo {
+                            interface_id: packet.interface_id,
+                            ..Default::default()
+                        };
+                        for option in packet.options {
+                            match option {
+                                EnhancedPacketOption::Comment(comment) => {
+                                    info.comment = Some(comment.into_owned())
+                                }
+                                EnhancedPacketOption::Flags(flags) => {
+                                    info.direction = match flags & 0b11 {
+                                        0b01 => Some(Direction::Inbound),
+                                        0b10 => Some(Direction::Outbound),
+                                        _ => None,
+                                    }
+                                }
+                                EnhancedPacketOption::DropCount(dropped) => {
+                                    info.dropped = Some(dropped)
+                                }
+                                _ => {}
+                            }
+                        }
+                        capture.packets.push(CapturedPacket {
+                            // `pcap-file` reads the number of units as
+                            // nanoseconds.
+                            timestamp: from_tsresol(packet.timestamp.as_nanos(), resolution),
+                            data: packet.data.into_owned(),
+                            info,
+                        });
+                    }
+                    Block::SimplePacket(packet) => capture.packets.push(CapturedPacket {
+                        timestamp: Duration::ZERO,
+                        data: packet.data.into_owned(),
+                        info: PacketInfo::default(),
+                    }),
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(capture)
+}
+
+/// Converts a number of timestamp `units` into a duration, using the pcapng
+/// `if_tsresol` value `resolution`. The unit is a negative power of 10, or a
+/// negative power of 2 if the most significant bit is set.
+fn from_tsresol(units: u128, resolution: u8) -> Duration {
+    let exponent = u32::from(resolution & 0x7f);
+    let units_per_second = if resolution & 0x80 == 0 {
+        10_u128.checked_pow(exponent)
+    } else {
+        2_u128.checked_pow(exponent)
+    }
+    .unwrap_or(u128::MAX);
+    let seconds = u64::try_from(units / units_per_second).unwrap_or(u64::MAX);
+    let nanos = (units % units_per_second) * 1_000_000_000 / units_per_second;
+    Duration::new(seconds, nanos as u32)
+}
+
+#[cfg(unix)]
+impl Drop for TempFifo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+
+    use pcap_file::DataLink;
+
+    use super::{from_tsresol, read_capture, CapturedPacket, ReadCaptureError, TempFifo};
+    use crate::{
+        pcap_stream::CaptureFormat,
+        sink::{Direction, PacketInfo, PacketSink, PcapNgSink, PcapSink, TimestampResolution},
+    };
+
+    #[test]
+    fn read_write() {
+        let fifo = TempFifo::new("read-write").unwrap();
+        let path = fifo.path().to_owned();
+        let writer = std::thread::spawn(move || {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .write_all(b"packet")
+                .unwrap();
+        });
+        let mut data = Vec::new();
+        fifo.open_reader().unwrap().read_to_end(&mut data).unwrap();
+        writer.join().unwrap();
+        assert_eq!(data, b"packet");
+    }
+
+    #[test]
+    fn unique_and_removed() {
+        let first = TempFifo::new("unique").unwrap();
+        let second = TempFifo::new("unique").unwrap();
+        assert_ne!(first.path(), second.path());
+        let path = first.path().to_owned();
+        assert!(path.exists());
+        drop(first);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn read_pcap_from_fifo() {
+        let fifo = TempFifo::new("read-pcap").unwrap();
+        let path = fifo.path().to_owned();
+        let writer = std::thread::spawn(move || {
+            let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+            let mut sink = PcapSink::new(file, DataLink::ETHERNET).unwrap();
+            sink.write_packet(Duration::from_micros(1_000_001), b"first")
+                .unwrap();
+            sink.write_packet(Duration::from_micros(2_000_002), b"second")
+                .unwrap();
+        });
+        let capture = fifo.read_capture().unwrap();
+        writer.join().unwrap();
+        assert_eq!(capture.format, CaptureFormat::Pcap);
+        assert_eq!(capture.datalinks, [DataLink::ETHERNET]);
+        assert_eq!(capture.data(), [&b"first"[..], b"second"]);
+        assert_eq!(
+            capture.packets[1].timestamp,
+            Duration::from_micros(2_000_002)
+        );
+    }
+
+    #[test]
+    fn read_pcapng() {
+        let mut sink =
+            PcapNgSink::with_resolution(Vec::new(), TimestampResolution::Microsecond).unwrap();
+        sink.add_interface(DataLink::ETHERNET, Some("eth0"), None)
+            .unwrap();
+        let wlan = sink
+            .add_interface(DataLink::IEEE802_11, Some("wlan0"), None)
+            .unwrap();
+        sink.write_packet(Duration::from_micros(1_500_000), b"first")
+            .unwrap();
+        let info = PacketInfo::builder()
+            .interface_id(wlan)
+            .direction(Direction::Outbound)
+            .dropped(2)
+            .comment("CRC error")
+            .build();
+        sink.write_packet_with_info(Duration::from_secs(2), b"second", &info)
+            .unwrap();
+        let capture = read_capture(&sink.into_inner()[..]).unwrap();
+        assert_eq!(capture.format, CaptureFormat::PcapNg);
+        assert_eq!(
+            capture.datalinks,
+            [DataLink::ETHERNET, DataLink::IEEE802_11]
+        );
+        assert_eq!(
+            capture.packets,
+            [
+                CapturedPacket {
+                    timestamp: Duration::from_micros(1_500_000),
+                    data: b"first".to_vec(),
+                    info: PacketInfo::default(),
+                },
+                CapturedPacket {
+                    timestamp: Duration::from_secs(2),
+                    data: b"second".to_vec(),
+                    info,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn read_invalid() {
+        assert!(matches!(
+            read_capture(&b"not a capture"[..]),
+            Err(ReadCaptureError::UnknownMagic(0x6e6f7420))
+        ));
+        let mut sink = PcapSink::new(Vec::new(), DataLink::ETHERNET).unwrap();
+        sink.write_packet(Duration::ZERO, b"packet").unwrap();
+        let bytes = sink.into_inner();
+        assert!(matches!(
+            read_capture(&bytes[..bytes.len() - 1]),
+            Err(ReadCaptureError::Pcap(_))
+        ));
+    }
+
+    #[test]
+    fn tsresol() {
+        assert_eq!(from_tsresol(1_500_000, 6), Duration::from_millis(1_500));
+        assert_eq!(from_tsresol(1_500_000_000, 9), Duration::from_millis(1_500));
+        assert_eq!(from_tsresol(3, 0x81), Duration::from_millis(1_500));
+    }
+}
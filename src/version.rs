@@ -0,0 +1,137 @@
+use std::{
+    fmt::Display,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+/// The major and minor version of Wireshark, used to change the behavior of
+/// the extcap depending on the features supported by the calling Wireshark.
+///
+/// Wireshark passes its version in
+/// [`--extcap-version`][crate::ExtcapArgs::extcap_version]. See
+/// [`ExtcapArgs::wireshark_version`][crate::ExtcapArgs::wireshark_version].
+///
+/// ```
+/// use r_extcap::WiresharkVersion;
+///
+/// let version: WiresharkVersion = "4.2".parse().unwrap();
+/// assert!(version >= WiresharkVersion::new(4, 0));
+/// assert_eq!("3.6.14".parse(), Ok(WiresharkVersion::new(3, 6)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WiresharkVersion {
+    /// The major version, like `4` in 4.2.
+    pub major: u32,
+    /// The minor version, like `2` in 4.2.
+    pub minor: u32,
+}
+
+impl WiresharkVersion {
+    /// The first version that offers a choice between multiple DLTs listed
+    /// for an interface. Older versions are only sent the preferred DLT by
+    /// [`DltsStep::print_from_interfaces`][crate::DltsStep::print_from_interfaces].
+    pub const MULTIPLE_DLTS: Self = Self::new(3, 0);
+
+    /// The first version that shows the interface toolbar, and passes the
+    /// `--extcap-control-in` and `--extcap-control-out` pipes to the capture.
+    pub const INTERFACE_TOOLBAR: Self = Self::new(2, 6);
+
+    /// Creates a version with the given major and minor version.
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+}
+
+/// Error parsing a [`WiresharkVersion`].
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Invalid Wireshark version \"{0}\"")]
+pub struct ParseVersionError(String);
+
+impl FromStr for WiresharkVersion {
+    type Err = ParseVersionError;
+
+    /// Parses a version in the form `major.minor`. Additional components,
+    /// like the patch version, are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('.');
+        let mut next = || {
+            parts
+                .next()
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(|| ParseVersionError(s.to_owned()))
+        };
+        Ok(Self::new(next()?, next()?))
+    }
+}
+
+impl Display for WiresharkVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The file that the version given with `--extcap-interfaces` is saved to.
+///
+/// Wireshark only passes `--extcap-version` when querying the interfaces,
+/// which it does every time it starts, so the version is saved there and read
+/// back in the later phases, which run in separate processes.
+fn saved_version_path() -> PathBuf {
+    let name = crate::executable_name().unwrap_or_else(|| String::from("extcap"));
+    crate::runtime_dir().join(format!("r-extcap-{name}.wireshark-version"))
+}
+
+/// Saves the version given with `--extcap-interfaces` for the later phases.
+/// See [`saved_version`].
+pub(crate) fn save_version(version: &str) -> io::Result<()> {
+    save_version_at(&saved_version_path(), version)
+}
+
+/// Returns the version saved by [`save_version`], if any.
+pub(crate) fn saved_version() -> Option<String> {
+    read_version_at(&saved_version_path())
+}
+
+fn save_version_at(path: &Path, version: &str) -> io::Result<()> {
+    // Write to a new file and rename it over the old one, so that a link
+    // planted at `path` is replaced instead of followed, and so that a phase
+    // running at the same time never reads a partially written version.
+    let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .and_then(|mut file| file.write_all(version.as_bytes()))
+        .and_then(|()| std::fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn read_version_at(path: &Path) -> Option<String> {
+    let version = std::fs::read_to_string(path).ok()?;
+    Some(version.trim().to_owned()).filter(|version| !version.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_version_at, save_version_at};
+
+    #[test]
+    fn save_and_read() {
+        let path = std::env::temp_dir().join(format!(
+            "r-extcap-version-test-{}.wireshark-version",
+            std::process::id()
+        ));
+        assert_eq!(read_version_at(&path), None);
+        save_version_at(&path, "4.2.0").unwrap();
+        assert_eq!(read_version_at(&path).as_deref(), Some("4.2.0"));
+        save_version_at(&path, "4.4").unwrap();
+        assert_eq!(read_version_at(&path).as_deref(), Some("4.4"));
+        std::fs::remove_file(path).unwrap();
+    }
+}